@@ -0,0 +1,60 @@
+//! Runs a bus from a configuration file and prints its events.
+//!
+//! ```bash
+//! $ cargo run --example bus -- bus.json 10
+//! ```
+//!
+//! Stops after the given number of events (default 10), saving the definitions if the
+//! configuration has `persist` set.
+use futures::StreamExt;
+use supmcu_rs::supmcu::bus::{BusConfig, BusEvent, SupMCUBus};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let config = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("usage: bus <config.json> [events]"))?;
+    let count: usize = args.next().map_or(Ok(10), |n| n.parse())?;
+
+    let mut bus = SupMCUBus::start(BusConfig::load(config)?)?;
+    let rt = tokio::runtime::Builder::new_current_thread().build()?;
+    rt.block_on(async {
+        let events = bus.events().take(count);
+        futures::pin_mut!(events);
+        while let Some(event) = events.next().await {
+            match event {
+                BusEvent::Telemetry {
+                    module, telemetry, ..
+                } => {
+                    println!(
+                        "{module:?} {}: {:?}",
+                        telemetry.definition.name, telemetry.data
+                    )
+                }
+                BusEvent::Health {
+                    address,
+                    alive,
+                    pollers,
+                } => {
+                    let degraded = pollers.iter().filter(|p| p.is_degraded()).count();
+                    println!("{address:#04X} alive: {alive}, degraded pollers: {degraded}")
+                }
+                BusEvent::FirmwareChanged { address, firmware } => {
+                    eprintln!("{address:#04X} now runs {firmware}")
+                }
+                BusEvent::Diagnosis(diagnosis) => println!("{diagnosis}"),
+                BusEvent::Error(e) => eprintln!("{e}"),
+                BusEvent::PollerDegraded(status) => eprintln!(
+                    "{} polled every {:?}",
+                    status.telemetry, status.effective_interval
+                ),
+                BusEvent::PollerRecovered(status) => {
+                    eprintln!("{} polled on time again", status.telemetry)
+                }
+                BusEvent::Lagged(dropped) => eprintln!("{dropped} events dropped"),
+            }
+        }
+    });
+    bus.stop()?;
+    Ok(())
+}
@@ -25,6 +25,33 @@ Discovering a definition for a single module at address 0x52.
 $ pumqry -p /dev/i2c-1 discover -f def.json 0x52
 ```
 
+Auditing a bus for firmware changes against a saved definition file.  Exits with 0 if the
+definitions are identical, 1 if they differ, and 2 on error.
+```bash
+$ pumqry -p /dev/i2c-1 discover --compare flight-def.json
+```
+
+Reading two bytes from, and writing two bytes to, a plain I2C sensor at address 0x48.  Addresses of
+the modules in the definition file passed with `-d` are refused unless `--force` is given.
+```bash
+$ pumqry -p /dev/i2c-1 raw read 0x48 2
+$ pumqry -p /dev/i2c-1 raw -d def.json write 0x48 0x01 0x80
+```
+
+Recording a query to a session file, then replaying it without hardware, e.g. to reproduce a
+parsing bug.
+```bash
+$ pumqry -p /dev/i2c-1 --record session.bin query -d def.json -m 0x52 -v 1 -s supmcu
+$ pumqry --replay session.bin query -d def.json -m 0x52 -v 1 -s supmcu
+```
+
+Running a macro stored in a definition file, e.g. to safe a module.  Exits with 1 if one of its
+verification steps fails.
+```bash
+$ pumqry -p /dev/i2c-1 macro -d def.json list
+$ pumqry -p /dev/i2c-1 macro -d def.json run safe_pim
+```
+
 
 ```bash
 $ pumqry --help
@@ -32,22 +59,31 @@ pumpkijn_supmcu-rs 0.1.0
 Jack Hughes <jack.hughes@pumpkininc.com>
 
 USAGE:
-    pumqry [OPTIONS] --path <DEVICE> <SUBCOMMAND>
+    pumqry [OPTIONS] <SUBCOMMAND>
 
 OPTIONS:
     -h, --help                         Print help information
     -p, --path <DEVICE>                Path for I2C device, e.g. /dev/i2c-1
-    -t, --device-type <DEVICE_TYPE>    Type of I2C device at the specified port. DEPRECATED - Only
-                                       kubos/linux type is currently supported [default: linux]
+        --record <FILE>                Record all I2C transactions to a session file (discover
+                                       and query only)
+        --replay <FILE>                Replay a recorded session file instead of using the I2C
+                                       device (discover and query only)
+    -t, --device-type <DEVICE_TYPE>    Type of I2C device at the specified port.  Backends this
+                                       build doesn't support are refused [default: linux]
                                        [possible values: i2c-driver, aardvark, linux, kubos]
     -V, --version                      Print version information
 
 SUBCOMMANDS:
+    diag        Compare the modules' counts of I2C errors with the host's to localize bus problems
     discover    Discover the telemetry/commands and query data from any Pumpkin SupMCU modules
                     on a particular I2C bus
+    dump        Write a telemetry item to a file, e.g. a log buffer
     help        Print this message or the help of the given subcommand(s)
     query       Query individual telemetry valus from any Pumpkin SupMCU module with a premade
                     definition file
+    macro       Run or list the command macros in a definition file
+    raw         Read from or write to plain (non-SupMCU) I2C devices on the bus
+    selftest    Run the self-tests of the modules in a definition file
 ```
 
 ```bash
@@ -64,12 +100,24 @@ OPTIONS:
     -d, --definition <DEFINITION>
             The definition file to load
 
+        --delay-ms <MS>
+            Milliseconds to wait for the response, instead of the module's response delay
+
     -h, --help
             Print help information
 
     -m, --module <MODULE>
             Them module name or I2C address to pull telemetry from
 
+    -r, --raw
+            Also print the raw response bytes as hex, including the header and footer
+
+        --retries <RETRIES>
+            Times to retry a non-ready response, instead of the default
+
+        --help-standard
+            List the standard SupMCU telemetry items and what they hold, then exit
+
     -s, --telemetry-type <TELEMETRY_TYPE>
             The type of telemetry to pull, either SupMCU or Module
 
@@ -110,14 +158,42 @@ OPTIONS:
 
     -q, --quiet
             Runs without outputing anything to stdout
+
+        --slim
+            Drop simulated values, commands and placeholder items from the saved and printed
+            definitions
+
+        --keep <NAME>
+            With --slim, only keep these telemetry items
+
+        --interactive
+            Review each discovered telemetry item, accepting, renaming, reformatting or skipping
+            it
+
+        --decisions <FILE>
+            Review decisions to replay, or with --interactive, to record
 ```
 */
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use flexi_logger::Logger;
-use std::path::PathBuf;
-use supmcu_rs::supmcu::{parsing, SupMCUMaster};
+use i2cdev::core::I2CDevice;
 use log::debug;
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+use supmcu_rs::supmcu::{
+    diag::{BusDiagnosis, Diagnosis},
+    diff::{self, DefinitionDiff, ModuleDiff},
+    parsing::{self, BusMacro, SelfTestFields, SlimOptions, SupMCUFormat, SupMCUModuleDefinition},
+    read_def_file,
+    review::{ReviewDecision, ReviewDecisions, ReviewItem},
+    standard_telemetry_items, BusReadiness, ModuleRef, ReadOptions, SupMCUMaster,
+};
+use supmcu_rs::SupMCUError;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -125,14 +201,211 @@ struct PumQry {
     #[clap(subcommand)]
     command: Commands,
     /// Path for I2C device, e.g. /dev/i2c-1
-    #[clap(short, long, parse(from_os_str), value_name = "DEVICE")]
-    path: PathBuf,
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        value_name = "DEVICE",
+        required_unless_present = "replay"
+    )]
+    path: Option<PathBuf>,
+    /// Record all I2C transactions to a session file (discover and query only)
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        conflicts_with = "replay"
+    )]
+    record: Option<PathBuf>,
+    /// Replay a recorded session file instead of using the I2C device (discover and query only)
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Type of I2C device at the specified port.  Backends this build doesn't support are
+    /// refused
+    #[clap(short = 't', long, value_enum, default_value = "linux")]
+    device_type: DeviceType,
+}
+
+/// The I2C backends a device can be driven through
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DeviceType {
+    I2cDriver,
+    Aardvark,
+    Linux,
+    /// KubOS exposes its buses as Linux I2C devices
+    Kubos,
+}
+
+impl DeviceType {
+    /// Fails if this build can't drive modules through the backend.  Every command opens
+    /// Linux I2C devices, the only backend the library implements so far.
+    fn check_supported(self) -> Result<(), anyhow::Error> {
+        match self {
+            DeviceType::Linux | DeviceType::Kubos => Ok(()),
+            DeviceType::I2cDriver | DeviceType::Aardvark => anyhow::bail!(
+                "The {self:?} backend isn't supported by this build, use --device-type linux"
+            ),
+        }
+    }
+}
+
+/// The session files to record to or replay from
+struct SessionFiles {
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     Discover(DiscoveryArgs),
     Query(QueryArgs),
+    Raw(RawArgs),
+    Selftest(SelftestArgs),
+    Dump(DumpArgs),
+    Macro(MacroArgs),
+    Diag(DiagArgs),
+}
+
+/// Compare the modules' counts of I2C errors with the host's to localize bus problems
+///
+/// Exits with 1 if any module or the host saw errors.  Modules whose counters can't be read
+/// only fail the diagnosis if the host saw errors.
+///
+/// Example: pumqry -p /dev/i2c-1 diag -d def.json --counters NACKS OVERRUNS ERRORS
+#[derive(Args, Debug)]
+struct DiagArgs {
+    /// The definition file to load.
+    #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+    definition: PathBuf,
+    /// The telemetry items counting the modules' NACKs sent, buffer overruns and bus errors,
+    /// from the firmware's documentation.  Without them only the host's counts are shown.
+    #[clap(long, number_of_values = 3, value_names = &["NACKS", "OVERRUNS", "ERRORS"])]
+    counters: Option<Vec<String>>,
+    /// Output format of the diagnoses.
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+/// Run or list the command macros in a definition file
+///
+/// Exits with 1 if a verification step of the macro fails.
+///
+/// Example: pumqry -p /dev/i2c-1 macro -d def.json run safe_pim
+#[derive(Args, Debug)]
+struct MacroArgs {
+    /// The definition file to load.
+    #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+    definition: PathBuf,
+    /// A JSON file of macros spanning several modules.
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    macros: Option<PathBuf>,
+    #[clap(subcommand)]
+    op: MacroOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum MacroOp {
+    /// Run a macro
+    Run {
+        /// Name of the macro
+        name: String,
+        /// Module name or I2C address to run the macro on, if several modules define it
+        #[clap(short, long, value_parser = parse_module)]
+        module: Option<ModuleOption>,
+        /// Output format of the report.
+        #[clap(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// List the available macros
+    List,
+}
+
+/// Write a telemetry item to a file, e.g. a log buffer.  String items are written as-is.
+///
+/// Example: pumqry -p /dev/i2c-1 dump -d def.json -m GPS -v log_buffer -o log.txt
+#[derive(Args, Debug)]
+struct DumpArgs {
+    /// The definition file to load.
+    #[clap(short, long)]
+    definition: PathBuf,
+
+    /// Them module name or I2C address to pull telemetry from
+    #[clap(short, long, value_parser = parse_module)]
+    module: ModuleOption,
+
+    /// Name of the telemetry item to dump.
+    #[clap(short, long)]
+    value: String,
+
+    /// The file to write to, instead of stdout.
+    #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// Run the self-tests of the modules in a definition file
+///
+/// Exits with 1 if any test fails.
+///
+/// Example: pumqry -p /dev/i2c-1 selftest -d def.json --result-field 1 --runs-field 0
+#[derive(Args, Debug)]
+struct SelftestArgs {
+    /// The definition file to load.
+    #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+    definition: PathBuf,
+    /// Seconds to wait for each module's self-test to complete.
+    #[clap(short, long, value_parser = parse_secs, value_name = "SECS", default_value = "10")]
+    timeout: Duration,
+    /// The field of the self-test results item holding the bitmask of failed tests.
+    #[clap(long, value_name = "FIELD")]
+    result_field: Option<usize>,
+    /// The field of the self-test results item counting completed runs, if any.
+    #[clap(long, value_name = "FIELD", requires = "result-field")]
+    runs_field: Option<usize>,
+    /// Output format of the results.
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+/// Read from or write to plain (non-SupMCU) I2C devices on the bus
+///
+/// Example: pumqry -p /dev/i2c-1 raw read 0x48 2
+#[derive(Args, Debug)]
+struct RawArgs {
+    /// Definition file of the SupMCU modules on the bus, whose addresses are refused unless forced.
+    #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+    definition: Option<PathBuf>,
+    /// Allow access to the addresses of SupMCU modules.
+    #[clap(long)]
+    force: bool,
+    #[clap(subcommand)]
+    op: RawOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum RawOp {
+    /// Read bytes from a device and print them as hex
+    Read {
+        /// I2C address of the device
+        #[clap(value_parser = parse_hex)]
+        address: u16,
+        /// Number of bytes to read
+        len: usize,
+        /// Read from a register using SMBus
+        #[clap(short, long, value_parser = parse_byte)]
+        register: Option<u8>,
+    },
+    /// Write bytes to a device
+    Write {
+        /// I2C address of the device
+        #[clap(value_parser = parse_hex)]
+        address: u16,
+        /// Bytes to write, in hex
+        #[clap(value_parser = parse_byte, required = true)]
+        bytes: Vec<u8>,
+        /// Write to a register using SMBus
+        #[clap(short, long, value_parser = parse_byte)]
+        register: Option<u8>,
+    },
 }
 
 /// Discover the telemetry/commands and query data from any Pumpkin SupMCU modules on a particular I2C bus.
@@ -152,14 +425,43 @@ struct DiscoveryArgs {
     /// List all of the available i2c addresses without getting telemetry data.
     #[clap(short, long)]
     list: bool,
+    /// Seconds to wait for all modules to respond before discovering them.
+    #[clap(short, long, value_parser = parse_secs, value_name = "SECS")]
+    wait: Option<Duration>,
     /// I2C address(es) to ignore
     #[clap(short, long, value_parser = parse_hex, value_name = "I2C ADDRESS TO IGNORE")]
     blacklist: Vec<u16>,
+    /// Compare the discovered definitions to a definition file and print the differences.
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    compare: Option<PathBuf>,
+    /// Output format of the comparison.
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+    /// Drop simulated values, commands and placeholder items from the saved and printed
+    /// definitions
+    #[clap(long)]
+    slim: bool,
+    /// With --slim, only keep these telemetry items
+    #[clap(long, value_name = "NAME", requires = "slim")]
+    keep: Vec<String>,
+    /// Review each discovered telemetry item, accepting, renaming, reformatting or skipping it
+    #[clap(long)]
+    interactive: bool,
+    /// Review decisions to replay, or with --interactive, to record
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    decisions: Option<PathBuf>,
     /// I2C address(es) of module(s) to read from
     #[clap(value_parser = parse_hex, value_name = "I2C ADDRESSES")]
     addrs: Vec<u16>,
 }
 
+/// How to print a definition comparison
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 /// An enum of the two different ways to specify a module
 #[derive(Clone, Debug, PartialEq)]
 enum ModuleOption {
@@ -167,6 +469,15 @@ enum ModuleOption {
     Address(u16),
 }
 
+impl From<&ModuleOption> for ModuleRef {
+    fn from(module: &ModuleOption) -> Self {
+        match module {
+            ModuleOption::Name(name) => ModuleRef::Name(name.clone()),
+            ModuleOption::Address(address) => ModuleRef::Address(*address),
+        }
+    }
+}
+
 /// An enum of the two different ways to specify a telemetry item
 #[derive(Clone, Debug, PartialEq)]
 enum TelemetryOption {
@@ -180,20 +491,45 @@ enum TelemetryOption {
 #[derive(Args, Debug)]
 struct QueryArgs {
     /// The definition file to load.
-    #[clap(short, long)]
-    definition: PathBuf,
+    #[clap(short, long, required_unless_present = "help_standard")]
+    definition: Option<PathBuf>,
 
     /// Them module name or I2C address to pull telemetry from
-    #[clap(short, long, value_parser = parse_module)]
-    module: ModuleOption,
+    #[clap(short, long, value_parser = parse_module, required_unless_present = "help_standard")]
+    module: Option<ModuleOption>,
 
-    /// Value to pull out of the module.
-    #[clap(short, long, value_parser = parse_tlm)]
-    value: TelemetryOption,
+    /// Value to pull out of the module, by index, name or part of a name.
+    #[clap(short, long, value_parser = parse_tlm, required_unless_present = "help_standard")]
+    value: Option<TelemetryOption>,
 
     /// The type of telemetry to pull, either SupMCU or Module
-    #[clap(short = 's', long, value_enum)]
-    telemetry_type: parsing::TelemetryType,
+    #[clap(
+        short = 's',
+        long,
+        value_enum,
+        required_unless_present = "help_standard"
+    )]
+    telemetry_type: Option<parsing::TelemetryType>,
+
+    /// Also print the raw response bytes as hex, including the header and footer
+    #[clap(short, long)]
+    raw: bool,
+
+    /// Print the values separated by spaces, with floats rounded to this many decimal places
+    #[clap(long)]
+    precision: Option<usize>,
+
+    /// Milliseconds to wait for the response, instead of the module's response delay
+    #[clap(long, value_name = "MS")]
+    delay_ms: Option<u64>,
+
+    /// Times to retry a non-ready response, instead of the default
+    #[clap(long)]
+    retries: Option<u8>,
+
+    /// List the standard SupMCU telemetry items and what they hold, then exit
+    #[clap(long, exclusive = true)]
+    help_standard: bool,
 }
 
 fn parse_module(s: &str) -> Result<ModuleOption, String> {
@@ -219,93 +555,604 @@ fn parse_hex(s: &str) -> Result<u16, String> {
         .map_err(|_| "Error parsing hex address".to_string())
 }
 
-fn discover(path: PathBuf, args: DiscoveryArgs) -> Result<(), anyhow::Error> {
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| "Error parsing hex byte".to_string())
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    s.parse::<f32>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+        .ok_or_else(|| format!("{s} isn't a non-negative number of seconds"))
+}
+
+fn discover(
+    path: PathBuf,
+    session: SessionFiles,
+    args: DiscoveryArgs,
+) -> Result<ExitCode, anyhow::Error> {
     let device = path.to_str().unwrap();
 
+    if let Some(file) = session.replay {
+        return discover_modules(&mut SupMCUMaster::from_session(file, false)?, args);
+    }
     if args.list {
         let addrs = SupMCUMaster::scan_bus(device, None).unwrap();
         for addr in addrs {
             print!("0x{addr:x} ");
         }
         println!();
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     let mut master = if args.addrs.is_empty() {
-        SupMCUMaster::new(device, Some(args.blacklist))
+        SupMCUMaster::new(device, Some(args.blacklist.clone()))
     } else {
-        SupMCUMaster::new_with_addrs(device, args.addrs)
+        SupMCUMaster::new_with_addrs(device, args.addrs.clone())
+    }?;
+    if let Some(file) = session.record {
+        master.record_session(file);
     }
-    .unwrap();
-    master.discover_modules().unwrap();
+    // End the session before failing, so the transactions leading up to the error are saved
+    let code = discover_modules(&mut master, args);
+    master.end_session()?;
+    code
+}
+
+fn discover_modules<I: I2CDevice + Send + Sync>(
+    master: &mut SupMCUMaster<I>,
+    args: DiscoveryArgs,
+) -> Result<ExitCode, anyhow::Error> {
+    if let Some(wait) = args.wait {
+        master.wait_for_bus(wait, BusReadiness::AllModules)?;
+    }
+    match (args.interactive, &args.decisions) {
+        (true, decisions_file) => {
+            let mut decisions = ReviewDecisions::default();
+            let (stdin, mut stdout) = (std::io::stdin(), std::io::stdout());
+            let mut input = stdin.lock();
+            master.discover_modules_with_review(|item| {
+                let decision = prompt_review(&item, &mut input, &mut stdout);
+                decisions.record(&item, &decision);
+                decision
+            })?;
+            if let Some(file) = decisions_file {
+                decisions.save(file)?;
+            }
+        }
+        (false, Some(file)) => {
+            let decisions = ReviewDecisions::load(file)?;
+            master.discover_modules_with_review(|item| decisions.decide(&item))?;
+        }
+        (false, None) => master.discover_modules()?,
+    }
+    let slim = args.slim.then(|| SlimOptions {
+        keep: (!args.keep.is_empty()).then(|| args.keep.clone()),
+        ..SlimOptions::all()
+    });
 
     if let Some(ref f) = args.file {
-        master.save_def_file(f)?;
+        match &slim {
+            Some(options) => master.save_slim_def_file(f, options.clone())?,
+            None => master.save_def_file(f)?,
+        }
+    }
+
+    if let Some(ref old) = args.compare {
+        return compare(old, &master.get_definitions()?, args.output);
     }
 
     if !(args.file.is_some() && args.quiet) {
+        let mut defs = master.get_definitions()?;
+        if let Some(options) = &slim {
+            defs = defs.iter().map(|def| def.slim(options.clone())).collect();
+        }
         if args.pretty {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&master.get_definitions()?)?
-            );
+            println!("{}", serde_json::to_string_pretty(&defs)?);
         } else {
-            println!("{}", serde_json::to_string(&master.get_definitions()?)?);
+            println!("{}", serde_json::to_string(&defs)?);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Asks on the terminal what to do with a discovered telemetry item, until the answer is valid
+fn prompt_review<R: BufRead, W: Write>(
+    item: &ReviewItem,
+    input: &mut R,
+    output: &mut W,
+) -> ReviewDecision {
+    let def = &item.definition;
+    loop {
+        let _ = write!(
+            output,
+            "{}@{:#04X} {} {}: {} ({})\n[a]ccept, [r]ename <NAME>, [f]ormat <FORMAT>, [s]kip? ",
+            item.module,
+            item.address,
+            def.telemetry_type,
+            def.idx,
+            def.name,
+            def.format.get_format_str()
+        );
+        let _ = output.flush();
+        let mut reply = String::new();
+        // Accept the rest once the input ends, rather than prompting forever
+        if input.read_line(&mut reply).unwrap_or(0) == 0 {
+            return ReviewDecision::Accept;
+        }
+        match parse_review(&reply) {
+            Ok(decision) => return decision,
+            Err(e) => {
+                let _ = writeln!(output, "{e}");
+            }
         }
     }
+}
+
+/// Parses an answer to [`prompt_review`], where an empty answer accepts the item
+fn parse_review(reply: &str) -> Result<ReviewDecision, String> {
+    let (choice, arg) = reply.trim().split_once(' ').unwrap_or((reply.trim(), ""));
+    let arg = arg.trim();
+    match (choice, arg) {
+        ("" | "a", "") => Ok(ReviewDecision::Accept),
+        ("s", "") => Ok(ReviewDecision::Skip),
+        ("r", name) if !name.is_empty() => Ok(ReviewDecision::Rename(name.to_string())),
+        ("f", format) if !format.is_empty() => {
+            let parsed = SupMCUFormat::new(format);
+            if parsed.get_format_str().len() == format.len() {
+                Ok(ReviewDecision::OverrideFormat(parsed))
+            } else {
+                Err(format!("Invalid format {format}"))
+            }
+        }
+        _ => Err(format!("Invalid answer {}", reply.trim())),
+    }
+}
+
+/// Prints the differences between a definition file and discovered definitions
+fn compare(
+    old: &Path,
+    new: &[SupMCUModuleDefinition],
+    output: OutputFormat,
+) -> Result<ExitCode, anyhow::Error> {
+    let old = read_def_file(old)?;
+    let diff = diff::diff_definitions(&old, new);
+    match output {
+        OutputFormat::Text => print!("{}", render_diff(&diff, std::io::stdout().is_terminal())),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+    }
+    Ok(if diff.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    })
+}
+
+/// Rows of a rendered module diff: the marker, the label, and the entries to list
+type DiffRow = (char, &'static str, fn(&ModuleDiff) -> Vec<String>);
+
+const DIFF_ROWS: &[DiffRow] = &[
+    ('~', "command name", |d| {
+        d.old_name
+            .iter()
+            .map(|old| format!("{old} -> {}", d.name))
+            .collect()
+    }),
+    ('+', "telemetry added", |d| d.added.clone()),
+    ('-', "telemetry removed", |d| d.removed.clone()),
+    ('~', "format changed", |d| {
+        d.format_changes
+            .iter()
+            .map(|c| format!("{}: {} -> {}", c.name, c.old, c.new))
+            .collect()
+    }),
+    ('~', "length changed", |d| {
+        d.length_changes
+            .iter()
+            .map(|c| format!("{}: {} -> {}", c.name, c.old, c.new))
+            .collect()
+    }),
+    ('+', "command added", |d| d.commands_added.clone()),
+    ('-', "command removed", |d| d.commands_removed.clone()),
+];
+
+/// Wraps a line in the ANSI color for its marker
+fn colorize(marker: char, line: String, color: bool) -> String {
+    let code = match marker {
+        '+' => "32",
+        '-' => "31",
+        _ => "33",
+    };
+    if color {
+        format!("\x1b[{code}m{line}\x1b[0m")
+    } else {
+        line
+    }
+}
+
+/// Renders a definition diff as human readable text
+fn render_diff(diff: &DefinitionDiff, color: bool) -> String {
+    if diff.is_empty() {
+        return "Definitions are identical\n".into();
+    }
+    let mut out = String::new();
+    for (name, addr) in diff.only_old.iter() {
+        out += &colorize(
+            '-',
+            format!("- {name} @ {addr:#04x}: missing from bus"),
+            color,
+        );
+        out += "\n";
+    }
+    for (name, addr) in diff.only_new.iter() {
+        out += &colorize(
+            '+',
+            format!("+ {name} @ {addr:#04x}: not in definition file"),
+            color,
+        );
+        out += "\n";
+    }
+    for module in diff.modules.iter() {
+        out += &format!("{} @ {:#04x}:\n", module.name, module.address);
+        for (marker, label, entries) in DIFF_ROWS {
+            for entry in entries(module) {
+                out += &colorize(*marker, format!("  {marker} {label}: {entry}"), color);
+                out += "\n";
+            }
+        }
+    }
+    out += &format!(
+        "{} module(s) changed, {} only in file, {} only on bus\n",
+        diff.modules.len(),
+        diff.only_old.len(),
+        diff.only_new.len()
+    );
+    out
+}
+
+/// Formats bytes as space separated hex, e.g. `01 a0 ff`
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn query(path: PathBuf, session: SessionFiles, args: QueryArgs) -> Result<(), anyhow::Error> {
+    if args.help_standard {
+        for (idx, meaning) in standard_telemetry_items() {
+            println!("{idx:>3}  {meaning}");
+        }
+        return Ok(());
+    }
+    // clap makes sure these are present without --help-standard
+    let (Some(definition), Some(module), Some(value), Some(telemetry_type)) = (
+        args.definition,
+        args.module,
+        args.value,
+        args.telemetry_type,
+    ) else {
+        anyhow::bail!("--definition, --module, --value and --telemetry-type are required");
+    };
+
+    let opts = ReadOptions {
+        response_delay: args.delay_ms.map(|ms| ms as f32 / 1000.0),
+        max_retries: args.retries,
+        keep_raw: args.raw,
+        ..Default::default()
+    };
+    let tlm = match session.replay {
+        Some(file) => query_module(
+            &mut SupMCUMaster::from_session(file, false)?,
+            module,
+            value,
+            telemetry_type,
+            &opts,
+        )?,
+        None => {
+            let mut master = SupMCUMaster::new(path.to_str().unwrap(), None)?;
+            master.load_def_file(&definition)?;
+            if let Some(file) = session.record {
+                master.record_session(file);
+            }
+            let tlm = query_module(&mut master, module, value, telemetry_type, &opts);
+            master.end_session()?;
+            tlm?
+        }
+    };
+    match args.precision {
+        Some(digits) => println!(
+            "{}",
+            tlm.data
+                .iter()
+                .map(|v| v.format_with_precision(digits))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        None => println!("{:?}", tlm.data),
+    }
     Ok(())
 }
 
-fn query(path: PathBuf, args: QueryArgs) -> Result<(), anyhow::Error> {
-    let mut master = SupMCUMaster::new(path.to_str().unwrap(), None).unwrap();
-    master.load_def_file(&args.definition).unwrap();
-    let tlm = if let Some(module) = match &args.module {
-        ModuleOption::Name(name) => master
-            .modules
-            .iter_mut()
-            .find(|module| &module.get_definition().unwrap().name == name),
-        ModuleOption::Address(addr) => master
-            .modules
-            .iter_mut()
-            .find(|module| &module.get_address() == addr),
-    } {
-        let mod_def = module.get_definition().unwrap().clone();
-        match args.value {
-            TelemetryOption::Name(name) => {
-                if let Some(tlm_def) =
-                    mod_def.telemetry.iter().find(|def| def.name == name)
-                {
-                    module.get_telemetry_by_def(tlm_def).unwrap()
-                } else {
-                    panic!(
-                        "Couldn't find telemetry item `{}` in {}",
-                        name, mod_def.name
-                    );
+fn query_module<I: I2CDevice + Send + Sync>(
+    master: &mut SupMCUMaster<I>,
+    module: ModuleOption,
+    value: TelemetryOption,
+    telemetry_type: parsing::TelemetryType,
+    opts: &ReadOptions,
+) -> Result<parsing::SupMCUTelemetry, anyhow::Error> {
+    let module = match master.module_by_ref_mut(&ModuleRef::from(&module)) {
+        Ok(module) => module,
+        Err(SupMCUError::ModuleNotFound(..) | SupMCUError::UnknownModuleName(_)) => {
+            let msg = match &module {
+                ModuleOption::Name(name) => format!("name `{}`", name),
+                ModuleOption::Address(addr) => format!("address `{}`", addr),
+            };
+            anyhow::bail!("Cannot find module with {}", msg);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mod_def = module.get_definition()?.clone();
+    let read = match value {
+        TelemetryOption::Name(name) => match find_telemetry(&mod_def.telemetry, &name) {
+            Ok(tlm_def) => module.get_telemetry_by_def_with(tlm_def, opts)?,
+            Err(msg) => anyhow::bail!("{} in {}", msg, mod_def.display_name()),
+        },
+        TelemetryOption::Index(idx) => module.get_telemetry_with(telemetry_type, idx, opts)?,
+    };
+    if let Some(raw) = &read.raw {
+        println!("{}", hex_bytes(raw));
+    }
+    Ok(read.telemetry)
+}
+
+/// Finds a telemetry item by name, or by part of its name if no item is called `name`.
+///
+/// Partial matches ignore case, and spaces or punctuation match the underscores of
+/// normalized names.  Fails listing the candidates if several items match.
+fn find_telemetry<'a>(
+    defs: &'a [parsing::SupMCUTelemetryDefinition],
+    name: &str,
+) -> Result<&'a parsing::SupMCUTelemetryDefinition, String> {
+    if let Some(def) = defs.iter().find(|def| def.name == name) {
+        return Ok(def);
+    }
+    let pattern: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let matches: Vec<_> = defs
+        .iter()
+        .filter(|def| def.name.to_lowercase().contains(&pattern))
+        .collect();
+    match matches.as_slice() {
+        [def] => Ok(def),
+        [] => Err(format!("Couldn't find telemetry item `{name}`")),
+        _ => Err(format!(
+            "Telemetry item `{name}` is ambiguous, did you mean one of: {}",
+            matches
+                .iter()
+                .map(|def| def.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn raw(path: PathBuf, args: RawArgs) -> Result<(), anyhow::Error> {
+    let device = path.to_str().unwrap();
+    let mut master = match &args.definition {
+        Some(file) => SupMCUMaster::new_from_file(device, file)?,
+        None => SupMCUMaster::new_with_addrs(device, vec![])?,
+    };
+    match args.op {
+        RawOp::Read {
+            address,
+            len,
+            register: Some(register),
+        } => {
+            let len = u8::try_from(len)?;
+            let bytes = master.raw_read_reg(address, register, len, args.force)?;
+            println!("{}", hex_bytes(&bytes));
+        }
+        RawOp::Read { address, len, .. } => {
+            let bytes = master.raw_read(address, len, args.force)?;
+            println!("{}", hex_bytes(&bytes));
+        }
+        RawOp::Write {
+            address,
+            bytes,
+            register: Some(register),
+        } => master.raw_write_reg(address, register, &bytes, args.force)?,
+        RawOp::Write { address, bytes, .. } => master.raw_write(address, &bytes, args.force)?,
+    }
+    Ok(())
+}
+
+fn selftest(path: PathBuf, args: SelftestArgs) -> Result<ExitCode, anyhow::Error> {
+    let mut master = SupMCUMaster::new_from_file(path.to_str().unwrap(), &args.definition)?;
+    if let Some(result) = args.result_field {
+        for module in master.modules.iter_mut() {
+            module.set_self_test_fields(SelfTestFields {
+                result,
+                runs: args.runs_field,
+            });
+        }
+    }
+    let summary = master.run_all_self_tests(args.timeout);
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+        OutputFormat::Text => {
+            for report in summary.reports.iter() {
+                let status = if report.passed { "PASS" } else { "FAIL" };
+                println!("{} @ {:#04X}: {status}", report.module, report.address);
+                for result in report.results.iter().filter(|r| !r.passed) {
+                    println!("  {} failed", result.name);
                 }
             }
-            TelemetryOption::Index(idx) => module
-                .get_telemetry(args.telemetry_type, idx)
-                .expect("Telemetry item not found"),
+            for (address, e) in summary.errors.iter() {
+                println!("{address:#04X}: ERROR {e}");
+            }
         }
+    }
+    Ok(if summary.passed() {
+        ExitCode::SUCCESS
     } else {
-        let msg = match &args.module {
-            ModuleOption::Name(name) => format!("name `{}`", name),
-            ModuleOption::Address(addr) => format!("address `{}`", addr),
-        };
-        panic!("Cannot find module with {}", msg);
+        ExitCode::FAILURE
+    })
+}
+
+fn diag(path: PathBuf, args: DiagArgs) -> Result<ExitCode, anyhow::Error> {
+    let mut master = SupMCUMaster::new_from_file(path.to_str().unwrap(), &args.definition)?;
+    let counters: Option<[String; 3]> = args.counters.map(|c| c.try_into().unwrap());
+    let diagnoses: Vec<BusDiagnosis> = master
+        .modules
+        .iter_mut()
+        .map(|module| {
+            if let Some(counters) = &counters {
+                module.set_remote_bus_items(counters.clone());
+            }
+            module.bus_diagnosis()
+        })
+        .collect();
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diagnoses)?),
+        OutputFormat::Text => {
+            for diagnosis in diagnoses.iter() {
+                println!("{diagnosis}");
+            }
+        }
+    }
+    Ok(
+        if diagnoses.iter().all(|d| match d.diagnosis {
+            Diagnosis::Healthy => true,
+            Diagnosis::RemoteUnavailable => d.local_delta.failures == 0,
+            _ => false,
+        }) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        },
+    )
+}
+
+fn dump(path: PathBuf, args: DumpArgs) -> Result<(), anyhow::Error> {
+    let mut master = SupMCUMaster::new_from_file(path.to_str().unwrap(), &args.definition)?;
+    let module = master.module_by_ref_mut(&ModuleRef::from(&args.module))?;
+    let def = find_telemetry(&module.get_definition()?.telemetry, &args.value)
+        .map_err(|msg| anyhow::anyhow!(msg))?
+        .clone();
+    let info = match &args.output {
+        Some(file) => module.dump_telemetry_to(&def, std::fs::File::create(file)?)?,
+        None => module.dump_telemetry_to(&def, std::io::stdout().lock())?,
     };
-    println!("{:?}", tlm.data);
+    debug!("{info:?}");
+    if info.checksum_valid == Some(false) {
+        eprintln!("Warning: response checksum is invalid");
+    }
     Ok(())
 }
 
-fn main() -> Result<(), anyhow::Error> {
+fn run_macro(path: PathBuf, args: MacroArgs) -> Result<ExitCode, anyhow::Error> {
+    let mut master = SupMCUMaster::new_from_file(path.to_str().unwrap(), &args.definition)?;
+    if let Some(file) = &args.macros {
+        let macros: Vec<BusMacro> = serde_json::from_reader(std::fs::File::open(file)?)?;
+        for bus_macro in macros {
+            master.add_macro(bus_macro);
+        }
+    }
+    let (name, module, output) = match args.op {
+        MacroOp::List => {
+            for bus_macro in master.macros() {
+                println!("{} ({} steps)", bus_macro.name, bus_macro.steps.len());
+            }
+            for def in master.get_definitions()? {
+                for command_macro in def.macros.iter() {
+                    println!(
+                        "{}: {} ({} steps)",
+                        def.name,
+                        command_macro.name,
+                        command_macro.steps.len()
+                    );
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        MacroOp::Run {
+            name,
+            module,
+            output,
+        } => (name, module, output),
+    };
+    let report = match module {
+        Some(module) => master
+            .module_by_ref_mut(&ModuleRef::from(&module))?
+            .run_macro(&name)?,
+        None => master.run_macro(&name)?,
+    };
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Text => match &report.failure {
+            None => println!("{}: ran {} steps", report.name, report.steps_run),
+            Some(failure) => println!(
+                "{}: step {} failed, {} @ {:#04X} was {:?}, expected {:?}",
+                report.name,
+                failure.step,
+                failure.name,
+                failure.address,
+                failure.actual,
+                failure.expected
+            ),
+        },
+    }
+    Ok(if report.completed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn main() -> Result<ExitCode, anyhow::Error> {
     let args = PumQry::parse();
     Logger::try_with_str("info")?.start()?;
     debug!("{:?}", args);
 
+    let path = args.path.unwrap_or_default();
+    let session = SessionFiles {
+        record: args.record,
+        replay: args.replay,
+    };
+    if (session.record.is_some() || session.replay.is_some())
+        && !matches!(args.command, Commands::Discover(_) | Commands::Query(_))
+    {
+        anyhow::bail!("--record and --replay are only supported by discover and query");
+    }
+    // Replays don't open the device
+    if session.replay.is_none() {
+        args.device_type.check_supported()?;
+    }
+
     match args.command {
-        Commands::Discover(discovery_args) => discover(args.path, discovery_args),
-        Commands::Query(query_args) => query(args.path, query_args),
+        Commands::Discover(discovery_args) => {
+            let comparing = discovery_args.compare.is_some();
+            discover(path, session, discovery_args).or_else(|e| {
+                if comparing {
+                    eprintln!("Error: {e:?}");
+                    Ok(ExitCode::from(2))
+                } else {
+                    Err(e)
+                }
+            })
+        }
+        Commands::Query(query_args) => query(path, session, query_args).map(|_| ExitCode::SUCCESS),
+        Commands::Raw(raw_args) => raw(path, raw_args).map(|_| ExitCode::SUCCESS),
+        Commands::Selftest(selftest_args) => selftest(path, selftest_args),
+        Commands::Dump(dump_args) => dump(path, dump_args).map(|_| ExitCode::SUCCESS),
+        Commands::Macro(macro_args) => run_macro(path, macro_args),
+        Commands::Diag(diag_args) => diag(path, diag_args),
     }
 }
 
@@ -318,6 +1165,60 @@ mod test {
         assert_eq!(parse_hex("0x2a").unwrap(), 42);
     }
 
+    #[test]
+    fn parse_secs_test() {
+        assert_eq!(parse_secs("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_secs("0"), Ok(Duration::ZERO));
+        assert!(parse_secs("-1").is_err());
+        assert!(parse_secs("NaN").is_err());
+        assert!(parse_secs("inf").is_err());
+        assert!(parse_secs("soon").is_err());
+    }
+
+    #[test]
+    fn discover_wait_parsed() {
+        let parse =
+            |wait| PumQry::try_parse_from(["pumqry", "-p", "/dev/i2c-1", "discover", "-w", wait]);
+        match parse("2.5").unwrap().command {
+            Commands::Discover(discover) => {
+                assert_eq!(discover.wait, Some(Duration::from_millis(2500)))
+            }
+            command => panic!("parsed {command:?}"),
+        }
+        assert!(parse("-1").is_err());
+        assert!(parse("NaN").is_err());
+    }
+
+    #[test]
+    fn parse_review_test() {
+        assert_eq!(parse_review("\n"), Ok(ReviewDecision::Accept));
+        assert_eq!(parse_review("a"), Ok(ReviewDecision::Accept));
+        assert_eq!(parse_review("s\n"), Ok(ReviewDecision::Skip));
+        assert_eq!(
+            parse_review("r  battery_voltage\n"),
+            Ok(ReviewDecision::Rename("battery_voltage".into()))
+        );
+        assert_eq!(
+            parse_review("f ss"),
+            Ok(ReviewDecision::OverrideFormat(SupMCUFormat::new("ss")))
+        );
+        assert!(parse_review("f s!").is_err());
+        assert!(parse_review("r").is_err());
+        assert!(parse_review("x").is_err());
+
+        let item = ReviewItem {
+            address: 0x52,
+            module: "BM2".into(),
+            definition: Default::default(),
+        };
+        let mut output = vec![];
+        let decision = prompt_review(&item, &mut "x\nr volts\n".as_bytes(), &mut output);
+        assert_eq!(decision, ReviewDecision::Rename("volts".into()));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Invalid answer x"));
+    }
+
     #[test]
     fn parse_tlm_test() {
         assert_eq!(parse_tlm("5").unwrap(), TelemetryOption::Index(5));
@@ -327,6 +1228,105 @@ mod test {
         );
     }
 
+    #[test]
+    fn find_telemetry_test() {
+        let defs: Vec<parsing::SupMCUTelemetryDefinition> = [
+            "battery_voltage",
+            "battery_current",
+            "bus_voltage_3v3",
+            "temperature",
+        ]
+        .into_iter()
+        .map(|name| parsing::SupMCUTelemetryDefinition {
+            name: name.into(),
+            ..Default::default()
+        })
+        .collect();
+        let find = |name| find_telemetry(&defs, name).map(|def| def.name.as_str());
+        assert_eq!(find("temperature"), Ok("temperature"));
+        assert_eq!(find("TEMP"), Ok("temperature"));
+        assert_eq!(find("battery volt"), Ok("battery_voltage"));
+        let err = find("volt").unwrap_err();
+        assert!(err.contains("did you mean"));
+        assert!(err.contains("battery_voltage") && err.contains("bus_voltage_3v3"));
+        assert!(find("pressure").unwrap_err().contains("Couldn't find"));
+    }
+
+    #[test]
+    fn parse_byte_test() {
+        assert_eq!(parse_byte("0x80").unwrap(), 0x80);
+        assert_eq!(parse_byte("1f").unwrap(), 0x1f);
+        assert!(parse_byte("0x100").is_err());
+    }
+
+    #[test]
+    fn hex_bytes_test() {
+        assert_eq!(hex_bytes(&[0x01, 0xa0, 0xff]), "01 a0 ff");
+        assert_eq!(hex_bytes(&[]), "");
+    }
+
+    #[test]
+    fn render_diff_test() {
+        assert_eq!(
+            render_diff(&DefinitionDiff::default(), false),
+            "Definitions are identical\n"
+        );
+
+        let diff = DefinitionDiff {
+            only_old: vec![("BM".into(), 0x5c)],
+            only_new: vec![],
+            modules: vec![ModuleDiff {
+                name: "GPS".into(),
+                address: 0x51,
+                added: vec!["Module new_item".into()],
+                format_changes: vec![diff::ItemChange {
+                    name: "SupMCU mcu_load".into(),
+                    old: "f".into(),
+                    new: "F".into(),
+                }],
+                commands_removed: vec!["GPS:OLD".into()],
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            render_diff(&diff, false),
+            "- BM @ 0x5c: missing from bus\n\
+             GPS @ 0x51:\n\
+             \x20 + telemetry added: Module new_item\n\
+             \x20 ~ format changed: SupMCU mcu_load: f -> F\n\
+             \x20 - command removed: GPS:OLD\n\
+             1 module(s) changed, 1 only in file, 0 only on bus\n"
+        );
+        assert!(render_diff(&diff, true).contains("\x1b[32m  + telemetry added"));
+    }
+
+    #[test]
+    fn device_type_test() {
+        let parse = |device_type| {
+            let args = [
+                "pumqry",
+                "-p",
+                "/dev/i2c-1",
+                "-t",
+                device_type,
+                "raw",
+                "read",
+                "48",
+                "2",
+            ];
+            PumQry::try_parse_from(args).map(|args| args.device_type)
+        };
+        assert!(
+            PumQry::try_parse_from(["pumqry", "-p", "/dev/i2c-1", "raw", "read", "48", "2"])
+                .is_ok_and(|args| args.device_type == DeviceType::Linux)
+        );
+        assert_eq!(parse("kubos").unwrap(), DeviceType::Kubos);
+        assert!(parse("spi").is_err());
+        assert!(DeviceType::Kubos.check_supported().is_ok());
+        let e = DeviceType::Aardvark.check_supported().unwrap_err();
+        assert!(e.to_string().contains("Aardvark backend isn't supported"));
+    }
+
     #[test]
     fn parse_module_test() {
         assert_eq!(parse_module("0x2a").unwrap(), ModuleOption::Address(42));
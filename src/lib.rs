@@ -2,14 +2,43 @@
 //!
 //! This crate is a rust rewrite of the [pumpkin_supmcu](https://gitlab.com/pumpkin-space-systems/public/pumpkin-supmcu) python package.
 //! Its purpose is to interact with modules by disovering and parsing telemetry data and communicating via I2C
+#![cfg_attr(not(test), warn(clippy::unwrap_used))]
 
+use async_graphql::ErrorExtensions;
 use i2cdev::linux::LinuxI2CError;
-use supmcu::parsing::{SupMCUValue, TelemetryType};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use supmcu::{
+    checksum::ChecksumKind,
+    parsing::{SupMCUValue, TelemetryType},
+};
 use thiserror::Error;
 
 pub mod supmcu;
 
+/// Broad classes of [`SupMCUError`], e.g. to decide how to handle an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum ErrorCategory {
+    /// Communicating over I2C or reading files failed
+    Transport,
+    /// A module responded in an unexpected way
+    Protocol,
+    /// Data couldn't be parsed
+    Parsing,
+    /// Definitions or settings are missing or invalid
+    Configuration,
+    /// The API was used in a way that isn't allowed
+    Usage,
+    /// A bug or runtime failure within the library
+    Internal,
+}
+
+/// The errors of this crate
+///
+/// New variants may be added in minor releases, so matches on this outside the crate need a
+/// wildcard arm.  Prefer [`SupMCUError::category`] or [`SupMCUError::is_retryable`] for
+/// deciding how to handle an error, which cover new variants too.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum SupMCUError {
     #[error("IoError: {0}")]
     IoError(#[from] std::io::Error),
@@ -27,22 +56,332 @@ pub enum SupMCUError {
     ParsingError(#[from] ParsingError),
     #[error("Failed to find {0} telemetry item at index {1}")]
     TelemetryIndexError(TelemetryType, usize),
-    #[error("module@{0:#04X}: {1} returned a non-ready response.  Try increasing `response_delay`")]
+    #[error(
+        "module@{0:#04X}: {1} returned a non-ready response.  Try increasing `response_delay`"
+    )]
     NonReadyError(u16, String),
-    #[error("Failed to validate data with checksum.")]
-    ValidationError,
+    #[error("{kind} checksum mismatch: expected {expected:#x}, got {actual:#x}")]
+    ValidationError {
+        kind: ChecksumKind,
+        expected: u32,
+        actual: u32,
+    },
     #[error("SupMCUModuleDefinition not found. Have you run discover?")]
     MissingDefinitionError,
     #[error("AsyncError: {0}")]
     AsyncError(#[from] tokio::task::JoinError),
     #[error("JSONError: {0}")]
     JSONError(#[from] serde_json::Error),
+    #[error("CBORError: {0}")]
+    CBORDeError(#[from] ciborium::de::Error<std::io::Error>),
+    #[error("CBORError: {0}")]
+    CBORSerError(#[from] ciborium::ser::Error<std::io::Error>),
+    #[cfg(feature = "toml")]
+    #[error("TOMLError: {0}")]
+    TOMLDeError(#[from] toml::de::Error),
+    #[cfg(feature = "toml")]
+    #[error("TOMLError: {0}")]
+    TOMLSerError(#[from] toml::ser::Error),
     #[error("Module not found: {0} {1}")]
     ModuleNotFound(String, u16),
     #[error("Unexpected value for {0}: {1}")]
     UnexpectedValue(String, SupMCUValue),
     #[error("Unknown telemetry name {0}")]
     UnknownTelemName(String),
+    #[error("No module is called {0}")]
+    UnknownModuleName(String),
+    #[error("Unknown command name {0}")]
+    UnknownCommandName(String),
+    #[error("Multiple module definitions with address {0:#04X}")]
+    DuplicateAddress(u16),
+    #[error("Timed out waiting for modules {0:x?}")]
+    BusTimeout(Vec<u16>),
+    #[error("Format of {0} changed from {1} to {2}")]
+    FormatDriftError(String, String, String),
+    #[error("Address {0:#04X} belongs to a SupMCU module, force raw access to use it anyway")]
+    ManagedAddress(u16),
+    #[error("module@{0:#04X} is asleep")]
+    ModuleAsleep(u16),
+    #[error("Invalid response delay {1} for {0}, it must be a non-negative number of seconds")]
+    InvalidResponseDelay(String, f32),
+    #[error("Unknown macro {0}")]
+    UnknownMacro(String),
+    #[error("Macro {0} is defined by several modules, run it on one of them")]
+    AmbiguousMacro(String),
+    #[error("Unsupported session file version {0}")]
+    UnsupportedSessionVersion(u32),
+    #[error("module@{0:#04X} diverged from the recorded session: {1}")]
+    SessionDivergence(u16, String),
+    #[error("module@{0:#04X} has inconsistent telemetry indices: {1}")]
+    InconsistentIndices(u16, String),
+    #[error("module@{0:#04X} is masked by operations rule {1}")]
+    MaskedByOpsRule(u16, String),
+    #[error("Argument {arg} of {command} is out of range, it must be from {min} to {max}")]
+    ArgumentOutOfRange {
+        command: String,
+        arg: i64,
+        min: i64,
+        max: i64,
+    },
+    #[error("module@{0:#04X} is in dry-run mode, no responses can be read")]
+    DryRun(u16),
+    #[error(
+        "Several modules match {0} ({}), use a unique name or an address",
+        .1.join(", ")
+    )]
+    AmbiguousModule(String, Vec<String>),
+    #[error("Another module is already called {0}")]
+    DuplicateModuleName(String),
+    #[error("Telemetry item {0} has no simulated values")]
+    NotSimulatable(String),
+    #[error("The {1} isn't in the slimmed definition of {0}, load the full definition to use it")]
+    Slimmed(String, String),
+    #[error(
+        "The {size} byte response of {item} from module@{address:#04X} is over the I2C \
+         adapter's transfer limit of {limit} bytes.  Raise the limit with \
+         set_max_transfer_len if the adapter allows it, or set continued_reads in the \
+         definition if the firmware continues responses across reads"
+    )]
+    TransferTooLong {
+        address: u16,
+        item: String,
+        size: usize,
+        limit: usize,
+    },
+    #[error("module@{0:#04X} has no telemetry change counter")]
+    NoChangeCounter(u16),
+    #[error(
+        "Only {sent} of the {total} bytes of `{command}` were written to module@{address:#04X}, \
+         the command was cancelled"
+    )]
+    PartialWrite {
+        address: u16,
+        command: String,
+        sent: usize,
+        total: usize,
+    },
+    #[error("Unknown template {0}")]
+    UnknownTemplate(String),
+    #[error("module@{0:#04X} doesn't fit its template: {1}")]
+    TemplateMismatch(u16, String),
+    #[error("A change set is already open")]
+    ChangeSetOpen,
+    #[error("The change set isn't open on this master")]
+    ChangeSetClosed,
+    #[error("Emergency stops aren't allowed on this master")]
+    EmergencyNotAllowed,
+    #[error(
+        "module@{0:#04X} has {1} SCPI prefixes listed, but the number of modules at the \
+         address is {2}"
+    )]
+    PrefixMismatch(u16, usize, usize),
+    #[error(
+        "The definition file has {definitions} definitions, but the number of modules found \
+         is {modules}"
+    )]
+    TooManyDefinitions { definitions: usize, modules: usize },
+    #[error("The bus is busy with another operation")]
+    WouldBlock,
+    #[error("module@{0:#04X} didn't complete its self-test in time")]
+    SelfTestTimeout(u16),
+    #[error("module@{0:#04X} has no {1} configured")]
+    NotConfigured(u16, String),
+}
+
+impl SupMCUError {
+    /// Returns the name of the error variant, e.g. `"NonReadyError"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SupMCUError::IoError(_) => "IoError",
+            SupMCUError::I2CDevError { .. } => "I2CDevError",
+            SupMCUError::I2CCommandError(..) => "I2CCommandError",
+            SupMCUError::I2CTelemetryError(..) => "I2CTelemetryError",
+            SupMCUError::ParsingError(_) => "ParsingError",
+            SupMCUError::TelemetryIndexError(..) => "TelemetryIndexError",
+            SupMCUError::NonReadyError(..) => "NonReadyError",
+            SupMCUError::ValidationError { .. } => "ValidationError",
+            SupMCUError::MissingDefinitionError => "MissingDefinitionError",
+            SupMCUError::AsyncError(_) => "AsyncError",
+            SupMCUError::JSONError(_) => "JSONError",
+            SupMCUError::CBORDeError(_) => "CBORDeError",
+            SupMCUError::CBORSerError(_) => "CBORSerError",
+            #[cfg(feature = "toml")]
+            SupMCUError::TOMLDeError(_) => "TOMLDeError",
+            #[cfg(feature = "toml")]
+            SupMCUError::TOMLSerError(_) => "TOMLSerError",
+            SupMCUError::ModuleNotFound(..) => "ModuleNotFound",
+            SupMCUError::UnexpectedValue(..) => "UnexpectedValue",
+            SupMCUError::UnknownTelemName(_) => "UnknownTelemName",
+            SupMCUError::UnknownModuleName(_) => "UnknownModuleName",
+            SupMCUError::UnknownCommandName(_) => "UnknownCommandName",
+            SupMCUError::DuplicateAddress(_) => "DuplicateAddress",
+            SupMCUError::BusTimeout(_) => "BusTimeout",
+            SupMCUError::FormatDriftError(..) => "FormatDriftError",
+            SupMCUError::ManagedAddress(_) => "ManagedAddress",
+            SupMCUError::ModuleAsleep(_) => "ModuleAsleep",
+            SupMCUError::InvalidResponseDelay(..) => "InvalidResponseDelay",
+            SupMCUError::UnknownMacro(_) => "UnknownMacro",
+            SupMCUError::AmbiguousMacro(_) => "AmbiguousMacro",
+            SupMCUError::UnsupportedSessionVersion(_) => "UnsupportedSessionVersion",
+            SupMCUError::SessionDivergence(..) => "SessionDivergence",
+            SupMCUError::InconsistentIndices(..) => "InconsistentIndices",
+            SupMCUError::MaskedByOpsRule(..) => "MaskedByOpsRule",
+            SupMCUError::ArgumentOutOfRange { .. } => "ArgumentOutOfRange",
+            SupMCUError::DryRun(_) => "DryRun",
+            SupMCUError::AmbiguousModule(..) => "AmbiguousModule",
+            SupMCUError::DuplicateModuleName(_) => "DuplicateModuleName",
+            SupMCUError::NotSimulatable(_) => "NotSimulatable",
+            SupMCUError::Slimmed(..) => "Slimmed",
+            SupMCUError::TransferTooLong { .. } => "TransferTooLong",
+            SupMCUError::NoChangeCounter(_) => "NoChangeCounter",
+            SupMCUError::PartialWrite { .. } => "PartialWrite",
+            SupMCUError::UnknownTemplate(_) => "UnknownTemplate",
+            SupMCUError::TemplateMismatch(..) => "TemplateMismatch",
+            SupMCUError::ChangeSetOpen => "ChangeSetOpen",
+            SupMCUError::ChangeSetClosed => "ChangeSetClosed",
+            SupMCUError::EmergencyNotAllowed => "EmergencyNotAllowed",
+            SupMCUError::PrefixMismatch(..) => "PrefixMismatch",
+            SupMCUError::TooManyDefinitions { .. } => "TooManyDefinitions",
+            SupMCUError::WouldBlock => "WouldBlock",
+            SupMCUError::SelfTestTimeout(_) => "SelfTestTimeout",
+            SupMCUError::NotConfigured(..) => "NotConfigured",
+        }
+    }
+
+    /// Returns the category of the error
+    pub fn category(&self) -> ErrorCategory {
+        // No wildcard, so that new variants have to be categorized
+        match self {
+            SupMCUError::IoError(_)
+            | SupMCUError::I2CDevError { .. }
+            | SupMCUError::I2CCommandError(..)
+            | SupMCUError::I2CTelemetryError(..)
+            | SupMCUError::BusTimeout(_)
+            | SupMCUError::PartialWrite { .. }
+            | SupMCUError::WouldBlock => ErrorCategory::Transport,
+            SupMCUError::NonReadyError(..)
+            | SupMCUError::ValidationError { .. }
+            | SupMCUError::UnexpectedValue(..)
+            | SupMCUError::FormatDriftError(..)
+            | SupMCUError::ModuleAsleep(_)
+            | SupMCUError::SessionDivergence(..)
+            | SupMCUError::InconsistentIndices(..)
+            | SupMCUError::SelfTestTimeout(_) => ErrorCategory::Protocol,
+            SupMCUError::ParsingError(_)
+            | SupMCUError::JSONError(_)
+            | SupMCUError::CBORDeError(_)
+            | SupMCUError::CBORSerError(_) => ErrorCategory::Parsing,
+            #[cfg(feature = "toml")]
+            SupMCUError::TOMLDeError(_) | SupMCUError::TOMLSerError(_) => ErrorCategory::Parsing,
+            SupMCUError::TelemetryIndexError(..)
+            | SupMCUError::MissingDefinitionError
+            | SupMCUError::ModuleNotFound(..)
+            | SupMCUError::UnknownTelemName(_)
+            | SupMCUError::UnknownModuleName(_)
+            | SupMCUError::UnknownCommandName(_)
+            | SupMCUError::DuplicateAddress(_)
+            | SupMCUError::InvalidResponseDelay(..)
+            | SupMCUError::UnknownMacro(_)
+            | SupMCUError::UnsupportedSessionVersion(_)
+            | SupMCUError::NotSimulatable(_)
+            | SupMCUError::Slimmed(..)
+            | SupMCUError::TransferTooLong { .. }
+            | SupMCUError::NoChangeCounter(_)
+            | SupMCUError::UnknownTemplate(_)
+            | SupMCUError::TemplateMismatch(..)
+            | SupMCUError::ChangeSetOpen
+            | SupMCUError::ChangeSetClosed
+            | SupMCUError::EmergencyNotAllowed
+            | SupMCUError::PrefixMismatch(..)
+            | SupMCUError::TooManyDefinitions { .. }
+            | SupMCUError::NotConfigured(..) => ErrorCategory::Configuration,
+            SupMCUError::ManagedAddress(_)
+            | SupMCUError::AmbiguousMacro(_)
+            | SupMCUError::MaskedByOpsRule(..)
+            | SupMCUError::ArgumentOutOfRange { .. }
+            | SupMCUError::DryRun(_)
+            | SupMCUError::AmbiguousModule(..)
+            | SupMCUError::DuplicateModuleName(_) => ErrorCategory::Usage,
+            SupMCUError::AsyncError(_) => ErrorCategory::Internal,
+        }
+    }
+
+    /// Returns true if trying again may succeed: non-ready responses, modules that timed out
+    /// and a busy bus.  Telemetry reads retry exactly these errors, up to the module's max
+    /// retries.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            SupMCUError::NonReadyError(..) | SupMCUError::BusTimeout(_) | SupMCUError::WouldBlock
+        )
+    }
+
+    /// Returns true if the error is caused by missing or invalid definitions or settings
+    pub fn is_configuration(&self) -> bool {
+        self.category() == ErrorCategory::Configuration
+    }
+
+    /// Returns the I2C address of the module the error occurred with, if known
+    pub fn address(&self) -> Option<u16> {
+        match self {
+            SupMCUError::I2CDevError { address, .. }
+            | SupMCUError::PartialWrite { address, .. } => Some(*address),
+            SupMCUError::I2CCommandError(address, _)
+            | SupMCUError::I2CTelemetryError(address, _)
+            | SupMCUError::NonReadyError(address, _)
+            | SupMCUError::ModuleNotFound(_, address)
+            | SupMCUError::DuplicateAddress(address)
+            | SupMCUError::ManagedAddress(address)
+            | SupMCUError::ModuleAsleep(address)
+            | SupMCUError::SessionDivergence(address, _)
+            | SupMCUError::InconsistentIndices(address, _)
+            | SupMCUError::MaskedByOpsRule(address, _)
+            | SupMCUError::DryRun(address)
+            | SupMCUError::NoChangeCounter(address)
+            | SupMCUError::TemplateMismatch(address, _)
+            | SupMCUError::PrefixMismatch(address, ..)
+            | SupMCUError::SelfTestTimeout(address)
+            | SupMCUError::NotConfigured(address, _) => Some(*address),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes the error as `{ kind, address?, field?, offset?, message }` for machine-readable
+/// APIs, with the field and byte offset of parsing errors that have them
+impl Serialize for SupMCUError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SupMCUError", 5)?;
+        state.serialize_field("kind", self.kind())?;
+        match self.address() {
+            Some(address) => state.serialize_field("address", &address)?,
+            None => state.skip_field("address")?,
+        }
+        let position = match self {
+            SupMCUError::ParsingError(e) => e.position(),
+            _ => None,
+        };
+        match position {
+            Some((field, offset)) => {
+                state.serialize_field("field", &field)?;
+                state.serialize_field("offset", &offset)?;
+            }
+            None => {
+                state.skip_field("field")?;
+                state.skip_field("offset")?;
+            }
+        }
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Converts the error into a GraphQL error with the variant name in the `kind` extension
+impl ErrorExtensions for SupMCUError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string())
+            .extend_with(|_, e| e.set("kind", self.kind().to_string()))
+    }
 }
 
 impl From<std::string::FromUtf8Error> for SupMCUError {
@@ -51,7 +390,11 @@ impl From<std::string::FromUtf8Error> for SupMCUError {
     }
 }
 
+/// The errors of parsing responses, definitions and commands
+///
+/// Like [`SupMCUError`], this may gain variants in minor releases.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ParsingError {
     #[error("Failed to convert bytes into object: {0}")]
     InvalidBytes(String),
@@ -67,6 +410,41 @@ pub enum ParsingError {
     VersionParsingError(String),
     #[error("Error parsing command {0}")]
     CommandParsingError(String),
+    #[error("Invalid command arguments: {0}")]
+    CommandArgumentError(String),
     #[error("Unknown MCU ID {0}")]
     McuIdParsingError(u8),
+    #[error("Unknown MCU type {0}")]
+    McuNameParsingError(String),
+    #[error("Telemetry item {0} has a string format but no length")]
+    MissingLengthError(String),
+    #[error("Failed to deserialize telemetry: {0}")]
+    DeserializeError(String),
+    #[error("Failed to parse field {index} ('{format}') at byte {offset}: {error}")]
+    FieldError {
+        /// The index of the field in the format
+        index: usize,
+        /// The field's format character
+        format: char,
+        /// The offset of the field in the buffer parsed, including the header if any
+        offset: u64,
+        error: Box<ParsingError>,
+    },
+    #[error("Format {format} takes {needed} bytes, but the response body is {actual}")]
+    LengthMismatch {
+        format: String,
+        /// The bytes the format takes, or its fixed part takes if it contains strings
+        needed: usize,
+        actual: usize,
+    },
+}
+
+impl ParsingError {
+    /// Returns the index and byte offset of the field that failed to parse, if known
+    pub fn position(&self) -> Option<(usize, u64)> {
+        match self {
+            ParsingError::FieldError { index, offset, .. } => Some((*index, *offset)),
+            _ => None,
+        }
+    }
 }
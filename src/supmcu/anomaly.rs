@@ -0,0 +1,211 @@
+/*!
+Anomaly bundles, the context of a failure in a single document that can be downlinked.
+
+[`super::SupMCUMaster::anomaly_bundle`] assembles an error with the state of the module it
+occurred with: an excerpt of the module's definition, its last transactions, its read stats
+and the recorded readings of its last reset cause.  Bundles are capped to a number of bytes
+of JSON, see [`AnomalyBundle::trim`].
+
+Bundles can also be generated for errors while polling, see
+[`super::SupMCUMaster::on_anomaly`].
+*/
+use super::{
+    checksum::ChecksumKind, history::HistoricSample, parsing::*, session::SessionEvent, ReadStats,
+    SupMCUModule, RESET_CAUSE_TLM,
+};
+use crate::{ErrorCategory, SupMCUError};
+use i2cdev::core::I2CDevice;
+use serde::Serialize;
+use std::{sync::Arc, time::SystemTime};
+
+/// The default cap on the size of a bundle's JSON, in bytes
+pub const DEFAULT_MAX_BYTES: usize = 4096;
+
+/// Called with the bundles generated for errors while polling
+pub type AnomalyHook = Arc<dyn Fn(AnomalyBundle) + Send + Sync>;
+
+/// A transaction with a module, kept for anomaly bundles
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RecentTransaction {
+    /// Host time of the transaction
+    pub at: SystemTime,
+    pub event: SessionEvent,
+}
+
+impl RecentTransaction {
+    pub(crate) fn new(event: SessionEvent) -> Self {
+        RecentTransaction {
+            at: SystemTime::now(),
+            event,
+        }
+    }
+
+    fn payload_len(&self) -> usize {
+        match &self.event {
+            SessionEvent::Write(cmd) => cmd.len(),
+            SessionEvent::Read(bytes) => bytes.len(),
+        }
+    }
+
+    /// Shortens the command or response to at most `len` bytes
+    fn truncate(&mut self, len: usize) {
+        match &mut self.event {
+            SessionEvent::Write(cmd) => {
+                let end = (0..=len.min(cmd.len()))
+                    .rev()
+                    .find(|i| cmd.is_char_boundary(*i))
+                    .unwrap_or(0);
+                cmd.truncate(end);
+            }
+            SessionEvent::Read(bytes) => bytes.truncate(len),
+        }
+    }
+}
+
+/// The parts of a module definition relevant to a failure
+#[derive(Clone, Debug, Serialize)]
+pub struct DefinitionExcerpt {
+    pub name: String,
+    pub mcu: McuType,
+    pub response_delay: f32,
+    pub checksum: ChecksumKind,
+    /// The telemetry item last requested from the module, if any
+    pub item: Option<SupMCUTelemetryDefinition>,
+}
+
+/// An error and the state of the module it occurred with
+#[derive(Clone, Debug, Serialize)]
+pub struct AnomalyBundle {
+    pub created: SystemTime,
+    /// The kind of the error, see [`SupMCUError::kind`]
+    pub kind: &'static str,
+    pub message: String,
+    pub category: ErrorCategory,
+    /// The address of the module the error occurred with, if known
+    pub address: Option<u16>,
+    pub definition: Option<DefinitionExcerpt>,
+    /// The last command sent to the module
+    pub last_command: Option<String>,
+    /// The last transactions with the module, oldest first.  They're shared with the module,
+    /// and only copied if trimmed.
+    pub transactions: Vec<Arc<RecentTransaction>>,
+    pub stats: Option<ReadStats>,
+    /// Readings of the module's last reset cause, oldest first.  Only kept if the item's
+    /// history is enabled, see [`super::SupMCUModule::enable_history`].
+    pub resets: Vec<HistoricSample>,
+    /// True if parts of the bundle were dropped to fit its size cap
+    pub trimmed: bool,
+}
+
+impl AnomalyBundle {
+    /// Creates a bundle with only the error
+    pub fn new(err: &SupMCUError) -> Self {
+        AnomalyBundle {
+            created: SystemTime::now(),
+            kind: err.kind(),
+            message: err.to_string(),
+            category: err.category(),
+            address: err.address(),
+            definition: None,
+            last_command: None,
+            transactions: vec![],
+            stats: None,
+            resets: vec![],
+            trimmed: false,
+        }
+    }
+
+    /// Adds the state of the module the error occurred with
+    pub(crate) fn add_module<T>(&mut self, module: &SupMCUModule<T>)
+    where
+        T: I2CDevice + Send + Sync,
+    {
+        if let Some(def) = &module.definition {
+            let item = def
+                .telemetry
+                .iter()
+                .find(|item| {
+                    module.create_tlm_command(item).ok().as_ref() == Some(&module.last_cmd)
+                })
+                .cloned();
+            self.definition = Some(DefinitionExcerpt {
+                name: def.name.clone(),
+                mcu: def.mcu,
+                response_delay: def.response_delay,
+                checksum: def.checksum,
+                item,
+            });
+            if let Some(reset) = def.telemetry.iter().find(|d| d.name == RESET_CAUSE_TLM) {
+                self.resets = module.history(reset).iter().cloned().collect();
+            }
+        }
+        if !module.last_cmd.is_empty() {
+            self.last_command = Some(module.last_cmd.clone());
+        }
+        self.transactions = module.recent.iter().cloned().collect();
+        self.stats = Some(module.stats);
+    }
+
+    /// Returns the bundle as JSON
+    pub fn to_json(&self) -> Result<String, SupMCUError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn json_len(&self) -> usize {
+        json_len(self)
+    }
+
+    /// Drops parts of the bundle until its JSON is at most `max_bytes` long.
+    ///
+    /// Transaction payloads are shortened first, then the oldest transactions, the reset
+    /// readings and the definition excerpt are dropped.  The error is always kept, so a very
+    /// small cap may not be met.  The bundle is serialized once, then the length is kept
+    /// up to date from the parts changed.
+    pub fn trim(&mut self, max_bytes: usize) {
+        let mut total = self.json_len();
+        if total <= max_bytes {
+            return;
+        }
+        total = total - json_len(&self.trimmed) + json_len(&true);
+        self.trimmed = true;
+        let mut len = self
+            .transactions
+            .iter()
+            .map(|t| t.payload_len())
+            .max()
+            .unwrap_or(0);
+        while len > 0 && total > max_bytes {
+            len /= 2;
+            for transaction in self.transactions.iter_mut() {
+                if transaction.payload_len() > len {
+                    let before = json_len(transaction);
+                    Arc::make_mut(transaction).truncate(len);
+                    total = total - before + json_len(transaction);
+                }
+            }
+        }
+        while !self.transactions.is_empty() && total > max_bytes {
+            total -= removed_len(&mut self.transactions);
+        }
+        while !self.resets.is_empty() && total > max_bytes {
+            total -= removed_len(&mut self.resets);
+        }
+        if total > max_bytes {
+            self.definition = None;
+        }
+    }
+}
+
+fn json_len<T: Serialize + ?Sized>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(usize::MAX, |json| json.len())
+}
+
+/// Removes the first element of a list, returning by how much its JSON got shorter
+fn removed_len<T: Serialize>(list: &mut Vec<T>) -> usize {
+    let removed = json_len(&list.remove(0));
+    // The separating comma goes too, unless it was the only element
+    match list.is_empty() {
+        true => removed,
+        false => removed + 1,
+    }
+}
@@ -0,0 +1,86 @@
+/*!
+The points where a [`SupMCUModule`](super::SupMCUModule) waits.
+
+By default modules wait with tokio's timer, which only works inside a tokio runtime.  The
+module's async methods don't need tokio otherwise, so with an [`AsyncRuntime`] built on another
+timer they run on any executor.  With the `generic-async` feature, [`SleeperRuntime`] builds one
+from a caller-supplied sleep function, e.g. `futures_timer::Delay::new`.
+
+The blocking paths of a module wait with [`AsyncRuntime::sleep_blocking`], which sleeps the
+thread unless a runtime overrides it, e.g. to run on a simulated clock.  The embedded runtime of
+a [`SupMCUMaster`](super::SupMCUMaster), used by its blocking methods, remains tokio.
+*/
+use futures::future::{self, Either};
+#[cfg(feature = "generic-async")]
+use std::{fmt, sync::Arc};
+use std::{future::Future, pin::Pin, time::Duration};
+
+/// A future completing after a delay
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The timer used by the asynchronous paths of a module
+pub trait AsyncRuntime: Send + Sync {
+    /// Returns a future completing once `duration` has passed
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    /// Blocks until `duration` has passed, for the blocking paths of a module
+    fn sleep_blocking(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Waits with tokio's timer, the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioRuntime;
+
+impl AsyncRuntime for TokioRuntime {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Waits with a caller-supplied sleep function
+#[cfg(feature = "generic-async")]
+#[derive(Clone)]
+pub struct SleeperRuntime(Arc<dyn Fn(Duration) -> Sleep + Send + Sync>);
+
+#[cfg(feature = "generic-async")]
+impl SleeperRuntime {
+    /// Creates a runtime sleeping with the futures returned by `sleeper`
+    pub fn new<F, Fut>(sleeper: F) -> Self
+    where
+        F: Fn(Duration) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        SleeperRuntime(Arc::new(move |duration| Box::pin(sleeper(duration))))
+    }
+}
+
+#[cfg(feature = "generic-async")]
+impl AsyncRuntime for SleeperRuntime {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (self.0)(duration)
+    }
+}
+
+#[cfg(feature = "generic-async")]
+impl fmt::Debug for SleeperRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SleeperRuntime")
+    }
+}
+
+/// Runs `fut` until it completes or `duration` passes on `rt`'s timer, returning `None` if
+/// it timed out
+pub async fn timeout<F: Future>(
+    rt: &dyn AsyncRuntime,
+    duration: Duration,
+    fut: F,
+) -> Option<F::Output> {
+    let sleep = rt.sleep(duration);
+    futures::pin_mut!(fut);
+    match future::select(fut, sleep).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
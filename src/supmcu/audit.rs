@@ -0,0 +1,249 @@
+/*!
+Audit logs of the commands written to the modules of a [`super::SupMCUMaster`] and the
+telemetry read from them.
+
+Once enabled with [`super::SupMCUMaster::enable_audit_log`], every command write, every
+completed telemetry read and every raw write, e.g. [`super::SupMCUMaster::raw_write`], is
+written to the sink as an [`AuditRecord`] and flushed.  Unlike the
+`log` output, records can't be filtered out by level.  Records are numbered in the order they
+are written across all modules, so a gap in the sequence numbers shows that records are
+missing.  The numbering continues when the log is enabled again, and when appending to a file
+it continues from the file's last record.
+
+Commands a module in dry-run mode doesn't send are recorded too, with the
+[`AuditOutcome::DryRun`] outcome.
+
+Payloads, the command text or the data read, longer than the format's limit are truncated, and
+the record then holds their original length in `truncated`.  Rotating the log is up to the sink.
+
+```no_run
+use supmcu_rs::supmcu::{audit::AuditFormat, SupMCUMaster};
+
+let mut master = SupMCUMaster::new("/dev/i2c-1", None)?;
+master.enable_audit_log_file("audit.jsonl", AuditFormat::default())?;
+# Ok::<(), supmcu_rs::SupMCUError>(())
+```
+*/
+use crate::SupMCUError;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+/// The default limit of the payloads of audit records, in bytes
+pub const DEFAULT_PAYLOAD_LIMIT: usize = 256;
+
+/// How audit records are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditFormat {
+    /// One JSON object per line, with payloads longer than `payload_limit` bytes truncated
+    JsonLines { payload_limit: usize },
+}
+
+impl Default for AuditFormat {
+    fn default() -> Self {
+        AuditFormat::JsonLines {
+            payload_limit: DEFAULT_PAYLOAD_LIMIT,
+        }
+    }
+}
+
+/// What an audit record is about
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A command was written, the payload is the command
+    Command,
+    /// A telemetry item was read, the payload is its data
+    Telemetry { telemetry: String },
+}
+
+/// Whether the audited operation succeeded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Ok,
+    Error,
+    /// The command wasn't sent, the module being in dry-run mode, see
+    /// [`super::SupMCUModule::is_dry_run`]
+    DryRun,
+}
+
+/// A line of an audit log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The number of records written before this one
+    pub seq: u64,
+    /// Microseconds since the Unix epoch
+    pub time_us: u64,
+    pub address: u16,
+    /// The module's name, if it has a definition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub outcome: AuditOutcome,
+    /// The command written or the data read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    /// The length of the payload before it was truncated, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An audit log being written, shared by the modules of a master
+pub(crate) struct AuditLog {
+    sink: Box<dyn Write + Send>,
+    format: AuditFormat,
+    /// The sequence number of the next record
+    pub(crate) seq: u64,
+}
+
+pub(crate) type AuditRecorder = Arc<Mutex<AuditLog>>;
+
+/// Locks an audit log for writing a record.  A thread that panicked while writing leaves at
+/// most its own record incomplete, so the log is recovered rather than dropping every
+/// record after it.
+pub(crate) fn lock(recorder: &AuditRecorder) -> MutexGuard<'_, AuditLog> {
+    recorder.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering the audit log after a panic while writing to it");
+        recorder.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// The largest part of an audit log file read at once when looking for its last record
+const TAIL_CHUNK: u64 = 4096;
+
+/// Returns the sequence number following the last record of an audit log file, or 0 if it
+/// has none.
+///
+/// An incomplete last line, left by a crash while writing a record, is terminated so the
+/// next record starts on a line of its own.  The file must be opened for reading and
+/// appending.
+pub(crate) fn next_seq_in(file: &mut File) -> Result<u64, SupMCUError> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut tail: Vec<u8> = vec![];
+    let mut start = len;
+    while start > 0 {
+        let chunk = TAIL_CHUNK.min(start);
+        start -= chunk;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0; chunk as usize];
+        file.read_exact(&mut buf)?;
+        buf.append(&mut tail);
+        tail = buf;
+        // Only lines known to be whole, which the first one isn't unless it starts the file
+        let whole = match start {
+            0 => &tail[..],
+            _ => match tail.iter().position(|b| *b == b'\n') {
+                Some(newline) => &tail[newline + 1..],
+                None => continue,
+            },
+        };
+        let last = whole
+            .split(|b| *b == b'\n')
+            .rev()
+            .find_map(|line| serde_json::from_slice::<AuditRecord>(line).ok());
+        if let Some(record) = last {
+            if tail.last() != Some(&b'\n') {
+                warn!("Terminating the incomplete last line of the audit log");
+                file.write_all(b"\n")?;
+            }
+            return Ok(record.seq + 1);
+        }
+    }
+    if len > 0 && tail.last() != Some(&b'\n') {
+        file.write_all(b"\n")?;
+    }
+    Ok(0)
+}
+
+impl AuditLog {
+    /// Creates a log numbering its records from `seq`
+    pub(crate) fn new<W: Write + Send + 'static>(sink: W, format: AuditFormat, seq: u64) -> Self {
+        AuditLog {
+            sink: Box::new(sink),
+            format,
+            seq,
+        }
+    }
+
+    /// Writes a record of an operation on the module at `address`, whose result is either
+    /// the payload or the error
+    pub(crate) fn record(
+        &mut self,
+        address: u16,
+        module: Option<&str>,
+        event: AuditEvent,
+        result: Result<String, &SupMCUError>,
+    ) {
+        let (outcome, payload, error) = match result {
+            Ok(payload) => (AuditOutcome::Ok, Some(payload), None),
+            Err(e) => (AuditOutcome::Error, None, Some(e.to_string())),
+        };
+        self.write(address, module, event, outcome, payload, error);
+    }
+
+    /// Writes a record of a command a module in dry-run mode didn't send
+    pub(crate) fn record_dry_run(&mut self, address: u16, module: Option<&str>, command: String) {
+        let outcome = AuditOutcome::DryRun;
+        self.write(
+            address,
+            module,
+            AuditEvent::Command,
+            outcome,
+            Some(command),
+            None,
+        );
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        module: Option<&str>,
+        event: AuditEvent,
+        outcome: AuditOutcome,
+        payload: Option<String>,
+        error: Option<String>,
+    ) {
+        let AuditFormat::JsonLines { payload_limit } = self.format;
+        let mut record = AuditRecord {
+            seq: self.seq,
+            time_us: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_micros() as u64),
+            address,
+            module: module.map(str::to_string),
+            event,
+            outcome,
+            payload,
+            truncated: None,
+            error,
+        };
+        if let Some(payload) = record.payload.as_mut() {
+            if payload.len() > payload_limit {
+                record.truncated = Some(payload.len());
+                let mut end = payload_limit;
+                while !payload.is_char_boundary(end) {
+                    end -= 1;
+                }
+                payload.truncate(end);
+            }
+        }
+        self.seq += 1;
+        let written = serde_json::to_writer(&mut self.sink, &record)
+            .map_err(SupMCUError::from)
+            .and_then(|_| Ok(writeln!(self.sink)?))
+            .and_then(|_| Ok(self.sink.flush()?));
+        if let Err(e) = written {
+            warn!("Couldn't write audit record {}: {e}", record.seq);
+        }
+    }
+}
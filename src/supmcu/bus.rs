@@ -0,0 +1,836 @@
+/*!
+A facade that runs a whole bus from a single configuration, for embedding in applications.
+
+[`SupMCUBus::start`] opens the bus, loads or discovers the module definitions according to the
+[`DiscoveryPolicy`], then polls the configured telemetry items and checks the modules' health
+on a worker thread.  Readings and health checks are delivered as [`BusEvent`]s through the
+returned [`BusHandle`], which also gives access to the underlying [`SupMCUMaster`].
+
+Polling adapts to slow modules: a read overlapping the next ticks of its item skips them
+rather than catching up, and an item whose reads keep taking longer than its interval is
+polled less often until they speed up again, see [`PollerStatus`], which health checks report
+for each module too.  Polling can be paused, e.g. for a commanding window, with
+[`BusHandle::pause`].
+
+Events wait for the consumer in a queue of at most [`EVENT_CAPACITY`] events.  A bus nobody
+takes events from keeps polling, dropping the oldest events, and the consumer is told how many
+it missed with a [`BusEvent::Lagged`].
+
+The latest reading of each polled item is also kept, so frequent queries that tolerate slightly
+stale data can be answered with [`BusHandle::latest`] without a bus transaction.
+
+Items of modules whose firmware has a telemetry change counter are only read, and only
+delivered, when the counter moved since they were last read.  Otherwise just the counter is
+read, see [`SupMCUModule::change_counter`](crate::supmcu::SupMCUModule::change_counter).
+
+```no_run
+use futures::StreamExt;
+use supmcu_rs::supmcu::bus::{BusConfig, SupMCUBus};
+
+let mut bus = SupMCUBus::start(BusConfig::load("bus.json")?)?;
+let rt = tokio::runtime::Builder::new_current_thread().build()?;
+rt.block_on(async {
+    let events = bus.events();
+    futures::pin_mut!(events);
+    while let Some(event) = events.next().await {
+        println!("{event:?}");
+    }
+});
+bus.stop()?;
+# Ok::<(), Box<dyn std::error::Error>>(())
+```
+*/
+#[cfg(any(test, feature = "test-utils"))]
+use crate::supmcu::sim::SimClock;
+use crate::{
+    supmcu::{diag::BusDiagnosis, ops::PauseHandle, parsing::*, ModuleRef, SupMCUMaster},
+    SupMCUError,
+};
+use futures::{stream, Stream};
+use i2cdev::{core::I2CDevice, linux::LinuxI2CDevice};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, task};
+
+/// How a [`SupMCUBus`] gets the definitions of its modules
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryPolicy {
+    /// Load the definition file, discovering the modules if it can't be loaded
+    #[default]
+    UseFileOrDiscover,
+    /// Always discover the modules, ignoring the definition file
+    AlwaysDiscover,
+    /// Only load the definition file, failing if it can't be loaded
+    FileOnly,
+}
+
+/// A telemetry item read periodically by a [`SupMCUBus`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PollEntry {
+    /// The module's command name or address
+    pub module: ModuleRef,
+    /// The name of the telemetry item
+    pub telemetry: String,
+    pub interval_ms: u64,
+}
+
+/// The telemetry items counting a module's own I2C errors, see
+/// [`SupMCUModule::set_remote_bus_items`](super::SupMCUModule::set_remote_bus_items)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteBusItems {
+    /// The module's command name or address
+    pub module: ModuleRef,
+    /// The names of the items counting NACKs sent, buffer overruns and other bus errors
+    pub items: [String; 3],
+}
+
+/// The configuration of a [`SupMCUBus`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BusConfig {
+    /// The I2C device, e.g. `/dev/i2c-1`
+    pub device: String,
+    /// The definition file to load definitions from, and save them to if `persist` is set
+    #[serde(default)]
+    pub def_file: Option<PathBuf>,
+    #[serde(default)]
+    pub discovery: DiscoveryPolicy,
+    #[serde(default)]
+    pub poll: Vec<PollEntry>,
+    /// How often every module is pinged, if at all
+    #[serde(default)]
+    pub health_interval_ms: Option<u64>,
+    /// Also diagnose the bus errors of every live module at each health check, see
+    /// [`SupMCUModule::bus_diagnosis`](super::SupMCUModule::bus_diagnosis)
+    #[serde(default)]
+    pub diagnose: bool,
+    /// The items counting each module's own I2C errors, which diagnoses compare the host's
+    /// failed reads with.  Modules without them are diagnosed as
+    /// [`Diagnosis::RemoteUnavailable`](super::diag::Diagnosis::RemoteUnavailable).
+    #[serde(default)]
+    pub remote_bus_items: Vec<RemoteBusItems>,
+    /// Save the definitions to `def_file` after discovery, and whenever they change
+    #[serde(default)]
+    pub persist: bool,
+}
+
+impl BusConfig {
+    /// Reads a JSON configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SupMCUError> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+}
+
+/// Something that happened on a [`SupMCUBus`]
+#[derive(Debug)]
+pub enum BusEvent {
+    /// A polled telemetry item was read
+    Telemetry {
+        module: ModuleRef,
+        telemetry: SupMCUTelemetry,
+        /// Reads of the item per second, see [`PollerStatus::achieved_rate_hz`]
+        achieved_rate_hz: Option<f64>,
+    },
+    /// A module was pinged by a health check
+    Health {
+        address: u16,
+        alive: bool,
+        /// How polling the module's configured items keeps up, see
+        /// [`BusHandle::poller_status`]
+        pollers: Vec<PollerStatus>,
+    },
+    /// A health check found a module running other firmware than its definition was
+    /// discovered with, see [`SupMCUModule::firmware_changed`](super::SupMCUModule::firmware_changed).
+    /// Sent once per module.
+    FirmwareChanged { address: u16, firmware: String },
+    /// A live module's bus errors were diagnosed by a health check, see [`BusConfig::diagnose`]
+    Diagnosis(BusDiagnosis),
+    /// Polling or persisting failed
+    Error(SupMCUError),
+    /// Reads of a polled item took longer than its interval, so it's polled less often
+    PollerDegraded(PollerStatus),
+    /// Reads of a degraded item got fast enough to poll it at its configured interval again
+    PollerRecovered(PollerStatus),
+    /// This many events were dropped, the oldest first, because the queue was full, see
+    /// [`EVENT_CAPACITY`]
+    Lagged(u64),
+}
+
+/// The most events a [`BusHandle`] holds for its consumer.  The oldest are dropped beyond
+/// that.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// The events of a bus waiting for its consumer
+#[derive(Default)]
+struct EventQueue {
+    state: Mutex<QueuedEvents>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueuedEvents {
+    events: VecDeque<BusEvent>,
+    /// Events dropped since the consumer was last told
+    dropped: u64,
+    /// Whether the worker has stopped, so no more events are coming
+    closed: bool,
+}
+
+impl EventQueue {
+    fn lock(&self) -> MutexGuard<'_, QueuedEvents> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes the next event, waiting for one.  Returns `None` once the worker has stopped and
+    /// every event has been taken.
+    async fn recv(&self) -> Option<BusEvent> {
+        loop {
+            {
+                let mut queued = self.lock();
+                if queued.dropped > 0 {
+                    return Some(BusEvent::Lagged(std::mem::take(&mut queued.dropped)));
+                }
+                if let Some(event) = queued.events.pop_front() {
+                    return Some(event);
+                }
+                if queued.closed {
+                    return None;
+                }
+            }
+            // A notification sent since the lock was released isn't lost, it's kept as a permit
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// The worker's end of an [`EventQueue`], closing it when dropped, even if the worker panicked
+struct EventSender(Arc<EventQueue>);
+
+impl EventSender {
+    /// Queues an event, dropping the oldest if the queue is full
+    fn send(&self, event: BusEvent) {
+        let mut queued = self.0.lock();
+        if queued.events.len() >= EVENT_CAPACITY {
+            queued.events.pop_front();
+            queued.dropped += 1;
+        }
+        queued.events.push_back(event);
+        drop(queued);
+        self.0.notify.notify_one();
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        self.0.lock().closed = true;
+        self.0.notify.notify_one();
+    }
+}
+
+/// Reads of a polled item taking longer than its effective interval this many times in a row
+/// double the interval
+const STRETCH_AFTER: u32 = 3;
+/// Reads of a degraded item fitting in half its effective interval this many times in a row
+/// halve the interval, down to the configured one
+const SHRINK_AFTER: u32 = 3;
+
+/// How polling a telemetry item keeps up with its configured interval
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PollerStatus {
+    pub module: ModuleRef,
+    pub telemetry: String,
+    /// The configured interval
+    pub target_interval: Duration,
+    /// The interval the item is polled at, longer than the target while degraded
+    pub effective_interval: Duration,
+    /// Reads per second, from the time between the last two reads
+    pub achieved_rate_hz: Option<f64>,
+    /// How long the last read took
+    pub last_latency: Option<Duration>,
+    /// Ticks skipped because a read was still in progress
+    pub skipped_ticks: u64,
+}
+
+impl PollerStatus {
+    fn new(entry: &PollEntry) -> Self {
+        let interval = Duration::from_millis(entry.interval_ms);
+        PollerStatus {
+            module: entry.module.clone(),
+            telemetry: entry.telemetry.clone(),
+            target_interval: interval,
+            effective_interval: interval,
+            achieved_rate_hz: None,
+            last_latency: None,
+            skipped_ticks: 0,
+        }
+    }
+
+    /// Returns true if the item is polled less often than configured
+    pub fn is_degraded(&self) -> bool {
+        self.effective_interval > self.target_interval
+    }
+}
+
+/// The polling state of a telemetry item
+struct Poller {
+    status: PollerStatus,
+    next: Duration,
+    last_start: Option<Duration>,
+    overruns: u32,
+    recoveries: u32,
+    /// The module's change counter when the item was last read, if it has one
+    last_counter: Option<u32>,
+}
+
+impl Poller {
+    fn new(entry: &PollEntry, start: Duration) -> Self {
+        Poller {
+            status: PollerStatus::new(entry),
+            next: start,
+            last_start: None,
+            overruns: 0,
+            recoveries: 0,
+            last_counter: None,
+        }
+    }
+
+    /// Accounts for a read from `start` to `end`, scheduling the next one.  Returns an event
+    /// if the effective interval changed.
+    fn record_read(&mut self, start: Duration, end: Duration) -> Option<BusEvent> {
+        let latency = end.saturating_sub(start);
+        if let Some(last) = self.last_start.replace(start) {
+            let achieved = start.saturating_sub(last);
+            self.status.achieved_rate_hz =
+                (!achieved.is_zero()).then(|| 1.0 / achieved.as_secs_f64());
+        }
+        self.status.last_latency = Some(latency);
+
+        let status = &mut self.status;
+        let (target, effective) = (status.target_interval, status.effective_interval);
+        let mut event = None;
+        if latency > effective {
+            self.recoveries = 0;
+            self.overruns += 1;
+            if self.overruns >= STRETCH_AFTER && !effective.is_zero() {
+                self.overruns = 0;
+                status.effective_interval = effective * 2;
+                warn!(
+                    "{} of {:?} takes {latency:?}, polling it every {:?}",
+                    status.telemetry, status.module, status.effective_interval
+                );
+                event = Some(BusEvent::PollerDegraded(status.clone()));
+            }
+        } else if effective > target && latency <= (effective / 2).max(target) {
+            self.overruns = 0;
+            self.recoveries += 1;
+            if self.recoveries >= SHRINK_AFTER {
+                self.recoveries = 0;
+                status.effective_interval = (effective / 2).max(target);
+                event = Some(if status.is_degraded() {
+                    BusEvent::PollerDegraded(status.clone())
+                } else {
+                    BusEvent::PollerRecovered(status.clone())
+                });
+            }
+        } else {
+            self.overruns = 0;
+            self.recoveries = 0;
+        }
+
+        // Skip the ticks the read overlapped instead of catching up on them
+        let interval = status.effective_interval;
+        self.next = start + interval;
+        if self.next <= end && !interval.is_zero() {
+            let missed = (end - self.next).as_nanos() / interval.as_nanos() + 1;
+            self.next += interval * missed as u32;
+            status.skipped_ticks += missed as u64;
+        }
+        event
+    }
+}
+
+/// The clock a bus worker schedules its polls and health checks with
+enum Clock {
+    /// The host's clock, started at the given instant
+    Host(Instant),
+    /// The virtual clock of a simulated bus
+    #[cfg(any(test, feature = "test-utils"))]
+    Sim(SimClock),
+}
+
+/// The clock of a bus worker, and the channel stopping it once disconnected
+struct WorkerClock {
+    clock: Clock,
+    stopped: mpsc::Receiver<()>,
+}
+
+impl WorkerClock {
+    /// Returns the time since the worker started
+    fn now(&self) -> Duration {
+        match &self.clock {
+            Clock::Host(start) => start.elapsed(),
+            #[cfg(any(test, feature = "test-utils"))]
+            Clock::Sim(clock) => clock.now(),
+        }
+    }
+
+    /// Waits until `deadline`, or until stopped without one.  Returns false once stopped.
+    fn wait_until(&self, deadline: Option<Duration>) -> bool {
+        match &self.clock {
+            Clock::Host(_) => {
+                let woken = match deadline {
+                    Some(deadline) => self
+                        .stopped
+                        .recv_timeout(deadline.saturating_sub(self.now())),
+                    None => self
+                        .stopped
+                        .recv()
+                        .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                };
+                matches!(woken, Err(mpsc::RecvTimeoutError::Timeout))
+            }
+            #[cfg(any(test, feature = "test-utils"))]
+            Clock::Sim(clock) => clock.wait_until(deadline, &self.stopped),
+        }
+    }
+}
+
+/// Starts [`BusHandle`]s, see the [module documentation](self)
+pub struct SupMCUBus;
+
+impl SupMCUBus {
+    /// Opens the bus described by `config` and starts polling it
+    pub fn start(config: BusConfig) -> Result<BusHandle<LinuxI2CDevice>, SupMCUError> {
+        let master = match (config.discovery, &config.def_file) {
+            (DiscoveryPolicy::AlwaysDiscover, _) | (DiscoveryPolicy::UseFileOrDiscover, None) => {
+                SupMCUMaster::new(&config.device, None)?
+            }
+            (DiscoveryPolicy::FileOnly, None) => return Err(SupMCUError::MissingDefinitionError),
+            (DiscoveryPolicy::FileOnly, Some(file)) => {
+                SupMCUMaster::new_from_file(&config.device, file)?
+            }
+            (DiscoveryPolicy::UseFileOrDiscover, Some(file)) => {
+                match SupMCUMaster::new_from_file(&config.device, file) {
+                    Ok(master) => master,
+                    Err(e) => {
+                        warn!("Couldn't load {}, discovering instead: {e}", file.display());
+                        SupMCUMaster::new(&config.device, None)?
+                    }
+                }
+            }
+        };
+        SupMCUBus::start_with_master(master, config)
+    }
+
+    /// Starts polling an already created master, ignoring `config.device`.
+    ///
+    /// Modules keep the definitions they have, except that they are all discovered with
+    /// [`DiscoveryPolicy::AlwaysDiscover`], and modules without one are discovered with
+    /// [`DiscoveryPolicy::UseFileOrDiscover`].
+    pub fn start_with_master<I>(
+        master: SupMCUMaster<I>,
+        config: BusConfig,
+    ) -> Result<BusHandle<I>, SupMCUError>
+    where
+        I: I2CDevice + Send + Sync + 'static,
+    {
+        SupMCUBus::start_with_clock(master, config, Clock::Host(Instant::now()))
+    }
+
+    /// Starts polling the master of a [`SimBus`](super::sim::SimBus) like
+    /// [`SupMCUBus::start_with_master`], scheduling the polls and health checks on the bus's
+    /// virtual clock.
+    ///
+    /// The worker only moves the clock as far as [`SimClock::run_for`] allows, so tests
+    /// decide how much virtual time passes and can check the bus in between.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn start_simulated<I>(
+        master: SupMCUMaster<I>,
+        config: BusConfig,
+        clock: SimClock,
+    ) -> Result<BusHandle<I>, SupMCUError>
+    where
+        I: I2CDevice + Send + Sync + 'static,
+    {
+        SupMCUBus::start_with_clock(master, config, Clock::Sim(clock))
+    }
+
+    fn start_with_clock<I>(
+        mut master: SupMCUMaster<I>,
+        config: BusConfig,
+        clock: Clock,
+    ) -> Result<BusHandle<I>, SupMCUError>
+    where
+        I: I2CDevice + Send + Sync + 'static,
+    {
+        let missing = master.modules.iter().any(|m| m.get_definition().is_err());
+        let discover = match config.discovery {
+            DiscoveryPolicy::AlwaysDiscover => true,
+            DiscoveryPolicy::UseFileOrDiscover => missing,
+            DiscoveryPolicy::FileOnly if missing => {
+                return Err(SupMCUError::MissingDefinitionError)
+            }
+            DiscoveryPolicy::FileOnly => false,
+        };
+        if discover {
+            master.discover_modules()?;
+        }
+        for remote in &config.remote_bus_items {
+            master
+                .module_by_ref_mut(&remote.module)?
+                .set_remote_bus_items(remote.items.clone());
+        }
+        if config.persist {
+            if let Some(file) = &config.def_file {
+                if discover {
+                    master.save_def_file(file)?;
+                }
+                master.def_file = Some(file.clone());
+            }
+        }
+
+        let pause = master.pause_handle();
+        let master = Arc::new(Mutex::new(master));
+        let pollers = Arc::new(Mutex::new(
+            config.poll.iter().map(PollerStatus::new).collect(),
+        ));
+        let latest = Arc::new(Mutex::new(vec![None; config.poll.len()]));
+        let (stop, stopped) = mpsc::channel();
+        let events = Arc::new(EventQueue::default());
+        let events_tx = EventSender(events.clone());
+        let worker = {
+            let master = master.clone();
+            let config = config.clone();
+            let pollers = pollers.clone();
+            let latest = latest.clone();
+            let pause = pause.clone();
+            thread::spawn(move || {
+                let clock = WorkerClock { clock, stopped };
+                run_worker(master, config, pollers, latest, pause, clock, events_tx)
+            })
+        };
+        Ok(BusHandle {
+            master,
+            pollers,
+            latest,
+            pause,
+            events,
+            persist: config.persist,
+            stop: Some(stop),
+            worker: Some(worker),
+        })
+    }
+}
+
+/// A running [`SupMCUBus`].  Dropping it stops the bus like [`BusHandle::stop`].
+pub struct BusHandle<I: I2CDevice + Send + Sync + 'static> {
+    master: Arc<Mutex<SupMCUMaster<I>>>,
+    pollers: Arc<Mutex<Vec<PollerStatus>>>,
+    /// The latest reading of each polled item, in the order they're configured
+    latest: Arc<Mutex<Vec<Option<SupMCUTelemetry>>>>,
+    pause: PauseHandle,
+    events: Arc<EventQueue>,
+    persist: bool,
+    stop: Option<mpsc::Sender<()>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl<I> BusHandle<I>
+where
+    I: I2CDevice + Send + Sync + 'static,
+{
+    /// Returns the events of the bus, in the order they happened.  The stream ends once the
+    /// bus is stopped and all its events have been taken.
+    ///
+    /// Events not taken in time are dropped, see [`EVENT_CAPACITY`].
+    pub fn events(&mut self) -> impl Stream<Item = BusEvent> + '_ {
+        stream::unfold(&*self.events, |events| async move {
+            events.recv().await.map(|event| (event, events))
+        })
+    }
+
+    /// Returns the underlying master.  Polling waits while it's locked.
+    pub fn master(&self) -> &Arc<Mutex<SupMCUMaster<I>>> {
+        &self.master
+    }
+
+    /// Returns how polling each configured telemetry item keeps up, in the order they're
+    /// configured
+    pub fn poller_status(&self) -> Vec<PollerStatus> {
+        self.pollers
+            .lock()
+            .map_or_else(|e| e.into_inner().clone(), |pollers| pollers.clone())
+    }
+
+    /// Returns the latest reading of the polled item called `name` of `module`, without a bus
+    /// transaction or locking the master.
+    ///
+    /// `module` must be the one the item is configured with.  Returns `None` if the item isn't
+    /// polled, or hasn't been read yet.
+    pub fn latest(&self, module: &ModuleRef, name: &str) -> Option<SupMCUTelemetry> {
+        let i = self
+            .poller_status()
+            .iter()
+            .position(|p| &p.module == module && p.telemetry == name)?;
+        self.latest
+            .lock()
+            .map_or_else(|e| e.into_inner()[i].clone(), |latest| latest[i].clone())
+    }
+
+    /// Pauses polling and health checks once the reads in progress finish, without locking
+    /// the master.  Ticks due while paused are skipped.
+    pub fn pause(&self) {
+        self.pause.pause();
+    }
+
+    /// Resumes polling paused by [`BusHandle::pause`]
+    pub fn resume(&self) {
+        self.pause.resume();
+    }
+
+    /// Reads a telemetry item from a module, whether it's polled or not
+    pub async fn get_telemetry(
+        &self,
+        module: ModuleRef,
+        name: String,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        self.with_master(move |master| {
+            master
+                .module_by_ref_mut(&module)?
+                .get_telemetry_by_name(&name)
+        })
+        .await
+    }
+
+    /// Reads a telemetry item from a module like [`BusHandle::get_telemetry`], but fails with
+    /// `WouldBlock` right away if polling or another operation is using the master, instead of
+    /// waiting for it.
+    ///
+    /// Only waiting for the bus is skipped: the read itself still blocks for a transaction,
+    /// including the module's response delay.  This suits single-threaded UIs reading
+    /// opportunistically.
+    pub fn try_get_telemetry(
+        &self,
+        module: &ModuleRef,
+        name: &str,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let mut master = match self.master.try_lock() {
+            Ok(master) => master,
+            Err(TryLockError::WouldBlock) => return Err(SupMCUError::WouldBlock),
+            Err(TryLockError::Poisoned(_)) => return Err(poisoned()),
+        };
+        master
+            .module_by_ref_mut(module)?
+            .get_telemetry_by_name(name)
+    }
+
+    /// Returns the definitions of all modules
+    pub async fn definitions(&self) -> Result<Vec<SupMCUModuleDefinition>, SupMCUError> {
+        self.with_master(|master| master.get_definitions()).await
+    }
+
+    /// Runs `f` with the master locked, without blocking the async runtime
+    async fn with_master<F, O>(&self, f: F) -> Result<O, SupMCUError>
+    where
+        F: FnOnce(&mut SupMCUMaster<I>) -> Result<O, SupMCUError> + Send + 'static,
+        O: Send + 'static,
+    {
+        let master = self.master.clone();
+        task::spawn_blocking(move || f(&mut *lock(&master)?)).await?
+    }
+
+    /// Stops polling, waiting for a read in progress to finish, then saves the definitions
+    /// if they have changed and `persist` is set.
+    ///
+    /// This blocks, so it shouldn't be called from an async task.
+    pub fn stop(mut self) -> Result<(), SupMCUError> {
+        self.shutdown()?;
+        if self.persist {
+            lock(&self.master)?.save_if_dirty()?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), SupMCUError> {
+        // Dropping the sender wakes the worker up
+        self.stop.take();
+        match self.worker.take().map(thread::JoinHandle::join) {
+            Some(Err(_)) => Err(SupMCUError::IoError(std::io::Error::other(
+                "bus worker panicked",
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<I> Drop for BusHandle<I>
+where
+    I: I2CDevice + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            warn!("{e}");
+        }
+    }
+}
+
+fn lock<I>(master: &Mutex<SupMCUMaster<I>>) -> Result<MutexGuard<'_, SupMCUMaster<I>>, SupMCUError>
+where
+    I: I2CDevice + Send + Sync,
+{
+    master.lock().map_err(|_| poisoned())
+}
+
+fn poisoned() -> SupMCUError {
+    SupMCUError::IoError(std::io::Error::other("bus master lock was poisoned"))
+}
+
+/// Polls the bus and checks its health until `stopped` is disconnected
+fn run_worker<I>(
+    master: Arc<Mutex<SupMCUMaster<I>>>,
+    config: BusConfig,
+    statuses: Arc<Mutex<Vec<PollerStatus>>>,
+    latest: Arc<Mutex<Vec<Option<SupMCUTelemetry>>>>,
+    pause: PauseHandle,
+    clock: WorkerClock,
+    events: EventSender,
+) where
+    I: I2CDevice + Send + Sync,
+{
+    let start = clock.now();
+    let mut pollers: Vec<Poller> = config
+        .poll
+        .iter()
+        .map(|entry| Poller::new(entry, start))
+        .collect();
+    let health_interval = config.health_interval_ms.map(Duration::from_millis);
+    let mut next_health = health_interval.map(|interval| start + interval);
+    // The firmware is only read once per session, so a change is only reported once
+    let mut firmware_reported = HashSet::new();
+    loop {
+        let next = pollers.iter().map(|p| p.next).chain(next_health).min();
+        if !clock.wait_until(next) {
+            return;
+        }
+        if pause.is_paused() {
+            // Skip what's due rather than catching up once resumed
+            let now = clock.now();
+            for poller in pollers.iter_mut().filter(|p| p.next <= now) {
+                poller.next = now + poller.status.effective_interval;
+            }
+            if let (Some(interval), Some(due)) = (health_interval, next_health.as_mut()) {
+                if *due <= now {
+                    *due = now + interval;
+                }
+            }
+            continue;
+        }
+        let Ok(mut master) = lock(&master) else {
+            return;
+        };
+        let now = clock.now();
+        // The change counter of each module is read once per cycle, by module index
+        let mut counters: HashMap<usize, Option<u32>> = HashMap::new();
+        for (i, (entry, poller)) in config.poll.iter().zip(pollers.iter_mut()).enumerate() {
+            if poller.next > now {
+                continue;
+            }
+            let read_start = clock.now();
+            let read = master.module_index(&entry.module).and_then(|index| {
+                let m = &mut master.modules[index];
+                let counter = match counters.get(&index) {
+                    Some(counter) => *counter,
+                    None => {
+                        // A failed counter read falls back to reading the item
+                        let counter = match m.get_definition()?.change_counter {
+                            Some(_) => m.change_counter().ok(),
+                            None => None,
+                        };
+                        counters.insert(index, counter);
+                        counter
+                    }
+                };
+                if counter.is_some() && counter == poller.last_counter {
+                    return Ok(None);
+                }
+                let telemetry = m.get_telemetry_by_name(&entry.telemetry)?;
+                poller.last_counter = counter;
+                Ok(Some(telemetry))
+            });
+            let change = poller.record_read(read_start, clock.now());
+            if let Ok(mut statuses) = statuses.lock() {
+                statuses[i] = poller.status.clone();
+            }
+            if let (Ok(Some(telemetry)), Ok(mut latest)) = (&read, latest.lock()) {
+                latest[i] = Some(telemetry.clone());
+            }
+            let event = match read {
+                Ok(Some(telemetry)) => Some(BusEvent::Telemetry {
+                    module: entry.module.clone(),
+                    telemetry,
+                    achieved_rate_hz: poller.status.achieved_rate_hz,
+                }),
+                Ok(None) => None,
+                Err(e) => {
+                    master.report_anomaly(&e);
+                    Some(BusEvent::Error(e))
+                }
+            };
+            // Nobody listening isn't a reason to stop polling, e.g. when only `master` is used,
+            // the queue drops the oldest events instead
+            if let Some(event) = event {
+                events.send(event);
+            }
+            if let Some(change) = change {
+                events.send(change);
+            }
+        }
+        if let (Some(interval), Some(due)) = (health_interval, next_health.as_mut()) {
+            if *due <= now {
+                *due = now + interval;
+                let polled: Vec<Option<usize>> = config
+                    .poll
+                    .iter()
+                    .map(|entry| master.module_index(&entry.module).ok())
+                    .collect();
+                let SupMCUMaster { modules, rt, .. } = &mut *master;
+                for (i, module) in modules.iter_mut().enumerate() {
+                    let alive = rt.block_on(module.ping_async());
+                    let address = module.get_address();
+                    let pollers = pollers
+                        .iter()
+                        .zip(&polled)
+                        .filter(|(_, module)| **module == Some(i))
+                        .map(|(poller, _)| poller.status.clone())
+                        .collect();
+                    events.send(BusEvent::Health {
+                        address,
+                        alive,
+                        pollers,
+                    });
+                    if module.firmware_changed() && !firmware_reported.contains(&address) {
+                        firmware_reported.insert(address);
+                        let firmware = module.current_firmware().unwrap_or_default().to_owned();
+                        events.send(BusEvent::FirmwareChanged { address, firmware });
+                    }
+                    if alive && config.diagnose {
+                        events.send(BusEvent::Diagnosis(module.bus_diagnosis()));
+                    }
+                }
+                if config.persist {
+                    if let Err(e) = master.save_if_dirty() {
+                        events.send(BusEvent::Error(e));
+                    }
+                }
+            }
+        }
+    }
+}
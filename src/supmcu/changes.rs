@@ -0,0 +1,158 @@
+/*!
+Change sets grouping definition changes, so they're committed or rolled back together.
+
+While a change set from [`super::SupMCUMaster::begin_changes`] is open, the master's
+configuration methods ([`super::SupMCUMaster::response_delay`],
+[`super::SupMCUMaster::rename_module`] and [`super::SupMCUMaster::apply_template`]) record
+their changes in it instead of saving the definition file.
+*/
+use super::{parsing::SupMCUModuleDefinition, SupMCUMaster};
+use crate::SupMCUError;
+use i2cdev::core::I2CDevice;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// A change to a module's definition made while a [`ChangeSet`] was open
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DefChange {
+    pub address: u16,
+    /// The display name of the module when it was changed
+    pub module: String,
+    /// The part of the definition that changed, e.g. `response_delay`
+    pub path: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for DefChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({:#04X}) {}: {} -> {}",
+            self.module, self.address, self.path, self.old, self.new
+        )
+    }
+}
+
+/// The changes recorded by an open change set, shared by the master and the [`ChangeSet`]
+#[derive(Debug, Default)]
+pub(crate) struct PendingChanges {
+    changes: Vec<DefChange>,
+    /// The addresses and definitions of the changed modules before their first change
+    originals: Vec<(u16, Option<SupMCUModuleDefinition>)>,
+    /// Whether the master's definitions were dirty when the change set was opened
+    dirty: bool,
+}
+
+impl PendingChanges {
+    pub(crate) fn new(dirty: bool) -> Self {
+        PendingChanges {
+            dirty,
+            ..Default::default()
+        }
+    }
+
+    /// Records a change to the definition of the module at `address`, before it's made
+    pub(crate) fn record(
+        &mut self,
+        address: u16,
+        def: Option<&SupMCUModuleDefinition>,
+        path: &str,
+        old: impl ToString,
+        new: impl ToString,
+    ) {
+        if !self.originals.iter().any(|(a, _)| *a == address) {
+            self.originals.push((address, def.cloned()));
+        }
+        self.changes.push(DefChange {
+            address,
+            module: def.map_or_else(
+                || format!("module@{address:#04X}"),
+                |d| d.display_name().to_string(),
+            ),
+            path: path.to_string(),
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+    }
+}
+
+/// Locks the changes of a change set.  They stay consistent if a holder of the lock
+/// panicked, each change being recorded under a single lock.
+pub(crate) fn lock(changes: &Mutex<PendingChanges>) -> MutexGuard<'_, PendingChanges> {
+    changes.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A group of definition changes being made to a master, see
+/// [`super::SupMCUMaster::begin_changes`].
+///
+/// Dropping a change set without committing or rolling it back keeps its changes in memory,
+/// marked dirty.
+#[derive(Debug)]
+pub struct ChangeSet(pub(crate) Arc<Mutex<PendingChanges>>);
+
+impl ChangeSet {
+    /// Returns the changes recorded so far, in the order they were made
+    pub fn changes(&self) -> Vec<DefChange> {
+        lock(&self.0).changes.clone()
+    }
+
+    /// Returns true if no changes were recorded
+    pub fn is_empty(&self) -> bool {
+        lock(&self.0).changes.is_empty()
+    }
+
+    /// Renders the changes as a human-readable diff, one change per line
+    pub fn summary(&self) -> String {
+        lock(&self.0)
+            .changes
+            .iter()
+            .map(|c| format!("{c}\n"))
+            .collect()
+    }
+
+    /// Keeps the changes, saving the definition file once if they were loaded from one.
+    ///
+    /// Returns whether the file was written, see [`SupMCUMaster::save_if_dirty`].
+    pub fn commit<I>(self, master: &mut SupMCUMaster<I>) -> Result<bool, SupMCUError>
+    where
+        I: I2CDevice + Send + Sync,
+    {
+        self.close(master)?;
+        master.save_if_dirty()
+    }
+
+    /// Discards the changes, restoring the definitions of the changed modules as they were
+    /// before the change set was opened
+    pub fn rollback<I>(self, master: &mut SupMCUMaster<I>) -> Result<(), SupMCUError>
+    where
+        I: I2CDevice + Send + Sync,
+    {
+        self.close(master)?;
+        let pending = std::mem::take(&mut *lock(&self.0));
+        for (address, original) in pending.originals {
+            if let Some(module) = master.modules.iter_mut().find(|m| m.address == address) {
+                module.definition = original;
+            }
+        }
+        master.dirty = pending.dirty;
+        Ok(())
+    }
+
+    /// Detaches the change set from `master`, failing if it isn't the one open on it
+    fn close<I>(&self, master: &mut SupMCUMaster<I>) -> Result<(), SupMCUError>
+    where
+        I: I2CDevice + Send + Sync,
+    {
+        match &master.changes {
+            Some(open) if Arc::ptr_eq(open, &self.0) => {
+                master.changes = None;
+                Ok(())
+            }
+            _ => Err(SupMCUError::ChangeSetClosed),
+        }
+    }
+}
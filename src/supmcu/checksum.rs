@@ -0,0 +1,162 @@
+/*!
+Checksums in the footers of telemetry responses.
+
+Firmware generations fill the footer of a response differently: older ones leave it zeroed,
+others store a CRC32-CKSUM, a CRC16-CCITT or an additive checksum of the header and data.
+The checksum is stored little-endian at the start of the footer, the rest of the footer is
+padding.  The [`ChecksumKind`] of a module is kept in its definition, and found by
+[`ChecksumKind::detect`] during discovery.
+*/
+use super::FOOTER_SIZE;
+use crate::SupMCUError;
+use async_graphql::Enum;
+use crc::{Crc, CRC_16_IBM_3740, CRC_32_CKSUM};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+/// CRC16-CCITT as used by the SupMCU firmware, also known as CCITT-FALSE
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+/// The checksum algorithm a module uses in the footers of its responses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Enum)]
+pub enum ChecksumKind {
+    /// The footer isn't checked
+    #[default]
+    None,
+    Crc32Cksum,
+    Crc16Ccitt,
+    /// The sum of the bytes, modulo 256
+    Sum8,
+}
+
+impl ChecksumKind {
+    /// The kinds [`ChecksumKind::detect`] tries, in order
+    const DETECTABLE: [ChecksumKind; 3] = [
+        ChecksumKind::Crc32Cksum,
+        ChecksumKind::Crc16Ccitt,
+        ChecksumKind::Sum8,
+    ];
+
+    pub fn is_none(&self) -> bool {
+        *self == ChecksumKind::None
+    }
+
+    /// Returns the number of footer bytes the checksum takes
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32Cksum => 4,
+            ChecksumKind::Crc16Ccitt => 2,
+            ChecksumKind::Sum8 => 1,
+        }
+    }
+
+    /// Computes the checksum of `data`
+    pub fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32Cksum => CRC32.checksum(data),
+            ChecksumKind::Crc16Ccitt => CRC16.checksum(data).into(),
+            ChecksumKind::Sum8 => data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).into(),
+        }
+    }
+
+    /// Creates the footer of a response with the header and data `data`
+    pub fn footer(self, data: &[u8]) -> Vec<u8> {
+        let mut footer = self.compute(data).to_le_bytes()[..self.size()].to_vec();
+        footer.resize(FOOTER_SIZE, 0);
+        footer
+    }
+
+    /// Checks the checksum in the footer of a response.
+    ///
+    /// Only the bytes of the footer the checksum takes are read, so padding is ignored.
+    /// Fails with `ValidationError` if the checksum doesn't match.
+    pub fn verify(self, response: &[u8]) -> Result<(), SupMCUError> {
+        if self.is_none() {
+            return Ok(());
+        }
+        let (data, footer) = response.split_at(response.len().saturating_sub(FOOTER_SIZE));
+        let expected = self.compute(data);
+        let actual = footer
+            .iter()
+            .take(self.size())
+            .rev()
+            .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(SupMCUError::ValidationError {
+                kind: self,
+                expected,
+                actual,
+            })
+        }
+    }
+
+    /// Finds the kind of checksum in the footer of a response.
+    ///
+    /// Returns `None` if the footer is zeroed or no kind matches.  A kind only matches if
+    /// the rest of the footer is zeroed, so that e.g. a CRC16 isn't taken for a sum.
+    ///
+    /// A checksum whose value happens to be 0 looks like a zeroed footer, so such a response
+    /// is detected as having no checksum.  Modules whose checksum kind is known should be
+    /// verified with [`ChecksumKind::verify`] instead.
+    pub fn detect(response: &[u8]) -> ChecksumKind {
+        let footer = &response[response.len().saturating_sub(FOOTER_SIZE)..];
+        ChecksumKind::DETECTABLE
+            .into_iter()
+            .find(|kind| {
+                kind.fits(footer)
+                    && footer.iter().take(kind.size()).any(|b| *b != 0)
+                    && kind.verify(response).is_ok()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Finds the kind of checksum in the footer of a response and checks it.
+    ///
+    /// The response is taken as one without a checksum only if the footer bytes a checksum
+    /// would take are zeroed.  Otherwise a kind must verify, see [`ChecksumKind::detect`],
+    /// and the `ValidationError` of the smallest kind the footer's padding fits is returned
+    /// if none does.  Like with [`ChecksumKind::detect`], a checksum whose value is 0 can't be
+    /// told from none, so it's accepted without being checked.
+    pub fn verify_detected(response: &[u8]) -> Result<ChecksumKind, SupMCUError> {
+        let footer = &response[response.len().saturating_sub(FOOTER_SIZE)..];
+        if footer
+            .iter()
+            .take(ChecksumKind::Crc32Cksum.size())
+            .all(|b| *b == 0)
+        {
+            return Ok(ChecksumKind::None);
+        }
+        match ChecksumKind::detect(response) {
+            ChecksumKind::None => {
+                let closest = ChecksumKind::DETECTABLE
+                    .into_iter()
+                    .rev()
+                    .find(|kind| kind.fits(footer))
+                    .unwrap_or(ChecksumKind::Crc32Cksum);
+                closest.verify(response).map(|_| closest)
+            }
+            kind => Ok(kind),
+        }
+    }
+
+    /// Returns whether the footer is zeroed past the bytes this kind's checksum takes
+    fn fits(self, footer: &[u8]) -> bool {
+        footer.iter().skip(self.size()).all(|b| *b == 0)
+    }
+}
+
+impl fmt::Display for ChecksumKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChecksumKind::None => "none",
+            ChecksumKind::Crc32Cksum => "CRC32-CKSUM",
+            ChecksumKind::Crc16Ccitt => "CRC16-CCITT",
+            ChecksumKind::Sum8 => "SUM8",
+        })
+    }
+}
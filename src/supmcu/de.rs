@@ -0,0 +1,305 @@
+/*!
+Deserializing telemetry values into user types with serde, e.g. for quick scripts.
+
+The values of a telemetry item are a sequence, read into the fields of the target in order:
+tuples, tuple structs and structs all work, and field names (or serde renames) don't matter.
+A single value can also be read into a primitive directly.
+
+Integers are read into any integer type they fit in, hex values included, and into floats
+that represent them exactly: 8 and 16 bit integers into `f32`, integers up to 32 bits into
+`f64`.  `Float` values are read into `f32` and `f64`, `Double` values only into `f64`.  Strings
+and chars are read into `String`.  Anything else fails with an error naming the field's index
+and its value.
+
+```no_run
+use serde::Deserialize;
+use supmcu_rs::supmcu::{parsing::TelemetryType, SupMCUMaster};
+
+#[derive(Deserialize)]
+struct Battery {
+    voltage: u16,
+    current: i16,
+    temperature: f32,
+}
+
+let mut master = SupMCUMaster::new("/dev/i2c-1", None)?;
+let module = &mut master.modules[0];
+let (voltage, current, temperature): (u16, i16, f32) = module.get_as(TelemetryType::Module, 3)?;
+let battery: Battery = module.get_as(TelemetryType::Module, 3)?;
+# Ok::<(), supmcu_rs::SupMCUError>(())
+```
+*/
+use super::parsing::SupMCUValue;
+use crate::ParsingError;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, SeqAccess, Visitor};
+use std::fmt;
+
+impl de::Error for ParsingError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ParsingError::DeserializeError(msg.to_string())
+    }
+}
+
+/// Deserializes telemetry values into `T`, see the [module documentation](self)
+pub fn from_values<T: DeserializeOwned>(values: &[SupMCUValue]) -> Result<T, ParsingError> {
+    T::deserialize(ValuesDeserializer { values })
+}
+
+/// Deserializes all the values of a telemetry item
+struct ValuesDeserializer<'a> {
+    values: &'a [SupMCUValue],
+}
+
+impl<'a> ValuesDeserializer<'a> {
+    /// Visits the values as a sequence, failing if the visitor doesn't take all of them
+    fn visit_values<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        let mut seq = Values {
+            values: self.values.iter().enumerate(),
+        };
+        let value = visitor.visit_seq(&mut seq)?;
+        match seq.values.next() {
+            Some((index, _)) => Err(ParsingError::DeserializeError(format!(
+                "the telemetry has {} values, but only {index} were read",
+                self.values.len()
+            ))),
+            None => Ok(value),
+        }
+    }
+
+    /// Returns the only value, for deserializing into a primitive
+    fn single(self) -> Result<ValueDeserializer<'a>, ParsingError> {
+        match self.values {
+            [value] => Ok(ValueDeserializer { value }),
+            values => Err(ParsingError::DeserializeError(format!(
+                "expected a single value, the telemetry has {}",
+                values.len()
+            ))),
+        }
+    }
+}
+
+/// Forwards deserializing a primitive to the only value
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+                self.single()?.$method(visitor).map_err(|e| at_field(0, e))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValuesDeserializer<'_> {
+    type Error = ParsingError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        self.visit_values(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParsingError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+/// The values of a telemetry item as a sequence
+struct Values<'a> {
+    values: std::iter::Enumerate<std::slice::Iter<'a, SupMCUValue>>,
+}
+
+impl<'de> SeqAccess<'de> for Values<'_> {
+    type Error = ParsingError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ParsingError> {
+        match self.values.next() {
+            Some((index, value)) => seed
+                .deserialize(ValueDeserializer { value })
+                .map(Some)
+                .map_err(|e| at_field(index, e)),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+/// Prefixes an error with the index of the field it occurred at
+fn at_field(index: usize, e: ParsingError) -> ParsingError {
+    ParsingError::DeserializeError(match e {
+        ParsingError::DeserializeError(msg) => format!("field {index}: {msg}"),
+        e => format!("field {index}: {e}"),
+    })
+}
+
+/// Deserializes a single telemetry value
+struct ValueDeserializer<'a> {
+    value: &'a SupMCUValue,
+}
+
+impl ValueDeserializer<'_> {
+    /// Returns the value if it's an integer
+    fn integer(&self) -> Option<i128> {
+        match *self.value {
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => Some(i.into()),
+            SupMCUValue::I8(i) => Some(i.into()),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => Some(i.into()),
+            SupMCUValue::I16(i) => Some(i.into()),
+            SupMCUValue::U32(i) => Some(i.into()),
+            SupMCUValue::I32(i) => Some(i.into()),
+            SupMCUValue::U64(i) => Some(i.into()),
+            SupMCUValue::I64(i) => Some(i.into()),
+            _ => None,
+        }
+    }
+
+    fn mismatch(&self, target: &str) -> ParsingError {
+        ParsingError::DeserializeError(format!("can't read {:?} as {target}", self.value))
+    }
+}
+
+/// Deserializes integer targets from any integer value they can hold
+macro_rules! deserialize_integer {
+    ($($method:ident $visit:ident $ty:ty)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+                match self.integer().and_then(|i| <$ty>::try_from(i).ok()) {
+                    Some(i) => visitor.$visit(i),
+                    None => Err(self.mismatch(stringify!($ty))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = ParsingError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        match self.value {
+            SupMCUValue::Str(s) => visitor.visit_str(s),
+            SupMCUValue::Char(c) => visitor.visit_char(*c),
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => visitor.visit_u8(*i),
+            SupMCUValue::I8(i) => visitor.visit_i8(*i),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => visitor.visit_u16(*i),
+            SupMCUValue::I16(i) => visitor.visit_i16(*i),
+            SupMCUValue::U32(i) => visitor.visit_u32(*i),
+            SupMCUValue::I32(i) => visitor.visit_i32(*i),
+            SupMCUValue::U64(i) => visitor.visit_u64(*i),
+            SupMCUValue::I64(i) => visitor.visit_i64(*i),
+            SupMCUValue::Float(f) => visitor.visit_f32(*f),
+            SupMCUValue::Double(f) => visitor.visit_f64(*f),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8 visit_i8 i8
+        deserialize_i16 visit_i16 i16
+        deserialize_i32 visit_i32 i32
+        deserialize_i64 visit_i64 i64
+        deserialize_u8 visit_u8 u8
+        deserialize_u16 visit_u16 u16
+        deserialize_u32 visit_u32 u32
+        deserialize_u64 visit_u64 u64
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        match *self.value {
+            SupMCUValue::Float(f) => visitor.visit_f32(f),
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => visitor.visit_f32(i.into()),
+            SupMCUValue::I8(i) => visitor.visit_f32(i.into()),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => visitor.visit_f32(i.into()),
+            SupMCUValue::I16(i) => visitor.visit_f32(i.into()),
+            _ => Err(self.mismatch("f32")),
+        }
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        match *self.value {
+            SupMCUValue::Float(f) => visitor.visit_f64(f.into()),
+            SupMCUValue::Double(f) => visitor.visit_f64(f),
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => visitor.visit_f64(i.into()),
+            SupMCUValue::I8(i) => visitor.visit_f64(i.into()),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => visitor.visit_f64(i.into()),
+            SupMCUValue::I16(i) => visitor.visit_f64(i.into()),
+            SupMCUValue::U32(i) => visitor.visit_f64(i.into()),
+            SupMCUValue::I32(i) => visitor.visit_f64(i.into()),
+            _ => Err(self.mismatch("f64")),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        match self.value {
+            SupMCUValue::Char(c) => visitor.visit_char(*c),
+            _ => Err(self.mismatch("char")),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        match self.value {
+            SupMCUValue::Str(s) => visitor.visit_str(s),
+            SupMCUValue::Char(c) => visitor.visit_string(c.to_string()),
+            _ => Err(self.mismatch("a string")),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParsingError> {
+        Err(self.mismatch("bool"))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParsingError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParsingError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParsingError> {
+        // Unit variants can be read from strings, e.g. states reported by name
+        match self.value {
+            SupMCUValue::Str(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            _ => Err(self.mismatch("an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
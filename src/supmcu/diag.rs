@@ -0,0 +1,228 @@
+/*!
+Diagnosing bus problems by comparing a module's own count of I2C errors with the host's, see
+[`super::SupMCUModule::bus_diagnosis`].
+
+The module counts the errors it sees on its side of the bus in telemetry items, while the host
+counts its failed reads in [`ReadStats`].  The items differ between firmware, and the standard
+SupMCU telemetry has none, so their names are set per module with
+[`super::SupMCUModule::set_remote_bus_items`], or for a bus with
+[`super::bus::BusConfig::remote_bus_items`].  Which side sees errors localizes a
+problem: failures only the host sees point at the harness, like wiring or pull-ups, while
+errors only the module counts point at the module, or another master on the bus.
+*/
+use super::ReadStats;
+use crate::{
+    supmcu::parsing::{SupMCUTelemetry, SupMCUValue},
+    SupMCUError,
+};
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// A module's own count of I2C errors, see [`super::SupMCUModule::remote_bus_stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RemoteBusStats {
+    /// Transfers the module refused with a NACK
+    pub nacks_sent: u64,
+    /// Received bytes the module dropped because its buffer was full
+    pub buffer_overruns: u64,
+    /// Other errors, e.g. lost arbitration or bus collisions
+    pub bus_errors: u64,
+}
+
+impl RemoteBusStats {
+    pub(crate) fn from_counts([nacks_sent, buffer_overruns, bus_errors]: [u64; 3]) -> Self {
+        RemoteBusStats {
+            nacks_sent,
+            buffer_overruns,
+            bus_errors,
+        }
+    }
+
+    /// Returns the number of errors of all kinds
+    pub fn total(&self) -> u64 {
+        self.nacks_sent + self.buffer_overruns + self.bus_errors
+    }
+
+    /// Returns the counts since `earlier`.  Counters going backwards were reset with the
+    /// module, so they count from 0.
+    fn since(&self, earlier: &RemoteBusStats) -> RemoteBusStats {
+        if self.nacks_sent < earlier.nacks_sent
+            || self.buffer_overruns < earlier.buffer_overruns
+            || self.bus_errors < earlier.bus_errors
+        {
+            return *self;
+        }
+        RemoteBusStats {
+            nacks_sent: self.nacks_sent - earlier.nacks_sent,
+            buffer_overruns: self.buffer_overruns - earlier.buffer_overruns,
+            bus_errors: self.bus_errors - earlier.bus_errors,
+        }
+    }
+}
+
+/// Returns the host's counts since `earlier`, from 0 if the stats were reset since
+fn local_since(local: &ReadStats, earlier: &ReadStats) -> ReadStats {
+    if local.successes < earlier.successes
+        || local.failures < earlier.failures
+        || local.retries < earlier.retries
+        || local.nonready < earlier.nonready
+    {
+        return *local;
+    }
+    ReadStats {
+        successes: local.successes - earlier.successes,
+        failures: local.failures - earlier.failures,
+        retries: local.retries - earlier.retries,
+        nonready: local.nonready - earlier.nonready,
+    }
+}
+
+/// Reads the count of a remote error counter item, which has to be a non-negative integer
+pub(crate) fn counter_value(tlm: &SupMCUTelemetry) -> Result<u64, SupMCUError> {
+    let value = tlm.data.first();
+    value.and_then(SupMCUValue::as_u64).ok_or_else(|| {
+        SupMCUError::UnexpectedValue(
+            tlm.definition.name.clone(),
+            value.cloned().unwrap_or(SupMCUValue::Str(String::new())),
+        )
+    })
+}
+
+/// Where the errors seen since the last diagnosis point, see [`BusDiagnosis`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Diagnosis {
+    /// Neither side saw errors
+    Healthy,
+    /// The host's reads failed, but the module didn't count errors
+    HostOnly,
+    /// The module counted errors, but the host's reads didn't fail
+    ModuleOnly,
+    /// Both sides saw errors
+    Both,
+    /// The module's counters couldn't be read
+    RemoteUnavailable,
+}
+
+impl Diagnosis {
+    /// Classifies the errors each side saw over the same period
+    pub fn classify(remote: &RemoteBusStats, local: &ReadStats) -> Diagnosis {
+        match (local.failures > 0, remote.total() > 0) {
+            (false, false) => Diagnosis::Healthy,
+            (true, false) => Diagnosis::HostOnly,
+            (false, true) => Diagnosis::ModuleOnly,
+            (true, true) => Diagnosis::Both,
+        }
+    }
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Diagnosis::Healthy => "healthy: neither side saw errors",
+            Diagnosis::HostOnly => {
+                "host saw failures the module didn't: suspect wiring or pull-ups"
+            }
+            Diagnosis::ModuleOnly => {
+                "module saw errors the host didn't: suspect the module or another master"
+            }
+            Diagnosis::Both => "both sides saw errors: suspect noise or contention on the bus",
+            Diagnosis::RemoteUnavailable => "module counters unavailable",
+        })
+    }
+}
+
+/// The counters of the last diagnosis of a module, which the next one is compared to
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct BusCounters {
+    remote: Option<RemoteBusStats>,
+    local: ReadStats,
+}
+
+/// A module's and the host's counts of bus errors side by side, see
+/// [`super::SupMCUModule::bus_diagnosis`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BusDiagnosis {
+    pub address: u16,
+    /// The module's counters, if they could be read
+    pub remote: Option<RemoteBusStats>,
+    /// Why the module's counters couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_error: Option<String>,
+    pub local: ReadStats,
+    /// The module's counts since the last diagnosis
+    pub remote_delta: Option<RemoteBusStats>,
+    /// The host's counts since the last diagnosis
+    pub local_delta: ReadStats,
+    /// Where the errors since the last diagnosis point
+    pub diagnosis: Diagnosis,
+}
+
+impl BusDiagnosis {
+    /// Compares the counters to the last diagnosis in `last`, and replaces it
+    pub(crate) fn new(
+        address: u16,
+        remote: Result<RemoteBusStats, SupMCUError>,
+        local: ReadStats,
+        last: &mut BusCounters,
+    ) -> Self {
+        let local_delta = local_since(&local, &last.local);
+        let (remote, remote_error) = match remote {
+            Ok(remote) => (Some(remote), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let remote_delta = remote.map(|r| r.since(&last.remote.unwrap_or_default()));
+        let diagnosis = remote_delta.map_or(Diagnosis::RemoteUnavailable, |delta| {
+            Diagnosis::classify(&delta, &local_delta)
+        });
+        *last = BusCounters {
+            remote: remote.or(last.remote),
+            local,
+        };
+        BusDiagnosis {
+            address,
+            remote,
+            remote_error,
+            local,
+            remote_delta,
+            local_delta,
+            diagnosis,
+        }
+    }
+}
+
+/// Renders the diagnosis and both sides' counts, with the counts since the last diagnosis in
+/// parentheses
+impl Display for BusDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (local, delta) = (&self.local, &self.local_delta);
+        writeln!(f, "{:#04X}: {}", self.address, self.diagnosis)?;
+        writeln!(
+            f,
+            "  host:   {} failed (+{}), {} non-ready (+{}), {} read (+{})",
+            local.failures,
+            delta.failures,
+            local.nonready,
+            delta.nonready,
+            local.successes,
+            delta.successes
+        )?;
+        match (&self.remote, &self.remote_delta) {
+            (Some(remote), Some(delta)) => write!(
+                f,
+                "  module: {} NACKs sent (+{}), {} overruns (+{}), {} bus errors (+{})",
+                remote.nacks_sent,
+                delta.nacks_sent,
+                remote.buffer_overruns,
+                delta.buffer_overruns,
+                remote.bus_errors,
+                delta.bus_errors
+            ),
+            _ => write!(
+                f,
+                "  module: unavailable ({})",
+                self.remote_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
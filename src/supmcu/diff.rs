@@ -0,0 +1,146 @@
+use crate::supmcu::parsing::*;
+use serde::Serialize;
+
+/// A change to a single property of a telemetry item
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ItemChange {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// The differences between two definitions of the same module
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ModuleDiff {
+    pub name: String,
+    pub address: u16,
+    /// The command name the module had in the old definition, if it changed
+    pub old_name: Option<String>,
+    /// Telemetry items only in the new definition
+    pub added: Vec<String>,
+    /// Telemetry items only in the old definition
+    pub removed: Vec<String>,
+    pub format_changes: Vec<ItemChange>,
+    pub length_changes: Vec<ItemChange>,
+    /// Commands only in the new definition
+    pub commands_added: Vec<String>,
+    /// Commands only in the old definition
+    pub commands_removed: Vec<String>,
+}
+
+impl ModuleDiff {
+    /// Returns true if the definitions are the same
+    pub fn is_empty(&self) -> bool {
+        self.old_name.is_none()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.format_changes.is_empty()
+            && self.length_changes.is_empty()
+            && self.commands_added.is_empty()
+            && self.commands_removed.is_empty()
+    }
+}
+
+/// The differences between two sets of module definitions, matched by address
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DefinitionDiff {
+    /// Modules (name, address) only in the old definitions
+    pub only_old: Vec<(String, u16)>,
+    /// Modules (name, address) only in the new definitions
+    pub only_new: Vec<(String, u16)>,
+    /// Modules present in both definitions that changed
+    pub modules: Vec<ModuleDiff>,
+}
+
+impl DefinitionDiff {
+    /// Returns true if the definitions are the same
+    pub fn is_empty(&self) -> bool {
+        self.only_old.is_empty() && self.only_new.is_empty() && self.modules.is_empty()
+    }
+}
+
+/// A key that identifies a telemetry item, e.g. `SupMCU firmware_version`
+fn item_key(def: &SupMCUTelemetryDefinition) -> String {
+    format!("{} {}", def.telemetry_type, def.name)
+}
+
+/// Compares two definitions of the same module
+pub fn diff_module(old: &SupMCUModuleDefinition, new: &SupMCUModuleDefinition) -> ModuleDiff {
+    let mut diff = ModuleDiff {
+        name: new.name.clone(),
+        address: new.address,
+        old_name: (old.name != new.name).then(|| old.name.clone()),
+        ..Default::default()
+    };
+
+    for new_item in new.telemetry.iter() {
+        let key = item_key(new_item);
+        match old.telemetry.iter().find(|i| item_key(i) == key) {
+            Some(old_item) => {
+                if old_item.format != new_item.format {
+                    diff.format_changes.push(ItemChange {
+                        name: key.clone(),
+                        old: old_item.format.get_format_str(),
+                        new: new_item.format.get_format_str(),
+                    });
+                }
+                if old_item.length != new_item.length {
+                    let fmt = |l: Option<usize>| l.map_or("-".into(), |l| l.to_string());
+                    diff.length_changes.push(ItemChange {
+                        name: key,
+                        old: fmt(old_item.length),
+                        new: fmt(new_item.length),
+                    });
+                }
+            }
+            None => diff.added.push(key),
+        }
+    }
+    diff.removed = old
+        .telemetry
+        .iter()
+        .map(item_key)
+        .filter(|key| !new.telemetry.iter().any(|i| &item_key(i) == key))
+        .collect();
+
+    let has_command =
+        |def: &SupMCUModuleDefinition, name: &str| def.commands.iter().any(|c| c.name == name);
+    diff.commands_added = new
+        .commands
+        .iter()
+        .filter(|c| !has_command(old, &c.name))
+        .map(|c| c.name.clone())
+        .collect();
+    diff.commands_removed = old
+        .commands
+        .iter()
+        .filter(|c| !has_command(new, &c.name))
+        .map(|c| c.name.clone())
+        .collect();
+    diff
+}
+
+/// Compares two sets of module definitions, matching modules by address
+pub fn diff_definitions(
+    old: &[SupMCUModuleDefinition],
+    new: &[SupMCUModuleDefinition],
+) -> DefinitionDiff {
+    let mut diff = DefinitionDiff::default();
+    for new_def in new {
+        match old.iter().find(|d| d.address == new_def.address) {
+            Some(old_def) => {
+                let module_diff = diff_module(old_def, new_def);
+                if !module_diff.is_empty() {
+                    diff.modules.push(module_diff);
+                }
+            }
+            None => diff.only_new.push((new_def.name.clone(), new_def.address)),
+        }
+    }
+    diff.only_old = old
+        .iter()
+        .filter(|d| !new.iter().any(|n| n.address == d.address))
+        .map(|d| (d.name.clone(), d.address))
+        .collect();
+    diff
+}
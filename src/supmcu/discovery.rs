@@ -73,6 +73,64 @@ impl Into<SupMCUTelemetryDefinition> for PremadeTelemetryDefs {
     }
 }
 
+/// Cleans up a string from a discovery response: drops the whitespace around the string and
+/// control characters, like the CR and LF some firmware builds terminate strings with.
+/// Interior control characters separate words, so each run of them and the whitespace
+/// around it becomes a single space.
+pub fn sanitize_string(raw: &str) -> String {
+    let mut clean = String::with_capacity(raw.len());
+    let mut gap = String::new();
+    let mut control = false;
+    for c in raw.chars() {
+        if c.is_control() || c.is_whitespace() {
+            control |= c.is_control();
+            gap.push(c);
+            continue;
+        }
+        match control {
+            true => clean.push(' '),
+            false => clean.push_str(&gap),
+        }
+        gap.clear();
+        control = false;
+        clean.push(c);
+    }
+    clean.trim().to_string()
+}
+
+/// Returns true if `version` looks like a SupMCU firmware version string, which starts with
+/// the module's command name, e.g. `BM2-...`
+pub(crate) fn is_version_string(version: &str) -> bool {
+    let version = sanitize_string(version);
+    let name = version.split([' ', '-']).next().unwrap_or_default();
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Returns the command name of a module from its firmware version string, e.g. `BM2` of
+/// `BM2-1.2 ...`
+pub(crate) fn command_name(version: &str) -> Result<String, ParsingError> {
+    let name = version
+        .split(' ')
+        .next()
+        .and_then(|word| word.split('-').next())
+        .ok_or_else(|| ParsingError::VersionParsingError(version.to_string()))?;
+    Ok(match name {
+        "GPSRM" => "GPS",
+        "RHM3" => "RHM",
+        name => name,
+    }
+    .to_string())
+}
+
+/// Cleans up a FORMAT response like [`sanitize_string`], also dropping whitespace between
+/// the format characters
+pub fn sanitize_format(raw: &str) -> String {
+    raw.chars()
+        .filter(|c| !c.is_control() && !c.is_whitespace())
+        .collect()
+}
+
 impl TryFrom<&str> for PremadeTelemetryDefs {
     type Error = ParsingError;
 
@@ -0,0 +1,237 @@
+/*!
+Exporting telemetry readings to other systems.
+
+[`JsonLinesSink`] writes readings as newline-delimited JSON, one [`JsonLine`] per reading, the
+simplest format to pipe into `jq` or a log aggregator:
+
+```no_run
+use supmcu_rs::supmcu::{export::JsonLinesSink, parsing::TelemetryType, SupMCUMaster};
+
+let mut master = SupMCUMaster::new("/dev/i2c-1", None)?;
+master.discover_modules()?;
+let mut sink = JsonLinesSink::new(std::io::stdout());
+let module = &mut master.modules[0];
+sink.write(module.get_address(), &module.get_telemetry(TelemetryType::SupMCU, 0)?)?;
+# Ok::<(), supmcu_rs::SupMCUError>(())
+```
+
+[`to_line_protocol`] renders readings as [InfluxDB line protocol], one line per reading, with the
+module's name and address and the telemetry type as tags and one field per value:
+
+```text
+bus_voltage,module=BM2,address=0x52,type=Module v0=8.12,v1=3i 1700000000000000000
+```
+
+[InfluxDB line protocol]: https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+*/
+use crate::{
+    supmcu::parsing::{SupMCUModuleDefinition, SupMCUTelemetry, SupMCUValue},
+    SupMCUError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write as _,
+    io,
+    io::Write,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::UNIX_EPOCH,
+};
+
+/// The most bytes [`send_udp`] puts in a datagram, so it fits in an Ethernet frame
+pub const MAX_DATAGRAM_LEN: usize = 1400;
+
+/// Escapes a measurement name, where commas and spaces are special
+fn escape_measurement(s: &str) -> String {
+    escape(s, &[',', ' '])
+}
+
+/// Escapes a tag key, tag value or field key, where commas, equal signs and spaces are special
+fn escape_key(s: &str) -> String {
+    escape(s, &[',', '=', ' '])
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    // A trailing backslash would escape the delimiter after it, so it's escaped itself
+    if s.ends_with('\\') {
+        escaped.push('\\');
+    }
+    escaped
+}
+
+/// Renders a value as a field value, or `None` if the protocol can't represent it (NaN and
+/// infinite floats)
+fn field_value(value: &SupMCUValue) -> Option<String> {
+    let string = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    Some(match value {
+        SupMCUValue::Str(s) => string(s),
+        SupMCUValue::Char(c) => string(&c.to_string()),
+        SupMCUValue::U8(v) | SupMCUValue::Hex8(v) => format!("{v}i"),
+        SupMCUValue::I8(v) => format!("{v}i"),
+        SupMCUValue::U16(v) | SupMCUValue::Hex16(v) => format!("{v}i"),
+        SupMCUValue::I16(v) => format!("{v}i"),
+        SupMCUValue::U32(v) => format!("{v}i"),
+        SupMCUValue::I32(v) => format!("{v}i"),
+        SupMCUValue::U64(v) if i64::try_from(*v).is_ok() => format!("{v}i"),
+        SupMCUValue::U64(v) => format!("{v}u"),
+        SupMCUValue::I64(v) => format!("{v}i"),
+        SupMCUValue::Float(v) if v.is_finite() => format!("{v}"),
+        SupMCUValue::Double(v) if v.is_finite() => format!("{v}"),
+        SupMCUValue::Float(_) | SupMCUValue::Double(_) => return None,
+    })
+}
+
+/// Renders telemetry readings as InfluxDB line protocol, one line per reading.
+///
+/// The measurement is the item's name after `measurement_prefix`, and fields are named `v0`
+/// to `vn` after the position of the values.  Readings are timestamped in nanoseconds from
+/// their host time, see
+/// [`SupMCUModule::set_host_timestamps`](crate::supmcu::SupMCUModule::set_host_timestamps),
+/// or left for the server to timestamp without one.  Readings without a value the protocol
+/// can represent are left out.
+pub fn to_line_protocol<'a, R>(readings: R, measurement_prefix: &str) -> String
+where
+    R: IntoIterator<Item = (&'a SupMCUModuleDefinition, &'a SupMCUTelemetry)>,
+{
+    let mut lines = String::new();
+    for (module, reading) in readings {
+        let fields = reading
+            .data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| Some(format!("v{i}={}", field_value(value)?)))
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+        let _ = write!(
+            lines,
+            "{},module={},address={:#04x},type={} {}",
+            escape_measurement(&format!("{measurement_prefix}{}", reading.definition.name)),
+            escape_key(module.display_name()),
+            module.address,
+            reading.definition.telemetry_type,
+            fields.join(",")
+        );
+        if let Some(time) = reading.host_time {
+            if let Ok(since_epoch) = time.duration_since(UNIX_EPOCH) {
+                let _ = write!(lines, " {}", since_epoch.as_nanos());
+            }
+        }
+        lines.push('\n');
+    }
+    lines
+}
+
+/// Sends line protocol to a UDP listener, e.g. InfluxDB's or Telegraf's, in as few datagrams
+/// of at most [`MAX_DATAGRAM_LEN`] bytes as whole lines fit in.
+///
+/// Returns the number of datagrams sent.  Lines aren't split, so nothing is sent if a line is
+/// longer than a datagram.
+pub fn send_udp<A: ToSocketAddrs>(lines: &str, addr: A) -> Result<usize, SupMCUError> {
+    if let Some(line) = lines
+        .split_inclusive('\n')
+        .find(|line| line.len() > MAX_DATAGRAM_LEN)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "a line of {} bytes doesn't fit in a datagram of {MAX_DATAGRAM_LEN}",
+                line.len()
+            ),
+        )
+        .into());
+    }
+    let target = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(target)?;
+    let mut datagram = String::new();
+    let mut sent = 0;
+    for line in lines.split_inclusive('\n') {
+        if !datagram.is_empty() && datagram.len() + line.len() > MAX_DATAGRAM_LEN {
+            socket.send(datagram.as_bytes())?;
+            sent += 1;
+            datagram.clear();
+        }
+        datagram.push_str(line);
+    }
+    if !datagram.is_empty() {
+        socket.send(datagram.as_bytes())?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// A line written by [`JsonLinesSink`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonLine {
+    pub address: u16,
+    pub telemetry: String,
+    /// The module's timestamp from the response header
+    pub timestamp: u32,
+    /// Microseconds since the Unix epoch when the reading was taken, if the module records
+    /// host timestamps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_us: Option<u64>,
+    pub values: Vec<SupMCUValue>,
+}
+
+impl JsonLine {
+    pub fn new(address: u16, reading: &SupMCUTelemetry) -> Self {
+        JsonLine {
+            address,
+            telemetry: reading.definition.name.clone(),
+            timestamp: reading.header.timestamp,
+            time_us: reading
+                .host_time
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_micros() as u64),
+            values: reading.data.clone(),
+        }
+    }
+}
+
+/// Writes readings as newline-delimited JSON to a file, socket or any other writer, see
+/// [`JsonLine`]
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer }
+    }
+
+    /// Writes a reading of the module at `address` as a line, and flushes it so readers see
+    /// whole lines
+    pub fn write(&mut self, address: u16, reading: &SupMCUTelemetry) -> Result<(), SupMCUError> {
+        serde_json::to_writer(&mut self.writer, &JsonLine::new(address, reading))?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
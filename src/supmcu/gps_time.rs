@@ -0,0 +1,69 @@
+/*!
+Conversions between GPS time, as reported by the GPSRM, and host time.
+
+The GPSRM reports time as a week number and a time of week in milliseconds since the GPS
+epoch (1980-01-06 00:00:00 UTC).  GPS time doesn't have leap seconds, so it runs ahead of UTC
+by the leap seconds inserted since the epoch, [`DEFAULT_LEAP_SECONDS`] at the time of writing.
+
+Receivers that only report the week modulo 1024 are ambiguous across week rollovers (the last
+ones were in 1999 and 2019), which [`resolve_week_rollover`] settles with a reference date.
+*/
+use std::time::{Duration, SystemTime};
+
+/// Seconds from the Unix epoch to the GPS epoch, 1980-01-06 00:00:00 UTC
+pub const GPS_EPOCH_UNIX_SECS: u64 = 315_964_800;
+/// The offset between GPS time and UTC since 2017-01-01
+pub const DEFAULT_LEAP_SECONDS: u8 = 18;
+/// Number of weeks after which a 10-bit week number rolls over
+pub const WEEK_ROLLOVER: u16 = 1024;
+
+const SECS_PER_WEEK: u64 = 7 * 24 * 60 * 60;
+
+/// Returns the GPS epoch as a `SystemTime`
+pub fn gps_epoch() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_SECS)
+}
+
+/// Converts a GPS week and time of week to UTC.
+///
+/// `week` is the full week number since the GPS epoch, see [`resolve_week_rollover`] for
+/// receivers reporting it modulo 1024.  `leap_seconds` is the GPS-UTC offset, usually
+/// [`DEFAULT_LEAP_SECONDS`].
+pub fn gps_week_tow_to_utc(week: u16, tow_ms: u32, leap_seconds: u8) -> SystemTime {
+    gps_epoch()
+        + Duration::from_secs(week as u64 * SECS_PER_WEEK)
+        + Duration::from_millis(tow_ms as u64)
+        - Duration::from_secs(leap_seconds as u64)
+}
+
+/// Converts UTC to a GPS week and time of week in milliseconds.
+///
+/// Returns `None` for times before the GPS epoch or after week 65535.
+pub fn utc_to_gps_week_tow(time: SystemTime, leap_seconds: u8) -> Option<(u16, u32)> {
+    let since_epoch = (time + Duration::from_secs(leap_seconds as u64))
+        .duration_since(gps_epoch())
+        .ok()?;
+    let week = u16::try_from(since_epoch.as_secs() / SECS_PER_WEEK).ok()?;
+    let tow = since_epoch - Duration::from_secs(week as u64 * SECS_PER_WEEK);
+    Some((week, tow.as_millis() as u32))
+}
+
+/// Resolves a week number reported modulo 1024 to the full week number closest to
+/// `reference`, or to the host's current time if no reference is given.
+///
+/// Week numbers of 1024 or more are already unambiguous and returned as they are.
+pub fn resolve_week_rollover(week: u16, reference: Option<SystemTime>) -> u16 {
+    if week >= WEEK_ROLLOVER {
+        return week;
+    }
+    let reference = reference.unwrap_or_else(SystemTime::now);
+    let reference_week = reference
+        .duration_since(gps_epoch())
+        .map_or(0, |d| (d.as_secs() / SECS_PER_WEEK) as i64);
+    let rollover = WEEK_ROLLOVER as i64;
+    // The number of rollovers putting the week closest to the reference
+    let rollovers = (reference_week - week as i64 + rollover / 2)
+        .div_euclid(rollover)
+        .max(0);
+    u16::try_from(week as i64 + rollovers * rollover).unwrap_or(u16::MAX)
+}
@@ -0,0 +1,84 @@
+/*!
+GraphQL mutations controlling modules, to merge into a server's schema.
+
+[`CommandMutation`] sends commands through a master shared with the rest of the server, e.g.
+the one of a [`BusHandle`](super::bus::BusHandle):
+
+```graphql
+mutation {
+  sendCommand(module: "BM2", command: "BM:LED ON", confirmation: "led_state", timeoutMs: 500) {
+    accepted
+    echoedCommand
+    confirmation
+    elapsedMs
+  }
+}
+```
+
+Errors carry the [`SupMCUError::kind`] in their `kind` extension, for clients to handle them
+without parsing the message.
+*/
+use crate::{
+    supmcu::{parsing::CommandResult, ModuleRef, SupMCUMaster},
+    SupMCUError,
+};
+use async_graphql::{ErrorExtensions, Object, Result};
+use i2cdev::core::I2CDevice;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The mutations sending commands to the modules of a master, see
+/// [`SupMCUModule::send_command_confirmed`](super::SupMCUModule::send_command_confirmed)
+pub struct CommandMutation<I>
+where
+    I: I2CDevice + Send + Sync,
+{
+    master: Arc<Mutex<SupMCUMaster<I>>>,
+}
+
+impl<I> CommandMutation<I>
+where
+    I: I2CDevice + Send + Sync,
+{
+    pub fn new(master: Arc<Mutex<SupMCUMaster<I>>>) -> Self {
+        CommandMutation { master }
+    }
+}
+
+#[Object]
+impl<I> CommandMutation<I>
+where
+    I: I2CDevice + Send + Sync + 'static,
+{
+    /// Sends a command to a module, given by name or by `0x` prefixed address, then reads the
+    /// `confirmation` telemetry item, if any, until it's ready or `timeoutMs` has passed
+    async fn send_command(
+        &self,
+        module: String,
+        command: String,
+        confirmation: Option<String>,
+        #[graphql(default = 1000)] timeout_ms: u64,
+    ) -> Result<CommandResult> {
+        let module = match module.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16)
+                .map(ModuleRef::Address)
+                .unwrap_or(ModuleRef::Name(module)),
+            None => ModuleRef::Name(module),
+        };
+        let mut master = self.master.lock().map_err(|_| {
+            SupMCUError::IoError(std::io::Error::other("bus master lock was poisoned")).extend()
+        })?;
+        master
+            .module_by_ref_mut(&module)
+            .and_then(|module| {
+                module.send_command_confirmed(
+                    command,
+                    confirmation.as_deref(),
+                    Duration::from_millis(timeout_ms),
+                )
+            })
+            .map_err(|e| e.extend())
+    }
+}
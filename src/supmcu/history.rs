@@ -0,0 +1,98 @@
+use crate::supmcu::parsing::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::SystemTime};
+
+/// A telemetry reading kept in a [`TelemetryHistory`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoricSample {
+    /// Host time the reading was received
+    pub received: SystemTime,
+    /// Module timestamp from the response header
+    pub timestamp: u32,
+    pub data: SupMCUTelemetryData,
+}
+
+/// The trend of a numeric telemetry value over recent samples
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Trend {
+    /// Least-squares slope, in units per sample
+    pub slope: f64,
+    /// Difference between the newest and oldest values
+    pub delta: f64,
+    /// Number of samples the trend was computed from
+    pub samples: usize,
+}
+
+/// A bounded history of the readings of one telemetry item
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryHistory {
+    pub key: TelemetryKey,
+    /// The maximum number of samples kept
+    pub depth: usize,
+    samples: VecDeque<HistoricSample>,
+}
+
+impl TelemetryHistory {
+    pub fn new(key: TelemetryKey, depth: usize) -> Self {
+        TelemetryHistory {
+            key,
+            depth,
+            samples: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// Adds a reading, dropping the oldest one if the history is full
+    pub fn push(&mut self, tlm: &SupMCUTelemetry) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.samples.len() >= self.depth {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(HistoricSample {
+            received: SystemTime::now(),
+            timestamp: tlm.header.timestamp,
+            data: tlm.data.clone(),
+        });
+    }
+
+    /// Returns the samples, oldest first
+    pub fn samples(&self) -> &VecDeque<HistoricSample> {
+        &self.samples
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Computes the trend of the value at `element` over the last `window` samples.
+    ///
+    /// Returns `None` if there are fewer than two samples or the value isn't numeric.
+    pub fn trend(&self, window: usize, element: usize) -> Option<Trend> {
+        let start = self.samples.len().saturating_sub(window);
+        let values = self
+            .samples
+            .range(start..)
+            .map(|s| s.data.get(element).and_then(SupMCUValue::as_f64))
+            .collect::<Option<Vec<f64>>>()?;
+        if values.len() < 2 {
+            return None;
+        }
+
+        let n = values.len() as f64;
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = values.iter().sum::<f64>() / n;
+        let (num, den) = values
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(num, den), (x, y)| {
+                let dx = x as f64 - mean_x;
+                (num + dx * (y - mean_y), den + dx * dx)
+            });
+        Some(Trend {
+            slope: num / den,
+            delta: values[values.len() - 1] - values[0],
+            samples: values.len(),
+        })
+    }
+}
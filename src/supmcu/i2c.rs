@@ -1,36 +1,178 @@
+//! A mock I2C device that answers SupMCU requests from a module definition, for tests.
+//!
+//! Telemetry values are random data from the device's PRNG unless scripted with
+//! [`TestI2CDevice::script`].  Requests the device can't answer, like unknown commands or
+//! SMBus transfers, fail with errors rather than panicking, like a module that doesn't answer.
+//! Available with the `test-utils` feature, see also [`super::sim`].
+
 use crate::{
-    supmcu::{discovery::PremadeTelemetryDefs, parsing::*, FOOTER_SIZE, HEADER_SIZE},
-    SupMCUError,
+    supmcu::{
+        discovery::PremadeTelemetryDefs,
+        parsing::*,
+        sim::{self, SimState, TransactionKind},
+        HEADER_SIZE,
+    },
+    ParsingError, SupMCUError,
 };
 use i2cdev::core::I2CDevice;
-use rand::{distributions::Bernoulli, prelude::Distribution, random, rngs::SmallRng};
-
-#[cfg(checksum)]
-use crate::supmcu::CRC32;
+use rand::{distributions::Bernoulli, prelude::Distribution, rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 pub struct TestI2CDevice {
     /// PRNG to generate telemetry values from
     rng: SmallRng,
+    /// PRNG to generate header readiness and timestamps from, separate so that telemetry
+    /// values only depend on the requests made
+    hdr_source: SmallRng,
     hdr_rng: Bernoulli,
     pub definition: SupMCUModuleDefinition,
     next_response: Option<Vec<u8>>,
+    /// Responses are non-ready until this time, to simulate a module booting
+    pub ready_at: Option<Instant>,
+    /// The number of upcoming responses that will be non-ready
+    pub nonready_responses: usize,
+    /// The number of upcoming reads that will fail
+    pub failed_reads: usize,
+    /// How long each read takes, to simulate a slow module or bus.  On a simulated bus this
+    /// passes on its clock, see [`super::sim::SimBus::real_time`].
+    pub read_latency: Duration,
+    /// Rewrites the strings of NAME and FORMAT responses, given the suffix and the string, to
+    /// simulate firmware quirks
+    pub string_quirk: Option<fn(&str, &str) -> String>,
+    /// Whether reads continue where the previous one stopped, like firmware supporting
+    /// continued reads, rather than starting over at the beginning of the response
+    pub continued_reads: bool,
+    /// Where the next continued read starts in the response
+    read_offset: usize,
+    /// Values to respond with instead of random data, per telemetry item
+    scripted: HashMap<TelemetryKey, VecDeque<SupMCUTelemetryData>>,
+    /// The value of the telemetry change counter, if the definition has one.  Random
+    /// telemetry values then stay the same until [`TestI2CDevice::change_telemetry`].
+    pub change_counter: u32,
+    /// The random data of each telemetry item since the last change, with a change counter
+    generated: HashMap<TelemetryKey, Vec<u8>>,
+    /// The simulated bus this device records its transactions to, if any
+    pub(crate) bus: Option<Arc<Mutex<SimState>>>,
 }
 
 impl TestI2CDevice {
     pub fn new(rng: SmallRng, def: SupMCUModuleDefinition, nonreadys: bool) -> Self {
+        let hdr_seed = rng.clone().gen::<u64>() ^ u64::from(def.address);
         TestI2CDevice {
             rng,
-            hdr_rng: Bernoulli::new(if nonreadys { 0.9 } else { 1.0 }).unwrap(),
+            hdr_source: SmallRng::seed_from_u64(hdr_seed),
+            hdr_rng: Bernoulli::new(if nonreadys { 0.9 } else { 1.0 })
+                .expect("probability is within 0 and 1"),
             definition: def,
             next_response: None,
+            ready_at: None,
+            nonready_responses: 0,
+            failed_reads: 0,
+            read_latency: Duration::ZERO,
+            string_quirk: None,
+            continued_reads: false,
+            read_offset: 0,
+            scripted: HashMap::new(),
+            change_counter: 0,
+            generated: HashMap::new(),
+            bus: None,
+        }
+    }
+
+    /// Makes the telemetry item respond with `values`, one per request, before going
+    /// back to random data.
+    pub fn script<K: Into<TelemetryKey>>(&mut self, key: K, values: Vec<SupMCUTelemetryData>) {
+        self.scripted.insert(key.into(), values.into());
+    }
+
+    /// Makes new random telemetry values and increments the change counter, like firmware
+    /// does when its telemetry changes
+    pub fn change_telemetry(&mut self) {
+        self.change_counter = self.change_counter.wrapping_add(1);
+        self.generated.clear();
+    }
+
+    /// Records a transaction to the simulated bus, if the device is on one
+    fn record(&self, kind: TransactionKind) {
+        if let Some(bus) = &self.bus {
+            sim::lock(bus).record(self.definition.address, kind);
         }
     }
 
+    /// Waits for `duration`, on the bus's clock if the device is on one
+    fn wait(&self, duration: Duration) {
+        match &self.bus {
+            Some(bus) => sim::wait(bus, duration),
+            None => thread::sleep(duration),
+        }
+    }
+
+    /// Returns the error of a request the device can't answer
+    fn unsupported(&self, what: &str) -> SupMCUError {
+        SupMCUError::I2CCommandError(self.definition.address, format!("{what} isn't simulated"))
+    }
+
+    /// Returns the length of the responses to a telemetry item
+    fn response_length(item: &SupMCUTelemetryDefinition) -> Result<usize, SupMCUError> {
+        let len = match item.format.get_byte_length() {
+            Some(len) => len,
+            None => item
+                .length
+                .ok_or_else(|| ParsingError::MissingLengthError(item.name.clone()))?,
+        };
+        Ok(len + HEADER_SIZE)
+    }
+
+    /// Returns the definition of a telemetry item by type and index, wherever it is in the
+    /// definition
+    fn item(
+        &self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<SupMCUTelemetryDefinition, SupMCUError> {
+        self.definition
+            .telemetry_item(telemetry_type, idx)
+            .cloned()
+            .ok_or(SupMCUError::TelemetryIndexError(telemetry_type, idx))
+    }
+
     /// Parses command strings and returns a vec of bytes as a response.  
     fn parse_cmd(&mut self, cmd: &str) -> Result<Vec<u8>, SupMCUError> {
-        println!("Parsing command {cmd:?}");
-        let (module, cmd) = cmd.trim_end().split_once(':').unwrap();
+        if cmd.trim().is_empty() {
+            // Like firmware, a bare newline only terminates the command buffer
+            return Ok(vec![]);
+        }
+        // Firmware sharing an address between modules only answers commands with its prefix
+        let cmd = match &self.definition.scpi_prefix {
+            Some(prefix) => cmd
+                .strip_prefix(prefix.as_str())
+                .and_then(|cmd| cmd.strip_prefix(':'))
+                .ok_or_else(|| ParsingError::CommandParsingError(cmd.to_string()))?,
+            None => cmd,
+        };
+        let (module, cmd) = cmd
+            .trim_end()
+            .split_once(':')
+            .ok_or_else(|| ParsingError::CommandParsingError(cmd.to_string()))?;
+        let parse_idx = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| ParsingError::CommandParsingError(cmd.to_string()))
+        };
 
+        if !cmd.starts_with("TEL?") && !cmd.starts_with("COM?") {
+            // Any other command doesn't have a response, so there is nothing to read back
+            return Ok(vec![]);
+        }
+        let telemetry_type = if module == "SUP" {
+            TelemetryType::SupMCU
+        } else {
+            TelemetryType::Module
+        };
         let mut buf = self.make_header();
 
         // Checking if request is for telemetry or a command
@@ -38,90 +180,82 @@ impl TestI2CDevice {
             // Checking for suffix like ',NAME' or ',LENGTH'
             if let Some(split) = cmd.split_once(',') {
                 // Suffix is present, parse it and create an appropriate response
-                let idx = split.0.replace("TEL? ", "").parse::<usize>().unwrap();
+                let idx = parse_idx(&split.0.replace("TEL? ", ""))?;
                 let resp_def: SupMCUTelemetryDefinition =
                     PremadeTelemetryDefs::try_from(split.1)?.into();
-                let len = resp_def
-                    .format
-                    .get_byte_length()
-                    .unwrap_or_else(|| resp_def.length.unwrap())
-                    + HEADER_SIZE;
-
-                buf.extend(match resp_def.name.to_uppercase().as_str() {
-                    "NAME" => {
-                        (self.definition.telemetry[idx].name.clone() + "\0").into_bytes()
-                    }
-                    "FORMAT" => self.definition.telemetry[idx]
-                        .format
-                        .get_format_str()
-                        .into_bytes(),
-                    "LENGTH" => (self.definition.telemetry[idx].length.unwrap() as u16)
+                let len = Self::response_length(&resp_def)?;
+
+                let item = self.item(telemetry_type, idx)?;
+                let quirk = |s: String| match self.string_quirk {
+                    Some(quirk) => quirk(split.1, &s),
+                    None => s,
+                };
+                // SIMULATABLE responses share the definition of LENGTH responses
+                buf.extend(match split.1.to_uppercase().as_str() {
+                    "NAME" => (quirk(item.name.clone()) + "\0").into_bytes(),
+                    "FORMAT" => quirk(item.format.get_format_str()).into_bytes(),
+                    "LENGTH" => (item.length.unwrap_or_default() as u16)
                         .to_le_bytes()
                         .to_vec(),
-                    "SIMULATABLE" => {
-                        vec![self.definition.telemetry[idx].simulatable() as u8]
-                    }
-                    _ => panic!("Invalid command suffix {}", split.1),
+                    "SIMULATABLE" => (item.simulatable() as u16).to_le_bytes().to_vec(),
+                    _ => return Err(ParsingError::CommandParsingError(cmd.to_string()).into()),
                 });
                 buf.resize(len, 0);
                 Ok(self.add_footer(buf))
             } else {
                 // Suffix isn't present, command is requesting telemetry data
-                let tel = if module == "SUP" {
-                    self.definition.get_supmcu_telemetry()
-                } else {
-                    self.definition.get_module_telemetry()
-                };
-                let idx = cmd.replace("TEL? ", "").parse::<usize>().unwrap();
-                let len = tel[idx]
-                    .format
-                    .get_byte_length()
-                    .unwrap_or_else(|| tel[idx].length.unwrap())
-                    + HEADER_SIZE;
-                buf.extend(self.make_data(&tel[idx]));
+                let idx = parse_idx(&cmd.replace("TEL? ", ""))?;
+                let item = self.item(telemetry_type, idx)?;
+                let len = Self::response_length(&item)?;
+                buf.extend(self.make_data(&item));
                 buf.resize(len, 0);
                 Ok(self.add_footer(buf))
             }
-        } else if cmd.starts_with("COM?") {
+        } else {
             // Request is for a command.
-            let idx = cmd.replace("COM? ", "").parse::<usize>().unwrap();
+            let idx = parse_idx(&cmd.replace("COM? ", ""))?;
             // This len stuff could maybe be a constant
             let cmd_def: SupMCUTelemetryDefinition = PremadeTelemetryDefs::CmdName.into();
-            let len = cmd_def.length.unwrap() + HEADER_SIZE;
+            let len = Self::response_length(&cmd_def)?;
+            let command = self
+                .definition
+                .commands
+                .get(idx)
+                .ok_or_else(|| ParsingError::CommandParsingError(cmd.to_string()))?;
 
-            buf.extend(self.definition.commands[idx].name.clone().into_bytes());
+            buf.extend(command.name.clone().into_bytes());
             buf.resize(len, 0);
             Ok(self.add_footer(buf))
-        } else {
-            // Needed an else condition to satisfy the compiler, but this shouldn't ever run
-            // unless other random commands are being sent during testing and need to be handled.
-            unimplemented!()
         }
     }
 
     /// Makes a header with a random timestamp and random readiness
     fn make_header(&mut self) -> Vec<u8> {
+        let booted = self.ready_at.is_none_or(|t| Instant::now() >= t);
+        let forced_nonready = self.nonready_responses > 0;
+        self.nonready_responses = self.nonready_responses.saturating_sub(1);
         SupMCUHDR {
-            ready: self.hdr_rng.sample(&mut rand::thread_rng()),
-            timestamp: random(),
+            ready: booted && !forced_nonready && self.hdr_rng.sample(&mut self.hdr_source),
+            timestamp: self.hdr_source.gen(),
         }
         .into()
     }
 
-    #[cfg(not(checksum))]
+    /// Adds a footer with the checksum kind of the device's definition
     fn add_footer(&mut self, mut data: Vec<u8>) -> Vec<u8> {
-        data.extend(std::iter::repeat(0).take(FOOTER_SIZE));
-        data
-    }
-
-    #[cfg(checksum)]
-    fn add_footer(&mut self, mut data: Vec<u8>) -> Vec<u8> {
-        data.extend(CRC32.checksum(data.as_slice()).to_le_bytes());
+        data.extend(self.definition.checksum.footer(&data));
         data
     }
 
     /// Creates a response to a telemetry reqeust using random data
     fn make_data(&mut self, def: &SupMCUTelemetryDefinition) -> Vec<u8> {
+        if let Some(data) = self
+            .scripted
+            .get_mut(&TelemetryKey::from(def))
+            .and_then(VecDeque::pop_front)
+        {
+            return data.into_iter().flat_map(Into::<Vec<u8>>::into).collect();
+        }
         // Some telemetry items require special handling, specifically the ones in discovery.rs
         match (def.idx, &def.telemetry_type) {
             // Version string request.  This currently works to provide the cmd name.
@@ -141,15 +275,26 @@ impl TestI2CDevice {
                 .to_le_bytes()
                 .to_vec(),
             // MCU ID
-            (19, TelemetryType::SupMCU) => (self.definition.mcu as u8)
-                .to_le_bytes()
-                .to_vec(),
+            (19, TelemetryType::SupMCU) => vec![self.definition.mcu.id()],
+            (idx, TelemetryType::SupMCU) if self.definition.change_counter == Some(idx) => {
+                match def.format.get_byte_length() {
+                    Some(2) => (self.change_counter as u16).to_le_bytes().to_vec(),
+                    _ => self.change_counter.to_le_bytes().to_vec(),
+                }
+            }
             _ => {
+                let key = TelemetryKey::from(def);
+                if let Some(buf) = self.generated.get(&key) {
+                    return buf.clone();
+                }
                 let data = def.format.random_data(&mut self.rng);
                 let mut buf = vec![];
                 for item in data {
                     buf.extend::<Vec<u8>>(item.into())
                 }
+                if self.definition.change_counter.is_some() {
+                    self.generated.insert(key, buf.clone());
+                }
                 buf
             }
         }
@@ -160,29 +305,60 @@ impl I2CDevice for TestI2CDevice {
     type Error = SupMCUError;
 
     fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
-        data.clone_from_slice(self.next_response.clone().unwrap().as_mut_slice());
+        self.wait(self.read_latency);
+        if self.failed_reads > 0 {
+            self.failed_reads -= 1;
+            self.record(TransactionKind::Fault("read failed".into()));
+            return Err(SupMCUError::I2CTelemetryError(
+                self.definition.address,
+                "injected read failure".into(),
+            ));
+        }
+        let Some(resp) = self.next_response.clone() else {
+            return Err(SupMCUError::I2CTelemetryError(
+                self.definition.address,
+                "nothing to read, no request was written".into(),
+            ));
+        };
+        let mut resp = &resp[self.read_offset.min(resp.len())..];
+        if self.continued_reads {
+            resp = &resp[..data.len().min(resp.len())];
+            self.read_offset += data.len();
+        }
+        // A shorter read gets the start of the response, like from a module
+        if data.len() > resp.len() {
+            return Err(SupMCUError::I2CTelemetryError(
+                self.definition.address,
+                format!(
+                    "read {} bytes of a {} byte response",
+                    data.len(),
+                    resp.len()
+                ),
+            ));
+        }
+        data.copy_from_slice(&resp[..data.len()]);
+        self.record(TransactionKind::Read(data.to_vec()));
         Ok(())
     }
 
     fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
-        self.next_response = Some(self.parse_cmd(&String::from_utf8(data.to_vec())?)?);
+        let cmd = String::from_utf8(data.to_vec())?;
+        self.record(TransactionKind::Write(cmd.clone()));
+        self.next_response = Some(self.parse_cmd(&cmd)?);
+        self.read_offset = 0;
         Ok(())
     }
 
     fn smbus_write_quick(&mut self, _bit: bool) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(self.unsupported("SMBus quick write"))
     }
 
     fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, Self::Error> {
-        unimplemented!()
+        Err(self.unsupported("SMBus block read"))
     }
 
-    fn smbus_write_block_data(
-        &mut self,
-        _register: u8,
-        _values: &[u8],
-    ) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<(), Self::Error> {
+        Err(self.unsupported("SMBus block write"))
     }
 
     fn smbus_process_block(
@@ -190,7 +366,7 @@ impl I2CDevice for TestI2CDevice {
         _register: u8,
         _values: &[u8],
     ) -> Result<Vec<u8>, Self::Error> {
-        unimplemented!()
+        Err(self.unsupported("SMBus block process call"))
     }
 
     fn smbus_read_i2c_block_data(
@@ -198,7 +374,7 @@ impl I2CDevice for TestI2CDevice {
         _register: u8,
         _len: u8,
     ) -> Result<Vec<u8>, Self::Error> {
-        unimplemented!()
+        Err(self.unsupported("SMBus I2C block read"))
     }
 
     fn smbus_write_i2c_block_data(
@@ -206,6 +382,6 @@ impl I2CDevice for TestI2CDevice {
         _register: u8,
         _values: &[u8],
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(self.unsupported("SMBus I2C block write"))
     }
 }
@@ -27,6 +27,9 @@ println!();
 
 Loading a definition file
 
+Definition files are JSON, or TOML when the `toml` feature is enabled and the file has a
+`.toml` extension.
+
 ```no_run
 # use supmcu_rs::SupMCUError;
 use supmcu_rs::supmcu::SupMCUMaster;
@@ -41,40 +44,100 @@ master.load_def_file(Path::new("definition.json"))?;
 ```
 */
 
-use crate::{ParsingError, SupMCUError};
+use crate::{ErrorCategory, ParsingError, SupMCUError};
+use anomaly::{AnomalyBundle, AnomalyHook, RecentTransaction};
 use async_graphql::Json;
+use async_rt::{AsyncRuntime, TokioRuntime};
 use async_scoped::TokioScope;
+use audit::{AuditEvent, AuditFormat, AuditLog, AuditRecorder};
+use changes::{ChangeSet, PendingChanges};
+use checksum::ChecksumKind;
 
-use futures::Future;
+use futures::{
+    future::{self, Either},
+    stream, Future, Stream,
+};
+use history::{HistoricSample, TelemetryHistory, Trend};
 use i2cdev::core::I2CDevice;
-use i2cdev::linux::LinuxI2CDevice;
-use log::{error, info, trace};
+use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use ops::{
+    EmergencyOutcome, EmergencyReport, ModuleEmergency, OpsMask, OpsMaskHandle, PauseHandle,
+};
 use parsing::*;
 use regex::Regex;
+use review::{ReviewDecision, ReviewItem};
+use session::{ReplayDevice, Session, SessionEvent, SessionLog, SessionModule, SessionRecorder};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Debug,
-    fs::File,
+    fs::{File, OpenOptions},
+    io::{Cursor, Write},
+    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::{runtime, time};
-
-#[cfg(checksum)]
-use crc::{Crc, CRC_32_CKSUM};
+use tokio::{
+    runtime,
+    sync::{watch, Mutex as AsyncMutex, OwnedMutexGuard, Semaphore},
+    time,
+};
+use tokio_util::sync::CancellationToken;
 
 #[cfg(not(test))]
 use log::debug; // Use log crate when building application
 #[cfg(test)]
 use std::println as debug;
 
+/// Anomaly bundles, the context of a failure for downlinking
+pub mod anomaly;
+/// The timer used by the asynchronous paths of modules
+pub mod async_rt;
+/// Audit logs of the commands sent and telemetry read
+pub mod audit;
+/// A facade running a whole bus from a single configuration
+pub mod bus;
+/// Change sets grouping definition changes, so they're committed or rolled back together
+pub mod changes;
+/// Checksums in the footers of telemetry responses
+pub mod checksum;
+/// Deserializing telemetry values into user types with serde
+pub mod de;
+/// Diagnosis of bus problems from the module's and the host's error counts
+pub mod diag;
+/// Comparison of module definitions, e.g. to audit firmware changes
+pub mod diff;
 mod discovery;
+/// Exporting telemetry readings to other systems, e.g. InfluxDB
+pub mod export;
+/// Conversions between GPS time and host time
+pub mod gps_time;
+/// GraphQL mutations controlling modules, for servers embedding the crate
+pub mod graphql;
+/// Bounded histories of telemetry readings
+pub mod history;
 
-#[cfg(test)]
-mod i2c;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod i2c;
+/// Operations masks, for flight rules restricting traffic to modules
+pub mod ops;
 /// Data structures and associated functions to parse data received from modules
 pub mod parsing;
+/// Reviewing discovered telemetry definitions before they're kept
+pub mod review;
+/// Normalization of SCPI command strings
+pub mod scpi;
+/// Recording and replaying sessions of I2C transactions
+pub mod session;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod sim;
 
 // Telemetry system in SupMCU modules steps:
 //
@@ -94,10 +157,138 @@ const HEADER_SIZE: usize = 5;
 const FOOTER_SIZE: usize = 8;
 const DEFAULT_RESPONSE_DELAY: f32 = 0.05;
 const DEFAULT_RETRIES: u8 = 5;
+/// The most bytes some Linux I2C adapters (e.g. the i.MX ones) read in one transfer, to pass
+/// to [`SupMCUModule::set_max_transfer_len`] on such buses
+pub const DEFAULT_MAX_TRANSFER_LEN: usize = 255;
+// Normalized name of the SupMCU telemetry item holding the last reset cause
+const RESET_CAUSE_TLM: &str = "last_processor_reset";
+/// Conventional names of the telemetry item counting a module's boots, in order of
+/// preference, looked up by [`SupMCUModule::boot_count`] if the definition doesn't set the item
+pub const BOOT_COUNT_ITEMS: [&str; 3] = ["boot_count", "reset_count", "number_of_resets"];
+// Index of the SupMCU telemetry item telling whether telemetry is being simulated
+const SIMULATED_TLM_IDX: usize = 16;
+// Command that starts a module's CPU self-tests (SUPervisor:SELFtest)
+const SELF_TEST_CMD: &str = "SUP:SELF";
+// Index of the SupMCU telemetry item holding the CPU self-test results (supmcu_cpu_self_tests)
+const SELF_TEST_TLM_IDX: usize = 4;
+// How many times a waking module is pinged before giving up
+const WAKE_PING_ATTEMPTS: u32 = 3;
 // The amount of extra time allowed when retrying a non-ready response
 const RETRY_TIME_INCREMENT: f64 = 0.1;
-#[cfg(checksum)]
-const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+// How long to wait before retrying an item that failed to refresh
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(60);
+// Bounds of the exponential backoff used when pinging modules that aren't ready
+const PING_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const PING_BACKOFF_MAX: Duration = Duration::from_millis(500);
+// Standard mode I2C takes 9 clock cycles at 100kHz to transfer a byte
+const I2C_BYTE_TIME: Duration = Duration::from_micros(90);
+// Bus utilization is averaged over this window unless changed with `set_utilization_window`
+const DEFAULT_UTILIZATION_WINDOW: Duration = Duration::from_secs(10);
+// How long transaction times are kept, which bounds the utilization window
+const MAX_UTILIZATION_WINDOW: Duration = Duration::from_secs(60);
+// The shortest utilization window, so that utilization is never divided by zero
+const MIN_UTILIZATION_WINDOW: Duration = Duration::from_millis(1);
+// The fraction of bus time a polling schedule may use before it is flagged
+const DEFAULT_UTILIZATION_CEILING: f64 = 0.5;
+// How long a single address may take to answer during a bus scan
+const SCAN_PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+// Probes still running after timing out that a scan tolerates before giving up
+const SCAN_MAX_HUNG_PROBES: usize = 4;
+// How many of a module's last transactions are kept for anomaly bundles
+const RECENT_TRANSACTIONS: usize = 16;
+// The most bytes an SMBus block read or write carries
+const SMBUS_BLOCK_MAX: usize = 32;
+
+/// Selects which phases of discovery are run, see [`SupMCUModule::discover_with_options`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoverOptions {
+    /// Discover the SupMCU telemetry definitions
+    pub supmcu: bool,
+    /// Discover the module-specific telemetry definitions
+    pub module: bool,
+    /// Discover the module's commands
+    pub commands: bool,
+    /// Fail discovery if the discovered telemetry indices aren't exactly `0..count` for the
+    /// item counts the module reports, instead of only logging a warning
+    pub strict_indices: bool,
+    /// The SCPI prefixes of the logical modules behind each I2C address, for firmware that
+    /// selects one of several modules sharing an address by prefix, see
+    /// [`SupMCUModule::scpi_prefix`].  The master's modules at an address are given its
+    /// prefixes in order, so it needs a module per prefix, e.g. by listing the address once
+    /// per prefix to [`SupMCUMaster::new_with_addrs`].  Only used by
+    /// [`SupMCUMaster::discover_modules_with_options`].
+    pub prefixes: BTreeMap<u16, Vec<String>>,
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        DiscoverOptions {
+            supmcu: true,
+            module: true,
+            commands: true,
+            strict_indices: false,
+            prefixes: BTreeMap::new(),
+        }
+    }
+}
+
+/// Appends the trailing newline commands are terminated with, if it isn't already present
+fn terminate_command(cmd: &str) -> String {
+    let mut cmd = cmd.to_string();
+    if !cmd.ends_with('\n') {
+        cmd += "\n";
+    }
+    cmd
+}
+
+/// The SupMCU telemetry items every module implements, by index.  Discovery relies on some of
+/// these, like the version string (0) and the item counts (14 and 17).
+pub const STANDARD_TELEMETRY_ITEMS: &[(usize, &str)] = &[
+    (
+        0,
+        "Firmware version string; the first word is the module's command name",
+    ),
+    (1, "Number of SCPI commands processed"),
+    (2, "Number of SCPI command errors"),
+    (3, "Voltage status flags"),
+    (4, "CPU self-test results"),
+    (5, "Time since reset, in seconds"),
+    (6, "Number of RTOS context switches since reset"),
+    (7, "Number of RTOS idle hook calls since reset"),
+    (8, "MCU load"),
+    (9, "Module serial number"),
+    (10, "Module I2C address"),
+    (11, "Oscillator tuning value"),
+    (12, "Number of NVM write cycles"),
+    (
+        13,
+        "Last processor reset cause (RCON register), see `ResetCause`",
+    ),
+    (14, "Number of SupMCU and module telemetry items"),
+    (15, "SupMCU temperature, in 0.1 K"),
+    (16, "Whether telemetry is being simulated"),
+    (17, "Number of commands"),
+    (18, "Bootloader version string"),
+    (19, "MCU ID, see `McuType`"),
+];
+
+/// Returns the well-known SupMCU telemetry indices and what they hold, see [`STANDARD_TELEMETRY_ITEMS`]
+pub fn standard_telemetry_items() -> &'static [(usize, &'static str)] {
+    STANDARD_TELEMETRY_ITEMS
+}
+
+/// Replaces non-alphanumeric substrings of a telemetry name with _ and makes everything lowercase
+pub(crate) fn normalize_name(name: &str) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // The pattern is a constant, so compiling it can't fail
+    #[allow(clippy::unwrap_used)]
+    let re = RE.get_or_init(|| Regex::new(r"[^a-zA-Z0-9]+").unwrap());
+    let mut s = re.replace_all(name, "_").to_lowercase();
+    if s.ends_with('_') {
+        s = s[..s.len() - 1].to_owned()
+    }
+    s
+}
 
 /**
   A struct to represent/interact with a SupMCU Module connected to via I2C
@@ -126,7 +317,6 @@ module.send_command("SUP:LED ON");
 # Ok::<(), SupMCUError>(())
 ```
  **/
-
 pub struct SupMCUModule<T: I2CDevice + Send + Sync> {
     i2c_dev: Box<T>,
     /// Time to wait between requesting data and trying to read data
@@ -134,23 +324,500 @@ pub struct SupMCUModule<T: I2CDevice + Send + Sync> {
     definition: Option<SupMCUModuleDefinition>,
     address: u16,
     max_retries: Option<u8>,
+    /// The byte order responses are parsed with, see
+    /// [`SupMCUModule::get_telemetry_by_def_endian`]
+    endianness: Endianness,
+    /// Detect the checksum of each response instead of using the definition's, see
+    /// [`SupMCUModule::get_telemetry_by_def_detect_checksum`]
+    detect_checksums: bool,
+    /// Overrides of the read settings for the current call, see
+    /// [`SupMCUModule::get_telemetry_by_def_with`]
+    read_options: ReadOptions,
+    /// When the current call's retries stop, from [`ReadOptions::timeout`]
+    read_deadline: Option<Instant>,
+    decoders: HashMap<String, TelemetryDecoder>,
+    format_verification: Option<FormatVerification>,
+    history: HashMap<TelemetryKey, TelemetryHistory>,
+    /// The bytes of the last telemetry response read, including the header and footer
+    last_response: Vec<u8>,
+    self_test_names: Option<Vec<String>>,
+    /// See [`SupMCUModule::set_self_test_fields`]
+    self_test_fields: Option<SelfTestFields>,
+    /// The power state the module was last commanded into
+    expected_state: PowerState,
+    /// The commands putting the module to sleep and waking it up, see
+    /// [`SupMCUModule::set_power_commands`]
+    power_commands: Option<(String, String)>,
+    stats: ReadStats,
+    /// The telemetry items counting the module's I2C errors, see
+    /// [`SupMCUModule::set_remote_bus_items`]
+    remote_bus_items: Option<[String; 3]>,
+    /// The telemetry item counting changes, see [`SupMCUModule::set_change_counter_item`]
+    change_counter_item: Option<String>,
+    /// The counters of the last [`SupMCUModule::bus_diagnosis`]
+    bus_counters: diag::BusCounters,
+    usage: BusUsage,
+    /// The session the module's transactions are recorded to, if any
+    recorder: Option<SessionRecorder>,
+    /// The operations mask of the module's master
+    ops: watch::Receiver<OpsMask>,
+    /// Whether telemetry collection is paused, see [`SupMCUMaster::pause_collection`]
+    paused: watch::Receiver<bool>,
+    /// The last values of the telemetry items bounding command parameters, by name
+    bounds: HashMap<String, SupMCUTelemetryData>,
+    /// Log commands instead of sending them, see [`SupMCUModule::is_dry_run`]
+    dry_run: bool,
+    /// Ignore the ops mask and dry-run mode, during an emergency stop
+    privileged: bool,
+    /// The last transactions with the module, oldest first, for anomaly bundles
+    recent: VecDeque<Arc<RecentTransaction>>,
+    /// Stamp telemetry with the host's wall-clock time when it's read
+    host_timestamps: bool,
+    /// Transfers reporting their length, see [`SupMCUModule::set_counted_transport`]
+    counted: Option<Arc<dyn CountedTransport<T>>>,
+    /// Passes device errors through as they are instead of wrapping them, for devices whose
+    /// errors are already `SupMCUError`s
+    device_error: Option<DeviceErrorMap<T>>,
+    /// The most bytes the I2C adapter reads in one transfer, if it has a limit
+    max_transfer_len: Option<usize>,
+    /// The timer of the asynchronous paths, see [`SupMCUModule::set_async_runtime`]
+    async_rt: Arc<dyn AsyncRuntime>,
+    /// Save the definition file whenever the response delay changes, see
+    /// [`SupMCUModule::set_persist_response_delay`]
+    persist_response_delay: bool,
+    /// The audit log the module's commands and reads are written to, if any
+    audit: Option<AuditRecorder>,
+    /// Discovery strings that were cleaned up, as the module sent them
+    raw_strings: HashMap<TelemetryKey, RawDiscoveryStrings>,
+    /// The SCPI prefix selecting the module among modules sharing its address, see
+    /// [`SupMCUModule::scpi_prefix`]
+    scpi_prefix: Option<String>,
+    /// Shared with the other modules at the address, if any
+    address_lock: AddressLock,
+    /// The firmware version string read this session, see [`SupMCUModule::firmware_changed`]
+    current_firmware: Option<String>,
+    /// Whether the firmware version was compared to the definition's this session
+    firmware_checked: bool,
+}
+
+/// Transfers with an I2C device that report the number of bytes the backend actually moved,
+/// for backends that may read or write less than asked, see
+/// [`SupMCUModule::set_counted_transport`].
+///
+/// Both methods default to the device's own transfer, assumed to move every byte, so a
+/// transport only overrides the ones its backend can count.
+pub trait CountedTransport<T: I2CDevice>: Send + Sync {
+    /// Reads from the device like [`I2CDevice::read`], returning the number of bytes read
+    fn read(&self, dev: &mut T, data: &mut [u8]) -> Result<usize, T::Error> {
+        dev.read(data).map(|_| data.len())
+    }
+
+    /// Writes to the device like [`I2CDevice::write`], returning the number of bytes written
+    fn write(&self, dev: &mut T, data: &[u8]) -> Result<usize, T::Error> {
+        dev.write(data).map(|_| data.len())
+    }
+}
+
+/// Maps an I2C device error to the `SupMCUError` to return, or `None` to wrap it
+pub(crate) type DeviceErrorMap<T> = fn(&<T as I2CDevice>::Error) -> Option<SupMCUError>;
+
+/// Settings for checking telemetry formats against the module while reading,
+/// see [`SupMCUModule::verify_formats`]
+#[derive(Clone, Debug)]
+struct FormatVerification {
+    interval: Duration,
+    strict: bool,
+    last_checked: HashMap<(TelemetryType, usize), Instant>,
+}
+
+/// The read overrides of a module for a `_with` call, see [`SupMCUModule::begin_read_with`].
+/// Restores the module's own settings when dropped.
+struct ReadWith<'a, T: I2CDevice + Send + Sync> {
+    module: &'a mut SupMCUModule<T>,
+    saved: Option<(ReadOptions, Option<Instant>)>,
+}
+
+impl<T: I2CDevice + Send + Sync> Deref for ReadWith<'_, T> {
+    type Target = SupMCUModule<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.module
+    }
+}
+
+impl<T: I2CDevice + Send + Sync> DerefMut for ReadWith<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.module
+    }
+}
+
+impl<T: I2CDevice + Send + Sync> Drop for ReadWith<'_, T> {
+    fn drop(&mut self) {
+        if let Some((options, deadline)) = self.saved.take() {
+            self.module.read_options = options;
+            self.module.read_deadline = deadline;
+        }
+    }
+}
+
+/// A module made privileged for an emergency stop, see [`SupMCUMaster::emergency_stop`].
+/// Drops its privileges when dropped, even if the stop panics.
+struct Privileged<'a, T: I2CDevice + Send + Sync>(&'a mut SupMCUModule<T>);
+
+impl<'a, T: I2CDevice + Send + Sync> Privileged<'a, T> {
+    fn new(module: &'a mut SupMCUModule<T>) -> Self {
+        module.privileged = true;
+        Privileged(module)
+    }
+}
+
+impl<T: I2CDevice + Send + Sync> Deref for Privileged<'_, T> {
+    type Target = SupMCUModule<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<T: I2CDevice + Send + Sync> DerefMut for Privileged<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+
+impl<T: I2CDevice + Send + Sync> Drop for Privileged<'_, T> {
+    fn drop(&mut self) {
+        self.0.privileged = false;
+    }
+}
+
+/// Serializes the transactions of the logical modules sharing an I2C address, so that one
+/// module's request isn't answered with another's response.  Modules with an address of
+/// their own don't share a lock and never wait.
+///
+/// The lock is reentrant per module: commands sent within one of the module's transactions
+/// don't wait for the transaction itself.
+#[derive(Debug, Default)]
+struct AddressLock {
+    shared: Option<Arc<AsyncMutex<()>>>,
+    held: Arc<AtomicBool>,
+}
+
+/// A transaction holding an [`AddressLock`] until it's dropped
+struct Transaction {
+    _guard: Option<OwnedMutexGuard<()>>,
+    held: Option<Arc<AtomicBool>>,
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if let Some(held) = &self.held {
+            held.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl AddressLock {
+    fn shared(lock: Arc<AsyncMutex<()>>) -> Self {
+        AddressLock {
+            shared: Some(lock),
+            held: Arc::default(),
+        }
+    }
+
+    /// Returns the shared lock if the module doesn't already hold it
+    fn unheld(&self) -> Option<&Arc<AsyncMutex<()>>> {
+        self.shared
+            .as_ref()
+            .filter(|_| !self.held.load(Ordering::Acquire))
+    }
+
+    fn transaction(&self, guard: Option<OwnedMutexGuard<()>>) -> Transaction {
+        let held = guard.as_ref().map(|_| {
+            self.held.store(true, Ordering::Release);
+            self.held.clone()
+        });
+        Transaction {
+            _guard: guard,
+            held,
+        }
+    }
+
+    /// Waits for the other modules at the address to finish their transactions.
+    ///
+    /// Blocking on a runtime thread is only possible on a multi-threaded runtime, so a
+    /// contended lock is reported as [`SupMCUError::WouldBlock`] on a current-thread runtime.
+    fn lock(&self) -> Result<Transaction, SupMCUError> {
+        let Some(lock) = self.unheld() else {
+            return Ok(self.transaction(None));
+        };
+        let guard = match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => match runtime::Handle::try_current().map(|h| h.runtime_flavor()) {
+                Err(_) => lock.clone().blocking_lock_owned(),
+                Ok(runtime::RuntimeFlavor::CurrentThread) => return Err(SupMCUError::WouldBlock),
+                Ok(_) => tokio::task::block_in_place(|| lock.clone().blocking_lock_owned()),
+            },
+        };
+        Ok(self.transaction(Some(guard)))
+    }
+
+    /// Waits for the other modules at the address to finish their transactions asynchronously
+    async fn lock_async(&self) -> Transaction {
+        let guard = match self.unheld() {
+            Some(lock) => Some(lock.clone().lock_owned().await),
+            None => None,
+        };
+        self.transaction(guard)
+    }
 }
 
 impl<T> SupMCUModule<T>
 where
     T: I2CDevice + Send + Sync,
 {
+    /// Creates a module that communicates through an already opened I2C device
+    pub(crate) fn from_device(i2c_dev: T, address: u16, max_retries: Option<u8>) -> Self {
+        SupMCUModule {
+            i2c_dev: Box::new(i2c_dev),
+            last_cmd: "".into(),
+            definition: None,
+            max_retries,
+            endianness: Endianness::Little,
+            detect_checksums: false,
+            read_options: ReadOptions::default(),
+            read_deadline: None,
+            address,
+            decoders: HashMap::new(),
+            format_verification: None,
+            history: HashMap::new(),
+            last_response: vec![],
+            self_test_names: None,
+            self_test_fields: None,
+            expected_state: PowerState::Awake,
+            power_commands: None,
+            stats: ReadStats::default(),
+            remote_bus_items: None,
+            change_counter_item: None,
+            bus_counters: diag::BusCounters::default(),
+            usage: BusUsage::default(),
+            recorder: None,
+            ops: OpsMaskHandle::default().subscribe(),
+            paused: PauseHandle::default().subscribe(),
+            bounds: HashMap::new(),
+            dry_run: false,
+            privileged: false,
+            recent: VecDeque::with_capacity(RECENT_TRANSACTIONS),
+            host_timestamps: false,
+            counted: None,
+            device_error: None,
+            max_transfer_len: None,
+            async_rt: Arc::new(TokioRuntime),
+            persist_response_delay: true,
+            audit: None,
+            raw_strings: HashMap::new(),
+            scpi_prefix: None,
+            address_lock: AddressLock::default(),
+            current_firmware: None,
+            firmware_checked: false,
+        }
+    }
+
+    /// Records a transaction to the module's recent transactions, and to the session being
+    /// recorded if any
+    fn record(&mut self, event: SessionEvent) {
+        if self.recent.len() >= RECENT_TRANSACTIONS {
+            self.recent.pop_front();
+        }
+        if let Some(Ok(mut log)) = self.recorder.as_ref().map(|r| r.lock()) {
+            log.record(self.address, event.clone());
+        }
+        self.recent
+            .push_back(Arc::new(RecentTransaction::new(event)));
+    }
+
+    /// Writes an audit record of a command or telemetry read, if audit logging is enabled
+    fn audit(&self, event: AuditEvent, result: Result<String, &SupMCUError>) {
+        if let Some(recorder) = &self.audit {
+            let module = self.definition.as_ref().map(|d| d.display_name());
+            audit::lock(recorder).record(self.address, module, event, result);
+        }
+    }
+
+    /// Writes an audit record of a completed telemetry read
+    fn audit_telemetry(
+        &self,
+        def: &SupMCUTelemetryDefinition,
+        resp: &Result<SupMCUTelemetry, SupMCUError>,
+    ) {
+        let event = AuditEvent::Telemetry {
+            telemetry: def.name.clone(),
+        };
+        self.audit(event, resp.as_ref().map(|tlm| format!("{:?}", tlm.data)));
+    }
+
+    /// Puts the module in or out of dry-run mode.
+    ///
+    /// In dry-run mode commands are checked and logged but not sent, and reading a response
+    /// fails with `DryRun`.  Only available with the `dry-run` feature, so that it can't be
+    /// left on in flight builds.
+    #[cfg(any(test, feature = "dry-run"))]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        if dry_run {
+            warn!("{:#04X}: dry run, commands won't be sent", self.address);
+        }
+        self.dry_run = dry_run;
+    }
+
+    /// Returns true if commands are logged instead of sent
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Sets whether telemetry read from the module carries the host's wall-clock time of the
+    /// read in [`SupMCUTelemetry::host_time`].
+    ///
+    /// Module timestamps are tick counters that aren't synchronized between modules, so the
+    /// host time is what puts several modules' telemetry on a common timeline.
+    pub fn set_host_timestamps(&mut self, enabled: bool) {
+        self.host_timestamps = enabled;
+    }
+
+    /// Sets the timer the module waits with, tokio's by default.
+    ///
+    /// With a timer that doesn't need tokio, e.g. a `SleeperRuntime` from [`async_rt`],
+    /// methods like [`SupMCUModule::get_telemetry_by_def_async`] run on any executor.  The
+    /// blocking methods wait with [`AsyncRuntime::sleep_blocking`].
+    pub fn set_async_runtime<R: AsyncRuntime + 'static>(&mut self, rt: R) {
+        self.async_rt = Arc::new(rt);
+    }
+
+    /// Sets whether [`SupMCUMaster::response_delay`] saves the definition file as soon as
+    /// the module's response delay changes, which is the default.
+    ///
+    /// Otherwise the change only marks the definitions dirty, and is saved by the next
+    /// [`SupMCUMaster::save_if_dirty`].  This saves flash wear when tuning the delay in a loop.
+    pub fn set_persist_response_delay(&mut self, enabled: bool) {
+        self.persist_response_delay = enabled;
+    }
+
+    /// Returns whether response delay changes are saved immediately, see
+    /// [`SupMCUModule::set_persist_response_delay`]
+    pub fn persists_response_delay(&self) -> bool {
+        self.persist_response_delay
+    }
+
+    /// Returns the bytes [`SupMCUModule::send_command`] writes for `cmd`, without sending it
+    pub fn command_bytes(&self, cmd: &str) -> Vec<u8> {
+        self.prefixed(&terminate_command(cmd)).into_bytes()
+    }
+
+    /// Converts an I2C device error, wrapping it with `wrap` unless the device passes its
+    /// errors through
+    fn device_error(&self, e: T::Error, wrap: fn(u16, String) -> SupMCUError) -> SupMCUError {
+        self.device_error
+            .and_then(|map| map(&e))
+            .unwrap_or_else(|| wrap(self.address, e.to_string()))
+    }
+
+    /// Prepends the module's SCPI prefix, if it has one, to a command
+    fn prefixed(&self, cmd: &str) -> String {
+        match &self.scpi_prefix {
+            Some(prefix) => format!("{prefix}:{cmd}"),
+            None => cmd.to_string(),
+        }
+    }
+
+    /// Returns the SCPI prefix selecting the module among logical modules sharing its I2C
+    /// address, if it has one.
+    ///
+    /// Every command is sent with the prefix, e.g. `A:SUP:TEL? 0`, while the module's
+    /// commands, errors and records leave it out.
+    pub fn scpi_prefix(&self) -> Option<&str> {
+        self.scpi_prefix.as_deref()
+    }
+
+    /// Sets the SCPI prefix of the module, and of its definition if it has one, see
+    /// [`SupMCUModule::scpi_prefix`]
+    pub fn set_scpi_prefix(&mut self, prefix: Option<String>) {
+        if let Some(def) = &mut self.definition {
+            def.scpi_prefix.clone_from(&prefix);
+        }
+        self.scpi_prefix = prefix;
+    }
+
+    /// Returns the name the module is told apart by in errors: its unique or command name,
+    /// else its SCPI prefix, else its address
+    fn label(&self) -> String {
+        match (&self.definition, &self.scpi_prefix) {
+            (Some(def), _) => def.display_name().to_string(),
+            (None, Some(prefix)) => format!("{prefix}:"),
+            (None, None) => format!("{:#04x}", self.address),
+        }
+    }
+
     /// Sends provided command to the module.
     ///
-    /// Also appends a trailing newline if one isn't already present.
+    /// Also appends a trailing newline if one isn't already present, and the module's SCPI
+    /// prefix if it has one.
     pub fn send_command<S: AsRef<str>>(&mut self, cmd: S) -> Result<(), SupMCUError> {
-        let mut cmd = cmd.as_ref().to_string();
-        if !cmd.ends_with('\n') {
-            cmd += "\n";
+        let cmd = terminate_command(cmd.as_ref());
+        let checked = match self.privileged {
+            true => Ok(()),
+            false => self.ops.borrow().check(self.address, &cmd),
+        };
+        if let Err(e) = checked {
+            self.audit(AuditEvent::Command, Err(&e));
+            return Err(e);
+        }
+        if self.dry_run && !self.privileged {
+            self.last_cmd = cmd[..cmd.len() - 1].to_string();
+            info!(
+                "{:#04X}: dry run, not sending `{}`",
+                self.address, self.last_cmd
+            );
+            if let Some(recorder) = &self.audit {
+                let module = self.definition.as_ref().map(|d| d.display_name());
+                audit::lock(recorder).record_dry_run(self.address, module, self.last_cmd.clone());
+            }
+            return Ok(());
+        }
+        let _transaction = self.address_lock.lock()?;
+        let bytes = self.prefixed(&cmd);
+        let start = Instant::now();
+        let written = match &self.counted {
+            Some(transport) => transport.write(&mut self.i2c_dev, bytes.as_bytes()),
+            None => self.i2c_dev.write(bytes.as_bytes()).map(|_| bytes.len()),
+        };
+        // The terminating newline isn't part of the command
+        let total = bytes.len() - 1;
+        let e = match written {
+            Ok(sent) if sent < bytes.len() => {
+                // Terminate the truncated command so the module doesn't run it with the next
+                // command appended, as a cancel
+                if let Err(e) = self.i2c_dev.write(b"\n") {
+                    warn!(
+                        "{:#04X}: couldn't cancel a partial write: {e}",
+                        self.address
+                    );
+                }
+                Some(SupMCUError::PartialWrite {
+                    address: self.address,
+                    command: cmd[..cmd.len() - 1].to_string(),
+                    sent: sent.min(total),
+                    total,
+                })
+            }
+            Ok(_) => None,
+            Err(e) => Some(self.device_error(e, SupMCUError::I2CCommandError)),
+        };
+        if let Some(e) = e {
+            self.audit(AuditEvent::Command, Err(&e));
+            return Err(e);
         }
-        self.i2c_dev
-            .write(cmd.as_bytes())
-            .map_err(|e| SupMCUError::I2CCommandError(self.address, e.to_string()))?;
+        self.usage.record_write(start);
+        self.audit(AuditEvent::Command, Ok(cmd[..cmd.len() - 1].to_string()));
+        self.record(SessionEvent::Write(bytes));
         self.last_cmd = cmd[..cmd.len() - 1].to_string();
         if let Ok(def) = self.get_definition() {
             debug!(
@@ -163,21 +830,109 @@ where
         Ok(())
     }
 
+    /// Sends a command from the module definition, validating the arguments against
+    /// the command's signature if it is known.
+    ///
+    /// Arguments of parameters bounded by telemetry (see [`TelemetryBound`]) are checked
+    /// against the telemetry value and the smallest valid value too, failing with
+    /// `ArgumentOutOfRange` without sending.
+    pub fn send_known_command<S: AsRef<str>>(
+        &mut self,
+        name: &str,
+        args: &[S],
+    ) -> Result<(), SupMCUError> {
+        let module_def = self.get_definition()?;
+        let command = module_def
+            .find_command(name)
+            .ok_or_else(|| module_def.missing_command(name))?
+            .clone();
+        if let Some(signature) = &command.signature {
+            signature.validate(args)?;
+            for (arg, param) in args.iter().zip(signature.params.iter()) {
+                let Some(bound) = &param.bounds_from else {
+                    continue;
+                };
+                let max = self.argument_max(bound)?;
+                let arg = arg.as_ref().parse::<i64>().map_err(|_| {
+                    ParsingError::CommandArgumentError(format!(
+                        "`{}` isn't an integer",
+                        arg.as_ref()
+                    ))
+                })?;
+                let min = bound.transform.min();
+                if arg < min || arg > max {
+                    return Err(SupMCUError::ArgumentOutOfRange {
+                        command: command.name,
+                        arg,
+                        min,
+                        max,
+                    });
+                }
+            }
+        }
+        let mut cmd = command.name.clone();
+        if !args.is_empty() {
+            cmd += " ";
+            cmd += &args.iter().map(|a| a.as_ref()).join(",");
+        }
+        self.send_command(cmd)
+    }
+
+    /// Returns the largest valid value of a bounded command parameter.
+    ///
+    /// The bounding telemetry item is read the first time, then its value is kept up to date
+    /// whenever the item is read.
+    fn argument_max(&mut self, bound: &TelemetryBound) -> Result<i64, SupMCUError> {
+        let data = match self.bounds.get(&bound.telemetry_name) {
+            Some(data) => data.clone(),
+            None => {
+                let data = self.get_telemetry_by_name(&bound.telemetry_name)?.data;
+                self.bounds
+                    .insert(bound.telemetry_name.clone(), data.clone());
+                data
+            }
+        };
+        match data.get(bound.element) {
+            Some(value) => match value.as_f64() {
+                Some(max) => Ok(bound.transform.apply(max as i64)),
+                None => Err(SupMCUError::UnexpectedValue(
+                    bound.telemetry_name.clone(),
+                    value.clone(),
+                )),
+            },
+            None => Err(ParsingError::CommandArgumentError(format!(
+                "{} has no element {}",
+                bound.telemetry_name, bound.element
+            ))
+            .into()),
+        }
+    }
+
+    /// Returns the definition of a telemetry item, or fails with `TelemetryIndexError`
+    fn telemetry_def(
+        &self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<SupMCUTelemetryDefinition, SupMCUError> {
+        let module_def = self.get_definition()?;
+        module_def
+            .telemetry_item(telemetry_type, idx)
+            .cloned()
+            .ok_or_else(|| {
+                module_def.missing_telemetry(
+                    format!("{telemetry_type:?} {idx}"),
+                    SupMCUError::TelemetryIndexError(telemetry_type, idx),
+                )
+            })
+    }
+
     /// Requests telemetry from the module using a telemetry definition found in the module definition.
     pub fn request_telemetry(
         &mut self,
         telemetry_type: TelemetryType,
         idx: usize,
     ) -> Result<(), SupMCUError> {
-        let mut def = self
-            .get_definition()?
-            .telemetry
-            .iter()
-            .filter(|x| x.idx == idx && x.telemetry_type == telemetry_type);
-        let d = def
-            .next()
-            .ok_or(SupMCUError::TelemetryIndexError(telemetry_type, idx))?
-            .to_owned();
+        let d = self.telemetry_def(telemetry_type, idx)?;
         self.request_telemetry_by_def(&d)
     }
 
@@ -187,15 +942,7 @@ where
         telemetry_type: TelemetryType,
         idx: usize,
     ) -> Result<SupMCUTelemetry, SupMCUError> {
-        let mut def = self
-            .get_definition()?
-            .telemetry
-            .iter()
-            .filter(|x| x.idx == idx && x.telemetry_type == telemetry_type);
-        let d = def
-            .next()
-            .ok_or(SupMCUError::TelemetryIndexError(telemetry_type, idx))?
-            .to_owned();
+        let d = self.telemetry_def(telemetry_type, idx)?;
         self.get_telemetry_by_def(&d)
     }
 
@@ -205,24 +952,174 @@ where
         telemetry_type: TelemetryType,
         idx: usize,
     ) -> Result<SupMCUTelemetry, SupMCUError> {
-        let mut def = self
-            .get_definition()?
-            .telemetry
-            .iter()
-            .filter(|x| x.idx == idx && x.telemetry_type == telemetry_type);
-        let d = def
-            .next()
-            .ok_or(SupMCUError::TelemetryIndexError(telemetry_type, idx))?
-            .to_owned();
+        let d = self.telemetry_def(telemetry_type, idx)?;
         self.get_telemetry_by_def_async(&d).await
     }
 
     /// Requests telemetry from the module using the provided definitions.
+    ///
+    /// Fails with `ModuleAsleep` if the module was put to sleep.
     pub fn request_telemetry_by_def(
         &mut self,
         def: &SupMCUTelemetryDefinition,
     ) -> Result<(), SupMCUError> {
-        self.send_command(self.create_tlm_command(def)?)
+        self.check_awake()?;
+        let cmd = self.create_tlm_command(def)?;
+        // Telemetry requests are safe to send again after a partial write
+        let mut retries = 0;
+        loop {
+            match self.send_command(&cmd) {
+                Err(SupMCUError::PartialWrite { .. })
+                    if retries < self.effective_max_retries().unwrap_or(0) =>
+                {
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Fails with `ModuleAsleep` if the module was put to sleep, so reads don't waste retries on it
+    fn check_awake(&self) -> Result<(), SupMCUError> {
+        match self.expected_state {
+            PowerState::Awake => Ok(()),
+            PowerState::Asleep => Err(SupMCUError::ModuleAsleep(self.address)),
+        }
+    }
+
+    /// Returns the counts of telemetry reads since the module was created or the stats were reset
+    pub fn stats(&self) -> &ReadStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = ReadStats::default();
+    }
+
+    /// Sets the names of the telemetry items counting the module's NACKs sent, buffer overruns
+    /// and other bus errors, in the order of the fields of [`diag::RemoteBusStats`].
+    ///
+    /// These come from the module's firmware documentation, there are no standard items.
+    pub fn set_remote_bus_items(&mut self, names: [String; 3]) {
+        self.remote_bus_items = Some(names);
+    }
+
+    /// Reads the module's own count of I2C errors from the telemetry items set with
+    /// [`SupMCUModule::set_remote_bus_items`].
+    ///
+    /// Fails with `NotConfigured` if the items aren't set, and `UnknownTelemName` if the
+    /// module's definition doesn't have them.
+    pub fn remote_bus_stats(&mut self) -> Result<diag::RemoteBusStats, SupMCUError> {
+        let names = self.remote_bus_items.as_ref().ok_or_else(|| {
+            SupMCUError::NotConfigured(self.address, "I2C error counter items".into())
+        })?;
+        let defs = self.telemetry_defs_by_names(names)?;
+        let mut counts = [0; 3];
+        for (count, def) in counts.iter_mut().zip(defs.iter()) {
+            *count = diag::counter_value(&self.get_telemetry_by_def(def)?)?;
+        }
+        Ok(diag::RemoteBusStats::from_counts(counts))
+    }
+
+    /// Compares the module's own count of I2C errors, see [`SupMCUModule::remote_bus_stats`],
+    /// with the host's, see [`SupMCUModule::stats`], to localize a bus problem.
+    ///
+    /// Deltas are since the last call, or since the module was created on the first one.  The
+    /// host's counts are taken before the module's counters are read, so those reads count
+    /// towards the next diagnosis.
+    pub fn bus_diagnosis(&mut self) -> diag::BusDiagnosis {
+        let local = self.stats;
+        let remote = self.remote_bus_stats();
+        diag::BusDiagnosis::new(self.address, remote, local, &mut self.bus_counters)
+    }
+
+    /// Returns the time spent writing to and reading from the module in the last `window`,
+    /// excluding the delays between requests and responses
+    pub fn bus_time(&self, window: Duration) -> Duration {
+        self.usage.busy_time(Instant::now(), window)
+    }
+
+    /// Returns the measured time of the last request and response of a telemetry item
+    pub fn item_cost<K: Into<TelemetryKey>>(&self, key: K) -> Option<Duration> {
+        self.usage.item_costs.get(&key.into()).copied()
+    }
+
+    /// Returns the time a telemetry request and response take on the bus.
+    ///
+    /// Uses the measured time if the item has been read, otherwise estimates it from the
+    /// number of bytes transferred.  The `bool` is true if the time was measured.
+    pub fn estimate_item_cost(
+        &self,
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<(Duration, bool), SupMCUError> {
+        if let Some(cost) = self.item_cost(def) {
+            return Ok((cost, true));
+        }
+        // The address byte, the command and its newline, then the address byte and response
+        let bytes = 1
+            + self.create_tlm_command(def)?.len()
+            + 1
+            + 1
+            + SupMCUModule::<T>::telemetry_response_size(def)?;
+        Ok((I2C_BYTE_TIME * bytes as u32, false))
+    }
+
+    /// Returns the power state the module was last commanded into
+    pub fn power_state(&self) -> PowerState {
+        self.expected_state
+    }
+
+    /// Sets the commands that put the module to sleep and wake it up.  They differ between
+    /// modules, so changing the power state fails with `NotConfigured` until they are set.
+    pub fn set_power_commands<S: Into<String>>(&mut self, sleep: S, wake: S) {
+        self.power_commands = Some((sleep.into(), wake.into()));
+    }
+
+    /// Returns the command that puts the module into a power state
+    fn power_command(&self, state: PowerState) -> Result<String, SupMCUError> {
+        let (sleep, wake) = self.power_commands.as_ref().ok_or_else(|| {
+            let what = match state {
+                PowerState::Asleep => "sleep command",
+                PowerState::Awake => "wake command",
+            };
+            SupMCUError::NotConfigured(self.address, what.into())
+        })?;
+        Ok(match state {
+            PowerState::Asleep => sleep.clone(),
+            PowerState::Awake => wake.clone(),
+        })
+    }
+
+    /// Commands the module into a power state, see [`SupMCUModule::set_power_commands`].
+    ///
+    /// While a module is asleep, telemetry requests fail immediately with `ModuleAsleep`.
+    /// Prefer [`SupMCUModule::wake`] for waking a module, which verifies that it answers again.
+    pub fn set_power_state(&mut self, state: PowerState) -> Result<(), SupMCUError> {
+        self.send_command(self.power_command(state)?)?;
+        debug!("{:#04X}: commanded {state:?}", self.address);
+        self.expected_state = state;
+        Ok(())
+    }
+
+    /// Wakes the module and pings it until it answers, waiting `settle` before each ping.
+    ///
+    /// Returns the number of pings it took.  If the module doesn't answer after a few pings,
+    /// it is still considered asleep and `ModuleAsleep` is returned.
+    pub fn wake(&mut self, settle: Duration) -> Result<u32, SupMCUError> {
+        self.send_command(self.power_command(PowerState::Awake)?)?;
+        let def: SupMCUTelemetryDefinition = discovery::PremadeTelemetryDefs::CmdAmount.into();
+        for attempt in 1..=WAKE_PING_ATTEMPTS {
+            self.async_rt.sleep_blocking(settle);
+            // The wake-verification read bypasses the asleep check
+            self.send_command(self.create_tlm_command(&def)?)?;
+            self.i2c_delay();
+            if self.read_telemetry_response(&def).is_ok() {
+                debug!("{:#04X}: awake after {attempt} ping(s)", self.address);
+                self.expected_state = PowerState::Awake;
+                return Ok(attempt);
+            }
+        }
+        Err(SupMCUError::ModuleAsleep(self.address))
     }
 
     /// Requests and parses telemetry from the module using the provided definition.
@@ -230,992 +1127,8425 @@ where
         &mut self,
         def: &SupMCUTelemetryDefinition,
     ) -> Result<SupMCUTelemetry, SupMCUError> {
+        self.check_awake()?;
+        if self.firmware_check_due() {
+            self.check_firmware();
+        }
+        if self.format_check_due(def) {
+            let format = self.query_format(def)?;
+            self.compare_format(def, &format)?;
+        }
+        let _transaction = self.address_lock.lock()?;
         self.request_telemetry_by_def(def)?;
         self.i2c_delay();
         self.read_telemetry_response_safe(def)
     }
 
-    /// Requests and parses telemetry from the module using the provided definition asynchronously
-    pub async fn get_telemetry_by_def_async(
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_def`], using
+    /// `retries` instead of the module's max retries for this read only.
+    ///
+    /// Like the max retries, the request is sent up to `retries + 1` more times after a
+    /// non-ready response.
+    pub fn get_telemetry_by_def_retries(
         &mut self,
         def: &SupMCUTelemetryDefinition,
+        retries: u8,
     ) -> Result<SupMCUTelemetry, SupMCUError> {
-        self.request_telemetry_by_def(def)?;
-        self.i2c_delay_async().await;
-        self.read_telemetry_response_safe_async(def).await
+        let max_retries = self.max_retries.replace(retries);
+        let tlm = self.get_telemetry_by_def(def);
+        self.max_retries = max_retries;
+        tlm
     }
 
-    /// Requests and parses all telemetry from the module
-    pub fn get_all_telemetry(
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_def`], reading the
+    /// numbers of the data in the byte order `endian` for this read only.
+    ///
+    /// This is an escape hatch for the odd item of another byte order on a mixed bus.  Custom
+    /// decoders are used as they are.
+    pub fn get_telemetry_by_def_endian(
         &mut self,
-    ) -> Result<HashMap<String, Json<SupMCUTelemetryData>>, SupMCUError> {
-        let mut telemetry = HashMap::new();
-        self.get_definition()?
-            .telemetry
-            .to_owned()
-            .iter()
-            .for_each(|d| {
-                match self.get_telemetry_by_def(d) {
-                    Ok(t) => telemetry.insert(d.name.clone(), Json(t.data)),
-                    Err(e) => {
-                        let v = Json(vec![SupMCUValue::Str(e.to_string())]);
-                        telemetry.insert(d.name.clone(), v)
-                    }
-                };
-            });
-        Ok(telemetry)
-    }
+        def: &SupMCUTelemetryDefinition,
+        endian: Endianness,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let endianness = std::mem::replace(&mut self.endianness, endian);
+        let tlm = self.get_telemetry_by_def(def);
+        self.endianness = endianness;
+        tlm
+    }
 
-    /// Requests and parses telemetry by name from module
-    pub fn get_telemetry_by_names(
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_def`], detecting
+    /// the layout of the response's footer instead of using the definition's checksum kind,
+    /// for this read only.
+    ///
+    /// This handles mixed buses, where some modules fill the footer with a checksum and others
+    /// leave it zeroed or don't send one, without configuring each module.  A response is
+    /// taken as one without a checksum if the checksum bytes of its footer are zeroed and its
+    /// header is valid, and must otherwise verify with a known checksum, see
+    /// [`ChecksumKind::verify_detected`].  Returns the kind of checksum found.
+    ///
+    /// Trying every kind costs up to three checksums of the response per read, which adds up
+    /// for large items like log buffers, but no extra I2C traffic.  A corrupted response whose
+    /// checksum matches no kind fails with `ValidationError`.
+    pub fn get_telemetry_by_def_detect_checksum(
         &mut self,
-        names: Vec<String>,
-    ) -> Result<HashMap<String, Json<SupMCUTelemetryData>>, SupMCUError> {
-        let available_names: Vec<&String> = self
-            .get_definition()?
-            .telemetry
-            .iter()
-            .map(|d| &d.name)
-            .collect();
-        for n in &names {
-            if !available_names.contains(&n) {
-                return Err(SupMCUError::UnknownTelemName(n.to_owned()));
-            }
-        }
-        let mut telemetry = HashMap::new();
-        self.get_definition()?
-            .telemetry
-            .to_owned()
-            .iter()
-            .filter(|d| names.contains(&d.name))
-            .for_each(|d| {
-                match self.get_telemetry_by_def(d) {
-                    Ok(t) => telemetry.insert(d.name.clone(), Json(t.data)),
-                    Err(e) => {
-                        let v = Json(vec![SupMCUValue::Str(e.to_string())]);
-                        telemetry.insert(d.name.clone(), v)
-                    }
-                };
-            });
-        Ok(telemetry)
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<(SupMCUTelemetry, ChecksumKind), SupMCUError> {
+        let detect_checksums = std::mem::replace(&mut self.detect_checksums, true);
+        let tlm = self.get_telemetry_by_def(def);
+        self.detect_checksums = detect_checksums;
+        Ok((tlm?, ChecksumKind::detect(&self.last_response)))
     }
 
-    /// Requests and parses all telemetry from the module asynchronously
-    pub async fn get_all_telemetry_async(
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_def`], with the
+    /// settings in `opts` overriding the module's for this call only.
+    pub fn get_telemetry_by_def_with(
         &mut self,
-    ) -> Result<Vec<Result<SupMCUTelemetry, SupMCUError>>, SupMCUError> {
-        let mut telemetry = vec![];
-        for tlm_def in self.get_definition()?.telemetry.clone() {
-            telemetry.push(self.get_telemetry_by_def_async(&tlm_def).await);
-        }
-        Ok(telemetry)
+        def: &SupMCUTelemetryDefinition,
+        opts: &ReadOptions,
+    ) -> Result<TelemetryRead, SupMCUError> {
+        let mut module = self.begin_read_with(opts)?;
+        let tlm = module.get_telemetry_by_def(def)?;
+        Ok(module.finish_read_with(tlm))
     }
 
-    /// Reads a response to a telemetry request from the module.
-    pub fn read_telemetry_response(
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_def_async`], with
+    /// the settings in `opts` overriding the module's for this call only.
+    pub async fn get_telemetry_by_def_with_async(
         &mut self,
         def: &SupMCUTelemetryDefinition,
-    ) -> Result<SupMCUTelemetry, SupMCUError> {
-        let size = SupMCUModule::<T>::telemetry_response_size(def);
-        let mut buff = vec![0u8; size];
-        self.i2c_dev
-            .read(buff.as_mut_slice())
-            .map_err(|e| SupMCUError::I2CTelemetryError(self.address, e.to_string()))?;
+        opts: &ReadOptions,
+    ) -> Result<TelemetryRead, SupMCUError> {
+        let mut module = self.begin_read_with(opts)?;
+        let tlm = module.get_telemetry_by_def_async(def).await?;
+        Ok(module.finish_read_with(tlm))
+    }
 
-        #[cfg(checksum)]
-        {
-            let checksum = buff.split_off(buff.capacity() - FOOTER_SIZE);
-            self.validate(&buff, checksum)?;
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry`], with the settings
+    /// in `opts` overriding the module's for this call only.
+    pub fn get_telemetry_with(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+        opts: &ReadOptions,
+    ) -> Result<TelemetryRead, SupMCUError> {
+        let d = self.telemetry_def(telemetry_type, idx)?;
+        self.get_telemetry_by_def_with(&d, opts)
+    }
+
+    /// Requests and parses telemetry like [`SupMCUModule::get_telemetry_by_name`], with the
+    /// settings in `opts` overriding the module's for this call only.
+    pub fn get_telemetry_by_name_with(
+        &mut self,
+        name: &str,
+        opts: &ReadOptions,
+    ) -> Result<TelemetryRead, SupMCUError> {
+        let def = self.telemetry_defs_by_names(&[name])?.remove(0);
+        self.get_telemetry_by_def_with(&def, opts)
+    }
+
+    /// Applies the overrides of a `_with` read until the returned guard is dropped, even if
+    /// the read fails or its future is dropped
+    fn begin_read_with(&mut self, opts: &ReadOptions) -> Result<ReadWith<'_, T>, SupMCUError> {
+        if let Some(delay) = opts.response_delay {
+            let name = self
+                .definition
+                .as_ref()
+                .map_or_else(|| format!("{:#04X}", self.address), |d| d.name.clone());
+            check_response_delay(&name, delay)?;
         }
+        let deadline = opts.timeout.map(|timeout| Instant::now() + timeout);
+        let saved = (
+            std::mem::replace(&mut self.read_options, opts.clone()),
+            std::mem::replace(&mut self.read_deadline, deadline),
+        );
+        Ok(ReadWith {
+            module: self,
+            saved: Some(saved),
+        })
+    }
 
-        trace!("Received telemetry response: {:?}", buff);
-        let tel =
-            SupMCUTelemetry::from_bytes(buff, def).map_err(SupMCUError::ParsingError)?;
-        if tel.header.ready {
-            Ok(tel)
-        } else {
-            Err(SupMCUError::NonReadyError(
-                self.address,
-                self.last_cmd.clone(),
-            ))
+    /// Wraps the telemetry of a `_with` read, keeping the raw response if it was asked for
+    fn finish_read_with(&self, tlm: SupMCUTelemetry) -> TelemetryRead {
+        TelemetryRead {
+            telemetry: tlm,
+            raw: self
+                .read_options
+                .keep_raw
+                .then(|| self.last_response.clone()),
         }
     }
 
-    /// Reads a response to a telemetry request and retries the request asynchronously if it comes back non-ready.
-    pub async fn read_telemetry_response_safe_async(
+    /// Returns the max retries of the current call
+    fn effective_max_retries(&self) -> Option<u8> {
+        self.read_options.max_retries.or(self.max_retries)
+    }
+
+    /// Fails with `BusTimeout` if the current call's [`ReadOptions::timeout`] has passed
+    fn check_read_deadline(&self) -> Result<(), SupMCUError> {
+        match self.read_deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                Err(SupMCUError::BusTimeout(vec![self.address]))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Requests and parses telemetry from the module using the provided definition, also
+    /// returning the raw bytes of the response.
+    pub fn get_telemetry_raw(
         &mut self,
         def: &SupMCUTelemetryDefinition,
-    ) -> Result<SupMCUTelemetry, SupMCUError> {
-        let resp = self.read_telemetry_response(def);
-        if let Err(SupMCUError::NonReadyError(..)) = resp {
-            self.retry_nonready_async(def, resp).await
-        } else {
-            resp
-        }
+    ) -> Result<(Vec<u8>, SupMCUTelemetry), SupMCUError> {
+        let tlm = self.get_telemetry_by_def(def)?;
+        Ok((self.last_response.clone(), tlm))
     }
 
-    /// Reads a response to a telemetry request and retries the request if it comes back non-ready.
-    pub fn read_telemetry_response_safe(
+    /// Returns the bytes of the last telemetry response read, including the header and footer.
+    ///
+    /// This is kept even if the response couldn't be parsed, which is useful for debugging.
+    pub fn last_raw_response(&self) -> &[u8] {
+        &self.last_response
+    }
+
+    /// Requests and parses telemetry from the module using the provided definition asynchronously
+    pub async fn get_telemetry_by_def_async(
         &mut self,
         def: &SupMCUTelemetryDefinition,
     ) -> Result<SupMCUTelemetry, SupMCUError> {
-        let resp = self.read_telemetry_response(def);
-        if let Err(SupMCUError::NonReadyError(..)) = resp {
-            self.retry_nonready(def, resp)
-        } else {
-            resp
+        self.check_awake()?;
+        if self.firmware_check_due() {
+            self.check_firmware_async().await;
+        }
+        if self.format_check_due(def) {
+            let format = self.query_format_async(def).await?;
+            self.compare_format(def, &format)?;
         }
+        let _transaction = self.address_lock.lock_async().await;
+        self.request_telemetry_by_def(def)?;
+        self.i2c_delay_async().await;
+        self.read_telemetry_response_safe_async(def).await
     }
 
-    /// Creates a telemetry request command from a telmetry definition
-    fn create_tlm_command(
-        &self,
-        def: &SupMCUTelemetryDefinition,
-    ) -> Result<String, SupMCUError> {
-        let cmd = if def.telemetry_type == TelemetryType::SupMCU {
-            "SUP"
-        } else {
-            &self.get_definition()?.name
-        };
-        Ok(format!("{cmd}:TEL? {}", def.idx))
+    /// Returns the firmware version string read from the module this session, if it was
+    pub fn current_firmware(&self) -> Option<&str> {
+        self.current_firmware.as_deref()
     }
 
-    /// Get the response delay of this module
-    fn response_delay(&self) -> f32 {
-        match &self.definition {
-            Some(def) => def.response_delay,
-            None => DEFAULT_RESPONSE_DELAY,
-        }
+    /// Returns true if the module's firmware version differs from the one its definition was
+    /// discovered with, so the definition may not fit the firmware anymore.
+    ///
+    /// The version is read once per session, before the first telemetry read from a module
+    /// whose definition records its firmware, and a warning is logged if it changed.
+    pub fn firmware_changed(&self) -> bool {
+        let discovered = self
+            .definition
+            .as_ref()
+            .and_then(|d| d.firmware_at_discovery.as_deref());
+        matches!(
+            (discovered, self.current_firmware()),
+            (Some(discovered), Some(current)) if discovered != current
+        )
     }
 
-    /// Sleeps for `self.response_delay` seconds.
-    fn i2c_delay(&self) {
-        thread::sleep(Duration::from_secs_f32(self.response_delay()));
+    /// Returns true if the firmware version hasn't been read this session yet
+    fn firmware_check_due(&self) -> bool {
+        !self.firmware_checked
+            && self
+                .definition
+                .as_ref()
+                .is_some_and(|d| d.firmware_at_discovery.is_some())
     }
 
-    /// Sleeps for `self.response_delay` seconds asynchronously.
-    async fn i2c_delay_async(&self) {
-        time::sleep(Duration::from_secs_f32(self.response_delay())).await;
+    /// Reads the firmware version, see [`SupMCUModule::firmware_changed`]
+    fn check_firmware(&mut self) {
+        let def: SupMCUTelemetryDefinition =
+            discovery::PremadeTelemetryDefs::FirmwareVersion.into();
+        let version = self.address_lock.lock().and_then(|_transaction| {
+            self.request_telemetry_by_def(&def)?;
+            self.i2c_delay();
+            self.read_telemetry_response_safe(&def)
+        });
+        self.note_firmware(version);
     }
 
-    /// Returns the length of a telemetry response using the definition.
-    ///
-    /// Shouldn't ever panic as long as the definition isn't broken, becuase either there
-    /// is a string, and the definition's length field should be Some, or there isn't a string,
-    /// and you can calculate the size from the format.
-    fn telemetry_response_size(def: &SupMCUTelemetryDefinition) -> usize {
-        def.format
-            .get_byte_length()
-            .unwrap_or_else(|| def.length.unwrap())
-            + HEADER_SIZE
-            + FOOTER_SIZE
-    }
-
-    /// Validates data received from a module using a CRC32 checksum.
-    #[cfg(checksum)]
-    fn validate(&self, data: &Vec<u8>, checksum: Vec<u8>) -> Result<(), SupMCUError> {
-        let mut rdr = Cursor::new(&checksum);
-        if CRC32.checksum(data) != rdr.read_u32::<LE>()? {
-            Err(SupMCUError::ValidationError())
-        } else {
-            Ok(())
-        }
+    /// Reads the firmware version asynchronously, see [`SupMCUModule::firmware_changed`]
+    async fn check_firmware_async(&mut self) {
+        let def: SupMCUTelemetryDefinition =
+            discovery::PremadeTelemetryDefs::FirmwareVersion.into();
+        let _transaction = self.address_lock.lock_async().await;
+        let version = match self.request_telemetry_by_def(&def) {
+            Ok(()) => {
+                self.i2c_delay_async().await;
+                self.read_telemetry_response_safe_async(&def).await
+            }
+            Err(e) => Err(e),
+        };
+        self.note_firmware(version);
     }
 
-    /// Discovers the command name by parsing the version string.
-    async fn discover_cmd_name(&mut self) -> Result<(), SupMCUError> {
-        debug!(
-            "Discovering module command name for address {}",
-            self.address
-        );
-        if let SupMCUValue::Str(version) = &self
-            .get_telemetry_by_def_async(
-                &discovery::PremadeTelemetryDefs::FirmwareVersion.into(),
-            )
-            .await?
-            .data[0]
-        {
-            let v = version.to_string();
-            info!("{:#04X}: {}", self.address, v);
-            let def = self.get_definition_mut()?;
-            let mut cmd_name = v
-                .split(' ')
-                .next()
-                .ok_or_else(|| ParsingError::VersionParsingError(v.clone()))?
-                .split('-')
-                .next()
-                .ok_or_else(|| ParsingError::VersionParsingError(v.clone()))?
-                .to_string();
-            if cmd_name == "GPSRM" {
-                cmd_name = String::from("GPS")
-            } else if cmd_name == "RHM3" {
-                cmd_name = String::from("RHM")
-            }
-            def.name = cmd_name;
-            def.simulatable = v.contains("(on STM)") || v.contains("(on QSM)");
-            debug!("Version: {v}");
-            debug!("CMD Name: {}", self.get_definition()?.name);
+    /// Keeps the firmware version read this session, warning if it differs from the one the
+    /// definition was discovered with.  A failed read is only logged, as the check doesn't
+    /// stop the read it was made for, and the version is read again before the next one.
+    fn note_firmware(&mut self, version: Result<SupMCUTelemetry, SupMCUError>) {
+        let version = match version.map(|tlm| tlm.data.into_iter().next()) {
+            Ok(Some(SupMCUValue::Str(version))) => discovery::sanitize_string(&version),
+            Ok(v) => {
+                warn!("{:#04X}: unexpected firmware version {v:?}", self.address);
+                self.firmware_checked = true;
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "{:#04X}: couldn't read the firmware version: {e}",
+                    self.address
+                );
+                return;
+            }
+        };
+        self.current_firmware = Some(version);
+        self.firmware_checked = true;
+        if self.firmware_changed() {
+            warn!(
+                "{:#04X}: firmware changed to `{}` since the definition was discovered, \
+                 rediscover the module",
+                self.address,
+                self.current_firmware().unwrap_or_default()
+            );
         }
-        Ok(())
     }
 
-    /// Discovers the definition (metadata) for a telemetry item.
+    /// Returns a stream that reads a telemetry item once per `interval`.
     ///
-    /// For each telemetry item it gets thee name, format, and sometimes length and simulatability.
-    async fn discover_telemetry_definition(
-        &mut self,
-        telemetry_type: TelemetryType,
-        idx: usize,
-    ) -> Result<SupMCUTelemetryDefinition, SupMCUError> {
-        // replace non-alphanumeric substrings with _ and make everything lowercase
-        fn normalize(name: String) -> String {
-            let re = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
-            let mut s = re.replace_all(&name, "_").to_lowercase();
-            if s.ends_with('_') {
-                s = s[..s.len() - 1].to_owned()
+    /// The stream doesn't spawn any tasks, it only reads when polled, so dropping it stops it
+    /// immediately.  If `cancel` is given, the stream ends once the token is cancelled.
+    ///
+    /// While an operations mask forbids the reads, the stream waits for the mask to change
+    /// instead of yielding `MaskedByOpsRule` errors.  Likewise it waits while collection is
+    /// paused, see [`SupMCUMaster::pause_collection`].
+    ///
+    /// Cancelling the token never interrupts a read: if a read is in progress it finishes
+    /// and its item is yielded before the stream ends.  Dropping the stream while a read is in
+    /// progress abandons it after the request has been sent, but before the response is
+    /// read.  This leaves the module in a clean state, as the next request replaces the
+    /// pending response, and the I2C transfers themselves can't be interrupted.
+    pub fn telemetry_stream<'a>(
+        &'a mut self,
+        def: &'a SupMCUTelemetryDefinition,
+        interval: Duration,
+        cancel: Option<CancellationToken>,
+    ) -> impl Stream<Item = Result<SupMCUTelemetry, SupMCUError>> + 'a {
+        let cancel = cancel.unwrap_or_default();
+        stream::unfold((self, None), move |(module, last_read)| {
+            let cancel = cancel.clone();
+            async move {
+                if cancel.is_cancelled() {
+                    return None;
+                }
+                if let Some(last_read) = last_read {
+                    let elapsed = Instant::now().saturating_duration_since(last_read);
+                    let next = module.async_rt.sleep(interval.saturating_sub(elapsed));
+                    let cancelled = cancel.cancelled();
+                    futures::pin_mut!(next, cancelled);
+                    if let Either::Right(_) = future::select(next, cancelled).await {
+                        return None;
+                    }
+                }
+                while *module.paused.borrow() || !module.telemetry_allowed(def) {
+                    let changed = future::select(
+                        Box::pin(module.ops.changed()),
+                        Box::pin(module.paused.changed()),
+                    );
+                    let cancelled = cancel.cancelled();
+                    futures::pin_mut!(cancelled);
+                    let changed = match future::select(changed, cancelled).await {
+                        Either::Left((changed, _)) => changed.factor_first().0,
+                        Either::Right(_) => return None,
+                    };
+                    // The master, and with it the mask, is gone
+                    changed.ok()?;
+                }
+                let start = Instant::now();
+                let resp = module.get_telemetry_by_def_async(def).await;
+                Some((resp, (module, Some(start))))
             }
-            s
-        }
+        })
+    }
 
-        debug!("Discovering {telemetry_type} telemetry item {idx}");
+    /// Returns false if the operations mask forbids requesting a telemetry item
+    fn telemetry_allowed(&self, def: &SupMCUTelemetryDefinition) -> bool {
+        self.create_tlm_command(def).map_or(true, |cmd| {
+            self.ops.borrow().level(self.address).allows(&cmd)
+        })
+    }
 
-        let mut def = SupMCUTelemetryDefinition {
-            idx,
-            telemetry_type,
-            ..Default::default()
+    /// Enables (or disables, with `None`) checking telemetry formats while reading.
+    ///
+    /// When enabled, the format of each telemetry item is re-queried from the module at most
+    /// once per `interval` and compared to the definition.  A mismatch is logged, and if
+    /// `strict` is set, returned as a `FormatDriftError` instead of parsing the telemetry.
+    pub fn verify_formats(&mut self, interval: Option<Duration>, strict: bool) {
+        self.format_verification = interval.map(|interval| FormatVerification {
+            interval,
+            strict,
+            last_checked: HashMap::new(),
+        });
+    }
+
+    /// Checks whether the format of a telemetry item should be verified, and if so
+    /// marks it as checked.
+    fn format_check_due(&mut self, def: &SupMCUTelemetryDefinition) -> bool {
+        let Some(verification) = self.format_verification.as_mut() else {
+            return false;
         };
+        let key = (def.telemetry_type, def.idx);
+        let due = verification
+            .last_checked
+            .get(&key)
+            .is_none_or(|t| t.elapsed() >= verification.interval);
+        if due {
+            verification.last_checked.insert(key, Instant::now());
+        }
+        due
+    }
 
-        trace!("Requesting telemetry name");
-        self.send_command(self.create_tlm_command(&def)? + ",NAME")?;
-        self.i2c_delay_async().await;
+    /// Requests a piece of metadata (e.g. `NAME` or `LENGTH`) of a telemetry item from the module
+    fn query_metadata(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+        suffix: &str,
+    ) -> Result<Option<SupMCUValue>, SupMCUError> {
+        let resp_def: SupMCUTelemetryDefinition =
+            discovery::PremadeTelemetryDefs::try_from(suffix)?.into();
+        let _transaction = self.address_lock.lock()?;
+        self.send_command(self.create_tlm_command(def)? + "," + suffix)?;
+        self.i2c_delay();
+        let resp = self.read_telemetry_response_safe(&resp_def)?;
+        Ok(resp.data.into_iter().next())
+    }
 
-        trace!("Parsing telemetry name");
-        let name_resp = self
-            .read_telemetry_response_safe_async(
-                &discovery::PremadeTelemetryDefs::Name.into(),
-            )
-            .await?;
-        if let SupMCUValue::Str(name) = &name_resp.data[0] {
-            def.name = normalize(name.to_string());
-        }
+    /// Returns the strings of discovery responses that had to be cleaned up, as the module sent
+    /// them, by telemetry item.
+    ///
+    /// Some firmware builds terminate NAME and FORMAT strings with CR LF, or pad them with
+    /// spaces, which discovery removes before using the strings.
+    pub fn raw_discovery_strings(&self) -> &HashMap<TelemetryKey, RawDiscoveryStrings> {
+        &self.raw_strings
+    }
 
-        trace!("Requesting telemetry format");
-        self.send_command(self.create_tlm_command(&def)? + ",FORMAT")?;
-        self.i2c_delay_async().await;
+    /// Cleans up the name of a telemetry item from a NAME response, keeping the raw name if
+    /// that changed it
+    fn sanitize_name(&mut self, def: &SupMCUTelemetryDefinition, raw: &str) -> String {
+        let name = discovery::sanitize_string(raw);
+        self.keep_raw_string(def, raw, &name, |raw_strings| &mut raw_strings.name);
+        name
+    }
 
-        trace!("Parsing telemetry format");
-        let format_resp = self
-            .read_telemetry_response_safe_async(
-                &discovery::PremadeTelemetryDefs::Format.into(),
-            )
-            .await?;
-        if let SupMCUValue::Str(format) = &format_resp.data[0] {
-            def.format = SupMCUFormat::new(format);
-        }
+    /// Cleans up the format string of a telemetry item from a FORMAT response, keeping the
+    /// raw string if that changed it
+    fn sanitize_format(&mut self, def: &SupMCUTelemetryDefinition, raw: &str) -> String {
+        let format = discovery::sanitize_format(raw);
+        self.keep_raw_string(def, raw, &format, |raw_strings| &mut raw_strings.format);
+        format
+    }
 
-        if def.format.get_byte_length().is_none() {
-            trace!("Format includes a string. Requesting telemetry length");
-            self.send_command(self.create_tlm_command(&def)? + ",LENGTH")?;
-            self.i2c_delay_async().await;
+    fn keep_raw_string<F>(&mut self, def: &SupMCUTelemetryDefinition, raw: &str, clean: &str, f: F)
+    where
+        F: FnOnce(&mut RawDiscoveryStrings) -> &mut Option<String>,
+    {
+        if raw == clean {
+            return;
+        }
+        debug!(
+            "{:#04X}: cleaned up {} item {} string {raw:?} to {clean:?}",
+            self.address, def.telemetry_type, def.idx
+        );
+        *f(self.raw_strings.entry(def.into()).or_default()) = Some(raw.to_string());
+    }
 
-            trace!("Parsing telemetry length");
-            let length_resp = self
-                .read_telemetry_response_safe_async(
-                    &discovery::PremadeTelemetryDefs::Length.into(),
-                )
-                .await?;
-            if let SupMCUValue::U16(length) = length_resp.data[0] {
-                def.length = Some(length.into());
-            }
+    /// Requests the format string of a telemetry item from the module
+    fn query_format(&mut self, def: &SupMCUTelemetryDefinition) -> Result<String, SupMCUError> {
+        match self.query_metadata(def, "FORMAT")? {
+            Some(SupMCUValue::Str(format)) => Ok(self.sanitize_format(def, &format)),
+            Some(v) => Err(SupMCUError::UnexpectedValue("format".into(), v)),
+            None => Ok(String::new()),
         }
+    }
 
-        if self.get_definition()?.simulatable {
-            trace!("Checking whether telemetry item is simulatable");
-            self.send_command(self.create_tlm_command(&def)? + ",SIMULATABLE")?;
-            self.i2c_delay_async().await;
+    /// Asks the module whether a telemetry item can be simulated, without discovering the
+    /// rest of its definition
+    pub fn is_telemetry_simulatable(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<bool, SupMCUError> {
+        match self.query_metadata(def, "SIMULATABLE")? {
+            Some(SupMCUValue::U16(simulatable)) => Ok(simulatable == 1),
+            Some(v) => Err(SupMCUError::UnexpectedValue(def.name.clone(), v)),
+            None => Ok(false),
+        }
+    }
 
-            trace!("Parsing simulatability");
-            let simulatable_resp = self
-                .read_telemetry_response_safe_async(
-                    &discovery::PremadeTelemetryDefs::Simulatable.into(),
-                )
-                .await?;
-            if let SupMCUValue::U16(simulatable) = simulatable_resp.data[0] {
-                if simulatable == 1 {
-                    trace!("Telemetry item is simulatable. Requesting default values.");
-                    let defaults = self.get_telemetry_by_def_async(&def).await?;
-                    def.default_sim_value = Some(defaults.data);
-                } else {
-                    trace!("Telemetry item is not simulatable.");
+    /// Re-reads one kind of metadata of a telemetry item and updates the definition in place
+    fn refresh_item(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+        scope: RefreshScope,
+    ) -> Result<(), SupMCUError> {
+        let def = self.telemetry_def(telemetry_type, idx)?;
+        let mut updated = def.clone();
+        match scope {
+            RefreshScope::Names => match self.query_metadata(&def, "NAME")? {
+                Some(SupMCUValue::Str(name)) => {
+                    updated.name = normalize_name(&self.sanitize_name(&def, &name))
                 }
+                Some(v) => return Err(SupMCUError::UnexpectedValue(def.name, v)),
+                None => {}
+            },
+            RefreshScope::Lengths => match self.query_metadata(&def, "LENGTH")? {
+                Some(SupMCUValue::U16(length)) => updated.length = Some(length.into()),
+                Some(v) => return Err(SupMCUError::UnexpectedValue(def.name, v)),
+                None => {}
+            },
+            RefreshScope::SimDefaults => {
+                updated.default_sim_value = Some(self.get_telemetry_by_def(&def)?.data)
             }
         }
-        Ok(def)
+        if let Some(d) = self
+            .get_definition_mut()?
+            .telemetry_item_mut(telemetry_type, idx)
+        {
+            *d = updated;
+        }
+        Ok(())
     }
 
-    async fn discover_all_telemetry(&mut self) -> Result<(), SupMCUError> {
-        debug!(
-            "Discovering SupMCU telemetry definitions for {}",
-            self.get_definition()?.name
-        );
-        let vals = self
-            .get_telemetry_by_def_async(
-                &discovery::PremadeTelemetryDefs::TlmAmount.into(),
-            )
-            .await?
-            .data;
-        if let SupMCUValue::U16(supmcu_amount) = vals[0] {
-            for i in 0..supmcu_amount {
-                let def = self
-                    .discover_telemetry_definition(TelemetryType::SupMCU, i as usize)
-                    .await?;
-                self.get_definition_mut()?.telemetry.push(def);
-            }
+    /// Requests the format string of a telemetry item from the module asynchronously
+    async fn query_format_async(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<String, SupMCUError> {
+        let _transaction = self.address_lock.lock_async().await;
+        self.send_command(self.create_tlm_command(def)? + ",FORMAT")?;
+        self.i2c_delay_async().await;
+        let resp = self
+            .read_telemetry_response_safe_async(&discovery::PremadeTelemetryDefs::Format.into())
+            .await?;
+        match resp.data.into_iter().next() {
+            Some(SupMCUValue::Str(format)) => Ok(self.sanitize_format(def, &format)),
+            Some(v) => Err(SupMCUError::UnexpectedValue("format".into(), v)),
+            None => Ok(String::new()),
         }
-        debug!(
-            "Discovering module telemetry definitions for {}",
-            self.get_definition()?.name
+    }
+
+    /// Compares a format string from the module to the definition's format
+    fn compare_format(
+        &self,
+        def: &SupMCUTelemetryDefinition,
+        format: &str,
+    ) -> Result<(), SupMCUError> {
+        let live = SupMCUFormat::new(format);
+        if live == def.format {
+            return Ok(());
+        }
+        warn!(
+            "{:#04X}: format of {} changed from {} to {}",
+            self.address,
+            def.name,
+            def.format.get_format_str(),
+            live.get_format_str()
         );
-        if let SupMCUValue::U16(module_amount) = vals[1] {
-            for i in 0..module_amount {
-                let def = self
-                    .discover_telemetry_definition(TelemetryType::Module, i as usize)
-                    .await?;
-                self.get_definition_mut()?.telemetry.push(def);
-            }
+        match &self.format_verification {
+            Some(v) if v.strict => Err(SupMCUError::FormatDriftError(
+                def.name.clone(),
+                def.format.get_format_str(),
+                live.get_format_str(),
+            )),
+            _ => Ok(()),
         }
-        Ok(())
     }
 
-    async fn discover_commands(&mut self) -> Result<(), SupMCUError> {
-        debug!("Discovering commands for {}", self.get_definition()?.name);
-        let val = self
-            .get_telemetry_by_def_async(
-                &discovery::PremadeTelemetryDefs::CmdAmount.into(),
-            )
-            .await?
-            .data;
-        if let SupMCUValue::U16(commands_amount) = val[0] {
-            for i in 0..commands_amount {
-                self.send_command(format!("SUP:COM? {i}"))?;
-                self.i2c_delay_async().await;
-                if let SupMCUValue::Str(name) = &self
-                    .read_telemetry_response_safe_async(
-                        &discovery::PremadeTelemetryDefs::CmdName.into(),
-                    )
-                    .await?
-                    .data[0]
-                {
-                    self.get_definition_mut()?.commands.push(SupMCUCommand {
-                        name: name.to_string(),
-                        idx: i,
-                    })
-                }
-            }
-        }
-        Ok(())
+    /// Requests and parses a telemetry item by name
+    pub fn get_telemetry_by_name(&mut self, name: &str) -> Result<SupMCUTelemetry, SupMCUError> {
+        let module_def = self.get_definition()?;
+        let def = module_def
+            .telemetry
+            .iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| {
+                module_def.missing_telemetry(
+                    name.to_string(),
+                    SupMCUError::UnknownTelemName(name.to_string()),
+                )
+            })?
+            .to_owned();
+        self.get_telemetry_by_def(&def)
     }
 
-    /// Discovers the module definition from the I2C bus.
-    async fn discover(&mut self) -> Result<(), SupMCUError> {
-        if self.definition.is_none() {
-            self.definition = Some(SupMCUModuleDefinition {
-                address: self.address,
-                ..Default::default()
-            });
-        }
-        self.discover_cmd_name().await?;
-        self.discover_all_telemetry().await?;
-        if self.get_definition()?.name != "DCPS" {
-            self.discover_commands().await?;
+    /// Requests a telemetry item and deserializes its values into `V`, e.g. a tuple or a
+    /// struct with a field per value, see [`de`]
+    pub fn get_as<V: serde::de::DeserializeOwned>(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<V, SupMCUError> {
+        self.get_telemetry(telemetry_type, idx)?.deserialize_into()
+    }
+
+    /// Reads and decodes the cause of the module's last reset.
+    ///
+    /// Requires a definition containing the `last_processor_reset` telemetry item.
+    pub fn reset_cause(&mut self) -> Result<ResetCause, SupMCUError> {
+        let tlm = self.get_telemetry_by_name(RESET_CAUSE_TLM)?;
+        match tlm.data.first() {
+            Some(SupMCUValue::I16(v)) => Ok(ResetCause::from(*v as u16)),
+            Some(SupMCUValue::U16(v)) | Some(SupMCUValue::Hex16(v)) => Ok(ResetCause::from(*v)),
+            Some(SupMCUValue::U8(v)) | Some(SupMCUValue::Hex8(v)) => Ok(ResetCause::from(*v)),
+            Some(v) => Err(SupMCUError::UnexpectedValue(
+                RESET_CAUSE_TLM.into(),
+                v.clone(),
+            )),
+            None => Err(SupMCUError::ParsingError(ParsingError::InvalidBytes(
+                format!("{RESET_CAUSE_TLM} returned no values"),
+            ))),
         }
-        Ok(())
     }
 
-    /// Returns the module definition as a mutable reference
-    pub fn get_definition_mut(
-        &mut self,
-    ) -> Result<&mut SupMCUModuleDefinition, SupMCUError> {
-        self.definition
-            .as_mut()
-            .ok_or(SupMCUError::MissingDefinitionError)
+    /// Reads the cause of the module's last reset, the same as [`SupMCUModule::reset_cause`]
+    pub fn reset_reason(&mut self) -> Result<ResetCause, SupMCUError> {
+        self.reset_cause()
     }
 
-    /// Returns the module definition as a immutable reference
-    pub fn get_definition(&self) -> Result<&SupMCUModuleDefinition, SupMCUError> {
-        self.definition
-            .as_ref()
-            .ok_or(SupMCUError::MissingDefinitionError)
+    /// Reads the number of times the module has booted.
+    ///
+    /// Boot counts aren't a standard SupMCU item, so the item comes from the module's firmware
+    /// documentation and is set in the definition's
+    /// [`boot_count`](parsing::SupMCUModuleDefinition::boot_count).  Without it, the first
+    /// item of the definition with one of the names in [`BOOT_COUNT_ITEMS`] is read, whether
+    /// it's a SupMCU or module item.  Fails with `UnknownTelemName` if there is none.
+    pub fn boot_count(&mut self) -> Result<u64, SupMCUError> {
+        let definition = self.get_definition()?;
+        let def = match definition.boot_count {
+            Some(idx) => self.telemetry_def(TelemetryType::Module, idx)?,
+            None => BOOT_COUNT_ITEMS
+                .iter()
+                .find_map(|name| definition.telemetry.iter().find(|d| d.name == *name))
+                .cloned()
+                .ok_or_else(|| SupMCUError::UnknownTelemName(BOOT_COUNT_ITEMS[0].into()))?,
+        };
+        diag::counter_value(&self.get_telemetry_by_def(&def)?)
     }
 
-    /// Sets the module definition
-    pub fn set_definition(&mut self, def: SupMCUModuleDefinition) {
-        self.address = def.address;
-        self.definition = Some(def);
+    /// Reads a simulatable telemetry item and checks that it returns its simulated values,
+    /// e.g. to verify the simulation setup of a hardware-in-the-loop test.
+    ///
+    /// The values are expected to be the definition's `default_sim_value`, or `expected` if
+    /// given, with numbers differing by at most `tolerance`.  Whether simulation is active is
+    /// read from the standard SupMCU item telling it.  Fails with `NotSimulatable` if there
+    /// are no values to expect, or `Slimmed` if they were removed from the definition.
+    pub fn check_simulated(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+        expected: Option<SupMCUTelemetryData>,
+        tolerance: f64,
+    ) -> Result<SimulationCheck, SupMCUError> {
+        let expected = match expected.or_else(|| def.default_sim_value.clone()) {
+            Some(expected) => expected,
+            None => return Err(self.get_definition()?.missing_sim_values(&def.name)),
+        };
+        let simulated_def = self.telemetry_def(TelemetryType::SupMCU, SIMULATED_TLM_IDX)?;
+        let active = match self.get_telemetry_by_def(&simulated_def)?.data.first() {
+            Some(v) => v.as_f64().is_some_and(|v| v != 0.0),
+            None => false,
+        };
+        let actual = self.get_telemetry_by_def(def)?.data;
+        let matches = expected.len() == actual.len()
+            && expected
+                .iter()
+                .zip(actual.iter())
+                .all(|(e, a)| e.within(a, tolerance));
+        Ok(SimulationCheck {
+            name: def.name.clone(),
+            active,
+            matches,
+            expected,
+            actual,
+        })
     }
 
-    /// Check if the module fits a particular definition, will match if addr OR cmd_name match
-    pub fn matches(&self, other: &SupMCUModuleDefinition) -> bool {
-        match self.get_definition() {
-            Ok(def) => other.address == def.address || other.name == def.name,
-            Err(_) => false,
-        }
+    /// Names the bits of the self-test result, e.g. for the tests of a particular firmware.
+    /// Unnamed bits are only reported when they fail, as `bit {n}`.
+    pub fn set_self_test_names(&mut self, names: Vec<String>) {
+        self.self_test_names = Some(names);
     }
 
-    /// Retries a failed telemetry request, increasing the response delay each time.
+    /// Sets which fields of the `supmcu_cpu_self_tests` item hold the self-test results.
+    /// Their layout depends on the firmware, so self-tests fail with `NotConfigured` until
+    /// this is set.
+    pub fn set_self_test_fields(&mut self, fields: SelfTestFields) {
+        self.self_test_fields = Some(fields);
+    }
+
+    /// Runs the module's CPU self-tests and decodes the results.
     ///
-    /// A NonReadyError may still be returned if the max retries is exceeded.
-    async fn retry_nonready_async(
+    /// This sends `SUP:SELF`, then polls the `supmcu_cpu_self_tests` item (SupMCU item 4)
+    /// until the run completes: once its run counter changes, or without a counter once its
+    /// values change from before the test.  The result field is decoded as the bitmask of
+    /// failed tests, see [`SupMCUModule::set_self_test_fields`].  Returns a
+    /// `SelfTestTimeout` if the run doesn't complete within `timeout`.
+    pub fn run_self_test(&mut self, timeout: Duration) -> Result<SelfTestReport, SupMCUError> {
+        // Blocking reads and waits never leave the future pending, so no runtime is needed
+        futures::executor::block_on(self.self_test(timeout, true))
+    }
+
+    /// Runs the module's CPU self-tests asynchronously, see [`SupMCUModule::run_self_test`]
+    pub async fn run_self_test_async(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<SelfTestReport, SupMCUError> {
+        self.self_test(timeout, false).await
+    }
+
+    /// Runs the module's CPU self-tests, reading and waiting either blocking or asynchronously
+    async fn self_test(
+        &mut self,
+        timeout: Duration,
+        blocking: bool,
+    ) -> Result<SelfTestReport, SupMCUError> {
+        let fields = self.self_test_fields.ok_or_else(|| {
+            SupMCUError::NotConfigured(self.address, "self-test result field".into())
+        })?;
+        let start = Instant::now();
+        let def = self.telemetry_def(TelemetryType::SupMCU, SELF_TEST_TLM_IDX)?;
+        let before = self.self_test_read(&def, blocking).await?;
+        self.send_command(SELF_TEST_CMD)?;
+        let bits = loop {
+            if start.elapsed() > timeout {
+                return Err(SupMCUError::SelfTestTimeout(self.address));
+            }
+            match blocking {
+                true => self.i2c_delay(),
+                false => self.i2c_delay_async().await,
+            }
+            let after = match self.self_test_read(&def, blocking).await {
+                Ok(after) => after,
+                Err(SupMCUError::NonReadyError(..)) => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(bits) = self.self_test_bits(&def, fields, &before, &after)? {
+                break bits;
+            }
+        };
+        self.self_test_report(bits, start)
+    }
+
+    /// Reads the self-test results item for [`SupMCUModule::self_test`]
+    async fn self_test_read(
         &mut self,
         def: &SupMCUTelemetryDefinition,
-        resp: Result<SupMCUTelemetry, SupMCUError>,
-    ) -> Result<SupMCUTelemetry, SupMCUError> {
-        if self.max_retries.is_none() {
-            return resp;
+        blocking: bool,
+    ) -> Result<SupMCUTelemetryData, SupMCUError> {
+        let tlm = match blocking {
+            true => self.get_telemetry_by_def(def)?,
+            false => self.get_telemetry_by_def_async(def).await?,
+        };
+        Ok(tlm.data)
+    }
+
+    /// Returns the failed test bits once the self-test has completed, or `None` while it's
+    /// still running
+    fn self_test_bits(
+        &self,
+        def: &SupMCUTelemetryDefinition,
+        fields: SelfTestFields,
+        before: &[SupMCUValue],
+        after: &[SupMCUValue],
+    ) -> Result<Option<u64>, SupMCUError> {
+        let running = match fields.runs {
+            Some(runs) => before.get(runs) == after.get(runs),
+            None => before == after,
+        };
+        if running {
+            trace!("{:#04X}: self-test running", self.address);
+            return Ok(None);
         }
-        let mut retries = 0;
-        loop {
-            self.send_command(self.last_cmd.clone())?;
-            time::sleep(time::Duration::from_secs_f64(
-                self.response_delay() as f64 + RETRY_TIME_INCREMENT * retries as f64,
-            ))
-            .await;
-            let resp = self.read_telemetry_response(def);
-            if let Err(SupMCUError::NonReadyError(..)) = resp {
-                debug!("{} sent a non-ready response.", self.get_definition()?.name);
-                retries += 1;
-                if retries > self.max_retries.unwrap() {
-                    debug!(
-                        "Max retries exceeded, returning `SupMCUError::NonReadyError`"
-                    );
-                    break resp;
+        match after.get(fields.result) {
+            Some(SupMCUValue::U64(v)) => Ok(Some(*v)),
+            Some(SupMCUValue::U32(v)) => Ok(Some(*v as u64)),
+            Some(SupMCUValue::U16(v) | SupMCUValue::Hex16(v)) => Ok(Some(*v as u64)),
+            Some(SupMCUValue::U8(v) | SupMCUValue::Hex8(v)) => Ok(Some(*v as u64)),
+            Some(v) => Err(SupMCUError::UnexpectedValue(def.name.clone(), v.clone())),
+            None => Err(SupMCUError::ParsingError(ParsingError::InvalidBytes(
+                format!("{} returned too few values", def.name),
+            ))),
+        }
+    }
+
+    /// Decodes the failed test bits of a completed self-test into a report
+    fn self_test_report(&self, bits: u64, start: Instant) -> Result<SelfTestReport, SupMCUError> {
+        let no_names: &[String] = &[];
+        let names = self.self_test_names.as_deref().unwrap_or(no_names);
+        Ok(SelfTestReport {
+            address: self.address,
+            module: self.get_definition()?.name.clone(),
+            passed: bits == 0,
+            results: decode_self_test(bits, names),
+            bits,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Sends a command, then optionally reads a telemetry item to confirm its effect.
+    ///
+    /// The confirmation item is requested until it comes back ready or `timeout` elapses,
+    /// in which case the command is reported as not accepted.
+    pub fn send_command_confirmed<S: AsRef<str>>(
+        &mut self,
+        cmd: S,
+        confirmation: Option<&str>,
+        timeout: Duration,
+    ) -> Result<CommandResult, SupMCUError> {
+        let start = Instant::now();
+        self.send_command(cmd)?;
+        let echoed_command = self.last_cmd.clone();
+        let confirm = confirmation.is_some();
+
+        let confirmation = match confirmation {
+            Some(name) => loop {
+                match self.get_telemetry_by_name(name) {
+                    Ok(tlm) => break Some(Json(tlm.data)),
+                    Err(SupMCUError::NonReadyError(..)) if start.elapsed() < timeout => {
+                        self.i2c_delay();
+                        continue;
+                    }
+                    Err(SupMCUError::NonReadyError(..)) => break None,
+                    Err(e) => return Err(e),
                 }
-                debug!("Retrying...");
-                continue;
-            } else {
-                break resp;
+            },
+            None => {
+                self.i2c_delay();
+                None
+            }
+        };
+
+        Ok(CommandResult {
+            accepted: !confirm || confirmation.is_some(),
+            echoed_command,
+            confirmation,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Runs a macro from the module definition, one step after another.
+    ///
+    /// If a verification step reads unexpected values the macro is aborted and the failure is
+    /// returned in the report.  Other errors abort the macro and are returned as is.
+    pub fn run_macro(&mut self, name: &str) -> Result<MacroReport, SupMCUError> {
+        let command_macro = self
+            .get_definition()?
+            .macros
+            .iter()
+            .find(|m| m.name == name)
+            .cloned()
+            .ok_or_else(|| SupMCUError::UnknownMacro(name.to_string()))?;
+        let start = Instant::now();
+        let mut report = MacroReport {
+            name: command_macro.name,
+            steps_run: 0,
+            failure: None,
+            elapsed_ms: 0,
+        };
+        for (idx, step) in command_macro.steps.iter().enumerate() {
+            report.steps_run += 1;
+            report.failure = self.run_macro_step(idx, step)?;
+            if report.failure.is_some() {
+                break;
             }
         }
+        report.elapsed_ms = start.elapsed().as_millis() as u64;
+        Ok(report)
     }
 
-    fn retry_nonready(
+    /// Runs a step of a macro, returning the failure if it is a verification that failed
+    fn run_macro_step(
         &mut self,
-        def: &SupMCUTelemetryDefinition,
-        resp: Result<SupMCUTelemetry, SupMCUError>,
-    ) -> Result<SupMCUTelemetry, SupMCUError> {
-        if self.max_retries.is_none() {
-            return resp;
+        idx: usize,
+        step: &MacroStep,
+    ) -> Result<Option<VerificationFailure>, SupMCUError> {
+        match step {
+            MacroStep::Command(cmd) => self.send_command(cmd)?,
+            MacroStep::Wait(duration) => self.async_rt.sleep_blocking(*duration),
+            MacroStep::VerifyTelemetry { name, expected, .. } => {
+                let actual = self.get_telemetry_by_name(name)?.data;
+                if !step.verifies(&actual) {
+                    return Ok(Some(VerificationFailure {
+                        step: idx,
+                        address: self.address,
+                        name: name.clone(),
+                        expected: expected.clone(),
+                        actual,
+                    }));
+                }
+            }
         }
-        let mut retries = 0;
-        loop {
-            self.send_command(self.last_cmd.clone())?;
-            thread::sleep(time::Duration::from_secs_f64(
-                self.response_delay() as f64 + RETRY_TIME_INCREMENT * retries as f64,
-            ));
-            let resp = self.read_telemetry_response(def);
-            if let Err(SupMCUError::NonReadyError(..)) = resp {
-                debug!("{} sent a non-ready response.", self.get_definition()?.name);
-                retries += 1;
-                if retries > self.max_retries.unwrap() {
-                    debug!(
-                        "Max retries exceeded, returning `SupMCUError::NonReadyError`"
-                    );
-                    break resp;
+        Ok(None)
+    }
+
+    /// Puts the module in the safe state its definition configures by `deadline`, for
+    /// [`SupMCUMaster::emergency_stop`].  The caller makes the module privileged.
+    ///
+    /// Each step gets the time left: a wait that would end after the deadline fails at once,
+    /// and reads stop retrying at the deadline.
+    async fn enter_safe_mode(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<EmergencyOutcome, SupMCUError> {
+        let def = self.get_definition()?;
+        let steps = match &def.safe_mode {
+            None => return Ok(EmergencyOutcome::NoSafeMode),
+            Some(SafeMode::Command(cmd)) => vec![MacroStep::Command(cmd.clone())],
+            Some(SafeMode::Macro(name)) => def
+                .macros
+                .iter()
+                .find(|m| &m.name == name)
+                .ok_or_else(|| SupMCUError::UnknownMacro(name.clone()))?
+                .steps
+                .clone(),
+        };
+        let mut verified = false;
+        for (idx, step) in steps.iter().enumerate() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(EmergencyOutcome::DeadlineExceeded);
+            }
+            match step {
+                MacroStep::Command(cmd) => self.send_command(cmd)?,
+                MacroStep::Wait(duration) if *duration > remaining => {
+                    return Ok(EmergencyOutcome::DeadlineExceeded);
+                }
+                MacroStep::Wait(duration) => self.async_rt.sleep(*duration).await,
+                MacroStep::VerifyTelemetry { name, expected, .. } => {
+                    let def = self.telemetry_defs_by_names(&[name])?.remove(0);
+                    let opts = ReadOptions {
+                        timeout: Some(remaining),
+                        ..Default::default()
+                    };
+                    let actual = match self.get_telemetry_by_def_with_async(&def, &opts).await {
+                        Ok(read) => read.telemetry.data,
+                        Err(SupMCUError::BusTimeout(_)) => {
+                            return Ok(EmergencyOutcome::DeadlineExceeded)
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    if !step.verifies(&actual) {
+                        let failure = VerificationFailure {
+                            step: idx,
+                            address: self.address,
+                            name: name.clone(),
+                            expected: expected.clone(),
+                            actual,
+                        };
+                        return Ok(EmergencyOutcome::VerificationFailed { failure });
+                    }
+                    verified = true;
                 }
-                debug!("Retrying...");
-                continue;
-            } else {
-                break resp;
             }
         }
+        Ok(if verified {
+            EmergencyOutcome::Verified
+        } else {
+            EmergencyOutcome::Sent
+        })
     }
 
-    /// Returns the address
-    pub fn get_address(&self) -> u16 {
-        self.address
+    /// Requests and parses all telemetry from the module
+    pub fn get_all_telemetry(
+        &mut self,
+    ) -> Result<HashMap<String, Json<SupMCUTelemetryData>>, SupMCUError> {
+        let mut telemetry = HashMap::new();
+        self.get_definition()?.sweep_order().iter().for_each(|d| {
+            match self.get_telemetry_by_def(d) {
+                Ok(t) => telemetry.insert(d.name.clone(), Json(t.data)),
+                Err(e) => {
+                    let v = Json(vec![SupMCUValue::Str(e.to_string())]);
+                    telemetry.insert(d.name.clone(), v)
+                }
+            };
+        });
+        Ok(telemetry)
     }
-}
 
-impl<T> Debug for SupMCUModule<T>
-where
-    T: I2CDevice + Send + Sync,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("SupMCUModule")
-            .field("address", &self.address)
-            .field("response_delay", &self.response_delay())
-            .field("max_retries", &self.max_retries)
-            .field("last_cmd", &self.last_cmd)
-            .finish()
+    /// Sets the name of the SupMCU telemetry item the module's firmware increments whenever a
+    /// module telemetry value changes, see [`SupMCUModule::get_all_telemetry_if_changed`].
+    ///
+    /// This comes from the module's firmware documentation, there is no standard item.  The
+    /// item is looked up in the current definition, if any, and again after each discovery of
+    /// the SupMCU telemetry.  A definition file can also set the counter's index directly.
+    pub fn set_change_counter_item<S: Into<String>>(&mut self, name: S) {
+        let name = name.into();
+        if let Ok(def) = self.get_definition_mut() {
+            def.change_counter = def.find_change_counter(&name);
+        }
+        self.change_counter_item = Some(name);
     }
-}
 
-impl SupMCUModule<LinuxI2CDevice> {
-    /// Creates a new SupMCUModule
-    pub fn new(
-        device: &str,
-        address: u16,
-        max_retries: Option<u8>,
-    ) -> Result<Self, SupMCUError> {
-        let dev = LinuxI2CDevice::new(device, address).map_err(|error| {
-            SupMCUError::I2CDevError {
-                device: String::from(device),
-                address,
-                error,
-            }
-        })?;
-        Ok(SupMCUModule {
-            i2c_dev: Box::new(dev),
-            last_cmd: "".into(),
-            definition: None,
-            max_retries,
-            address,
-        })
+    /// Reads the module's telemetry change counter, which its firmware increments whenever a
+    /// module telemetry value changes.  Fails with `NoChangeCounter` if the definition has none.
+    pub fn change_counter(&mut self) -> Result<u32, SupMCUError> {
+        let idx = self
+            .get_definition()?
+            .change_counter
+            .ok_or(SupMCUError::NoChangeCounter(self.address))?;
+        let tlm = self.get_telemetry(TelemetryType::SupMCU, idx)?;
+        match tlm.data.first() {
+            Some(SupMCUValue::U16(count)) => Ok((*count).into()),
+            Some(SupMCUValue::U32(count)) => Ok(*count),
+            value => Err(SupMCUError::UnexpectedValue(
+                tlm.definition.name,
+                value.cloned().unwrap_or(SupMCUValue::Str(String::new())),
+            )),
+        }
     }
 
-    /// Creates a new SupMCUModule from a SupMCUModuleDefinition
-    pub fn new_from_def(
-        device: &str,
-        max_retries: Option<u8>,
-        def: SupMCUModuleDefinition,
-    ) -> Result<Self, SupMCUError> {
-        let address = def.address;
-        let dev = LinuxI2CDevice::new(device, def.address).map_err(|error| {
-            SupMCUError::I2CDevError {
-                device: String::from(device),
-                address,
-                error,
+    /// Sweeps all telemetry like [`SupMCUModule::get_all_telemetry`], but only if the change
+    /// counter moved from `last_counter`, which is then updated.
+    ///
+    /// Only the counter is read while nothing changes.  Start with a value the counter
+    /// hasn't reached, e.g. `u32::MAX`, so the first call sweeps.  Modules without a change
+    /// counter are always swept.
+    pub fn get_all_telemetry_if_changed(
+        &mut self,
+        last_counter: &mut u32,
+    ) -> Result<TelemetryChange, SupMCUError> {
+        if self.get_definition()?.change_counter.is_some() {
+            let counter = self.change_counter()?;
+            if counter == *last_counter {
+                return Ok(TelemetryChange::Unchanged);
             }
-        })?;
-        Ok(SupMCUModule {
-            i2c_dev: Box::new(dev),
-            definition: Some(def),
-            last_cmd: "".into(),
-            max_retries,
-            address,
-        })
+            *last_counter = counter;
+        }
+        self.get_all_telemetry().map(TelemetryChange::Changed)
     }
-}
 
-/**
-A struct to represent an I2C bus of SupMCU modules
+    /// Returns the definitions of the telemetry items called `names`, in that order and
+    /// without repeats, failing on the first unknown name
+    fn telemetry_defs_by_names<S: AsRef<str>>(
+        &self,
+        names: &[S],
+    ) -> Result<Vec<SupMCUTelemetryDefinition>, SupMCUError> {
+        // Reversed so the first of several items with the same name wins, like a search
+        let module_def = self.get_definition()?;
+        let by_name: HashMap<&str, &SupMCUTelemetryDefinition> = module_def
+            .telemetry
+            .iter()
+            .rev()
+            .map(|d| (d.name.as_str(), d))
+            .collect();
+        names
+            .iter()
+            .map(AsRef::as_ref)
+            .unique()
+            .map(|name| {
+                by_name.get(name).map(|d| (*d).clone()).ok_or_else(|| {
+                    module_def.missing_telemetry(
+                        name.to_string(),
+                        SupMCUError::UnknownTelemName(name.to_string()),
+                    )
+                })
+            })
+            .collect()
+    }
 
-This basically just holds a vec of [`SupMCUModule`]s and an async runtime.
-The async runtime is used to run async functions like [`SupMCUModule.get_telemetry_by_def_async`](SupMCUModule#memthod.get_telemetry_by_def_async)
-from withing a sync context.  This allows you to take advantage of the speedups
-that come from accessing modules in parallel without having to deal with an entire
-async application.
+    /// Requests and parses telemetry items by name, in the order of `names`.
+    ///
+    /// All names are looked up before anything is read, so an unknown name fails with
+    /// `UnknownTelemName` without any I2C traffic.  An item that can't be read doesn't stop the
+    /// others, its error is returned in its place.  See
+    /// [`SupMCUModule::get_telemetry_by_names_strict`] to stop at the first error instead.
+    pub fn get_telemetry_by_names<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Result<IndexMap<String, Result<SupMCUTelemetry, SupMCUError>>, SupMCUError> {
+        Ok(self
+            .telemetry_defs_by_names(names)?
+            .into_iter()
+            .map(|def| {
+                let tlm = self.get_telemetry_by_def(&def);
+                (def.name, tlm)
+            })
+            .collect())
+    }
 
-```no_run
-# use supmcu_rs::SupMCUError;
-use supmcu_rs::supmcu::{
-    SupMCUMaster,
-    parsing::*
-};
-use std::{
-    time::Duration,
-    path::Path
-};
+    /// Requests and parses telemetry items by name, in the order of `names`, stopping at the
+    /// first item that can't be read
+    pub fn get_telemetry_by_names_strict<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Result<IndexMap<String, SupMCUTelemetry>, SupMCUError> {
+        self.telemetry_defs_by_names(names)?
+            .into_iter()
+            .map(|def| Ok((def.name.clone(), self.get_telemetry_by_def(&def)?)))
+            .collect()
+    }
 
-// Initialize master from definition file
-let mut master = SupMCUMaster::new("/dev/i2c-1", None)?;
-master.load_def_file(Path::new("definition.json"))?;
+    /// Requests and parses telemetry items by name asynchronously, see
+    /// [`SupMCUModule::get_telemetry_by_names`]
+    pub async fn get_telemetry_by_names_async<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Result<IndexMap<String, Result<SupMCUTelemetry, SupMCUError>>, SupMCUError> {
+        let mut telemetry = IndexMap::new();
+        for def in self.telemetry_defs_by_names(names)? {
+            let tlm = self.get_telemetry_by_def_async(&def).await;
+            telemetry.insert(def.name, tlm);
+        }
+        Ok(telemetry)
+    }
 
-// Get the first telemetry item  (version string) from each module
-let versions = master
-    .for_each(|module| module.get_telemetry_async(TelemetryType::SupMCU, 0))
-    .into_iter()
-    .collect::<Result<Vec<SupMCUTelemetry>, SupMCUError>>()?;
-
-for version in versions {
-    // Prints the version string from each module in the definition.json file
-    println!("{}", &version.data[0]);
-}
-# Ok::<(), SupMCUError>(())
-```
-**/
-
-/// A SupMCUMaster is used to communicate with SupMCU modules over an I2C bus 
-pub struct SupMCUMaster<I: I2CDevice + Send + Sync> {
-    /// The [`SupMCUModule`]s available to control
-    pub modules: Vec<SupMCUModule<I>>,
-    def_file: Option<PathBuf>,
-    rt: runtime::Runtime,
-}
+    /// Requests and parses telemetry items by name asynchronously, stopping at the first item
+    /// that can't be read, see [`SupMCUModule::get_telemetry_by_names_strict`]
+    pub async fn get_telemetry_by_names_strict_async<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Result<IndexMap<String, SupMCUTelemetry>, SupMCUError> {
+        let mut telemetry = IndexMap::new();
+        for def in self.telemetry_defs_by_names(names)? {
+            let tlm = self.get_telemetry_by_def_async(&def).await?;
+            telemetry.insert(def.name, tlm);
+        }
+        Ok(telemetry)
+    }
 
-impl<I> SupMCUMaster<I>
-where
-    I: I2CDevice + Send + Sync,
-{
+    /// Requests and parses all telemetry from the module asynchronously, in the order of
+    /// [`SupMCUModuleDefinition::sweep_order`]
+    pub async fn get_all_telemetry_async(
+        &mut self,
+    ) -> Result<Vec<Result<SupMCUTelemetry, SupMCUError>>, SupMCUError> {
+        let mut telemetry = vec![];
+        for tlm_def in self.get_definition()?.sweep_order() {
+            telemetry.push(self.get_telemetry_by_def_async(&tlm_def).await);
+        }
+        Ok(telemetry)
+    }
 
-    /// Discover the definitions for each stored module
-    pub fn discover_modules(&mut self) -> Result<(), SupMCUError> {
-        log::info!(
-            "Discovering modules: {:?}",
-            self.modules
-                .iter()
-                .map(|m| format!("{:#04X}", m.address))
-                .collect::<Vec<String>>()
-        );
-        self.for_each(|module: &mut SupMCUModule<I>| module.discover())
-            .into_iter()
-            // Consolidating the vec of results into one result
-            .collect::<Result<Vec<()>, SupMCUError>>()?;
-        Ok(())
+    /// Reads the bytes of a response to a telemetry request, including the header and footer
+    fn read_response_bytes(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<Vec<u8>, SupMCUError> {
+        if self.dry_run && !self.privileged {
+            return Err(SupMCUError::DryRun(self.address));
+        }
+        let size = SupMCUModule::<T>::telemetry_response_size(def)?;
+        let transfer_len = match self.max_transfer_len {
+            Some(limit) if limit < size => {
+                if !self.get_definition().is_ok_and(|d| d.continued_reads) {
+                    return Err(SupMCUError::TransferTooLong {
+                        address: self.address,
+                        item: def.name.clone(),
+                        size,
+                        limit,
+                    });
+                }
+                limit.max(1)
+            }
+            _ => size,
+        };
+        let mut buff = vec![0u8; size];
+        let start = Instant::now();
+        // Responses over the transfer limit are read in parts, the module continuing each
+        // read where the previous one stopped
+        let mut read = 0;
+        for chunk in buff.chunks_mut(transfer_len) {
+            let len = chunk.len();
+            let chunk_read = match &self.counted {
+                Some(transport) => transport.read(&mut self.i2c_dev, chunk),
+                None => self.i2c_dev.read(chunk).map(|_| len),
+            }
+            .map_err(|e| self.device_error(e, SupMCUError::I2CTelemetryError))?;
+            read += chunk_read;
+            if chunk_read < len {
+                break;
+            }
+        }
+        self.usage.record_read(start, def);
+        self.record(SessionEvent::Read(buff.clone()));
+        self.last_response.clone_from(&buff);
+        if read < size {
+            return Err(ParsingError::InvalidBytes(format!(
+                "Short read from {:#04X}: got {read} of {size} bytes for {}",
+                self.address, def.name
+            ))
+            .into());
+        }
+        Ok(buff)
     }
 
-    /// Discover an individual module's definition
-    pub fn discover_module(
+    /// Reads the bytes of a response to a telemetry request, failing with a `NonReadyError`
+    /// if the response isn't ready
+    fn read_ready_response_bytes(
         &mut self,
-        module: &SupMCUModuleDefinition,
-    ) -> Result<(), SupMCUError> {
-        for m in self.modules.iter_mut() {
-            if m.matches(module) {
-                return self.rt.block_on(async { m.discover().await });
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<Vec<u8>, SupMCUError> {
+        let resp = self.read_response_bytes(def).and_then(|buff| {
+            if buff.first().is_some_and(|b| b & 0b01 == 1) {
+                Ok(buff)
+            } else {
+                Err(SupMCUError::NonReadyError(
+                    self.address,
+                    self.last_cmd.clone(),
+                ))
             }
+        });
+        self.stats.record(&resp);
+        resp
+    }
+
+    /// Returns the kind of checksum in the module's responses, from its definition
+    fn checksum_kind(&self) -> ChecksumKind {
+        self.definition
+            .as_ref()
+            .map_or(ChecksumKind::None, |def| def.checksum)
+    }
+
+    /// Checks the checksum of a response, of the kind in the definition or, while detecting
+    /// checksums, of the kind in its footer, see [`ChecksumKind::verify_detected`].  Responses
+    /// without one only have their header to check, whose first byte is just the ready flag.
+    fn verify_checksum(&self, buff: &[u8]) -> Result<(), SupMCUError> {
+        if self.read_options.verify_checksum == Some(false) {
+            return Ok(());
+        }
+        if !self.detect_checksums {
+            return self.checksum_kind().verify(buff);
+        }
+        if !ChecksumKind::verify_detected(buff)?.is_none() || buff.first().is_some_and(|b| *b <= 1)
+        {
+            Ok(())
+        } else {
+            Err(ParsingError::InvalidBytes(format!(
+                "Response from {:#04X} has neither a valid checksum nor a valid header",
+                self.address
+            ))
+            .into())
         }
-        Err(SupMCUError::ModuleNotFound(
-            module.name.clone(),
-            module.address,
-        ))
     }
 
-    /// Get module definitions of this SupMCUMaster
-    pub fn get_definitions(&self) -> Result<Vec<SupMCUModuleDefinition>, SupMCUError> {
-        self.modules
+    /// Returns whether the checksum of a response is valid, or `None` if checksums aren't used
+    fn checksum_status(&self, buff: &[u8]) -> Option<bool> {
+        let kind = self.checksum_kind();
+        (!kind.is_none()).then(|| kind.verify(buff).is_ok())
+    }
+
+    /// Reads a telemetry item and writes it to `writer`.
+    ///
+    /// Items that are a single string are written as the raw bytes of the string, without
+    /// decoding it, which keeps large items like log buffers out of memory as `String`s.  Other
+    /// items, including ones mixing strings with other fields, are parsed and written as comma
+    /// separated values followed by a newline.
+    pub fn dump_telemetry_to<W: Write>(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+        mut writer: W,
+    ) -> Result<DumpInfo, SupMCUError> {
+        if def.format.get_format_str() != "S" {
+            let tlm = self.get_telemetry_by_def(def)?;
+            let text = format!("{}\n", tlm.data.iter().join(", "));
+            writer.write_all(text.as_bytes())?;
+            return Ok(DumpInfo {
+                bytes_written: text.len(),
+                timestamp: tlm.header.timestamp,
+                checksum_valid: self.checksum_status(&self.last_response),
+            });
+        }
+
+        let buff = {
+            let _transaction = self.address_lock.lock()?;
+            self.request_telemetry_by_def(def)?;
+            self.i2c_delay();
+            let resp = self.read_ready_response_bytes(def);
+            if resp.as_ref().is_err_and(SupMCUError::is_retryable) {
+                self.retry_nonready_with(resp, |module| module.read_ready_response_bytes(def))?
+            } else {
+                resp?
+            }
+        };
+        let header = SupMCUHDR::try_from(&mut Cursor::new(&buff))?;
+        let payload = &buff[HEADER_SIZE..buff.len() - FOOTER_SIZE];
+        // Strings are NUL terminated, the rest of the response is padding
+        let end = payload
             .iter()
-            .map(|module| Ok(module.get_definition()?.clone()))
-            .collect::<Result<Vec<SupMCUModuleDefinition>, SupMCUError>>()
+            .position(|b| *b == 0)
+            .unwrap_or(payload.len());
+        writer.write_all(&payload[..end])?;
+        Ok(DumpInfo {
+            bytes_written: end,
+            timestamp: header.timestamp,
+            checksum_valid: self.checksum_status(&buff),
+        })
     }
 
-    /// Getting all the telemetry for each stored module
-    pub fn get_all_telemetry(
+    /// Reads a response to a telemetry request from the module.
+    pub fn read_telemetry_response(
         &mut self,
-    ) -> Vec<Vec<Result<SupMCUTelemetry, SupMCUError>>> {
-        self.for_each(|module| async { module.get_all_telemetry_async().await.unwrap() })
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let resp = self.read_and_parse_response(def);
+        self.stats.record(&resp);
+        resp
     }
 
-    /// Runs a closure for a specific module
-    pub fn with_module<F: FnOnce(&SupMCUModule<I>) -> O, O: Send + 'static>(
-        &self,
-        module: &SupMCUModuleDefinition,
-        f: F,
-    ) -> Result<O, SupMCUError> {
-        self.modules
-            .iter()
-            .find(|m| m.matches(module))
-            .map(f)
-            .ok_or(SupMCUError::ModuleNotFound(
-                module.name.clone(),
-                module.address,
+    fn read_and_parse_response(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let buff = self.read_response_bytes(def)?;
+        let host_time = self.host_timestamps.then(SystemTime::now);
+        self.verify_checksum(&buff)?;
+
+        trace!("Received telemetry response: {:?}", buff);
+        let mut tel = match self.decoders.get(&def.name) {
+            Some(decoder) => SupMCUTelemetry::from_bytes_with_decoder(buff, def, decoder),
+            None => SupMCUTelemetry::from_bytes_endian(buff, def, self.endianness),
+        }
+        .map_err(SupMCUError::ParsingError)?;
+        tel.host_time = host_time;
+        if tel.header.ready {
+            if let Some(history) = self.history.get_mut(&TelemetryKey::from(def)) {
+                history.push(&tel);
+            }
+            if let Some(bound) = self.bounds.get_mut(&def.name) {
+                bound.clone_from(&tel.data);
+            }
+            Ok(tel)
+        } else {
+            Err(SupMCUError::NonReadyError(
+                self.address,
+                self.last_cmd.clone(),
             ))
+        }
     }
 
-    /// Runs a closure for a specific module, mutable
-    pub fn with_module_mut<F: FnOnce(&mut SupMCUModule<I>) -> O, O: Send + 'static>(
+    /// Reads a response to a telemetry request and retries the request asynchronously if it fails
+    /// with a [retryable](SupMCUError::is_retryable) error.
+    pub async fn read_telemetry_response_safe_async(
         &mut self,
-        module: &SupMCUModuleDefinition,
-        f: F,
-    ) -> Result<O, SupMCUError> {
-        self.modules
-            .iter_mut()
-            .find(|m| m.matches(module))
-            .map(f)
-            .ok_or(SupMCUError::ModuleNotFound(
-                module.name.clone(),
-                module.address,
-            ))
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let mut resp = self.read_telemetry_response(def);
+        if resp.as_ref().is_err_and(SupMCUError::is_retryable) {
+            resp = self.retry_nonready_async(def, resp).await;
+        }
+        self.audit_telemetry(def, &resp);
+        resp
     }
 
-    /// Sends a command to a module
-    pub fn send_command(
+    /// Reads a response to a telemetry request and retries the request if it fails with a
+    /// [retryable](SupMCUError::is_retryable) error.
+    pub fn read_telemetry_response_safe(
         &mut self,
-        module: &SupMCUModuleDefinition,
-        command: &str,
-    ) -> Result<(), SupMCUError> {
-        let module_command = |module: &mut SupMCUModule<I>| module.send_command(command);
-        self.with_module_mut(module, module_command)?
+        def: &SupMCUTelemetryDefinition,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let mut resp = self.read_telemetry_response(def);
+        if resp.as_ref().is_err_and(SupMCUError::is_retryable) {
+            resp = self.retry_nonready(def, resp);
+        }
+        self.audit_telemetry(def, &resp);
+        resp
     }
 
-    /// Updates a module's response delay
-    pub fn response_delay(
+    /// Polls a telemetry item until the module responds with a ready header.
+    ///
+    /// Useful after sending a command that triggers a long operation.  Returns a
+    /// `NonReadyError` if the module still isn't ready after `timeout`.
+    pub fn wait_ready(
         &mut self,
-        module: &SupMCUModuleDefinition,
-        delay: f32,
+        def: &SupMCUTelemetryDefinition,
+        timeout: Duration,
+        poll_interval: Duration,
     ) -> Result<(), SupMCUError> {
-        self.with_module_mut(module, |m| -> Result<(), SupMCUError> {
-            m.definition
-                .as_mut()
-                .ok_or(SupMCUError::MissingDefinitionError)?
-                .response_delay = delay;
-            Ok(())
-        })??;
-        if let Some(file) = &self.def_file {
-            self.save_def_file(file)?;
+        let start = Instant::now();
+        loop {
+            let transaction = self.address_lock.lock()?;
+            self.request_telemetry_by_def(def)?;
+            self.i2c_delay();
+            let resp = self.read_telemetry_response(def);
+            drop(transaction);
+            match resp {
+                Ok(_) => return Ok(()),
+                Err(SupMCUError::NonReadyError(..)) if start.elapsed() < timeout => {
+                    trace!("{:#04X} not ready yet, polling again", self.address);
+                    self.async_rt.sleep_blocking(poll_interval);
+                }
+                Err(e) => return Err(e),
+            }
         }
-        Ok(())
     }
 
-    /// Runs an async function for each module and returns their results in a Vec
-    pub fn for_each<'a, F, T, O>(&'a mut self, f: F) -> Vec<O>
-    where
-        F: Fn(&'a mut SupMCUModule<I>) -> T,
-        T: Future<Output = O> + Send,
-        O: Send + 'static,
-    {
-        // Wait for the entire async block to finish
-        self.rt.block_on(async {
-            // We need a scope so that self doesn't have to be moved
-            let (_, outputs) = TokioScope::scope_and_block(|s| {
-                for module in self.modules.iter_mut() {
-                    // Spawn the provided function within the scope
-                    s.spawn(f(module));
-                }
-            });
-            // Unwrap the Result<O, JoinError>
-            outputs.into_iter().map(|t| t.unwrap()).collect::<Vec<O>>()
-        })
+    /// Creates a telemetry request command from a telmetry definition
+    fn create_tlm_command(&self, def: &SupMCUTelemetryDefinition) -> Result<String, SupMCUError> {
+        let cmd = if def.telemetry_type == TelemetryType::SupMCU {
+            "SUP"
+        } else {
+            &self.get_definition()?.name
+        };
+        Ok(format!("{cmd}:TEL? {}", def.idx))
     }
 
-    /// Load a SupMCU master from a definition file instead of discovering modules.
-    pub fn load_def_file(&mut self, file: &Path) -> Result<(), SupMCUError> {
-        let defs: Vec<SupMCUModuleDefinition> = serde_json::from_reader(File::open(file)?)?;
-        for (def, module) in defs.into_iter().zip(self.modules.iter_mut()) {
-            module.set_definition(def);
+    /// Get the response delay of this module
+    fn response_delay(&self) -> f32 {
+        match (self.read_options.response_delay, &self.definition) {
+            (Some(delay), _) => delay,
+            (None, Some(def)) => def.response_delay,
+            (None, None) => DEFAULT_RESPONSE_DELAY,
         }
-        self.def_file = Some(file.to_path_buf());
-        Ok(())
     }
 
-    /// Save the modules definitions to a definition file
-    pub fn save_def_file<P: AsRef<Path>>(&self, file: P) -> Result<(), SupMCUError> {
-        let file = File::create(&file)?;
-        serde_json::to_writer(file, &self.get_definitions()?).unwrap();
-        Ok(())
+    /// Sleeps for `self.response_delay` seconds.
+    fn i2c_delay(&self) {
+        self.async_rt
+            .sleep_blocking(Duration::from_secs_f32(self.response_delay()));
     }
-}
 
-impl SupMCUMaster<LinuxI2CDevice> {
-    /// Uses single byte reads to determine what addresses on the bus are populated.
+    /// Sleeps for `self.response_delay` seconds asynchronously.
+    async fn i2c_delay_async(&self) {
+        self.async_rt
+            .sleep(Duration::from_secs_f32(self.response_delay()))
+            .await;
+    }
+
+    /// Returns the length of a telemetry response using the definition.
     ///
-    /// Checks addresses between 0x03 and 0x77, inclusive.a
-    pub fn scan_bus(
-        device: &str,
-        blacklist: Option<Vec<u16>>,
-    ) -> Result<Vec<u16>, SupMCUError> {
-        debug!("scanning I2C bus");
-        let address = 0x03;
-        let mut dev = LinuxI2CDevice::new(device, address).map_err(|error| {
-            SupMCUError::I2CDevError {
-                device: String::from(device),
-                address,
-                error,
-            }
-        })?;
-        let mut addresses = vec![];
+    /// Either there is a string, and the definition's length field should be Some, or there
+    /// isn't a string, and you can calculate the size from the format.  A broken definition
+    /// (a string without a length) returns a `MissingLengthError`.
+    fn telemetry_response_size(def: &SupMCUTelemetryDefinition) -> Result<usize, SupMCUError> {
+        def.format
+            .response_size(def.length)
+            .ok_or_else(|| ParsingError::MissingLengthError(def.name.clone()).into())
+    }
 
-        for i in 0x03..0x78 {
-            trace!("checking address 0x{i:x}");
-            if dev.set_slave_address(i).is_err() {
-                error!("failed to set address 0x{i:x}");
-                continue;
-            }
-            if dev.smbus_read_byte().is_ok() {
-                debug!("found valid address 0x{i:x}");
-                if let Some(blacklist) = &blacklist {
-                    if let Err(_idx) = blacklist.binary_search(&i) {
-                        addresses.push(i);
-                    } else {
-                        debug!("skipping blacklisted address 0x{i:x}");
-                    }
-                } else {
-                    addresses.push(i);
-                }
+    /// Discovers the command name by parsing the version string.
+    async fn discover_cmd_name(&mut self) -> Result<(), SupMCUError> {
+        debug!(
+            "Discovering module command name for address {}",
+            self.address
+        );
+        if let SupMCUValue::Str(version) = &self
+            .get_telemetry_by_def_async(&discovery::PremadeTelemetryDefs::FirmwareVersion.into())
+            .await?
+            .data[0]
+        {
+            let v = discovery::sanitize_string(version);
+            info!("{:#04X}: {}", self.address, v);
+            let checksum = ChecksumKind::detect(&self.last_response);
+            debug!("{:#04X}: {checksum} checksums", self.address);
+            let def = self.get_definition_mut()?;
+            def.checksum = checksum;
+            def.name = discovery::command_name(&v)?;
+            def.simulatable = v.contains("(on STM)") || v.contains("(on QSM)");
+            def.firmware_at_discovery = Some(v.clone());
+            debug!("Version: {v}");
+            debug!("CMD Name: {}", self.get_definition()?.name);
+            self.current_firmware = Some(v);
+            self.firmware_checked = true;
+        }
+        Ok(())
+    }
+
+    /// Reads the MCU ID of the module into its definition, if its SupMCU telemetry has the
+    /// item; older firmware doesn't
+    async fn discover_mcu(&mut self) -> Result<(), SupMCUError> {
+        let mcu_id: SupMCUTelemetryDefinition = discovery::PremadeTelemetryDefs::McuId.into();
+        if self
+            .get_definition()?
+            .telemetry_item(TelemetryType::SupMCU, mcu_id.idx)
+            .is_none()
+        {
+            return Ok(());
+        }
+        let tlm = self.get_telemetry_by_def_async(&mcu_id).await?;
+        let id = match tlm.data.first() {
+            Some(SupMCUValue::U8(id)) => *id,
+            value => {
+                return Err(SupMCUError::UnexpectedValue(
+                    mcu_id.name,
+                    value.cloned().unwrap_or(SupMCUValue::Str(String::new())),
+                ))
             }
+        };
+        let mcu = McuType::from(id);
+        if let McuType::Other(id) = mcu {
+            warn!("{:#04X}: unknown MCU ID {id}", self.address);
         }
-        Ok(addresses)
+        self.get_definition_mut()?.mcu = mcu;
+        Ok(())
     }
 
-    fn new_ext<S: AsRef<str>>(
-        device: S,
-        max_retries: Option<u8>,
-        addresses: Option<Vec<u16>>,
-        blacklist: Option<Vec<u16>>,
-    ) -> Result<Self, SupMCUError> {
-        let device = device.as_ref();
-        let addresses = if let Some(addrs) = addresses {
-            addrs
+    /// Discovers the definition (metadata) for a telemetry item.
+    ///
+    /// For each telemetry item it gets thee name, format, and sometimes length and simulatability.
+    async fn discover_telemetry_definition(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<SupMCUTelemetryDefinition, SupMCUError> {
+        debug!("Discovering {telemetry_type} telemetry item {idx}");
+
+        let mut def = SupMCUTelemetryDefinition {
+            idx,
+            telemetry_type,
+            ..Default::default()
+        };
+
+        trace!("Requesting telemetry name");
+        let transaction = self.address_lock.lock_async().await;
+        self.send_command(self.create_tlm_command(&def)? + ",NAME")?;
+        self.i2c_delay_async().await;
+
+        trace!("Parsing telemetry name");
+        let name_resp = self
+            .read_telemetry_response_safe_async(&discovery::PremadeTelemetryDefs::Name.into())
+            .await?;
+        drop(transaction);
+        if let SupMCUValue::Str(name) = &name_resp.data[0] {
+            def.name = normalize_name(&self.sanitize_name(&def, name));
+        }
+
+        trace!("Requesting telemetry format");
+        let transaction = self.address_lock.lock_async().await;
+        self.send_command(self.create_tlm_command(&def)? + ",FORMAT")?;
+        self.i2c_delay_async().await;
+
+        trace!("Parsing telemetry format");
+        let format_resp = self
+            .read_telemetry_response_safe_async(&discovery::PremadeTelemetryDefs::Format.into())
+            .await?;
+        drop(transaction);
+        if let SupMCUValue::Str(format) = &format_resp.data[0] {
+            def.format = SupMCUFormat::new(&self.sanitize_format(&def, format));
+        }
+
+        if def.format.get_byte_length().is_none() {
+            trace!("Format includes a string. Requesting telemetry length");
+            let transaction = self.address_lock.lock_async().await;
+            self.send_command(self.create_tlm_command(&def)? + ",LENGTH")?;
+            self.i2c_delay_async().await;
+
+            trace!("Parsing telemetry length");
+            let length_resp = self
+                .read_telemetry_response_safe_async(&discovery::PremadeTelemetryDefs::Length.into())
+                .await?;
+            drop(transaction);
+            if let SupMCUValue::U16(length) = length_resp.data[0] {
+                def.length = Some(length.into());
+            }
+        }
+
+        if self.get_definition()?.simulatable {
+            trace!("Checking whether telemetry item is simulatable");
+            let transaction = self.address_lock.lock_async().await;
+            self.send_command(self.create_tlm_command(&def)? + ",SIMULATABLE")?;
+            self.i2c_delay_async().await;
+
+            trace!("Parsing simulatability");
+            let simulatable_resp = self
+                .read_telemetry_response_safe_async(
+                    &discovery::PremadeTelemetryDefs::Simulatable.into(),
+                )
+                .await?;
+            drop(transaction);
+            if let SupMCUValue::U16(simulatable) = simulatable_resp.data[0] {
+                if simulatable == 1 {
+                    trace!("Telemetry item is simulatable. Requesting default values.");
+                    let defaults = self.get_telemetry_by_def_async(&def).await?;
+                    def.default_sim_value = Some(defaults.data);
+                } else {
+                    trace!("Telemetry item is not simulatable.");
+                }
+            }
+        }
+        Ok(def)
+    }
+
+    async fn discover_all_telemetry(
+        &mut self,
+        options: &DiscoverOptions,
+    ) -> Result<(), SupMCUError> {
+        let vals = self
+            .get_telemetry_by_def_async(&discovery::PremadeTelemetryDefs::TlmAmount.into())
+            .await?
+            .data;
+        if options.supmcu {
+            debug!(
+                "Discovering SupMCU telemetry definitions for {}",
+                self.get_definition()?.name
+            );
+            if let SupMCUValue::U16(supmcu_amount) = vals[0] {
+                for i in 0..supmcu_amount {
+                    let def = self
+                        .discover_telemetry_definition(TelemetryType::SupMCU, i as usize)
+                        .await?;
+                    self.get_definition_mut()?.telemetry.push(def);
+                }
+            }
+            self.discover_mcu().await?;
+            if let Some(name) = self.change_counter_item.clone() {
+                let def = self.get_definition_mut()?;
+                def.change_counter = def.find_change_counter(&name);
+            }
+        }
+        if options.module {
+            debug!(
+                "Discovering module telemetry definitions for {}",
+                self.get_definition()?.name
+            );
+            if let SupMCUValue::U16(module_amount) = vals[1] {
+                for i in 0..module_amount {
+                    let def = self
+                        .discover_telemetry_definition(TelemetryType::Module, i as usize)
+                        .await?;
+                    self.get_definition_mut()?.telemetry.push(def);
+                }
+            }
+        }
+        self.check_indices(&vals, options)
+    }
+
+    /// Checks the discovered telemetry indices against the item counts the module reported,
+    /// warning about any problems, or failing with `InconsistentIndices` if `strict_indices`
+    /// is set.
+    fn check_indices(
+        &self,
+        counts: &SupMCUTelemetryData,
+        options: &DiscoverOptions,
+    ) -> Result<(), SupMCUError> {
+        let def = self.get_definition()?;
+        let mut problems = vec![];
+        for (discovered, telemetry_type, count) in [
+            (options.supmcu, TelemetryType::SupMCU, counts.first()),
+            (options.module, TelemetryType::Module, counts.get(1)),
+        ] {
+            if let (true, Some(SupMCUValue::U16(count))) = (discovered, count) {
+                problems.extend(def.index_problems(telemetry_type, *count as usize));
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let problems = problems.iter().join(", ");
+        if options.strict_indices {
+            Err(SupMCUError::InconsistentIndices(self.address, problems))
         } else {
-            SupMCUMaster::scan_bus(device, blacklist)?
+            warn!("{}@{:#04X}: {problems}", def.name, self.address);
+            Ok(())
+        }
+    }
+
+    async fn discover_commands(&mut self) -> Result<(), SupMCUError> {
+        debug!("Discovering commands for {}", self.get_definition()?.name);
+        let val = self
+            .get_telemetry_by_def_async(&discovery::PremadeTelemetryDefs::CmdAmount.into())
+            .await?
+            .data;
+        if let SupMCUValue::U16(commands_amount) = val[0] {
+            for i in 0..commands_amount {
+                let _transaction = self.address_lock.lock_async().await;
+                self.send_command(format!("SUP:COM? {i}"))?;
+                self.i2c_delay_async().await;
+                if let SupMCUValue::Str(name) = &self
+                    .read_telemetry_response_safe_async(
+                        &discovery::PremadeTelemetryDefs::CmdName.into(),
+                    )
+                    .await?
+                    .data[0]
+                {
+                    let command = SupMCUCommand::parse(&discovery::sanitize_string(name), i);
+                    self.get_definition_mut()?.commands.push(command)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discovers the module definition from the I2C bus.
+    async fn discover(&mut self) -> Result<(), SupMCUError> {
+        self.discover_with_options(DiscoverOptions::default()).await
+    }
+
+    /// Discovers the module definition from the I2C bus, only running the phases enabled in `options`.
+    ///
+    /// The command name is always discovered.
+    pub async fn discover_with_options(
+        &mut self,
+        options: DiscoverOptions,
+    ) -> Result<(), SupMCUError> {
+        if self.definition.is_none() {
+            self.definition = Some(SupMCUModuleDefinition {
+                address: self.address,
+                scpi_prefix: self.scpi_prefix.clone(),
+                ..Default::default()
+            });
+        }
+        self.discover_cmd_name().await?;
+        if options.supmcu || options.module {
+            self.discover_all_telemetry(&options).await?;
+        }
+        if options.commands && self.get_definition()?.name != "DCPS" {
+            self.discover_commands().await?;
+        }
+        self.get_definition_mut()?.discovered_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    /// Requests and parses a telemetry item, discovering its definition first if the module
+    /// definition doesn't have it.
+    ///
+    /// The discovered item is added to the module definition so later reads use it.  If the
+    /// module has no definition yet, only its command name is discovered to start one.
+    pub async fn get_telemetry_autodiscover(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        if self.definition.is_none() {
+            self.definition = Some(SupMCUModuleDefinition {
+                address: self.address,
+                scpi_prefix: self.scpi_prefix.clone(),
+                ..Default::default()
+            });
+            if let Err(e) = self.discover_cmd_name().await {
+                self.definition = None;
+                return Err(e);
+            }
+        }
+        let known = self
+            .get_definition()?
+            .telemetry_item(telemetry_type, idx)
+            .cloned();
+        let def = match known {
+            Some(def) => def,
+            None => {
+                let def = self
+                    .discover_telemetry_definition(telemetry_type, idx)
+                    .await?;
+                self.get_definition_mut()?.telemetry.push(def.clone());
+                def
+            }
+        };
+        self.get_telemetry_by_def_async(&def).await
+    }
+
+    /// Returns the module definition as a mutable reference
+    pub fn get_definition_mut(&mut self) -> Result<&mut SupMCUModuleDefinition, SupMCUError> {
+        self.definition
+            .as_mut()
+            .ok_or(SupMCUError::MissingDefinitionError)
+    }
+
+    /// Returns the module definition as a immutable reference
+    pub fn get_definition(&self) -> Result<&SupMCUModuleDefinition, SupMCUError> {
+        self.definition
+            .as_ref()
+            .ok_or(SupMCUError::MissingDefinitionError)
+    }
+
+    /// Sets the module definition
+    pub fn set_definition(&mut self, def: SupMCUModuleDefinition) {
+        self.address = def.address;
+        self.scpi_prefix.clone_from(&def.scpi_prefix);
+        self.definition = Some(def);
+    }
+
+    /// Sets the module definition from a template, the definition of another module of the
+    /// same type, keeping the module's address.
+    ///
+    /// This skips discovering modules whose telemetry layout is known, see
+    /// [`SupMCUModule::verify_template`] to check that the template fits.
+    pub fn apply_template(&mut self, template: &SupMCUModuleDefinition) {
+        self.definition = Some(SupMCUModuleDefinition {
+            address: self.address,
+            // Unique names tell modules of the same type apart, so they aren't templated
+            unique_name: None,
+            scpi_prefix: self.scpi_prefix.clone(),
+            ..template.clone()
+        });
+    }
+
+    /// Checks that the module's definition fits it, from its command name and its number of
+    /// telemetry items, failing with `TemplateMismatch` if it doesn't.  This takes two reads,
+    /// rather than the hundreds of a discovery.
+    pub fn verify_template(&mut self) -> Result<(), SupMCUError> {
+        let def = self.get_definition()?.clone();
+        let address = self.address;
+        let mismatch = |what: String| SupMCUError::TemplateMismatch(address, what);
+        let version =
+            self.get_telemetry_by_def(&discovery::PremadeTelemetryDefs::FirmwareVersion.into())?;
+        let name = match version.data.first() {
+            Some(SupMCUValue::Str(version)) => {
+                discovery::command_name(&discovery::sanitize_string(version))?
+            }
+            _ => String::new(),
+        };
+        if name != def.name {
+            return Err(mismatch(format!(
+                "the module is a {name}, not a {}",
+                def.name
+            )));
+        }
+        // Slimmed templates don't have all the items
+        if def.slimmed.as_ref().is_some_and(|s| s.telemetry > 0) {
+            return Ok(());
+        }
+        let counts = self
+            .get_telemetry_by_def(&discovery::PremadeTelemetryDefs::TlmAmount.into())?
+            .data;
+        for (telemetry_type, count, expected) in [
+            (
+                TelemetryType::SupMCU,
+                counts.first(),
+                def.get_supmcu_telemetry().len(),
+            ),
+            (
+                TelemetryType::Module,
+                counts.get(1),
+                def.get_module_telemetry().len(),
+            ),
+        ] {
+            match count {
+                Some(SupMCUValue::U16(count)) if *count as usize == expected => {}
+                Some(SupMCUValue::U16(count)) => {
+                    return Err(mismatch(format!(
+                        "it has {count} {telemetry_type} telemetry items, not {expected}"
+                    )))
+                }
+                _ => {
+                    return Err(mismatch(format!(
+                        "it didn't report its number of {telemetry_type} telemetry items"
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the module is the one referred to by `module`
+    pub fn matches_ref(&self, module: &ModuleRef) -> bool {
+        match module {
+            ModuleRef::Address(address) => self.address == *address,
+            ModuleRef::Name(name) => self
+                .get_definition()
+                .is_ok_and(|d| &d.name == name || d.unique_name.as_ref() == Some(name)),
+        }
+    }
+
+    /// Check if the module fits a particular definition, will match if addr OR cmd_name match
+    pub fn matches(&self, other: &SupMCUModuleDefinition) -> bool {
+        match self.get_definition() {
+            Ok(def) => other.address == def.address || other.name == def.name,
+            Err(_) => false,
+        }
+    }
+
+    /// Retries a failed telemetry request, increasing the response delay each time.
+    ///
+    /// The last error is returned if the max retries is exceeded.
+    async fn retry_nonready_async(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+        resp: Result<SupMCUTelemetry, SupMCUError>,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let Some(max_retries) = self.effective_max_retries() else {
+            return resp;
+        };
+        let mut retries = 0;
+        loop {
+            self.check_read_deadline()?;
+            self.stats.retries += 1;
+            self.send_command(self.last_cmd.clone())?;
+            self.async_rt
+                .sleep(Duration::from_secs_f64(
+                    self.response_delay() as f64 + RETRY_TIME_INCREMENT * retries as f64,
+                ))
+                .await;
+            let resp = self.read_telemetry_response(def);
+            match &resp {
+                Err(e) if e.is_retryable() => {
+                    debug!("{}: {e}", self.get_definition()?.name);
+                    retries += 1;
+                    if retries > max_retries {
+                        debug!("Max retries exceeded, returning the last error");
+                        break resp;
+                    }
+                    debug!("Retrying...");
+                }
+                _ => break resp,
+            }
+        }
+    }
+
+    fn retry_nonready(
+        &mut self,
+        def: &SupMCUTelemetryDefinition,
+        resp: Result<SupMCUTelemetry, SupMCUError>,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        self.retry_nonready_with(resp, |module| module.read_telemetry_response(def))
+    }
+
+    /// Retries a failed telemetry request like [`SupMCUModule::retry_nonready`], reading each
+    /// response with `read`
+    fn retry_nonready_with<R>(
+        &mut self,
+        resp: Result<R, SupMCUError>,
+        mut read: impl FnMut(&mut Self) -> Result<R, SupMCUError>,
+    ) -> Result<R, SupMCUError> {
+        let Some(max_retries) = self.effective_max_retries() else {
+            return resp;
+        };
+        let mut retries = 0;
+        loop {
+            self.check_read_deadline()?;
+            self.stats.retries += 1;
+            self.send_command(self.last_cmd.clone())?;
+            self.async_rt.sleep_blocking(time::Duration::from_secs_f64(
+                self.response_delay() as f64 + RETRY_TIME_INCREMENT * retries as f64,
+            ));
+            let resp = read(self);
+            match &resp {
+                Err(e) if e.is_retryable() => {
+                    debug!("{}: {e}", self.get_definition()?.name);
+                    retries += 1;
+                    if retries > max_retries {
+                        debug!("Max retries exceeded, returning the last error");
+                        break resp;
+                    }
+                    debug!("Retrying...");
+                }
+                _ => break resp,
+            }
+        }
+    }
+
+    /// Reads the number of SupMCU and module telemetry items the module has, without
+    /// discovering the items
+    pub fn telemetry_counts(&mut self) -> Result<(u16, u16), SupMCUError> {
+        match self.read_counts(discovery::PremadeTelemetryDefs::TlmAmount)?[..] {
+            [supmcu, module] => Ok((supmcu, module)),
+            ref counts => Err(ParsingError::InvalidBytes(format!(
+                "Expected 2 telemetry counts, got {counts:?}"
+            ))
+            .into()),
+        }
+    }
+
+    /// Reads the number of commands the module has, without discovering the commands
+    pub fn command_count(&mut self) -> Result<u16, SupMCUError> {
+        match self.read_counts(discovery::PremadeTelemetryDefs::CmdAmount)?[..] {
+            [commands] => Ok(commands),
+            ref counts => Err(ParsingError::InvalidBytes(format!(
+                "Expected 1 command count, got {counts:?}"
+            ))
+            .into()),
+        }
+    }
+
+    /// Reads one of the discovery telemetry items made of counts
+    fn read_counts(
+        &mut self,
+        item: discovery::PremadeTelemetryDefs,
+    ) -> Result<Vec<u16>, SupMCUError> {
+        let def: SupMCUTelemetryDefinition = item.into();
+        self.get_telemetry_by_def(&def)?
+            .data
+            .into_iter()
+            .map(|v| match v {
+                SupMCUValue::U16(count) => Ok(count),
+                v => Err(SupMCUError::UnexpectedValue(def.name.clone(), v)),
+            })
+            .collect()
+    }
+
+    /// Checks whether the module answers a small telemetry request with a ready response.
+    ///
+    /// This doesn't need a module definition, so it can be used before discovery.
+    pub async fn ping_async(&mut self) -> bool {
+        let def: SupMCUTelemetryDefinition = discovery::PremadeTelemetryDefs::CmdAmount.into();
+        let _transaction = self.address_lock.lock_async().await;
+        if self.request_telemetry_by_def(&def).is_err() {
+            return false;
+        }
+        self.i2c_delay_async().await;
+        self.read_telemetry_response(&def).is_ok()
+    }
+
+    /// Pings the module with exponential backoff until it is ready, `deadline` passes, or
+    /// `stop` returns true.  Returns how long it took the module to become ready.
+    async fn ping_until_ready<F: Fn() -> bool>(
+        &mut self,
+        deadline: Instant,
+        stop: F,
+    ) -> Option<Duration> {
+        let start = Instant::now();
+        let mut backoff = PING_BACKOFF_MIN;
+        loop {
+            if self.ping_async().await {
+                return Some(start.elapsed());
+            }
+            let now = Instant::now();
+            if now >= deadline || stop() {
+                return None;
+            }
+            trace!(
+                "{:#04X} not responding, retrying in {backoff:?}",
+                self.address
+            );
+            self.async_rt.sleep(backoff.min(deadline - now)).await;
+            backoff = (backoff * 2).min(PING_BACKOFF_MAX);
+        }
+    }
+
+    /// Returns the address
+    pub fn get_address(&self) -> u16 {
+        self.address
+    }
+
+    /// Registers a custom decoder for the telemetry item called `name`.
+    ///
+    /// The decoder overrides the format-based parsing of the item's data, which allows
+    /// handling application-specific encodings (packed dates, fixed-point values, etc.).
+    pub fn register_decoder<S, F>(&mut self, name: S, decoder: F)
+    where
+        S: Into<String>,
+        F: Fn(&[u8]) -> Result<SupMCUTelemetryData, ParsingError> + Send + Sync + 'static,
+    {
+        self.decoders.insert(name.into(), Arc::new(decoder));
+    }
+
+    /// Starts keeping the last `depth` readings of a telemetry item.
+    ///
+    /// Every successful read of the item is recorded.  Enabling an already enabled history
+    /// clears it.
+    pub fn enable_history<K: Into<TelemetryKey>>(&mut self, key: K, depth: usize) {
+        let key = key.into();
+        self.history.insert(key, TelemetryHistory::new(key, depth));
+    }
+
+    /// Stops keeping readings of a telemetry item and discards its history
+    pub fn disable_history<K: Into<TelemetryKey>>(&mut self, key: K) {
+        self.history.remove(&key.into());
+    }
+
+    /// Returns the recorded readings of a telemetry item, oldest first
+    pub fn history<K: Into<TelemetryKey>>(&self, key: K) -> &VecDeque<HistoricSample> {
+        static NO_HISTORY: VecDeque<HistoricSample> = VecDeque::new();
+        self.history
+            .get(&key.into())
+            .map_or(&NO_HISTORY, |history| history.samples())
+    }
+
+    /// Discards the recorded readings of a telemetry item, keeping its history enabled
+    pub fn clear_history<K: Into<TelemetryKey>>(&mut self, key: K) {
+        if let Some(history) = self.history.get_mut(&key.into()) {
+            history.clear();
+        }
+    }
+
+    /// Computes the trend of the first value of a telemetry item over the last `window` readings
+    pub fn trend<K: Into<TelemetryKey>>(&self, key: K, window: usize) -> Option<Trend> {
+        self.trend_of(key, window, 0)
+    }
+
+    /// Computes the trend of the value at `element` of a telemetry item over the last `window` readings
+    pub fn trend_of<K: Into<TelemetryKey>>(
+        &self,
+        key: K,
+        window: usize,
+        element: usize,
+    ) -> Option<Trend> {
+        self.history.get(&key.into())?.trend(window, element)
+    }
+
+    /// Returns copies of all the enabled histories, e.g. for including in a snapshot
+    pub fn export_history(&self) -> Vec<TelemetryHistory> {
+        self.history.values().cloned().collect()
+    }
+
+    /// Sets how responses are read and commands written when the I2C backend can report the
+    /// number of bytes it moved, see [`CountedTransport`].
+    ///
+    /// `I2CDevice::read` is meant to fill the whole buffer, but nonconforming backends may
+    /// return fewer bytes, leaving zeros that would parse as valid data.  With a counted read,
+    /// short reads fail with `ParsingError::InvalidBytes` instead.
+    ///
+    /// If an adapter splits a write, the module may only get the start of a command, e.g.
+    /// `PIM:CHAN 1` of `PIM:CHAN 1,OFF`.  With a counted write, a partial write is cancelled
+    /// by terminating it with a bare newline, and fails with `PartialWrite`.  Commands aren't
+    /// sent again, as they may not be safe to repeat, but telemetry requests are, up to the
+    /// module's retries.
+    ///
+    /// Linux I2C devices don't need one: the kernel sends a transfer as a single message,
+    /// which either completes or fails.
+    pub fn set_counted_transport<C: CountedTransport<T> + 'static>(&mut self, transport: C) {
+        self.counted = Some(Arc::new(transport));
+    }
+
+    /// Goes back to assuming every transfer moves all of its bytes, see
+    /// [`SupMCUModule::set_counted_transport`]
+    pub fn clear_counted_transport(&mut self) {
+        self.counted = None;
+    }
+
+    /// Returns true if the module answers a firmware version request with a valid version
+    /// string, see [`SupMCUMaster::is_supmcu`]
+    fn has_version_string(&mut self) -> bool {
+        match self.get_telemetry_by_def(&discovery::PremadeTelemetryDefs::FirmwareVersion.into()) {
+            Ok(tlm) => matches!(
+                tlm.data.first(),
+                Some(SupMCUValue::Str(version)) if discovery::is_version_string(version)
+            ),
+            Err(e) => {
+                debug!("{:#04X}: no version string: {e}", self.address);
+                false
+            }
+        }
+    }
+
+    /// Sets (or clears, with `None`) the most bytes the I2C adapter reads in one transfer.
+    ///
+    /// Modules have no limit by default, adapters that can't read long responses at once need
+    /// one, e.g. [`DEFAULT_MAX_TRANSFER_LEN`].  Responses over the limit are read in several
+    /// transfers if the definition sets `continued_reads`, otherwise reading them fails with
+    /// `TransferTooLong`.
+    pub fn set_max_transfer_len(&mut self, limit: Option<usize>) {
+        self.max_transfer_len = limit;
+    }
+
+    /// Returns the most bytes the I2C adapter reads in one transfer, if it has a limit
+    pub fn max_transfer_len(&self) -> Option<usize> {
+        self.max_transfer_len
+    }
+
+    /// Removes the custom decoder for the telemetry item called `name`, if there is one
+    pub fn unregister_decoder(&mut self, name: &str) -> Option<TelemetryDecoder> {
+        self.decoders.remove(name)
+    }
+}
+
+impl<T> Debug for SupMCUModule<T>
+where
+    T: I2CDevice + Send + Sync,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SupMCUModule")
+            .field("address", &self.address)
+            .field("response_delay", &self.response_delay())
+            .field("max_retries", &self.max_retries)
+            .field("last_cmd", &self.last_cmd)
+            .finish()
+    }
+}
+
+impl SupMCUModule<LinuxI2CDevice> {
+    /// Creates a new SupMCUModule
+    pub fn new(device: &str, address: u16, max_retries: Option<u8>) -> Result<Self, SupMCUError> {
+        let dev =
+            LinuxI2CDevice::new(device, address).map_err(|error| SupMCUError::I2CDevError {
+                device: String::from(device),
+                address,
+                error,
+            })?;
+        Ok(SupMCUModule::from_device(dev, address, max_retries))
+    }
+
+    /// Creates a new SupMCUModule from a SupMCUModuleDefinition
+    pub fn new_from_def(
+        device: &str,
+        max_retries: Option<u8>,
+        def: SupMCUModuleDefinition,
+    ) -> Result<Self, SupMCUError> {
+        let address = def.address;
+        let dev =
+            LinuxI2CDevice::new(device, def.address).map_err(|error| SupMCUError::I2CDevError {
+                device: String::from(device),
+                address,
+                error,
+            })?;
+        let mut module = SupMCUModule::from_device(dev, address, max_retries);
+        module.set_definition(def);
+        Ok(module)
+    }
+}
+
+/// A reference to a module on the bus, by command name or I2C address
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ModuleRef {
+    Name(String),
+    Address(u16),
+}
+
+impl From<u16> for ModuleRef {
+    fn from(address: u16) -> Self {
+        ModuleRef::Address(address)
+    }
+}
+
+impl From<&str> for ModuleRef {
+    fn from(name: &str) -> Self {
+        ModuleRef::Name(name.to_string())
+    }
+}
+
+impl From<&SupMCUModuleDefinition> for ModuleRef {
+    fn from(def: &SupMCUModuleDefinition) -> Self {
+        ModuleRef::Address(def.address)
+    }
+}
+
+impl From<&ModuleRef> for SupMCUError {
+    fn from(module: &ModuleRef) -> Self {
+        match module {
+            ModuleRef::Name(name) => SupMCUError::UnknownModuleName(name.clone()),
+            ModuleRef::Address(address) => SupMCUError::ModuleNotFound(String::new(), *address),
+        }
+    }
+}
+
+/// Counts of a module's telemetry reads, see [`SupMCUModule::stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ReadStats {
+    /// Ready responses that were read and parsed
+    pub successes: u64,
+    /// Reads that failed for reasons other than a non-ready response
+    pub failures: u64,
+    /// Requests re-sent after a non-ready response
+    pub retries: u64,
+    /// Non-ready responses
+    pub nonready: u64,
+}
+
+impl ReadStats {
+    fn record<T>(&mut self, resp: &Result<T, SupMCUError>) {
+        match resp {
+            Ok(_) => self.successes += 1,
+            Err(SupMCUError::NonReadyError(..)) => self.nonready += 1,
+            Err(_) => self.failures += 1,
+        }
+    }
+}
+
+/// Time a module has spent transferring data on the bus
+#[derive(Clone, Debug, Default)]
+struct BusUsage {
+    /// When each transaction started and how long it took, oldest first
+    transactions: VecDeque<(Instant, Duration)>,
+    /// The time of the last request and response of each telemetry item
+    item_costs: HashMap<TelemetryKey, Duration>,
+    /// The time of the last write, which the next read is a response to
+    last_write: Duration,
+}
+
+impl BusUsage {
+    fn record(&mut self, start: Instant, busy: Duration) {
+        self.transactions.push_back((start, busy));
+        while self
+            .transactions
+            .front()
+            .is_some_and(|(at, _)| start.saturating_duration_since(*at) > MAX_UTILIZATION_WINDOW)
+        {
+            self.transactions.pop_front();
+        }
+    }
+
+    fn record_write(&mut self, start: Instant) {
+        self.last_write = start.elapsed();
+        self.record(start, self.last_write);
+    }
+
+    fn record_read(&mut self, start: Instant, def: &SupMCUTelemetryDefinition) {
+        let busy = start.elapsed();
+        self.record(start, busy);
+        self.item_costs.insert(def.into(), self.last_write + busy);
+    }
+
+    /// Returns the time spent in transactions that started in the `window` before `now`
+    fn busy_time(&self, now: Instant, window: Duration) -> Duration {
+        self.transactions
+            .iter()
+            .rev()
+            .take_while(|(at, _)| now.saturating_duration_since(*at) <= window)
+            .map(|(_, busy)| *busy)
+            .sum()
+    }
+}
+
+/// The fraction of time spent transferring data on the bus over a window
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct BusUtilization {
+    pub window: Duration,
+    /// The fraction of the window all modules spent on the bus
+    pub total: f64,
+    /// The fraction of the window each module spent on the bus, by address
+    pub modules: Vec<(u16, f64)>,
+}
+
+/// The predicted bus cost of polling a telemetry item
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ItemEstimate {
+    pub address: u16,
+    pub key: TelemetryKey,
+    pub period: Duration,
+    /// The time a request and response take on the bus
+    pub cost: Duration,
+    /// Whether the cost was measured, rather than estimated from the response size
+    pub measured: bool,
+    /// The fraction of bus time polling the item uses
+    pub utilization: f64,
+}
+
+/// The predicted bus utilization of a polling schedule, see [`SupMCUMaster::estimate_schedule`]
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ScheduleEstimate {
+    pub items: Vec<ItemEstimate>,
+    /// The fraction of bus time the whole schedule uses
+    pub utilization: f64,
+    pub ceiling: f64,
+    /// Whether the schedule uses more than `ceiling` of the bus time
+    pub exceeds_ceiling: bool,
+}
+
+impl std::ops::Add for ReadStats {
+    type Output = ReadStats;
+
+    fn add(self, other: ReadStats) -> ReadStats {
+        ReadStats {
+            successes: self.successes + other.successes,
+            failures: self.failures + other.failures,
+            retries: self.retries + other.retries,
+            nonready: self.nonready + other.nonready,
+        }
+    }
+}
+
+/// The power state of a module, see [`SupMCUModule::set_power_state`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PowerState {
+    #[default]
+    Awake,
+    Asleep,
+}
+
+/// The metadata refreshed by [`SupMCUMaster::refresh_metadata`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefreshScope {
+    /// Default values of simulatable telemetry items
+    SimDefaults,
+    /// Lengths of telemetry items containing strings
+    Lengths,
+    /// Names of all telemetry items
+    Names,
+}
+
+impl RefreshScope {
+    /// Whether a telemetry item has metadata in this scope
+    fn includes(&self, def: &SupMCUTelemetryDefinition) -> bool {
+        match self {
+            RefreshScope::SimDefaults => def.simulatable(),
+            RefreshScope::Lengths => def.format.get_byte_length().is_none(),
+            RefreshScope::Names => true,
+        }
+    }
+}
+
+/// Overrides of a module's read settings for a single call, see
+/// [`SupMCUModule::get_telemetry_by_def_with`].  Unset fields use the module's configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadOptions {
+    /// Seconds to wait for the response, instead of the definition's response delay
+    pub response_delay: Option<f32>,
+    /// Times a non-ready response is retried, instead of the module's max retries
+    pub max_retries: Option<u8>,
+    /// Stops retrying non-ready responses with `BusTimeout` once this has passed since the
+    /// call started.  A transfer in progress isn't interrupted.
+    pub timeout: Option<Duration>,
+    /// Whether to check the response's checksum, `false` skipping it
+    pub verify_checksum: Option<bool>,
+    /// Keeps the bytes of the response in [`TelemetryRead::raw`]
+    pub keep_raw: bool,
+}
+
+/// A reading from [`SupMCUModule::get_telemetry_by_def_with`]
+#[derive(Clone, Debug)]
+pub struct TelemetryRead {
+    pub telemetry: SupMCUTelemetry,
+    /// The bytes of the response, including the header and footer, if
+    /// [`ReadOptions::keep_raw`] was set
+    pub raw: Option<Vec<u8>>,
+}
+
+/// The outcome of a [`SupMCUModule::dump_telemetry_to`] call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpInfo {
+    pub bytes_written: usize,
+    /// Module timestamp from the response header
+    pub timestamp: u32,
+    /// Whether the response checksum was valid, or `None` if checksums aren't checked
+    pub checksum_valid: Option<bool>,
+}
+
+/// The outcome of a [`SupMCUMaster::refresh_metadata`] call
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    /// Items refreshed during this call
+    pub refreshed: usize,
+    /// Items that failed during this call, and will be retried after a cool-down
+    pub failed: usize,
+    /// Items left before the current pass over the bus is complete
+    pub remaining: usize,
+}
+
+/// The condition [`SupMCUMaster::wait_for_bus`] waits for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusReadiness {
+    /// Every module of the master is ready
+    AllModules,
+    /// At least one module is ready
+    AnyModule,
+    /// At least `n` modules are ready
+    AtLeast(usize),
+    /// The modules at these addresses are ready
+    Addresses(Vec<u16>),
+    /// The modules with these names are ready (requires definitions)
+    Names(Vec<String>),
+}
+
+/// The outcome of [`SupMCUMaster::wait_for_bus`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadinessReport {
+    /// Addresses of modules that became ready, and how long it took them
+    pub ready: Vec<(u16, Duration)>,
+    /// Addresses of modules that didn't become ready
+    pub not_ready: Vec<u16>,
+}
+
+/// A telemetry item of an interleaved sweep, see
+/// [`SupMCUMaster::get_all_telemetry_interleaved_until`]
+#[derive(Debug)]
+pub struct SweepItem {
+    pub definition: SupMCUTelemetryDefinition,
+    /// The result of reading the item, or `None` if the sweep was cancelled before it
+    pub result: Option<Result<SupMCUTelemetry, SupMCUError>>,
+}
+
+impl SweepItem {
+    /// Returns true if the item was read, successfully or not
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some()
+    }
+}
+
+/// The outcome of [`SupMCUModule::get_all_telemetry_if_changed`]
+#[derive(Debug, PartialEq)]
+pub enum TelemetryChange {
+    /// The change counter hasn't moved, so nothing else was read
+    Unchanged,
+    /// The telemetry changed, or the module has no change counter, and was swept
+    Changed(HashMap<String, Json<SupMCUTelemetryData>>),
+}
+
+/**
+A struct to represent an I2C bus of SupMCU modules
+
+This basically just holds a vec of [`SupMCUModule`]s and an async runtime.
+The async runtime is used to run async functions like [`SupMCUModule.get_telemetry_by_def_async`](SupMCUModule#memthod.get_telemetry_by_def_async)
+from withing a sync context.  This allows you to take advantage of the speedups
+that come from accessing modules in parallel without having to deal with an entire
+async application.  Applications that already have a tokio runtime can have the master use
+it instead, see [`SupMCUMaster::set_runtime_handle`].
+
+```no_run
+# use supmcu_rs::SupMCUError;
+use supmcu_rs::supmcu::{
+    SupMCUMaster,
+    parsing::*
+};
+use std::{
+    time::Duration,
+    path::Path
+};
+
+// Initialize master from definition file
+let mut master = SupMCUMaster::new("/dev/i2c-1", None)?;
+master.load_def_file(Path::new("definition.json"))?;
+
+// Get the first telemetry item  (version string) from each module
+let versions = master
+    .for_each(|module| module.get_telemetry_async(TelemetryType::SupMCU, 0))
+    .into_iter()
+    .collect::<Result<Vec<SupMCUTelemetry>, SupMCUError>>()?;
+
+for version in versions {
+    // Prints the version string from each module in the definition.json file
+    println!("{}", &version.data[0]);
+}
+# Ok::<(), SupMCUError>(())
+```
+**/
+/// A SupMCUMaster is used to communicate with SupMCU modules over an I2C bus
+pub struct SupMCUMaster<I: I2CDevice + Send + Sync> {
+    /// The [`SupMCUModule`]s available to control.  Add modules with
+    /// [`SupMCUMaster::add_module`].
+    pub modules: Vec<SupMCUModule<I>>,
+    device: String,
+    def_file: Option<PathBuf>,
+    load_report: MasterLoadReport,
+    refresh_cursor: usize,
+    refresh_cooldowns: HashMap<(u16, TelemetryType, usize), Instant>,
+    dirty: bool,
+    /// When the modules were last discovered
+    discovered_at: Option<SystemTime>,
+    utilization_window: Duration,
+    utilization_ceiling: f64,
+    macros: Vec<BusMacro>,
+    /// Definitions of known module types, see [`SupMCUMaster::add_template`]
+    templates: Vec<SupMCUModuleDefinition>,
+    /// The open change set, see [`SupMCUMaster::begin_changes`]
+    changes: Option<Arc<Mutex<PendingChanges>>>,
+    /// Whether [`SupMCUMaster::emergency_stop`] may be used
+    emergency_allowed: bool,
+    emergency_deadline: Duration,
+    session: Option<SessionRecorder>,
+    /// The audit log raw writes are recorded to, shared with the modules
+    audit: Option<AuditRecorder>,
+    /// The sequence number of the next audit record while the audit log is disabled
+    audit_seq: u64,
+    ops: OpsMaskHandle,
+    pause: PauseHandle,
+    anomaly_max_bytes: usize,
+    /// The categories of errors bundles are generated for while polling, and the hook
+    /// receiving them
+    anomaly_hook: Option<(Vec<ErrorCategory>, AnomalyHook)>,
+    /// How many modules are accessed at once by the methods running on all modules
+    concurrency_limit: Option<usize>,
+    rt: MasterRuntime,
+}
+
+/// The tokio runtime a [`SupMCUMaster`] runs modules on, its own or an application's, see
+/// [`SupMCUMaster::set_runtime_handle`]
+struct MasterRuntime {
+    handle: runtime::Handle,
+    /// The runtime the master created, if it isn't using an application's
+    owned: Option<runtime::Runtime>,
+}
+
+impl MasterRuntime {
+    /// Creates the master's own runtime.  An application's runtime is only used once it's
+    /// set explicitly, even if the master is created from one of its tasks.
+    fn new() -> Result<Self, SupMCUError> {
+        let rt = runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        Ok(MasterRuntime {
+            handle: rt.handle().clone(),
+            owned: Some(rt),
+        })
+    }
+
+    fn shared(handle: runtime::Handle) -> Self {
+        MasterRuntime {
+            handle,
+            owned: None,
+        }
+    }
+
+    /// Runs a future to completion.  Called from a task of a multi-threaded runtime, the
+    /// worker thread is handed over to the runtime's other tasks while blocking.
+    fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        match runtime::Handle::try_current() {
+            Ok(_) => tokio::task::block_in_place(|| self.handle.block_on(fut)),
+            Err(_) => self.handle.block_on(fut),
+        }
+    }
+}
+
+impl Drop for MasterRuntime {
+    /// Shuts the master's own runtime down without waiting, which unlike dropping it is
+    /// allowed from async code.  Nothing is left running on it, as everything it runs is
+    /// blocked on.
+    fn drop(&mut self) {
+        if let Some(rt) = self.owned.take() {
+            rt.shutdown_background();
+        }
+    }
+}
+
+/// The outcome of initializing a [`SupMCUMaster`] from module definitions
+#[derive(Debug, Default)]
+pub struct MasterLoadReport {
+    /// Addresses of the modules that were initialized
+    pub loaded: Vec<u16>,
+    /// Definitions of the modules that couldn't be initialized, and why
+    pub failed: Vec<(SupMCUModuleDefinition, SupMCUError)>,
+}
+
+/// The outcome of running the self-tests of all modules
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SelfTestSummary {
+    pub reports: Vec<SelfTestReport>,
+    /// Modules whose self-test couldn't be run, and why
+    pub errors: Vec<(u16, SupMCUError)>,
+}
+
+impl SelfTestSummary {
+    /// Returns true if every module ran its self-test and passed
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty() && self.reports.iter().all(|r| r.passed)
+    }
+}
+
+impl<I> SupMCUMaster<I>
+where
+    I: I2CDevice + Send + Sync,
+{
+    /// Creates a master for already created modules
+    pub(crate) fn from_modules(
+        mut modules: Vec<SupMCUModule<I>>,
+        device: String,
+    ) -> Result<Self, SupMCUError> {
+        let ops = OpsMaskHandle::default();
+        let pause = PauseHandle::default();
+        for module in modules.iter_mut() {
+            module.ops = ops.subscribe();
+            module.paused = pause.subscribe();
+        }
+        share_address_locks(&mut modules);
+        Ok(SupMCUMaster {
+            modules,
+            device,
+            def_file: None,
+            load_report: MasterLoadReport::default(),
+            refresh_cursor: 0,
+            refresh_cooldowns: HashMap::new(),
+            dirty: false,
+            discovered_at: None,
+            utilization_window: DEFAULT_UTILIZATION_WINDOW,
+            utilization_ceiling: DEFAULT_UTILIZATION_CEILING,
+            macros: vec![],
+            templates: vec![],
+            changes: None,
+            emergency_allowed: false,
+            emergency_deadline: ops::DEFAULT_EMERGENCY_DEADLINE,
+            session: None,
+            audit: None,
+            audit_seq: 0,
+            ops,
+            pause,
+            anomaly_max_bytes: anomaly::DEFAULT_MAX_BYTES,
+            anomaly_hook: None,
+            concurrency_limit: None,
+            rt: MasterRuntime::new()?,
+        })
+    }
+
+    /// Builds a master from module definitions, using `open` to create each module.
+    ///
+    /// Duplicate addresses are rejected before any module is opened, unless the modules at an
+    /// address have different SCPI prefixes.  Unless `strict` is set,
+    /// modules that fail to open are skipped and recorded in the [`MasterLoadReport`].
+    fn from_defs<F>(
+        defs: Vec<SupMCUModuleDefinition>,
+        device: String,
+        def_file: Option<PathBuf>,
+        strict: bool,
+        mut open: F,
+    ) -> Result<Self, SupMCUError>
+    where
+        F: FnMut(SupMCUModuleDefinition) -> Result<SupMCUModule<I>, SupMCUError>,
+    {
+        let mut addresses = HashSet::new();
+        for def in defs.iter() {
+            if !addresses.insert((def.address, def.scpi_prefix.as_deref())) {
+                return Err(SupMCUError::DuplicateAddress(def.address));
+            }
+        }
+
+        let mut modules = vec![];
+        let mut load_report = MasterLoadReport::default();
+        for def in defs {
+            match open(def.clone()) {
+                Ok(module) => {
+                    load_report.loaded.push(module.address);
+                    modules.push(module);
+                }
+                Err(e) if !strict => {
+                    warn!("Skipping {def}: {e}");
+                    load_report.failed.push((def, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let mut master = SupMCUMaster::from_modules(modules, device)?;
+        master.def_file = def_file;
+        master.load_report = load_report;
+        master.disambiguate_names();
+        Ok(master)
+    }
+
+    /// Adds a module to the master.
+    ///
+    /// The module shares the master's operations rules, pause, session recording and audit
+    /// log, and the transactions of modules at the same address, unlike a module pushed to
+    /// [`SupMCUMaster::modules`] directly.
+    pub fn add_module(&mut self, mut module: SupMCUModule<I>) {
+        module.ops = self.ops.subscribe();
+        module.paused = self.pause.subscribe();
+        module.audit.clone_from(&self.audit);
+        if let Some(recorder) = &self.session {
+            if let Ok(mut log) = recorder.lock() {
+                log.session.modules.push(SessionModule {
+                    address: module.address,
+                    max_retries: module.max_retries,
+                    definition: module.definition.clone(),
+                });
+            }
+            module.recorder = Some(recorder.clone());
+        }
+        self.modules.push(module);
+        share_address_locks(&mut self.modules);
+    }
+
+    /// Returns the report of which modules were (or weren't) initialized from definitions
+    pub fn load_report(&self) -> &MasterLoadReport {
+        &self.load_report
+    }
+
+    /// Discover the definitions for each stored module
+    pub fn discover_modules(&mut self) -> Result<(), SupMCUError> {
+        log::info!(
+            "Discovering modules: {:?}",
+            self.modules
+                .iter()
+                .map(|m| format!("{:#04X}", m.address))
+                .collect::<Vec<String>>()
+        );
+        self.for_each(|module: &mut SupMCUModule<I>| module.discover())
+            .into_iter()
+            // Consolidating the vec of results into one result
+            .collect::<Result<Vec<()>, SupMCUError>>()?;
+        self.discovered_at = Some(SystemTime::now());
+        self.disambiguate_names();
+        Ok(())
+    }
+
+    /// Discover the definitions for each stored module, only running the phases enabled in `options`
+    pub fn discover_modules_with_options(
+        &mut self,
+        options: DiscoverOptions,
+    ) -> Result<(), SupMCUError> {
+        self.apply_prefixes(&options.prefixes)?;
+        self.for_each(|module: &mut SupMCUModule<I>| module.discover_with_options(options.clone()))
+            .into_iter()
+            .collect::<Result<Vec<()>, SupMCUError>>()?;
+        self.discovered_at = Some(SystemTime::now());
+        self.disambiguate_names();
+        Ok(())
+    }
+
+    /// Discovers the definitions of all modules, offering each discovered telemetry item to
+    /// `reviewer` before it's kept.
+    ///
+    /// Items are offered module by module, in the order they were discovered, and are kept,
+    /// renamed, given another format or left out as the reviewer decides, see
+    /// [`review::ReviewDecisions`] to record and replay decisions.  Standard items the library
+    /// reads itself are kept even if the reviewer leaves them out or renames them, see
+    /// [`review::INTERNAL_ITEMS`].
+    pub fn discover_modules_with_review<F>(&mut self, mut reviewer: F) -> Result<(), SupMCUError>
+    where
+        F: FnMut(ReviewItem) -> ReviewDecision,
+    {
+        self.discover_modules()?;
+        for module in self.modules.iter_mut() {
+            let address = module.address;
+            let def = module.get_definition_mut()?;
+            let mut kept = vec![];
+            for item in std::mem::take(&mut def.telemetry) {
+                let decision = reviewer(ReviewItem {
+                    address,
+                    module: def.name.clone(),
+                    definition: item.clone(),
+                });
+                let decision = if decision.breaks_internal_item(&item) {
+                    warn!(
+                        "{address:#04X}: rejected {decision:?} of {}, the library reads it itself",
+                        item.name
+                    );
+                    ReviewDecision::Accept
+                } else {
+                    decision
+                };
+                let counter = item.telemetry_type == TelemetryType::SupMCU
+                    && def.change_counter == Some(item.idx);
+                match decision.apply(item) {
+                    Some(item) => kept.push(item),
+                    None if counter => def.change_counter = None,
+                    None => (),
+                }
+            }
+            def.telemetry = kept;
+        }
+        Ok(())
+    }
+
+    /// Discover an individual module's definition
+    pub fn discover_module(&mut self, module: &SupMCUModuleDefinition) -> Result<(), SupMCUError> {
+        let i = self.def_index(module)?;
+        let m = &mut self.modules[i];
+        self.rt.block_on(async { m.discover().await })?;
+        self.discovered_at = Some(SystemTime::now());
+        self.disambiguate_names();
+        Ok(())
+    }
+
+    /// Waits until the modules required by `required` respond to pings, or `timeout` passes.
+    ///
+    /// Each module is pinged with exponential backoff, so this is suitable for waiting for a
+    /// freshly powered stack to boot.  If the requirement isn't met in time a `BusTimeout`
+    /// listing the required modules that never answered is returned.
+    pub fn wait_for_bus(
+        &mut self,
+        timeout: Duration,
+        required: BusReadiness,
+    ) -> Result<ReadinessReport, SupMCUError> {
+        let addresses: Vec<u16> = self.modules.iter().map(|m| m.address).collect();
+        let (needed, count) = match &required {
+            BusReadiness::AllModules => (addresses.clone(), addresses.len()),
+            BusReadiness::AnyModule => (vec![], 1),
+            BusReadiness::AtLeast(n) => (vec![], *n),
+            BusReadiness::Addresses(addrs) => (addrs.clone(), addrs.len()),
+            BusReadiness::Names(names) => {
+                let addrs = names
+                    .iter()
+                    .map(|name| {
+                        let i = self.module_index(&ModuleRef::Name(name.clone()))?;
+                        Ok(self.modules[i].address)
+                    })
+                    .collect::<Result<Vec<u16>, SupMCUError>>()?;
+                let count = addrs.len();
+                (addrs, count)
+            }
+        };
+        for addr in needed.iter() {
+            if !addresses.contains(addr) {
+                return Err(SupMCUError::ModuleNotFound(String::new(), *addr));
+            }
+        }
+        let is_met =
+            |ready: &[u16]| ready.len() >= count && needed.iter().all(|addr| ready.contains(addr));
+
+        let deadline = Instant::now() + timeout;
+        let ready = Arc::new(Mutex::new(vec![]));
+        let is_met = &is_met;
+        let times = self.for_each(|module| {
+            let ready = ready.clone();
+            async move {
+                let address = module.address;
+                // The lock is never held across a panic, so it can't be poisoned
+                #[allow(clippy::unwrap_used)]
+                let stop = || is_met(ready.lock().unwrap().as_slice());
+                let time = module.ping_until_ready(deadline, stop).await;
+                if time.is_some() {
+                    #[allow(clippy::unwrap_used)]
+                    ready.lock().unwrap().push(address);
+                }
+                (address, time)
+            }
+        });
+
+        let mut report = ReadinessReport::default();
+        for (address, time) in times {
+            match time {
+                Some(time) => report.ready.push((address, time)),
+                None => report.not_ready.push(address),
+            }
+        }
+        let ready: Vec<u16> = report.ready.iter().map(|(addr, _)| *addr).collect();
+        if is_met(ready.as_slice()) {
+            Ok(report)
+        } else if needed.is_empty() {
+            Err(SupMCUError::BusTimeout(report.not_ready))
+        } else {
+            Err(SupMCUError::BusTimeout(
+                needed.into_iter().filter(|a| !ready.contains(a)).collect(),
+            ))
+        }
+    }
+
+    /// Get module definitions of this SupMCUMaster
+    pub fn get_definitions(&self) -> Result<Vec<SupMCUModuleDefinition>, SupMCUError> {
+        self.modules
+            .iter()
+            .map(|module| Ok(module.get_definition()?.clone()))
+            .collect::<Result<Vec<SupMCUModuleDefinition>, SupMCUError>>()
+    }
+
+    /// Returns the definitions discovered longer than `max_age` ago, or at an unknown time,
+    /// which should be checked against the modules' current firmware, see
+    /// [`SupMCUModuleDefinition::age`]
+    pub fn stale_definitions(&self, max_age: Duration) -> Vec<&SupMCUModuleDefinition> {
+        self.modules
+            .iter()
+            .filter_map(|module| module.get_definition().ok())
+            .filter(|def| def.age().is_none_or(|age| age > max_age))
+            .collect()
+    }
+
+    /// Getting all the telemetry for each stored module
+    pub fn get_all_telemetry(&mut self) -> Vec<Vec<Result<SupMCUTelemetry, SupMCUError>>> {
+        let telemetry = self.for_each(|module| async {
+            module
+                .get_all_telemetry_async()
+                .await
+                .unwrap_or_else(|e| vec![Err(e)])
+        });
+        for err in telemetry
+            .iter()
+            .flatten()
+            .filter_map(|tlm| tlm.as_ref().err())
+        {
+            self.report_anomaly(err);
+        }
+        telemetry
+    }
+
+    /// Gets all the telemetry of each module like [`SupMCUMaster::get_all_telemetry`], but
+    /// reading one item per module per turn.
+    ///
+    /// Modules with many items don't end up alone on the bus at the end of the sweep, which
+    /// keeps partial results evenly distributed, see
+    /// [`SupMCUMaster::get_all_telemetry_interleaved_until`].
+    pub fn get_all_telemetry_interleaved(
+        &mut self,
+    ) -> Vec<Vec<Result<SupMCUTelemetry, SupMCUError>>> {
+        self.get_all_telemetry_interleaved_until(&CancellationToken::new())
+            .into_iter()
+            .map(|items| match items {
+                Ok(items) => items.into_iter().filter_map(|item| item.result).collect(),
+                Err(e) => vec![Err(e)],
+            })
+            .collect()
+    }
+
+    /// Gets all the telemetry of each module, one item per module per turn, until the sweep
+    /// completes or `cancel` is cancelled.
+    ///
+    /// Each turn reads the next item of every module in parallel.  Cancellation is checked
+    /// between turns, so when interrupted every module has had the same number of items read
+    /// (or all of them, if it has fewer).  Items that weren't read have no result.
+    #[allow(clippy::unwrap_used)]
+    pub fn get_all_telemetry_interleaved_until(
+        &mut self,
+        cancel: &CancellationToken,
+    ) -> Vec<Result<Vec<SweepItem>, SupMCUError>> {
+        let mut sweeps: Vec<Result<Vec<SweepItem>, SupMCUError>> = self
+            .modules
+            .iter()
+            .map(|module| {
+                Ok(module
+                    .get_definition()?
+                    .sweep_order()
+                    .into_iter()
+                    .map(|definition| SweepItem {
+                        definition,
+                        result: None,
+                    })
+                    .collect())
+            })
+            .collect();
+        let turns = sweeps
+            .iter()
+            .filter_map(|sweep| sweep.as_ref().ok())
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0);
+        for turn in 0..turns {
+            if cancel.is_cancelled() {
+                debug!("Interleaved sweep cancelled after {turn} of {turns} turns");
+                break;
+            }
+            let defs: Vec<Option<SupMCUTelemetryDefinition>> = sweeps
+                .iter()
+                .map(|sweep| Some(sweep.as_ref().ok()?.get(turn)?.definition.clone()))
+                .collect();
+            let semaphore = self.concurrency_semaphore();
+            let semaphore = &semaphore;
+            let results = self.rt.block_on(async {
+                let (_, outputs) = TokioScope::scope_and_block(|s| {
+                    let modules = self.modules.iter_mut().zip(defs.iter()).enumerate();
+                    for (i, (module, def)) in modules {
+                        if let Some(def) = def {
+                            s.spawn(async move {
+                                let _permit = semaphore.acquire().await;
+                                (i, module.get_telemetry_by_def_async(def).await)
+                            });
+                        }
+                    }
+                });
+                // Only fails if a task panicked, in which case the panic is propagated
+                outputs.into_iter().map(|t| t.unwrap()).collect::<Vec<_>>()
+            });
+            // Tasks finish in any order, so results are matched to modules by index
+            for (i, result) in results {
+                if let Ok(sweep) = &mut sweeps[i] {
+                    if let Err(e) = &result {
+                        self.report_anomaly(e);
+                    }
+                    sweep[turn].result = Some(result);
+                }
+            }
+        }
+        sweeps
+    }
+
+    /// Returns the index of the module referred to by `module`.
+    ///
+    /// Names are matched against unique names first, then command names.  A command name
+    /// shared by several modules fails with `AmbiguousModule`.
+    fn module_index(&self, module: &ModuleRef) -> Result<usize, SupMCUError> {
+        let name = match module {
+            ModuleRef::Address(address) => {
+                let matching: Vec<usize> = self
+                    .modules
+                    .iter()
+                    .positions(|m| m.address == *address)
+                    .collect();
+                return match matching.as_slice() {
+                    [i] => Ok(*i),
+                    [] => Err(module.into()),
+                    // Modules sharing an address are told apart by name
+                    _ => Err(SupMCUError::AmbiguousModule(
+                        format!("{address:#04X}"),
+                        matching.iter().map(|i| self.modules[*i].label()).collect(),
+                    )),
+                };
+            }
+            ModuleRef::Name(name) => name,
+        };
+        let defs = || {
+            self.modules
+                .iter()
+                .enumerate()
+                .filter_map(|(i, m)| Some((i, m.get_definition().ok()?)))
+        };
+        if let Some((i, _)) = defs().find(|(_, d)| d.unique_name.as_ref() == Some(name)) {
+            return Ok(i);
+        }
+        let matching: Vec<(usize, u16)> = defs()
+            .filter(|(_, d)| &d.name == name)
+            .map(|(i, d)| (i, d.address))
+            .collect();
+        match matching.as_slice() {
+            [(i, _)] => Ok(*i),
+            [] => Err(module.into()),
+            _ => Err(SupMCUError::AmbiguousModule(
+                name.clone(),
+                matching
+                    .iter()
+                    .map(|(_, address)| format!("{address:#04x}"))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Returns the index of the module a definition is for, by address or else by name
+    fn def_index(&self, module: &SupMCUModuleDefinition) -> Result<usize, SupMCUError> {
+        let same_prefix = self.modules.iter().position(|m| {
+            m.address == module.address
+                && m.scpi_prefix.is_some()
+                && m.scpi_prefix == module.scpi_prefix
+        });
+        if let Some(i) = same_prefix {
+            return Ok(i);
+        }
+        self.module_index(&ModuleRef::Address(module.address))
+            .or_else(|_| self.module_index(&ModuleRef::Name(module.display_name().into())))
+            .map_err(|e| match e {
+                SupMCUError::ModuleNotFound(..) | SupMCUError::UnknownModuleName(_) => {
+                    SupMCUError::ModuleNotFound(module.name.clone(), module.address)
+                }
+                e => e,
+            })
+    }
+
+    /// Returns the module referred to by `module`, see [`SupMCUMaster::module_by_ref_mut`]
+    pub fn module_by_ref(&self, module: &ModuleRef) -> Result<&SupMCUModule<I>, SupMCUError> {
+        let i = self.module_index(module)?;
+        Ok(&self.modules[i])
+    }
+
+    /// Returns the module referred to by `module`.
+    ///
+    /// Names are matched against unique names first, then command names.  A command name
+    /// shared by several modules fails with `AmbiguousModule`.
+    pub fn module_by_ref_mut(
+        &mut self,
+        module: &ModuleRef,
+    ) -> Result<&mut SupMCUModule<I>, SupMCUError> {
+        let i = self.module_index(module)?;
+        Ok(&mut self.modules[i])
+    }
+
+    /// Gives the modules at each address the SCPI prefixes listed for it, in order, see
+    /// [`DiscoverOptions::prefixes`].
+    ///
+    /// Fails with `PrefixMismatch` if an address doesn't have a module for each prefix.
+    fn apply_prefixes(&mut self, prefixes: &BTreeMap<u16, Vec<String>>) -> Result<(), SupMCUError> {
+        for (address, prefixes) in prefixes {
+            let count = self
+                .modules
+                .iter()
+                .filter(|m| m.address == *address)
+                .count();
+            if count != prefixes.len() {
+                return Err(SupMCUError::PrefixMismatch(*address, prefixes.len(), count));
+            }
+            let modules = self.modules.iter_mut().filter(|m| m.address == *address);
+            for (module, prefix) in modules.zip(prefixes) {
+                if module.scpi_prefix() != Some(prefix.as_str()) {
+                    module.set_scpi_prefix(Some(prefix.clone()));
+                    self.dirty = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives modules sharing a command name a unique name ending with their address, e.g.
+    /// `BIM_0x41`, and their SCPI prefix if they have one, e.g. `BIM_0x41_A`, unless they
+    /// already have one
+    fn disambiguate_names(&mut self) {
+        let counts = self
+            .modules
+            .iter()
+            .filter_map(|m| m.get_definition().ok())
+            .counts_by(|d| d.name.clone());
+        for module in self.modules.iter_mut() {
+            let address = module.address;
+            let Ok(def) = module.get_definition_mut() else {
+                continue;
+            };
+            if def.unique_name.is_none() && counts.get(&def.name).is_some_and(|n| *n > 1) {
+                let unique_name = match &def.scpi_prefix {
+                    Some(prefix) => format!("{}_{address:#04x}_{prefix}", def.name),
+                    None => format!("{}_{address:#04x}", def.name),
+                };
+                warn!(
+                    "Several modules are called {}, naming {address:#04X} {unique_name}",
+                    def.name
+                );
+                def.unique_name = Some(unique_name);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Gives a module a unique name, e.g. to tell apart modules of the same model by their
+    /// role, and saves the definitions if they were loaded from a file and no change set is
+    /// open, see [`SupMCUMaster::begin_changes`].
+    ///
+    /// Fails with `DuplicateModuleName` if another module already has the name as its unique
+    /// or command name.
+    pub fn rename_module(
+        &mut self,
+        current: &ModuleRef,
+        new_name: &str,
+    ) -> Result<(), SupMCUError> {
+        let i = self.module_index(current)?;
+        let taken = self.modules.iter().enumerate().any(|(j, m)| {
+            j != i
+                && m.get_definition()
+                    .is_ok_and(|d| d.name == new_name || d.unique_name.as_deref() == Some(new_name))
+        });
+        if taken {
+            return Err(SupMCUError::DuplicateModuleName(new_name.to_string()));
+        }
+        let module = &mut self.modules[i];
+        if let Some(changes) = open_changes(&mut self.changes) {
+            let def = module.get_definition()?;
+            changes::lock(changes).record(
+                module.address,
+                Some(def),
+                "unique_name",
+                def.unique_name.as_deref().unwrap_or("-"),
+                new_name,
+            );
+        }
+        module.get_definition_mut()?.unique_name = Some(new_name.to_string());
+        self.dirty = true;
+        if self.changes.is_none() {
+            self.save_if_dirty()?;
+        }
+        Ok(())
+    }
+
+    /// Runs a closure for a specific module, found by address or else by name
+    pub fn with_module<F: FnOnce(&SupMCUModule<I>) -> O, O: Send + 'static>(
+        &self,
+        module: &SupMCUModuleDefinition,
+        f: F,
+    ) -> Result<O, SupMCUError> {
+        let i = self.def_index(module)?;
+        Ok(f(&self.modules[i]))
+    }
+
+    /// Runs a closure for a specific module, mutable
+    pub fn with_module_mut<F: FnOnce(&mut SupMCUModule<I>) -> O, O: Send + 'static>(
+        &mut self,
+        module: &SupMCUModuleDefinition,
+        f: F,
+    ) -> Result<O, SupMCUError> {
+        let i = self.def_index(module)?;
+        Ok(f(&mut self.modules[i]))
+    }
+
+    /// Reads a telemetry item from a module, discovering its definition first if it's missing,
+    /// see [`SupMCUModule::get_telemetry_autodiscover`].
+    ///
+    /// Definitions are marked dirty if an item was discovered.
+    pub fn get_telemetry_autodiscover(
+        &mut self,
+        module: &ModuleRef,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Result<SupMCUTelemetry, SupMCUError> {
+        let i = self.module_index(module)?;
+        let m = &mut self.modules[i];
+        let known = m.get_definition().map_or(0, |def| def.telemetry.len());
+        let resp = self
+            .rt
+            .block_on(m.get_telemetry_autodiscover(telemetry_type, idx));
+        if m.get_definition().map_or(0, |def| def.telemetry.len()) != known {
+            self.dirty = true;
+        }
+        resp
+    }
+
+    /// Sends a command to a module
+    pub fn send_command(
+        &mut self,
+        module: &SupMCUModuleDefinition,
+        command: &str,
+    ) -> Result<(), SupMCUError> {
+        let module_command = |module: &mut SupMCUModule<I>| module.send_command(command);
+        self.with_module_mut(module, module_command)?
+    }
+
+    /// Sends different commands to different modules in parallel.
+    ///
+    /// Each command is sent to the module its [`ModuleRef`] refers to, and commands for the
+    /// same module are sent in order.  The results are returned in the order of `commands`.
+    pub fn send_commands(
+        &mut self,
+        commands: &[(ModuleRef, String)],
+    ) -> Vec<(ModuleRef, Result<(), SupMCUError>)> {
+        let (targets, mut lookup_errors): (Vec<Option<u16>>, Vec<Option<SupMCUError>>) = commands
+            .iter()
+            .map(|(module, _)| match self.module_index(module) {
+                Ok(i) => (Some(self.modules[i].address), None),
+                Err(e) => (None, Some(e)),
+            })
+            .unzip();
+        let targets = &targets;
+
+        let mut results: Vec<Option<Result<(), SupMCUError>>> = self
+            .for_each(|module| async move {
+                let mut sent = vec![];
+                for (i, (_, cmd)) in commands.iter().enumerate() {
+                    if targets[i] == Some(module.address) {
+                        sent.push((i, module.send_command(cmd)));
+                    }
+                }
+                sent
+            })
+            .into_iter()
+            .flatten()
+            .fold(
+                commands.iter().map(|_| None).collect(),
+                |mut results: Vec<_>, (i, result)| {
+                    results[i] = Some(result);
+                    results
+                },
+            );
+
+        commands
+            .iter()
+            .zip(results.iter_mut().zip(lookup_errors.iter_mut()))
+            .map(|((module, _), (result, lookup_error))| {
+                let result = result
+                    .take()
+                    .unwrap_or_else(|| Err(lookup_error.take().unwrap_or_else(|| module.into())));
+                (module.clone(), result)
+            })
+            .collect()
+    }
+
+    /// Registers a custom decoder for a telemetry item of a module
+    pub fn register_decoder<S, F>(
+        &mut self,
+        module: &SupMCUModuleDefinition,
+        name: S,
+        decoder: F,
+    ) -> Result<(), SupMCUError>
+    where
+        S: Into<String>,
+        F: Fn(&[u8]) -> Result<SupMCUTelemetryData, ParsingError> + Send + Sync + 'static,
+    {
+        self.with_module_mut(module, |m| m.register_decoder(name, decoder))
+    }
+
+    /// Updates a module's response delay.
+    ///
+    /// The definition file is saved right away, unless the module doesn't persist response
+    /// delay changes, see [`SupMCUModule::set_persist_response_delay`], or a change set is
+    /// open, see [`SupMCUMaster::begin_changes`].
+    pub fn response_delay(
+        &mut self,
+        module: &SupMCUModuleDefinition,
+        delay: f32,
+    ) -> Result<(), SupMCUError> {
+        check_response_delay(&module.name, delay)?;
+        let changes = open_changes(&mut self.changes).cloned();
+        let persist = self.with_module_mut(module, |m| -> Result<bool, SupMCUError> {
+            let def = m
+                .definition
+                .as_mut()
+                .ok_or(SupMCUError::MissingDefinitionError)?;
+            if let Some(changes) = changes {
+                changes::lock(&changes).record(
+                    m.address,
+                    Some(def),
+                    "response_delay",
+                    def.response_delay,
+                    delay,
+                );
+            }
+            def.response_delay = delay;
+            Ok(m.persist_response_delay)
+        })??;
+        match &self.def_file {
+            Some(file) if persist && self.changes.is_none() => self.save_def_file(file)?,
+            _ => self.dirty = true,
+        }
+        Ok(())
+    }
+
+    /// Sets the most bytes the I2C adapter reads in one transfer for all modules, see
+    /// [`SupMCUModule::set_max_transfer_len`]
+    pub fn set_max_transfer_len(&mut self, limit: Option<usize>) {
+        for module in self.modules.iter_mut() {
+            module.set_max_transfer_len(limit);
+        }
+    }
+
+    /// Sets whether response delay changes of all modules are saved immediately, see
+    /// [`SupMCUModule::set_persist_response_delay`]
+    pub fn set_persist_response_delay(&mut self, enabled: bool) {
+        for module in self.modules.iter_mut() {
+            module.set_persist_response_delay(enabled);
+        }
+    }
+
+    /// Refreshes telemetry metadata of the modules in place, within a time budget.
+    ///
+    /// Items are visited round-robin across modules, and each call resumes where the
+    /// previous one stopped, so repeated calls make a full pass over the bus without
+    /// blocking for long.  At least one item is refreshed per call.  Items that fail are
+    /// skipped and retried on a later pass after a cool-down.  Refreshed definitions are
+    /// marked dirty, see [`SupMCUMaster::save_if_dirty`].
+    pub fn refresh_metadata(&mut self, scope: RefreshScope, budget: Duration) -> RefreshReport {
+        // (module, telemetry type, idx) of each item in scope, interleaved across modules
+        let per_module: Vec<Vec<(TelemetryType, usize)>> = self
+            .modules
+            .iter()
+            .map(|m| {
+                m.get_definition().map_or(vec![], |d| {
+                    d.telemetry
+                        .iter()
+                        .filter(|t| scope.includes(t))
+                        .map(|t| (t.telemetry_type, t.idx))
+                        .collect()
+                })
+            })
+            .collect();
+        let longest = per_module.iter().map(Vec::len).max().unwrap_or(0);
+        let items: Vec<(usize, TelemetryType, usize)> = (0..longest)
+            .flat_map(|i| {
+                per_module
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(m, tlm)| tlm.get(i).map(|(t, idx)| (m, *t, *idx)))
+            })
+            .collect();
+
+        if self.refresh_cursor >= items.len() {
+            self.refresh_cursor = 0;
+        }
+        let start = Instant::now();
+        let mut report = RefreshReport::default();
+        while self.refresh_cursor < items.len()
+            && (report.refreshed + report.failed == 0 || start.elapsed() < budget)
+        {
+            let (m, telemetry_type, idx) = items[self.refresh_cursor];
+            self.refresh_cursor += 1;
+            let module = &mut self.modules[m];
+            let key = (module.address, telemetry_type, idx);
+            if self
+                .refresh_cooldowns
+                .get(&key)
+                .is_some_and(|until| Instant::now() < *until)
+            {
+                continue;
+            }
+            match module.refresh_item(telemetry_type, idx, scope) {
+                Ok(()) => {
+                    self.refresh_cooldowns.remove(&key);
+                    self.dirty = true;
+                    report.refreshed += 1;
+                }
+                Err(e) => {
+                    warn!(
+                        "{:#04X}: failed to refresh {telemetry_type} item {idx}: {e}",
+                        key.0
+                    );
+                    self.refresh_cooldowns
+                        .insert(key, Instant::now() + REFRESH_COOLDOWN);
+                    report.failed += 1;
+                }
+            }
+        }
+        report.remaining = items.len() - self.refresh_cursor;
+        report
+    }
+
+    /// Returns true if definitions have changed since they were last saved by [`SupMCUMaster::save_if_dirty`]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Saves the definitions to the definition file they were loaded from, if they have changed.
+    ///
+    /// Nothing is saved while a change set is open, so the file only gets committed changes,
+    /// see [`SupMCUMaster::begin_changes`].  Returns whether the file was written.
+    pub fn save_if_dirty(&mut self) -> Result<bool, SupMCUError> {
+        if open_changes(&mut self.changes).is_some() {
+            return Ok(false);
+        }
+        match &self.def_file {
+            Some(file) if self.dirty => {
+                self.save_def_file(file)?;
+                self.dirty = false;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Opens a change set, grouping the definition changes made through
+    /// [`SupMCUMaster::response_delay`], [`SupMCUMaster::rename_module`] and
+    /// [`SupMCUMaster::apply_template`] until it's committed or rolled back.
+    ///
+    /// The definition file isn't saved while the change set is open, see
+    /// [`ChangeSet::commit`].  Fails with `ChangeSetOpen` if one already is.
+    pub fn begin_changes(&mut self) -> Result<ChangeSet, SupMCUError> {
+        if open_changes(&mut self.changes).is_some() {
+            return Err(SupMCUError::ChangeSetOpen);
+        }
+        let changes = Arc::new(Mutex::new(PendingChanges::new(self.dirty)));
+        self.changes = Some(changes.clone());
+        Ok(ChangeSet(changes))
+    }
+
+    /// Returns the read statistics of all modules added together
+    pub fn read_stats(&self) -> ReadStats {
+        self.modules
+            .iter()
+            .fold(ReadStats::default(), |total, m| total + *m.stats())
+    }
+
+    /// Starts recording all transactions with the modules to a session file at `path`.
+    ///
+    /// The file is written by [`SupMCUMaster::end_session`], along with the modules'
+    /// definitions as they are now, or when the master is dropped if the session wasn't
+    /// ended.  Starting a new recording writes the current one.
+    pub fn record_session<P: AsRef<Path>>(&mut self, path: P) {
+        let modules = self
+            .modules
+            .iter()
+            .map(|m| SessionModule {
+                address: m.address,
+                max_retries: m.max_retries,
+                definition: m.definition.clone(),
+            })
+            .collect();
+        let recorder = Arc::new(Mutex::new(SessionLog::new(
+            path.as_ref().to_path_buf(),
+            self.device.clone(),
+            modules,
+        )));
+        for module in self.modules.iter_mut() {
+            module.recorder = Some(recorder.clone());
+        }
+        self.session = Some(recorder);
+    }
+
+    /// Starts writing an audit record of every command written to the modules and every
+    /// telemetry item read from them to `sink`, see the [`audit`] module.  Enabling it again
+    /// replaces the sink, and the records continue the numbering of the previous ones.
+    pub fn enable_audit_log<W: Write + Send + 'static>(&mut self, sink: W, format: AuditFormat) {
+        self.start_audit_log(sink, format, 0);
+    }
+
+    /// Starts writing an audit log to the file at `path`, appending to it if it exists.  The
+    /// records continue the numbering of the file's last record, or of the previous records
+    /// of this master if they're further along.
+    pub fn enable_audit_log_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        format: AuditFormat,
+    ) -> Result<(), SupMCUError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let seq = audit::next_seq_in(&mut file)?;
+        self.start_audit_log(file, format, seq);
+        Ok(())
+    }
+
+    /// Starts writing an audit log numbered from `seq`, or from after the previous records
+    /// of the master if they're further along
+    fn start_audit_log<W: Write + Send + 'static>(
+        &mut self,
+        sink: W,
+        format: AuditFormat,
+        seq: u64,
+    ) {
+        let seq = seq.max(self.next_audit_seq());
+        let recorder = Arc::new(Mutex::new(AuditLog::new(sink, format, seq)));
+        for module in self.modules.iter_mut() {
+            module.audit = Some(recorder.clone());
+        }
+        self.audit = Some(recorder);
+    }
+
+    /// Returns the sequence number of the next audit record
+    fn next_audit_seq(&self) -> u64 {
+        match &self.audit {
+            Some(recorder) => audit::lock(recorder).seq,
+            None => self.audit_seq,
+        }
+    }
+
+    /// Stops writing the audit log
+    pub fn disable_audit_log(&mut self) {
+        self.audit_seq = self.next_audit_seq();
+        for module in self.modules.iter_mut() {
+            module.audit = None;
+        }
+        self.audit = None;
+    }
+
+    /// Writes an audit record of a raw write, if audit logging is enabled.  Raw writes bypass
+    /// the modules, so they are recorded as commands to the address with the bytes written.
+    fn audit_raw_write(
+        &self,
+        address: u16,
+        register: Option<u8>,
+        bytes: &[u8],
+        result: Result<(), &SupMCUError>,
+    ) {
+        if let Some(recorder) = &self.audit {
+            let module = self
+                .modules
+                .iter()
+                .find(|m| m.address == address)
+                .and_then(|m| m.definition.as_ref())
+                .map(|d| d.display_name());
+            let payload = match register {
+                Some(register) => format!("raw {register:#04x} {bytes:02x?}"),
+                None => format!("raw {bytes:02x?}"),
+            };
+            let result = result.map(|_| payload);
+            audit::lock(recorder).record(address, module, AuditEvent::Command, result);
+        }
+    }
+
+    /// Stops recording and writes the session file, if a session is being recorded
+    pub fn end_session(&mut self) -> Result<(), SupMCUError> {
+        for module in self.modules.iter_mut() {
+            module.recorder = None;
+        }
+        let Some(recorder) = self.session.take() else {
+            return Ok(());
+        };
+        let mut log = recorder.lock().map_err(|_| {
+            SupMCUError::IoError(std::io::Error::other("session recorder lock was poisoned"))
+        })?;
+        log.save()
+    }
+
+    /// Applies a flight rule restricting the operations of some modules, replacing the
+    /// current one.  Set [`OpsMask::default`] to lift it.
+    ///
+    /// Commands a module isn't allowed fail with `MaskedByOpsRule` without any bus traffic,
+    /// and telemetry streams of masked modules pause until the mask allows them again.  Raw
+    /// I2C access is masked as well, see [`OpsMask::check_raw`].
+    pub fn set_operations_mask(&mut self, mask: OpsMask) {
+        self.ops.set(mask);
+    }
+
+    /// Returns the current operations mask
+    pub fn operations_mask(&self) -> OpsMask {
+        self.ops.get()
+    }
+
+    /// Returns a handle for changing the operations mask while the modules are borrowed,
+    /// e.g. by telemetry streams
+    pub fn operations_mask_handle(&self) -> OpsMaskHandle {
+        self.ops.clone()
+    }
+
+    /// Pauses telemetry collection: telemetry streams and bus pollers finish the reads in
+    /// progress, then wait until [`SupMCUMaster::resume_collection`].
+    ///
+    /// This keeps the bus free for commanding without tearing down the collection.  Direct
+    /// reads and commands aren't affected.
+    pub fn pause_collection(&self) {
+        self.pause.pause();
+    }
+
+    /// Resumes telemetry collection paused by [`SupMCUMaster::pause_collection`]
+    pub fn resume_collection(&self) {
+        self.pause.resume();
+    }
+
+    /// Returns true if telemetry collection is paused
+    pub fn collection_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Returns a handle for pausing and resuming collection while the modules are borrowed,
+    /// e.g. by telemetry streams
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Assembles an error and the state of the module it occurred with into a bundle that
+    /// can be downlinked, see [`anomaly`].
+    ///
+    /// The module is found by the error's address, if it has one.  The bundle is trimmed to
+    /// the cap set with [`SupMCUMaster::set_anomaly_max_bytes`].
+    pub fn anomaly_bundle(&self, err: &SupMCUError) -> AnomalyBundle {
+        let mut bundle = AnomalyBundle::new(err);
+        if let Some(module) = err
+            .address()
+            .and_then(|address| self.modules.iter().find(|m| m.address == address))
+        {
+            bundle.add_module(module);
+        }
+        bundle.trim(self.anomaly_max_bytes);
+        bundle
+    }
+
+    /// Sets the cap on the size of anomaly bundles' JSON, in bytes
+    pub fn set_anomaly_max_bytes(&mut self, max_bytes: usize) {
+        self.anomaly_max_bytes = max_bytes;
+    }
+
+    /// Generates anomaly bundles for errors in `categories` while polling, e.g. in
+    /// [`SupMCUMaster::get_all_telemetry`] or by a [`bus::SupMCUBus`], and passes them to
+    /// `hook`.  Replaces any previous hook.
+    pub fn on_anomaly<F>(&mut self, categories: &[ErrorCategory], hook: F)
+    where
+        F: Fn(AnomalyBundle) + Send + Sync + 'static,
+    {
+        self.anomaly_hook = Some((categories.to_vec(), Arc::new(hook)));
+    }
+
+    /// Stops generating anomaly bundles while polling
+    pub fn clear_anomaly_hook(&mut self) {
+        self.anomaly_hook = None;
+    }
+
+    /// Passes a bundle for `err` to the anomaly hook, if its category is selected
+    pub(crate) fn report_anomaly(&self, err: &SupMCUError) {
+        if let Some((categories, hook)) = &self.anomaly_hook {
+            if categories.contains(&err.category()) {
+                hook(self.anomaly_bundle(err));
+            }
+        }
+    }
+
+    /// Puts all modules in or out of dry-run mode, see [`SupMCUModule::set_dry_run`]
+    #[cfg(any(test, feature = "dry-run"))]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        for module in self.modules.iter_mut() {
+            module.set_dry_run(dry_run);
+        }
+    }
+
+    /// Returns true if any module is in dry-run mode
+    pub fn is_dry_run(&self) -> bool {
+        self.modules.iter().any(|m| m.is_dry_run())
+    }
+
+    /// Sets whether all modules stamp telemetry with the host's wall-clock time, see
+    /// [`SupMCUModule::set_host_timestamps`]
+    pub fn set_host_timestamps(&mut self, enabled: bool) {
+        for module in self.modules.iter_mut() {
+            module.set_host_timestamps(enabled);
+        }
+    }
+
+    /// Adds a macro spanning several modules, replacing any with the same name
+    pub fn add_macro(&mut self, bus_macro: BusMacro) {
+        self.macros.retain(|m| m.name != bus_macro.name);
+        self.macros.push(bus_macro);
+    }
+
+    /// Returns the macros spanning several modules
+    pub fn macros(&self) -> &[BusMacro] {
+        &self.macros
+    }
+
+    /// Runs a macro.
+    ///
+    /// Macros added with [`SupMCUMaster::add_macro`] are run step by step on the modules they
+    /// name.  Otherwise the macro is looked up in the module definitions and run with
+    /// [`SupMCUModule::run_macro`], failing with `AmbiguousMacro` if more than one module has
+    /// it.
+    pub fn run_macro(&mut self, name: &str) -> Result<MacroReport, SupMCUError> {
+        let Some(bus_macro) = self.macros.iter().find(|m| m.name == name).cloned() else {
+            let mut modules = self.modules.iter_mut().filter(|m| {
+                m.get_definition()
+                    .is_ok_and(|d| d.macros.iter().any(|m| m.name == name))
+            });
+            return match (modules.next(), modules.next()) {
+                (Some(module), None) => module.run_macro(name),
+                (Some(_), Some(_)) => Err(SupMCUError::AmbiguousMacro(name.to_string())),
+                (None, _) => Err(SupMCUError::UnknownMacro(name.to_string())),
+            };
+        };
+        let start = Instant::now();
+        let mut report = MacroReport {
+            name: bus_macro.name,
+            steps_run: 0,
+            failure: None,
+            elapsed_ms: 0,
+        };
+        // Every step's module is looked up before any step runs, so that a macro naming an
+        // unknown module fails without running partway
+        let modules = bus_macro
+            .steps
+            .iter()
+            .map(|step| self.module_index(&ModuleRef::Name(step.module.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (idx, (step, i)) in bus_macro.steps.iter().zip(modules).enumerate() {
+            report.steps_run += 1;
+            report.failure = self.modules[i].run_macro_step(idx, &step.step)?;
+            if report.failure.is_some() {
+                break;
+            }
+        }
+        report.elapsed_ms = start.elapsed().as_millis() as u64;
+        Ok(report)
+    }
+
+    /// Allows or forbids [`SupMCUMaster::emergency_stop`], which is forbidden by default since
+    /// it overrides the ops mask and dry-run mode
+    pub fn allow_emergency(&mut self, allowed: bool) {
+        self.emergency_allowed = allowed;
+    }
+
+    /// Sets how long [`SupMCUMaster::emergency_stop`] waits for modules to be safed,
+    /// [`ops::DEFAULT_EMERGENCY_DEADLINE`] by default
+    pub fn set_emergency_deadline(&mut self, deadline: Duration) {
+        self.emergency_deadline = deadline;
+    }
+
+    /// Puts every module in the safe state its definition configures, see
+    /// [`parsing::SafeMode`], as fast as possible.
+    ///
+    /// Modules are safed in parallel, ignoring the ops mask and dry-run mode, and modules
+    /// without a safe mode are left alone.  Modules that aren't safed, including verified, by
+    /// the deadline are reported as such rather than retried.  Fails with
+    /// `EmergencyNotAllowed` unless allowed with [`SupMCUMaster::allow_emergency`].
+    ///
+    /// Each of a module's steps is given the time left until the deadline, see
+    /// [`ops::EmergencyOutcome::DeadlineExceeded`].  I2C transfers block though, so a
+    /// transfer that hangs isn't interrupted, and holds up the report until it returns.
+    pub fn emergency_stop(&mut self) -> Result<EmergencyReport, SupMCUError> {
+        if !self.emergency_allowed {
+            return Err(SupMCUError::EmergencyNotAllowed);
+        }
+        error!(
+            "Emergency stop of modules {:?}",
+            self.modules.iter().map(|m| m.address).collect::<Vec<_>>()
+        );
+        let start = Instant::now();
+        let deadline = start + self.emergency_deadline;
+        let modules = self.for_each(|module| async move {
+            let mut module = Privileged::new(module);
+            let started = Instant::now();
+            let safed = module.enter_safe_mode(deadline);
+            let outcome = match time::timeout_at(time::Instant::from_std(deadline), safed).await {
+                Ok(Ok(outcome)) => outcome,
+                Ok(Err(e)) => EmergencyOutcome::Error {
+                    error: e.to_string(),
+                },
+                Err(_) => EmergencyOutcome::DeadlineExceeded,
+            };
+            ModuleEmergency {
+                address: module.address,
+                module: module
+                    .get_definition()
+                    .ok()
+                    .map(|d| d.display_name().to_string()),
+                outcome,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+            }
+        });
+        let report = EmergencyReport {
+            modules,
+            deadline_ms: self.emergency_deadline.as_millis() as u64,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        };
+        for module in report.unconfirmed() {
+            error!(
+                "{:#04X}: not confirmed safe: {:?}",
+                module.address, module.outcome
+            );
+        }
+        Ok(report)
+    }
+
+    /// Adds the definition of a known module type as a template, replacing any with the same
+    /// command name, see [`SupMCUMaster::apply_template`]
+    pub fn add_template(&mut self, template: SupMCUModuleDefinition) {
+        self.templates.retain(|t| t.name != template.name);
+        self.templates.push(template);
+    }
+
+    /// Adds the definitions in a definition file as templates, e.g. one saved from a
+    /// discovery of known-good hardware
+    pub fn load_templates(&mut self, file: &Path) -> Result<(), SupMCUError> {
+        for template in read_def_file(file)? {
+            self.add_template(template);
+        }
+        Ok(())
+    }
+
+    /// Returns the templates
+    pub fn templates(&self) -> &[SupMCUModuleDefinition] {
+        &self.templates
+    }
+
+    /// Sets the definition of a module from the template called `name` instead of discovering
+    /// it, see [`SupMCUModule::apply_template`].
+    ///
+    /// If `verify` is set, the template is checked with [`SupMCUModule::verify_template`], and
+    /// the module keeps its previous definition if it doesn't fit.
+    pub fn apply_template(
+        &mut self,
+        module: &ModuleRef,
+        name: &str,
+        verify: bool,
+    ) -> Result<(), SupMCUError> {
+        let template = self
+            .templates
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| SupMCUError::UnknownTemplate(name.to_string()))?
+            .clone();
+        let i = self.module_index(module)?;
+        let module = &mut self.modules[i];
+        let previous = module.definition.take();
+        module.apply_template(&template);
+        if verify {
+            if let Err(e) = module.verify_template() {
+                module.definition = previous;
+                return Err(e);
+            }
+        }
+        if let Some(changes) = open_changes(&mut self.changes) {
+            let old = previous.as_ref().map_or("-", |d| d.name.as_str());
+            changes::lock(changes).record(
+                module.address,
+                previous.as_ref(),
+                "definition",
+                old,
+                format!("template {name}"),
+            );
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Sets the window [`SupMCUMaster::bus_utilization`] averages over, from a millisecond to
+    /// a minute
+    pub fn set_utilization_window(&mut self, window: Duration) {
+        self.utilization_window = window.clamp(MIN_UTILIZATION_WINDOW, MAX_UTILIZATION_WINDOW);
+    }
+
+    /// Sets the fraction of bus time above which [`SupMCUMaster::estimate_schedule`] flags a
+    /// schedule
+    pub fn set_utilization_ceiling(&mut self, ceiling: f64) {
+        self.utilization_ceiling = ceiling;
+    }
+
+    /// Returns the fraction of the utilization window spent writing to and reading from
+    /// modules, excluding the delays between requests and responses
+    pub fn bus_utilization(&self) -> BusUtilization {
+        let window = self.utilization_window;
+        let modules: Vec<(u16, f64)> = self
+            .modules
+            .iter()
+            .map(|m| {
+                let busy = m.bus_time(window);
+                (m.address, busy.as_secs_f64() / window.as_secs_f64())
+            })
+            .collect();
+        BusUtilization {
+            window,
+            total: modules.iter().map(|(_, u)| u).sum(),
+            modules,
+        }
+    }
+
+    /// Predicts the bus utilization of polling each telemetry item `(address, key)` once
+    /// per period.
+    ///
+    /// Items that have been read use their measured cost, others are estimated from
+    /// the size of their request and response.  An item with a zero period is polled back
+    /// to back, taking all of the bus time.
+    pub fn estimate_schedule(
+        &self,
+        items: &[(u16, TelemetryKey, Duration)],
+    ) -> Result<ScheduleEstimate, SupMCUError> {
+        let items = items
+            .iter()
+            .map(|(address, key, period)| {
+                let module = self
+                    .modules
+                    .iter()
+                    .find(|m| m.address == *address)
+                    .ok_or_else(|| SupMCUError::ModuleNotFound(String::new(), *address))?;
+                let def = module
+                    .get_definition()?
+                    .telemetry
+                    .iter()
+                    .find(|d| TelemetryKey::from(*d) == *key)
+                    .ok_or(SupMCUError::TelemetryIndexError(
+                        key.telemetry_type,
+                        key.idx,
+                    ))?;
+                let (cost, measured) = module.estimate_item_cost(def)?;
+                Ok(ItemEstimate {
+                    address: *address,
+                    key: *key,
+                    period: *period,
+                    cost,
+                    measured,
+                    utilization: match period.is_zero() {
+                        true => 1.0,
+                        false => cost.as_secs_f64() / period.as_secs_f64(),
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, SupMCUError>>()?;
+        let utilization = items.iter().map(|i| i.utilization).sum();
+        Ok(ScheduleEstimate {
+            items,
+            utilization,
+            ceiling: self.utilization_ceiling,
+            exceeds_ceiling: utilization > self.utilization_ceiling,
+        })
+    }
+
+    /// Returns the power state each module was last commanded into, by address
+    pub fn power_states(&self) -> Vec<(u16, PowerState)> {
+        self.modules
+            .iter()
+            .map(|m| (m.address, m.power_state()))
+            .collect()
+    }
+
+    /// Puts every module to sleep except the ones named in `names`, e.g. when entering safe mode.
+    ///
+    /// Modules without a definition can't be identified, so they are left awake.  Returns the
+    /// result of commanding each module that was put to sleep.
+    pub fn sleep_all_except<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Vec<(u16, Result<(), SupMCUError>)> {
+        self.modules
+            .iter_mut()
+            .filter(|m| {
+                m.get_definition()
+                    .is_ok_and(|def| !names.iter().any(|n| n.as_ref() == def.name))
+            })
+            .map(|m| (m.address, m.set_power_state(PowerState::Asleep)))
+            .collect()
+    }
+
+    /// Runs the self-tests of all modules in parallel, see [`SupMCUModule::run_self_test`]
+    pub fn run_all_self_tests(&mut self, timeout: Duration) -> SelfTestSummary {
+        self.for_each(|module| async move {
+            (module.address, module.run_self_test_async(timeout).await)
+        })
+        .into_iter()
+        .fold(
+            SelfTestSummary::default(),
+            |mut summary, (address, result)| {
+                match result {
+                    Ok(report) => summary.reports.push(report),
+                    Err(e) => summary.errors.push((address, e)),
+                }
+                summary
+            },
+        )
+    }
+
+    /// Checks that raw I2C access to `address` won't bypass a managed SupMCU module.
+    ///
+    /// Returns `ManagedAddress` if a module has the address, unless `force` is set.
+    pub fn check_raw_address(&self, address: u16, force: bool) -> Result<(), SupMCUError> {
+        if !force && self.modules.iter().any(|m| m.get_address() == address) {
+            Err(SupMCUError::ManagedAddress(address))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs an async function for each module and returns their results in a Vec, in the order
+    /// of the modules.
+    ///
+    /// At most [`SupMCUMaster::set_concurrency_limit`] modules are run at once.
+    pub fn for_each<'a, F, T, O>(&'a mut self, f: F) -> Vec<O>
+    where
+        F: Fn(&'a mut SupMCUModule<I>) -> T,
+        T: Future<Output = O> + Send,
+        O: Send + 'static,
+    {
+        let limit = self.concurrency_limit.unwrap_or(Semaphore::MAX_PERMITS);
+        self.for_each_limited(limit, f)
+            .into_iter()
+            .map(|(_, output)| output)
+            .collect()
+    }
+
+    /// Runs an async function for each module, at most `limit` at once, and returns their
+    /// results tagged with the modules' addresses, in the order of the modules
+    #[allow(clippy::unwrap_used)]
+    pub fn for_each_limited<'a, F, T, O>(&'a mut self, limit: usize, f: F) -> Vec<(u16, O)>
+    where
+        F: Fn(&'a mut SupMCUModule<I>) -> T,
+        T: Future<Output = O> + Send,
+        O: Send + 'static,
+    {
+        let semaphore = Semaphore::new(limit.clamp(1, Semaphore::MAX_PERMITS));
+        let semaphore = &semaphore;
+        // Wait for the entire async block to finish
+        self.rt.block_on(async {
+            // We need a scope so that self doesn't have to be moved
+            let (_, outputs) = TokioScope::scope_and_block(|s| {
+                for (i, module) in self.modules.iter_mut().enumerate() {
+                    let address = module.address;
+                    let task = f(module);
+                    // Spawn the provided function within the scope
+                    s.spawn(async move {
+                        let _permit = semaphore.acquire().await;
+                        (i, address, task.await)
+                    });
+                }
+            });
+            // Unwrap the Result<O, JoinError>.  This only fails if a task panicked,
+            // in which case the panic is propagated.  Tasks finish in any order, so the
+            // results are sorted back into the order of the modules.
+            outputs
+                .into_iter()
+                .map(|t| t.unwrap())
+                .sorted_by_key(|(i, _, _)| *i)
+                .map(|(_, address, output)| (address, output))
+                .collect::<Vec<(u16, O)>>()
+        })
+    }
+
+    /// Sets how many modules are accessed at once by [`SupMCUMaster::for_each`] and the methods
+    /// built on it, such as discovery, sweeps and [`SupMCUMaster::wait_for_bus`].  `None`,
+    /// the default, runs all modules at once.
+    ///
+    /// The kernel's I2C driver serializes transfers anyway, so on large buses a limit trades
+    /// no throughput for less latency jitter.
+    pub fn set_concurrency_limit(&mut self, limit: Option<usize>) {
+        self.concurrency_limit = limit;
+    }
+
+    /// Runs the modules on an application's tokio runtime instead of the master's own, which
+    /// is shut down.
+    ///
+    /// A master created from a task of a runtime still uses its own until this is called.
+    /// The runtime has to be multi-threaded with the time driver enabled, and the master's
+    /// blocking methods, like [`SupMCUMaster::for_each`], can then be called from its tasks:
+    /// they hand the worker thread over to the other tasks while blocking.  On a
+    /// current-thread runtime, use the modules' async methods directly instead, e.g. with
+    /// [`for_each_limited_async`].
+    pub fn set_runtime_handle(&mut self, handle: runtime::Handle) {
+        self.rt = MasterRuntime::shared(handle);
+    }
+
+    /// Returns a handle to the tokio runtime the modules run on, the master's own or the one
+    /// set with [`SupMCUMaster::set_runtime_handle`], e.g. to spawn an application's tasks on
+    pub fn runtime_handle(&self) -> runtime::Handle {
+        self.rt.handle.clone()
+    }
+
+    /// Returns the semaphore bounding how many modules are accessed at once
+    fn concurrency_semaphore(&self) -> Semaphore {
+        let limit = self.concurrency_limit.unwrap_or(Semaphore::MAX_PERMITS);
+        Semaphore::new(limit.clamp(1, Semaphore::MAX_PERMITS))
+    }
+
+    /// Load a SupMCU master from a definition file instead of discovering modules.
+    ///
+    /// The definitions are given to the modules in order.  Fails with `TooManyDefinitions`,
+    /// without changing any module, if the file has more definitions than there are modules.
+    pub fn load_def_file(&mut self, file: &Path) -> Result<(), SupMCUError> {
+        let defs = read_def_file(file)?;
+        if defs.len() > self.modules.len() {
+            return Err(SupMCUError::TooManyDefinitions {
+                definitions: defs.len(),
+                modules: self.modules.len(),
+            });
+        }
+        for (def, module) in defs.into_iter().zip(self.modules.iter_mut()) {
+            module.set_definition(def);
+        }
+        // The definitions may put several modules at one address
+        share_address_locks(&mut self.modules);
+        self.def_file = Some(file.to_path_buf());
+        self.disambiguate_names();
+        Ok(())
+    }
+
+    /// Save the modules definitions to a definition file
+    ///
+    /// Fails with `ChangeSetOpen` while a change set is open, as the definitions have changes
+    /// that may still be rolled back, see [`SupMCUMaster::begin_changes`].
+    pub fn save_def_file<P: AsRef<Path>>(&self, file: P) -> Result<(), SupMCUError> {
+        if self
+            .changes
+            .as_ref()
+            .is_some_and(|c| Arc::strong_count(c) > 1)
+        {
+            return Err(SupMCUError::ChangeSetOpen);
+        }
+        write_def_file(file.as_ref(), &self.get_definitions()?)
+    }
+
+    /// Saves the modules' definitions to a definition file, slimmed with `options`, see
+    /// [`SupMCUModuleDefinition::slim`]
+    pub fn save_slim_def_file<P: AsRef<Path>>(
+        &self,
+        file: P,
+        options: SlimOptions,
+    ) -> Result<(), SupMCUError> {
+        let defs: Vec<SupMCUModuleDefinition> = self
+            .get_definitions()?
+            .iter()
+            .map(|def| def.slim(options.clone()))
+            .collect();
+        write_def_file(file.as_ref(), &defs)
+    }
+
+    /// Returns the definitions of all modules along with the device path, discovery time and
+    /// crate version, as a self-describing record of the bus.
+    pub fn export_archive(&self) -> Result<ArchiveDoc, SupMCUError> {
+        Ok(ArchiveDoc {
+            metadata: ArchiveMetadata {
+                device: self.device.clone(),
+                discovered_at: self.discovered_at.map(unix_secs),
+                exported_at: unix_secs(SystemTime::now()),
+                crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            modules: self.get_definitions()?,
+        })
+    }
+
+    /// Loads the definitions in an archive made by [`SupMCUMaster::export_archive`].
+    ///
+    /// Definitions are matched to modules by address.  If a definition has no module at
+    /// its address, `ModuleNotFound` is returned and no definitions are loaded.
+    pub fn import_archive(&mut self, archive: ArchiveDoc) -> Result<(), SupMCUError> {
+        for def in archive.modules.iter() {
+            check_response_delay(&def.name, def.response_delay)?;
+        }
+        if let Some(def) = archive
+            .modules
+            .iter()
+            .find(|def| !self.modules.iter().any(|m| m.address == def.address))
+        {
+            return Err(SupMCUError::ModuleNotFound(def.name.clone(), def.address));
+        }
+        for def in archive.modules {
+            if let Some(module) = self.modules.iter_mut().find(|m| m.address == def.address) {
+                module.set_definition(def);
+            }
+        }
+        self.discovered_at = archive
+            .metadata
+            .discovered_at
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        self.disambiguate_names();
+        Ok(())
+    }
+}
+
+/// Gives the modules sharing an I2C address a shared lock serializing their transactions
+fn share_address_locks<I>(modules: &mut [SupMCUModule<I>])
+where
+    I: I2CDevice + Send + Sync,
+{
+    let counts = modules.iter().counts_by(|m| m.address);
+    let mut locks: HashMap<u16, Arc<AsyncMutex<()>>> = HashMap::new();
+    for module in modules.iter_mut() {
+        module.address_lock = match counts[&module.address] {
+            1 => AddressLock::default(),
+            _ => AddressLock::shared(locks.entry(module.address).or_default().clone()),
+        };
+    }
+}
+
+/// Runs an async function for each of `modules`, at most `limit` at once, and returns their
+/// results tagged with the modules' addresses, in the order of the modules.
+///
+/// Unlike [`SupMCUMaster::for_each_limited`] the functions are polled by the caller's task
+/// instead of being spawned, so this works on any executor, see [`async_rt`].
+pub async fn for_each_limited_async<'a, I, F, T, O>(
+    modules: &'a mut [SupMCUModule<I>],
+    limit: usize,
+    f: F,
+) -> Vec<(u16, O)>
+where
+    I: I2CDevice + Send + Sync,
+    F: Fn(&'a mut SupMCUModule<I>) -> T,
+    T: Future<Output = O>,
+{
+    use futures::StreamExt;
+    stream::iter(modules.iter_mut())
+        .map(|module| {
+            let address = module.address;
+            let task = f(module);
+            async move { (address, task.await) }
+        })
+        .buffered(limit.max(1))
+        .collect()
+        .await
+}
+
+impl SupMCUMaster<ReplayDevice> {
+    /// Creates a master whose modules answer with the responses in a session file recorded
+    /// with [`SupMCUMaster::record_session`].
+    ///
+    /// With `strict`, any command or read that differs from the recorded order fails with
+    /// `SessionDivergence`.  The modules start with the definitions they had when recording
+    /// started.
+    pub fn from_session<P: AsRef<Path>>(path: P, strict: bool) -> Result<Self, SupMCUError> {
+        let session = Session::load(path)?;
+        let modules = session
+            .modules
+            .iter()
+            .map(|m| {
+                let dev = ReplayDevice::new(&session, m.address, strict);
+                let mut module = SupMCUModule::from_device(dev, m.address, m.max_retries);
+                module.device_error = Some(|e| match e {
+                    SupMCUError::SessionDivergence(address, msg) => {
+                        Some(SupMCUError::SessionDivergence(*address, msg.clone()))
+                    }
+                    _ => None,
+                });
+                module.definition.clone_from(&m.definition);
+                if let Some(def) = &m.definition {
+                    module.scpi_prefix.clone_from(&def.scpi_prefix);
+                }
+                module
+            })
+            .collect();
+        SupMCUMaster::from_modules(modules, session.device.clone())
+    }
+}
+
+/// Information about where and when an [`ArchiveDoc`] was made
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveMetadata {
+    /// The I2C device the modules are on
+    pub device: String,
+    /// When the modules were discovered, in seconds since the Unix epoch.  `None` if the
+    /// definitions were loaded rather than discovered.
+    pub discovered_at: Option<u64>,
+    /// When the archive was made, in seconds since the Unix epoch
+    pub exported_at: u64,
+    /// The version of supmcu-rs that made the archive
+    pub crate_version: String,
+}
+
+/// The definitions of all modules on a bus along with metadata, for mission records
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveDoc {
+    pub metadata: ArchiveMetadata,
+    pub modules: Vec<SupMCUModuleDefinition>,
+}
+
+/// Returns the number of seconds between the Unix epoch and `time`
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The layout of a TOML definition file, which can't have an array at the top level
+#[cfg(feature = "toml")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TomlDefinitions {
+    modules: Vec<SupMCUModuleDefinition>,
+}
+
+/// Checks whether a definition file should be read/written as TOML
+#[cfg(feature = "toml")]
+fn is_toml(file: &Path) -> bool {
+    file.extension().is_some_and(|ext| ext == "toml")
+}
+
+/// Checks that a response delay can be slept for, so a bad value fails when it is set or
+/// loaded rather than panicking when a module is read
+fn check_response_delay(name: &str, delay: f32) -> Result<(), SupMCUError> {
+    match Duration::try_from_secs_f32(delay) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(SupMCUError::InvalidResponseDelay(name.to_string(), delay)),
+    }
+}
+
+/// Reads module definitions from a definition file, checking their response delays.
+///
+/// The file is JSON, or TOML if it has a `.toml` extension and the `toml` feature is enabled.
+pub fn read_def_file(file: &Path) -> Result<Vec<SupMCUModuleDefinition>, SupMCUError> {
+    let defs = parse_def_file(file)?;
+    for def in defs.iter() {
+        check_response_delay(&def.name, def.response_delay)?;
+    }
+    Ok(defs)
+}
+
+/// Parses module definitions from a definition file
+fn parse_def_file(file: &Path) -> Result<Vec<SupMCUModuleDefinition>, SupMCUError> {
+    #[cfg(feature = "toml")]
+    if is_toml(file) {
+        let defs: TomlDefinitions = toml::from_str(&std::fs::read_to_string(file)?)?;
+        return Ok(defs.modules);
+    }
+    Ok(serde_json::from_reader(File::open(file)?)?)
+}
+
+/// Returns the open change set, forgetting it if its [`ChangeSet`] was dropped
+fn open_changes(
+    changes: &mut Option<Arc<Mutex<PendingChanges>>>,
+) -> Option<&Arc<Mutex<PendingChanges>>> {
+    if changes.as_ref().is_some_and(|c| Arc::strong_count(c) == 1) {
+        *changes = None;
+    }
+    changes.as_ref()
+}
+
+/// Writes a definition file, through a temporary file so it's never left half written
+fn write_def_file(file: &Path, defs: &[SupMCUModuleDefinition]) -> Result<(), SupMCUError> {
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    #[cfg(feature = "toml")]
+    if is_toml(file) {
+        let modules = defs.to_vec();
+        std::fs::write(&tmp, toml::to_string(&TomlDefinitions { modules })?)?;
+        std::fs::rename(&tmp, file)?;
+        return Ok(());
+    }
+    serde_json::to_writer(File::create(&tmp)?, defs)?;
+    std::fs::rename(&tmp, file)?;
+    Ok(())
+}
+
+/// Joins the threads of bus scan probes that finished
+fn join_probes(probes: Vec<(u16, thread::JoinHandle<()>)>) {
+    for (address, probe) in probes {
+        if probe.join().is_err() {
+            error!("probe of address 0x{address:x} panicked");
+        }
+    }
+}
+
+impl SupMCUMaster<LinuxI2CDevice> {
+    /// Uses single byte reads to determine what addresses on the bus are populated.
+    ///
+    /// Checks addresses between 0x03 and 0x77, inclusive.  Addresses that don't answer within
+    /// 100ms are treated as absent.
+    pub fn scan_bus(device: &str, blacklist: Option<Vec<u16>>) -> Result<Vec<u16>, SupMCUError> {
+        SupMCUMaster::scan_bus_with_timeout(device, blacklist, SCAN_PROBE_TIMEOUT)
+    }
+
+    /// Checks whether the device at `address` is a SupMCU module, by reading its firmware
+    /// version telemetry.
+    ///
+    /// Returns true if a valid version string came back.  Only fails if the I2C device can't
+    /// be opened; a device that doesn't answer, or answers with something else, isn't one.
+    pub fn is_supmcu(device: &str, address: u16) -> Result<bool, SupMCUError> {
+        Ok(SupMCUModule::new(device, address, Some(1))?.has_version_string())
+    }
+
+    /// Like [`SupMCUMaster::scan_bus`], with a custom timeout for probing each address.
+    ///
+    /// Each probe runs on its own thread so a device holding the bus can't stall the scan.
+    /// A probe that times out is treated as absent, and its thread is joined once the
+    /// transfer returns.  If several probes hang at once the bus is stuck, and the scan fails
+    /// with `BusTimeout` listing their addresses rather than leaving more threads behind.
+    pub fn scan_bus_with_timeout(
+        device: &str,
+        blacklist: Option<Vec<u16>>,
+        probe_timeout: Duration,
+    ) -> Result<Vec<u16>, SupMCUError> {
+        debug!("scanning I2C bus");
+        // Make sure the bus can be opened at all before probing addresses
+        let address = 0x03;
+        LinuxI2CDevice::new(device, address).map_err(|error| SupMCUError::I2CDevError {
+            device: String::from(device),
+            address,
+            error,
+        })?;
+        let mut addresses = vec![];
+        let mut hung: Vec<(u16, thread::JoinHandle<()>)> = vec![];
+
+        for i in 0x03..0x78 {
+            trace!("checking address 0x{i:x}");
+            let (finished, still_hung) = hung.into_iter().partition(|(_, p)| p.is_finished());
+            hung = still_hung;
+            join_probes(finished);
+            if hung.len() >= SCAN_MAX_HUNG_PROBES {
+                return Err(SupMCUError::BusTimeout(
+                    hung.iter().map(|(address, _)| *address).collect(),
+                ));
+            }
+            let (tx, rx) = mpsc::channel();
+            let path = device.to_string();
+            let probe = thread::spawn(move || {
+                let found = match LinuxI2CDevice::new(&path, i) {
+                    Ok(mut dev) => dev.smbus_read_byte().is_ok(),
+                    Err(_) => {
+                        error!("failed to set address 0x{i:x}");
+                        false
+                    }
+                };
+                // The scan may have stopped waiting for this probe already
+                let _ = tx.send(found);
+            });
+            let found = rx.recv_timeout(probe_timeout);
+            match found {
+                Ok(_) => join_probes(vec![(i, probe)]),
+                Err(_) => hung.push((i, probe)),
+            }
+            match found {
+                Ok(true) => {
+                    debug!("found valid address 0x{i:x}");
+                    if let Some(blacklist) = &blacklist {
+                        if let Err(_idx) = blacklist.binary_search(&i) {
+                            addresses.push(i);
+                        } else {
+                            debug!("skipping blacklisted address 0x{i:x}");
+                        }
+                    } else {
+                        addresses.push(i);
+                    }
+                }
+                Ok(false) => (),
+                Err(_) => warn!("probe of address 0x{i:x} timed out, treating it as absent"),
+            }
+        }
+        let (finished, still_hung) = hung.into_iter().partition(|(_, p)| p.is_finished());
+        join_probes(finished);
+        for (address, _) in still_hung {
+            warn!("probe of address 0x{address:x} is still running after the scan");
+        }
+        Ok(addresses)
+    }
+
+    fn new_ext<S: AsRef<str>>(
+        device: S,
+        max_retries: Option<u8>,
+        addresses: Option<Vec<u16>>,
+        blacklist: Option<Vec<u16>>,
+    ) -> Result<Self, SupMCUError> {
+        let device = device.as_ref();
+        let addresses = if let Some(addrs) = addresses {
+            addrs
+        } else {
+            SupMCUMaster::scan_bus(device, blacklist)?
+        };
+        let modules = addresses
+            .into_iter()
+            .map(|addr| SupMCUModule::new(device, addr, max_retries))
+            .collect::<Result<Vec<SupMCUModule<LinuxI2CDevice>>, SupMCUError>>()?;
+        SupMCUMaster::from_modules(modules, device.to_string())
+    }
+
+    /// Initialize a SupMCUMaster with empty SupMCUModules, usually followed by discovery.
+    pub fn new<S: AsRef<str>>(device: S, blacklist: Option<Vec<u16>>) -> Result<Self, SupMCUError> {
+        SupMCUMaster::new_ext(device, Some(DEFAULT_RETRIES), None, blacklist)
+    }
+
+    /// Initialize a SupMCUMaster, specifying addresses of modules to interact with
+    pub fn new_with_addrs<S: AsRef<str>>(
+        device: S,
+        addresses: Vec<u16>,
+    ) -> Result<Self, SupMCUError> {
+        SupMCUMaster::new_ext(device, Some(DEFAULT_RETRIES), Some(addresses), None)
+    }
+
+    /// Initialize a SupMCUMaster with modules definitions that have been saved to disk
+    ///
+    /// Modules that can't be opened are skipped, see [`SupMCUMaster::load_report`].
+    pub fn new_from_file<S: AsRef<str>, P: AsRef<Path>>(
+        device: S,
+        file: P,
+    ) -> Result<Self, SupMCUError> {
+        SupMCUMaster::new_from_file_ext(device, file, false)
+    }
+
+    /// Initialize a SupMCUMaster with modules definitions that have been saved to disk,
+    /// failing if any of the modules can't be opened.
+    pub fn new_from_file_strict<S: AsRef<str>, P: AsRef<Path>>(
+        device: S,
+        file: P,
+    ) -> Result<Self, SupMCUError> {
+        SupMCUMaster::new_from_file_ext(device, file, true)
+    }
+
+    fn new_from_file_ext<S: AsRef<str>, P: AsRef<Path>>(
+        device: S,
+        file: P,
+        strict: bool,
+    ) -> Result<Self, SupMCUError> {
+        let device = device.as_ref();
+        let def_file = Some(PathBuf::from(file.as_ref()));
+        let defs = read_def_file(file.as_ref())?;
+        SupMCUMaster::from_defs(defs, device.to_string(), def_file, strict, |d| {
+            SupMCUModule::new_from_def(device, None, d)
+        })
+    }
+
+    /// Initialize a SupMCUMaster without allowing any attempts to retry telemetry requests
+    /// that return non-ready responses.
+    pub fn new_no_retries<S: AsRef<str>>(device: S) -> Result<Self, SupMCUError> {
+        SupMCUMaster::new_ext(device, None, None, None)
+    }
+
+    /// Runs an operation reading, or writing if `write` is set, the I2C device of this bus at
+    /// an arbitrary address, if the operations mask allows it
+    fn with_device<R>(
+        &self,
+        address: u16,
+        write: bool,
+        op: impl FnOnce(&mut LinuxI2CDevice) -> Result<R, LinuxI2CError>,
+    ) -> Result<R, SupMCUError> {
+        self.ops.get().check_raw(address, write)?;
+        LinuxI2CDevice::new(&self.device, address)
+            .and_then(|mut dev| op(&mut dev))
+            .map_err(|error| SupMCUError::I2CDevError {
+                device: self.device.clone(),
+                address,
+                error,
+            })
+    }
+
+    /// Fails if `len` bytes don't fit in an SMBus block transfer
+    fn check_block_len(command: &str, len: usize) -> Result<(), SupMCUError> {
+        if len > SMBUS_BLOCK_MAX {
+            return Err(SupMCUError::ArgumentOutOfRange {
+                command: command.into(),
+                arg: len as i64,
+                min: 0,
+                max: SMBUS_BLOCK_MAX as i64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads a single byte from a register of a (non-SupMCU) device on the bus using SMBus.
+    pub fn smbus_read_byte(&self, address: u16, register: u8) -> Result<u8, SupMCUError> {
+        let byte = self.with_device(address, false, |dev| dev.smbus_read_byte_data(register))?;
+        trace!("{address:#04X}: read {byte:#04x} from register {register:#04x}");
+        Ok(byte)
+    }
+
+    /// Writes a single byte to a register of a (non-SupMCU) device on the bus using SMBus.
+    pub fn smbus_write_byte(
+        &self,
+        address: u16,
+        register: u8,
+        value: u8,
+    ) -> Result<(), SupMCUError> {
+        self.with_device(address, true, |dev| {
+            dev.smbus_write_byte_data(register, value)
+        })?;
+        trace!("{address:#04X}: wrote {value:#04x} to register {register:#04x}");
+        Ok(())
+    }
+
+    /// Reads `len` (at most 32) bytes from a register of a (non-SupMCU) device on the bus
+    /// using an SMBus block read.
+    pub fn smbus_read_block(
+        &self,
+        address: u16,
+        register: u8,
+        len: u8,
+    ) -> Result<Vec<u8>, SupMCUError> {
+        Self::check_block_len("SMBus block read", len as usize)?;
+        let buf = self.with_device(address, false, |dev| {
+            dev.smbus_read_i2c_block_data(register, len)
+        })?;
+        trace!("{address:#04X}: read {buf:02x?} from register {register:#04x}");
+        Ok(buf)
+    }
+
+    /// Writes at most 32 bytes to a register of a (non-SupMCU) device on the bus using an
+    /// SMBus block write.
+    pub fn smbus_write_block(
+        &self,
+        address: u16,
+        register: u8,
+        bytes: &[u8],
+    ) -> Result<(), SupMCUError> {
+        Self::check_block_len("SMBus block write", bytes.len())?;
+        self.with_device(address, true, |dev| {
+            dev.smbus_write_i2c_block_data(register, bytes)
+        })?;
+        trace!("{address:#04X}: wrote {bytes:02x?} to register {register:#04x}");
+        Ok(())
+    }
+
+    /// Reads `len` bytes from a (non-SupMCU) device on the bus, without any SupMCU framing.
+    ///
+    /// Fails for addresses of managed modules unless `force` is set.
+    pub fn raw_read(
+        &mut self,
+        address: u16,
+        len: usize,
+        force: bool,
+    ) -> Result<Vec<u8>, SupMCUError> {
+        self.check_raw_address(address, force)?;
+        let mut buf = vec![0u8; len];
+        self.with_device(address, false, |dev| dev.read(&mut buf))?;
+        trace!("{address:#04X}: read {buf:02x?}");
+        Ok(buf)
+    }
+
+    /// Writes bytes to a (non-SupMCU) device on the bus, without any SupMCU framing.
+    ///
+    /// Fails for addresses of managed modules unless `force` is set.  Every attempt is
+    /// recorded in the audit log, forced or not.
+    pub fn raw_write(
+        &mut self,
+        address: u16,
+        bytes: &[u8],
+        force: bool,
+    ) -> Result<(), SupMCUError> {
+        let written = self
+            .check_raw_address(address, force)
+            .and_then(|_| self.with_device(address, true, |dev| dev.write(bytes)));
+        self.audit_raw_write(address, None, bytes, written.as_ref().map(|_| ()));
+        written?;
+        trace!("{address:#04X}: wrote {bytes:02x?}");
+        Ok(())
+    }
+
+    /// Reads `len` (at most 32) bytes from a register of a (non-SupMCU) device using SMBus,
+    /// see [`SupMCUMaster::smbus_read_block`].
+    ///
+    /// Fails for addresses of managed modules unless `force` is set.
+    pub fn raw_read_reg(
+        &mut self,
+        address: u16,
+        register: u8,
+        len: u8,
+        force: bool,
+    ) -> Result<Vec<u8>, SupMCUError> {
+        self.check_raw_address(address, force)?;
+        self.smbus_read_block(address, register, len)
+    }
+
+    /// Writes at most 32 bytes to a register of a (non-SupMCU) device using SMBus, see
+    /// [`SupMCUMaster::smbus_write_block`].
+    ///
+    /// Fails for addresses of managed modules unless `force` is set.  Every attempt is
+    /// recorded in the audit log, forced or not.
+    pub fn raw_write_reg(
+        &mut self,
+        address: u16,
+        register: u8,
+        bytes: &[u8],
+        force: bool,
+    ) -> Result<(), SupMCUError> {
+        let written = self
+            .check_raw_address(address, force)
+            .and_then(|_| self.smbus_write_block(address, register, bytes));
+        self.audit_raw_write(address, Some(register), bytes, written.as_ref().map(|_| ()));
+        written
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use i2c::TestI2CDevice;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    mod faults;
+
+    impl SupMCUModule<TestI2CDevice> {
+        pub fn new_test(
+            rng: SmallRng,
+            def: SupMCUModuleDefinition,
+            nonreadys: bool,
+            max_retries: Option<u8>,
+        ) -> Result<Self, SupMCUError> {
+            let address = def.address;
+            Ok(SupMCUModule::from_device(
+                TestI2CDevice::new(rng, def, nonreadys),
+                address,
+                max_retries,
+            ))
+        }
+
+        pub fn update_def(&mut self) {
+            self.i2c_dev.definition = self.definition.clone().unwrap();
+        }
+    }
+
+    impl SupMCUMaster<TestI2CDevice> {
+        pub fn new_test(
+            rng: SmallRng,
+            nonreadys: bool,
+            max_retries: Option<u8>,
+        ) -> Result<Self, SupMCUError> {
+            let defs: Vec<SupMCUModuleDefinition> =
+                serde_json::from_reader(File::open(Path::new("test-definition.json"))?)?;
+
+            let modules = defs
+                .into_iter()
+                .map(|def| SupMCUModule::new_test(rng.clone(), def, nonreadys, max_retries))
+                .collect::<Result<Vec<SupMCUModule<TestI2CDevice>>, SupMCUError>>()?;
+            SupMCUMaster::from_modules(modules, "".into())
+        }
+    }
+
+    #[test]
+    fn discover_module() {
+        let rng = SmallRng::from_entropy();
+
+        SupMCUMaster::new_test(rng, true, Some(5))
+            .unwrap()
+            .discover_modules()
+            .unwrap();
+    }
+
+    #[test]
+    fn discover_mcu_id() {
+        let mut def = read_def_file(Path::new("test-definition.json"))
+            .unwrap()
+            .remove(0);
+        assert_eq!(def.mcu, McuType::PIC24EP512MC206);
+        def.response_delay = 0.0;
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let discover = |def: &SupMCUModuleDefinition, supmcu| {
+            let rng = SmallRng::seed_from_u64(1457);
+            let mut module = SupMCUModule::new_test(rng, def.clone(), false, Some(5)).unwrap();
+            let options = DiscoverOptions {
+                supmcu,
+                commands: false,
+                ..Default::default()
+            };
+            rt.block_on(module.discover_with_options(options)).unwrap();
+            module.get_definition().unwrap().mcu
+        };
+        assert_eq!(discover(&def, true), McuType::PIC24EP512MC206);
+        // The MCU ID is a SupMCU item, so it's only read with them
+        assert_eq!(discover(&def, false), McuType::UNKNOWN);
+
+        // Older firmware without the item
+        def.telemetry
+            .retain(|d| !(d.telemetry_type == TelemetryType::SupMCU && d.idx == 19));
+        assert_eq!(discover(&def, true), McuType::UNKNOWN);
+    }
+
+    #[test]
+    fn discover_supmcu_only() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .discover_modules_with_options(DiscoverOptions {
+                supmcu: true,
+                module: false,
+                commands: false,
+                ..Default::default()
+            })
+            .unwrap();
+        for def in master.get_definitions().unwrap() {
+            assert!(!def.get_supmcu_telemetry().is_empty());
+            assert!(def.get_module_telemetry().is_empty());
+            assert!(def.commands.is_empty());
+        }
+    }
+
+    #[test]
+    fn discover_strict_indices() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master.modules.truncate(1);
+        let module = &mut master.modules[0];
+        let address = module.address;
+
+        // Module item 2 is listed as a second item 1, leaving a gap
+        let mut def = module.i2c_dev.definition.clone();
+        def.telemetry_item_mut(TelemetryType::Module, 2)
+            .unwrap()
+            .idx = 1;
+        let counts = vec![
+            SupMCUValue::U16(def.get_supmcu_telemetry().len() as u16),
+            SupMCUValue::U16(def.get_module_telemetry().len() as u16),
+        ];
+        module.set_definition(def);
+        let strict = DiscoverOptions {
+            strict_indices: true,
+            ..Default::default()
+        };
+        let expected = "Module item 1 is duplicated, Module item 2 is missing";
+        assert!(matches!(
+            module.check_indices(&counts, &strict),
+            Err(SupMCUError::InconsistentIndices(a, problems))
+                if a == address && problems == expected
+        ));
+        // Without strict_indices the problems are only warned about
+        module
+            .check_indices(&counts, &DiscoverOptions::default())
+            .unwrap();
+
+        // Discovering again appends a second copy of every discovered item
+        module.definition = None;
+        let options = DiscoverOptions {
+            module: false,
+            commands: false,
+            ..strict
+        };
+        master
+            .discover_modules_with_options(options.clone())
+            .unwrap();
+        let duplicated = (0..master.modules[0]
+            .i2c_dev
+            .definition
+            .get_supmcu_telemetry()
+            .len())
+            .map(|idx| IndexProblem::Duplicate(TelemetryType::SupMCU, idx))
+            .join(", ");
+        assert!(matches!(
+            master.discover_modules_with_options(options),
+            Err(SupMCUError::InconsistentIndices(a, problems))
+                if a == address && problems == duplicated
+        ));
+    }
+
+    #[test]
+    fn telemetry_autodiscover() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        let address = master.modules[0].address;
+        let expected = master.modules[0]
+            .i2c_dev
+            .definition
+            .telemetry
+            .iter()
+            .find(|d| d.telemetry_type == TelemetryType::Module)
+            .unwrap()
+            .clone();
+        let module = ModuleRef::Address(address);
+
+        let resp = master
+            .get_telemetry_autodiscover(&module, TelemetryType::Module, expected.idx)
+            .unwrap();
+        assert_eq!(resp.definition.name, normalize_name(&expected.name));
+        assert!(master.is_dirty());
+        let def = master.modules[0].get_definition().unwrap();
+        assert_eq!(def.name, master.modules[0].i2c_dev.definition.name);
+        assert_eq!(def.telemetry.len(), 1);
+
+        // The discovered item is reused
+        master
+            .get_telemetry_autodiscover(&module, TelemetryType::Module, expected.idx)
+            .unwrap();
+        assert_eq!(
+            master.modules[0].get_definition().unwrap().telemetry.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn diff_discovered_definitions() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master.discover_modules().unwrap();
+        let live = master.get_definitions().unwrap();
+
+        // A saved definition file from before a firmware change
+        let mut saved = live.clone();
+        assert!(diff::diff_definitions(&saved, &live).is_empty());
+
+        let removed = saved[0].telemetry.pop().unwrap();
+        saved[1].telemetry[1].format = SupMCUFormat::new("u");
+        saved[1].commands.push(SupMCUCommand::parse("EPSM:OLD", 99));
+        let gone = saved.pop().unwrap();
+
+        let diff = diff::diff_definitions(&saved, &live);
+        assert!(diff.only_old.is_empty());
+        assert_eq!(diff.only_new, vec![(gone.name, gone.address)]);
+        assert_eq!(diff.modules.len(), 2);
+        assert_eq!(
+            diff.modules[0].added,
+            vec![format!("{} {}", removed.telemetry_type, removed.name)]
+        );
+        assert_eq!(diff.modules[1].format_changes.len(), 1);
+        assert_eq!(
+            diff.modules[1].commands_removed,
+            vec!["EPSM:OLD".to_string()]
+        );
+    }
+
+    #[test]
+    fn discovery_review() {
+        use review::{ReviewDecision, ReviewDecisions};
+
+        let path = "test-review.tmp.json";
+        let sim_master = || {
+            let mut master =
+                SupMCUMaster::new_test(SmallRng::from_entropy(), false, Some(5)).unwrap();
+            master.modules.truncate(1);
+            master
+        };
+        let mut master = sim_master();
+        master.discover_modules().unwrap();
+        let discovered = master.get_definitions().unwrap().remove(0);
+        let module_items: Vec<&SupMCUTelemetryDefinition> = discovered
+            .telemetry
+            .iter()
+            .filter(|t| t.telemetry_type == TelemetryType::Module)
+            .take(4)
+            .collect();
+        assert_eq!(module_items.len(), 4);
+
+        let decide = |item: &review::ReviewItem| match item.definition.telemetry_type {
+            TelemetryType::SupMCU => ReviewDecision::Accept,
+            TelemetryType::Module => match item.definition.idx {
+                i if i == module_items[0].idx => ReviewDecision::Rename("renamed".into()),
+                i if i == module_items[1].idx => {
+                    ReviewDecision::OverrideFormat(SupMCUFormat::new("ss"))
+                }
+                i if i == module_items[2].idx => ReviewDecision::Skip,
+                _ => ReviewDecision::Accept,
+            },
+        };
+        let mut decisions = ReviewDecisions::default();
+        let mut offered = 0;
+        let mut master = sim_master();
+        master
+            .discover_modules_with_review(|item| {
+                offered += 1;
+                assert_eq!(item.module, discovered.name);
+                let decision = decide(&item);
+                decisions.record(&item, &decision);
+                decision
+            })
+            .unwrap();
+        let reviewed = master.get_definitions().unwrap().remove(0);
+        let find = |idx| {
+            reviewed
+                .telemetry
+                .iter()
+                .find(|t| t.telemetry_type == TelemetryType::Module && t.idx == idx)
+        };
+
+        assert_eq!(offered, discovered.telemetry.len());
+        assert_eq!(reviewed.telemetry.len(), discovered.telemetry.len() - 1);
+        assert_eq!(find(module_items[0].idx).unwrap().name, "renamed");
+        let overridden = find(module_items[1].idx).unwrap();
+        assert_eq!(overridden.format, SupMCUFormat::new("ss"));
+        assert_eq!(overridden.name, module_items[1].name);
+        assert!(overridden.default_sim_value.is_none());
+        assert!(find(module_items[2].idx).is_none());
+        assert_eq!(
+            find(module_items[3].idx).unwrap().name,
+            module_items[3].name
+        );
+        assert_eq!(decisions.decisions.len(), 3);
+
+        // Recorded decisions replay to the same definition
+        decisions.save(path).unwrap();
+        let loaded = ReviewDecisions::load(path);
+        std::fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, decisions);
+        let mut master = sim_master();
+        master
+            .discover_modules_with_review(|item| loaded.decide(&item))
+            .unwrap();
+        // Default simulated values are random, so only the layout is compared
+        let layout = |def: &SupMCUModuleDefinition| {
+            def.telemetry
+                .iter()
+                .map(|t| (t.telemetry_type, t.idx, t.name.clone(), t.format.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            layout(&master.get_definitions().unwrap()[0]),
+            layout(&reviewed)
+        );
+    }
+
+    #[test]
+    fn review_keeps_internal_items() {
+        let mut bus = sim_bus(1491);
+        bus.master.modules.truncate(1);
+        let discovered = bus.master.modules[0].get_definition().unwrap().clone();
+        bus.master
+            .discover_modules_with_review(|item| match item.definition.idx % 2 {
+                0 => ReviewDecision::Skip,
+                _ => ReviewDecision::Rename(format!("{}_renamed", item.definition.name)),
+            })
+            .unwrap();
+        let reviewed = bus.master.modules[0].get_definition().unwrap();
+        for idx in review::INTERNAL_ITEMS {
+            let kept = reviewed.telemetry_item(TelemetryType::SupMCU, idx);
+            let original = discovered.telemetry_item(TelemetryType::SupMCU, idx);
+            assert_eq!(kept.map(|d| &d.name), original.map(|d| &d.name));
+        }
+        assert!(reviewed.telemetry.iter().any(|d| d.name == RESET_CAUSE_TLM));
+        // Other items are still left out or renamed
+        assert!(reviewed
+            .telemetry
+            .iter()
+            .filter(|d| !review::INTERNAL_ITEMS.contains(&d.idx)
+                || d.telemetry_type == TelemetryType::Module)
+            .all(|d| d.name.ends_with("_renamed")));
+    }
+
+    #[test]
+    fn nonready_no_retry() {
+        let mut bus = sim_bus(4);
+        let address = bus.master.modules[0].address;
+        bus.master.modules[0].max_retries = None;
+        bus.inject(
+            address,
+            sim::FaultPlan {
+                nonready: 1,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            bus.master.modules[0].get_telemetry(TelemetryType::SupMCU, 1),
+            Err(SupMCUError::NonReadyError(a, _)) if a == address
+        ));
+        let writes = bus
+            .transcript()
+            .iter()
+            .filter(|t| matches!(t.kind, sim::TransactionKind::Write(_)))
+            .count();
+        assert_eq!(writes, 1);
+    }
+
+    #[test]
+    fn get_telemetry_values() {
+        // Telemetry values are generated from this rng
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng.clone(), false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        for module in master.modules.iter_mut() {
+            // rng needs to be cloned so that each module starts with a "fresh"/unused rng initialized from the same seed
+            let mut local_rng = rng.clone();
+            for tel_def in module
+                .get_definition_mut()
+                .unwrap()
+                .telemetry
+                .clone()
+                .iter_mut()
+            {
+                // Skip telemetry items that have special purposes
+                if tel_def.telemetry_type == TelemetryType::SupMCU
+                    && (tel_def.idx == 0
+                        || tel_def.idx == 14
+                        || tel_def.idx == 17
+                        || tel_def.idx == 19)
+                {
+                    continue;
+                }
+                assert_eq!(
+                    // Because both functions are using the exact same rng, the numbers generated should be the same
+                    module.get_telemetry_by_def(tel_def).unwrap().data,
+                    tel_def.format.random_data(&mut local_rng)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn load_defs_skips_failed_modules() {
+        let rng = SmallRng::from_entropy();
+        let mut defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        let good = defs.len();
+        defs.push(SupMCUModuleDefinition {
+            name: "BOGUS".into(),
+            address: 0x7f,
+            ..Default::default()
+        });
+        let open = |def: SupMCUModuleDefinition| -> Result<_, SupMCUError> {
+            if def.address == 0x7f {
+                return Err(SupMCUError::ModuleNotFound(def.name, def.address));
+            }
+            let mut module = SupMCUModule::new_test(rng.clone(), def.clone(), false, None)?;
+            module.set_definition(def);
+            Ok(module)
+        };
+
+        let master = SupMCUMaster::from_defs(defs.clone(), "".into(), None, false, open).unwrap();
+        assert_eq!(master.modules.len(), good);
+        assert_eq!(master.load_report().loaded.len(), good);
+        assert_eq!(master.load_report().failed.len(), 1);
+        assert_eq!(master.load_report().failed[0].0.name, "BOGUS");
+
+        assert!(SupMCUMaster::from_defs(defs.clone(), "".into(), None, true, open).is_err());
+
+        defs.push(defs[0].clone());
+        assert!(matches!(
+            SupMCUMaster::from_defs(defs, "".into(), None, false, open),
+            Err(SupMCUError::DuplicateAddress(_))
+        ));
+    }
+
+    #[test]
+    fn custom_decoder() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        let def = module
+            .get_definition()
+            .unwrap()
+            .telemetry
+            .iter()
+            .find(|d| d.format.get_byte_length().is_some())
+            .unwrap()
+            .clone();
+        module.register_decoder(def.name.clone(), |data| {
+            Ok(vec![SupMCUValue::U32(data.len() as u32)])
+        });
+        assert_eq!(
+            module.get_telemetry_by_def(&def).unwrap().data,
+            vec![SupMCUValue::U32(
+                def.format.get_byte_length().unwrap() as u32
+            )]
+        );
+        assert!(module.unregister_decoder(&def.name).is_some());
+    }
+
+    #[test]
+    fn send_command_confirmed() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        let name = module.get_definition().unwrap().telemetry[1].name.clone();
+
+        let result = module
+            .send_command_confirmed("SUP:LED ON", Some(&name), Duration::from_secs(1))
+            .unwrap();
+        assert!(result.accepted);
+        assert_eq!(result.echoed_command, "SUP:LED ON");
+        assert_eq!(result.confirmation.unwrap().0.len(), 1);
+
+        let result = module
+            .send_command_confirmed("SUP:LED OFF", None, Duration::from_secs(1))
+            .unwrap();
+        assert!(result.accepted);
+        assert!(result.confirmation.is_none());
+
+        assert!(matches!(
+            module.send_command_confirmed("SUP:LED ON", Some("nope"), Duration::ZERO),
+            Err(SupMCUError::UnknownTelemName(_))
+        ));
+
+        // Non-ready confirmations are polled once per response delay
+        let mut bus = sim_bus(1449);
+        let address = bus.master.modules[0].address;
+        let plan = sim::FaultPlan {
+            nonready: 3,
+            ..Default::default()
+        };
+        bus.inject(address, plan).unwrap();
+        let start = bus.now();
+        let module = &mut bus.master.modules[0];
+        module.max_retries = None;
+        let delay = Duration::from_secs_f32(module.response_delay());
+        let result = module
+            .send_command_confirmed("SUP:LED ON", Some(&name), Duration::from_secs(10))
+            .unwrap();
+        assert!(result.accepted);
+        // Four reads, each waiting for its response, and a wait before each of the last three
+        assert_eq!(bus.now() - start, delay * 7);
+    }
+
+    #[test]
+    fn wait_for_bus() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, None).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let now = Instant::now();
+        for (i, module) in master.modules.iter_mut().enumerate() {
+            module.i2c_dev.ready_at = Some(now + Duration::from_millis(30 * i as u64));
+        }
+        let report = master
+            .wait_for_bus(Duration::from_secs(5), BusReadiness::AllModules)
+            .unwrap();
+        assert_eq!(report.ready.len(), master.modules.len());
+        assert!(report.not_ready.is_empty());
+
+        // The last module never comes up
+        let dead = master.modules.last().unwrap().address;
+        master.modules.last_mut().unwrap().i2c_dev.ready_at =
+            Some(Instant::now() + Duration::from_secs(3600));
+        match master.wait_for_bus(Duration::from_millis(200), BusReadiness::AllModules) {
+            Err(SupMCUError::BusTimeout(addrs)) => assert_eq!(addrs, vec![dead]),
+            r => panic!("expected a timeout, got {r:?}"),
+        }
+        master
+            .wait_for_bus(Duration::from_millis(200), BusReadiness::AtLeast(2))
+            .unwrap();
+        master
+            .wait_for_bus(
+                Duration::from_millis(200),
+                BusReadiness::Names(vec!["GPS".into()]),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn reset_cause() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng.clone(), false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        let def = module
+            .get_definition()
+            .unwrap()
+            .telemetry
+            .iter()
+            .find(|d| d.name == RESET_CAUSE_TLM)
+            .unwrap()
+            .clone();
+        // The module hasn't generated any data yet, so it will use the same values
+        let expected = match def.format.random_data(&mut rng.clone())[0] {
+            SupMCUValue::I16(v) => ResetCause::from(v as u16),
+            ref v => panic!("unexpected value {v:?}"),
+        };
+        assert_eq!(module.reset_cause().unwrap(), expected);
+    }
+
+    #[test]
+    fn boot_count() {
+        let mut defs = read_def_file(Path::new("test-definition.json")).unwrap();
+        defs.truncate(2);
+        let idx = defs[0].get_module_telemetry().len();
+        let boots = |name: &str, idx| SupMCUTelemetryDefinition {
+            name: name.into(),
+            format: SupMCUFormat::new("l"),
+            idx,
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        };
+        // The conventional name that's preferred is read
+        let reset_count = boots("reset_count", idx);
+        let boot_count = boots("boot_count", idx + 1);
+        let resets = boots("resets_since_launch", idx + 2);
+        defs[0]
+            .telemetry
+            .extend([reset_count.clone(), boot_count.clone(), resets.clone()]);
+        let mut bus = sim::SimBus::new(4, defs).unwrap();
+        let address = bus.master.modules[0].address;
+        let device = bus.device_mut(address).unwrap();
+        device.script(&reset_count, vec![vec![SupMCUValue::U64(3)]]);
+        device.script(&boot_count, vec![vec![SupMCUValue::U64(17)]; 2]);
+        device.script(&resets, vec![vec![SupMCUValue::U64(4)]]);
+        assert_eq!(bus.master.modules[0].boot_count().unwrap(), 17);
+
+        // An item set in the definition is read whatever it's called
+        let module = &mut bus.master.modules[0];
+        module.get_definition_mut().unwrap().boot_count = Some(resets.idx);
+        assert_eq!(module.boot_count().unwrap(), 4);
+
+        assert!(matches!(
+            bus.master.modules[1].boot_count(),
+            Err(SupMCUError::UnknownTelemName(name)) if name == BOOT_COUNT_ITEMS[0]
+        ));
+    }
+
+    #[test]
+    fn string_without_length() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let mut def = master.modules[0].get_definition().unwrap().telemetry[0].clone();
+        def.length = None;
+        assert!(matches!(
+            master.modules[0].get_telemetry_by_def(&def),
+            Err(SupMCUError::ParsingError(ParsingError::MissingLengthError(
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn save_def_file_bad_path() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        assert!(master
+            .save_def_file(Path::new("no/such/directory/def.json"))
+            .is_err());
+    }
+
+    #[test]
+    fn all_telemetry_without_definition() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        for results in master.get_all_telemetry() {
+            assert!(matches!(
+                results.as_slice(),
+                [Err(SupMCUError::MissingDefinitionError)]
+            ));
+        }
+    }
+
+    #[test]
+    fn verify_formats() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        module.verify_formats(Some(Duration::ZERO), true);
+        let mut def = module.get_definition().unwrap().telemetry[1].clone();
+        module.get_telemetry_by_def(&def).unwrap();
+
+        def.format = SupMCUFormat::new("k");
+        assert!(matches!(
+            module.get_telemetry_by_def(&def),
+            Err(SupMCUError::FormatDriftError(..))
+        ));
+
+        // Only checked once per interval
+        module.verify_formats(Some(Duration::from_secs(3600)), true);
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+        module.get_telemetry_by_def(&def).unwrap();
+        let mut drifted = def.clone();
+        drifted.format = SupMCUFormat::new("k");
+        module.get_telemetry_by_def(&drifted).unwrap();
+    }
+
+    #[test]
+    fn send_commands() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let commands = vec![
+            (ModuleRef::Name("GPS".into()), "SUP:LED ON".to_string()),
+            (ModuleRef::Address(0x54), "SUP:LED OFF".to_string()),
+            (ModuleRef::Address(0x10), "SUP:LED ON".to_string()),
+            (ModuleRef::Address(0x54), "SUP:LED FLASH".to_string()),
+        ];
+        let results = master.send_commands(&commands);
+        assert_eq!(results.len(), commands.len());
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_ok());
+        assert!(matches!(
+            results[2].1,
+            Err(SupMCUError::ModuleNotFound(_, 0x10))
+        ));
+        assert!(results[3].1.is_ok());
+        assert_eq!(results[2].0, ModuleRef::Address(0x10));
+        assert_eq!(master.modules[0].last_cmd, "SUP:LED ON");
+        assert_eq!(master.modules[1].last_cmd, "SUP:LED FLASH");
+    }
+
+    #[test]
+    fn refresh_metadata() {
+        let master = &mut sim_bus(10).master;
+        let names: Vec<String> = master
+            .modules
+            .iter()
+            .map(|m| m.get_definition().unwrap().telemetry[0].name.clone())
+            .collect();
+        for module in master.modules.iter_mut() {
+            for def in module.get_definition_mut().unwrap().telemetry.iter_mut() {
+                def.name = "stale".into();
+            }
+        }
+        let total: usize = master
+            .modules
+            .iter()
+            .map(|m| m.get_definition().unwrap().telemetry.len())
+            .sum();
+
+        // With no budget, one item is refreshed per call, moving round-robin across modules
+        let report = master.refresh_metadata(RefreshScope::Names, Duration::ZERO);
+        assert_eq!(report.refreshed, 1);
+        assert_eq!(report.remaining, total - 1);
+        let report = master.refresh_metadata(RefreshScope::Names, Duration::ZERO);
+        assert_eq!(report.refreshed, 1);
+        assert_eq!(report.remaining, total - 2);
+        let first_names: Vec<&str> = master
+            .modules
+            .iter()
+            .map(|m| m.get_definition().unwrap().telemetry[0].name.as_str())
+            .collect();
+        assert_eq!(first_names[..2], [names[0].as_str(), names[1].as_str()]);
+        assert!(first_names[2..].iter().all(|n| *n == "stale"));
+        assert!(master.is_dirty());
+
+        // A large budget finishes the pass
+        let report = master.refresh_metadata(RefreshScope::Names, Duration::from_secs(3600));
+        assert_eq!(report.refreshed + report.failed, total - 2);
+        assert_eq!(report.remaining, 0);
+        for (module, name) in master.modules.iter().zip(names) {
+            assert_eq!(module.get_definition().unwrap().telemetry[0].name, name);
+        }
+    }
+
+    /// Scripts the self-test results item of a module to report `values` when polled
+    fn script_self_test(
+        module: &mut SupMCUModule<TestI2CDevice>,
+        values: Vec<SupMCUTelemetryData>,
+    ) {
+        let def = module
+            .telemetry_def(TelemetryType::SupMCU, SELF_TEST_TLM_IDX)
+            .unwrap();
+        module.i2c_dev.script(&def, values);
+        module.set_self_test_fields(SelfTestFields {
+            result: 1,
+            runs: Some(0),
+        });
+    }
+
+    /// Self-test results after `runs` runs, with the failed test bits `bits`
+    fn self_test_values(runs: u64, bits: u64) -> SupMCUTelemetryData {
+        vec![
+            SupMCUValue::U64(runs),
+            SupMCUValue::U64(bits),
+            SupMCUValue::U16(0),
+            SupMCUValue::U16(0),
+            SupMCUValue::U16(0),
+        ]
+    }
+
+    #[test]
+    fn self_test_completes_after_polls() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let before = self_test_values(3, 0b1);
+        let module = &mut master.modules[0];
+        script_self_test(
+            module,
+            vec![
+                before.clone(),
+                before.clone(),
+                before,
+                self_test_values(4, 0),
+            ],
+        );
+        module.set_self_test_names(vec!["flash".into(), "ram".into()]);
+
+        let report = module.run_self_test(Duration::from_secs(5)).unwrap();
+        assert!(report.passed);
+        assert_eq!(report.bits, 0);
+        let names: Vec<&str> = report.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["flash", "ram"]);
+        assert!(report.results.iter().all(|r| r.passed));
+    }
+
+    #[test]
+    fn self_test_times_out() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let before = self_test_values(3, 0);
+        let module = &mut master.modules[0];
+        script_self_test(module, vec![before; 100]);
+        assert!(matches!(
+            module.run_self_test(Duration::from_millis(100)),
+            Err(SupMCUError::SelfTestTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn self_test_counts_runs() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        module.self_test_fields = None;
+        assert!(matches!(
+            module.run_self_test(Duration::from_secs(5)),
+            Err(SupMCUError::NotConfigured(..))
+        ));
+
+        // A run with the same results as the last is noticed by its run counter
+        script_self_test(
+            module,
+            vec![self_test_values(3, 0b10), self_test_values(4, 0b10)],
+        );
+        let report = module.run_self_test(Duration::from_secs(5)).unwrap();
+        assert_eq!(report.bits, 0b10);
+
+        // Without a counter it can't be told from a run still going
+        script_self_test(module, vec![self_test_values(3, 0b10); 100]);
+        module.set_self_test_fields(SelfTestFields {
+            result: 1,
+            runs: None,
+        });
+        assert!(matches!(
+            module.run_self_test(Duration::from_millis(100)),
+            Err(SupMCUError::SelfTestTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn self_test_reports_failure() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        for (i, module) in master.modules.iter_mut().enumerate() {
+            let bits = if i == 0 { 0b100 } else { 0 };
+            script_self_test(
+                module,
+                vec![self_test_values(0, 0), self_test_values(1, bits)],
+            );
+        }
+
+        let summary = master.run_all_self_tests(Duration::from_secs(5));
+        assert!(!summary.passed());
+        assert!(summary.errors.is_empty());
+        let failed: Vec<&SelfTestReport> = summary.reports.iter().filter(|r| !r.passed).collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].bits, 0b100);
+        let failed_tests: Vec<&str> = failed[0]
+            .results
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(failed_tests, vec!["bit 2"]);
+    }
+
+    #[test]
+    fn self_tests_run_in_parallel() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        for module in master.modules.iter_mut() {
+            module.get_definition_mut().unwrap().response_delay = 0.2;
+            script_self_test(module, vec![self_test_values(0, 0), self_test_values(1, 0)]);
+        }
+
+        // Each self-test waits out three response delays.  Blocking the runtime's two
+        // workers on them would take over 2s for the six modules.
+        let start = Instant::now();
+        let summary = master.run_all_self_tests(Duration::from_secs(5));
+        assert!(summary.passed(), "{summary:?}");
+        assert!(
+            start.elapsed() < Duration::from_millis(1500),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+
+    fn sim_bus(seed: u64) -> sim::SimBus {
+        let defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        sim::SimBus::new(seed, defs).unwrap()
+    }
+
+    #[test]
+    fn host_timestamps() {
+        let mut bus = sim_bus(2);
+        let module = &mut bus.master.modules[0];
+        let tlm = module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+        assert!(tlm.host_time.is_none());
+
+        module.set_host_timestamps(true);
+        let before = SystemTime::now();
+        let tlm = module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+        let host_time = tlm.host_time.unwrap();
+        assert!(host_time >= before && host_time <= SystemTime::now());
+    }
+
+    #[test]
+    fn short_reads() {
+        let mut bus = sim_bus(4);
+        let module = &mut bus.master.modules[0];
+        struct Whole;
+        impl<T: I2CDevice> CountedTransport<T> for Whole {}
+        module.set_counted_transport(Whole);
+        module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+
+        /// Reads all but the last 3 bytes
+        struct Short;
+        impl<T: I2CDevice> CountedTransport<T> for Short {
+            fn read(&self, dev: &mut T, data: &mut [u8]) -> Result<usize, T::Error> {
+                dev.read(data)?;
+                let len = data.len() - 3;
+                data[len..].fill(0);
+                Ok(len)
+            }
+        }
+        module.set_counted_transport(Short);
+        assert!(matches!(
+            module.get_telemetry(TelemetryType::SupMCU, 1),
+            Err(SupMCUError::ParsingError(ParsingError::InvalidBytes(msg))) if msg.contains("Short read")
+        ));
+    }
+
+    #[test]
+    fn partial_writes() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut bus = sim_bus(21);
+        let mut module = bus.master.modules.remove(0);
+        let writes = |bus: &sim::SimBus| -> Vec<String> {
+            bus.transcript()
+                .into_iter()
+                .filter_map(|t| match t.kind {
+                    sim::TransactionKind::Write(cmd) => Some(cmd),
+                    _ => None,
+                })
+                .collect()
+        };
+        /// Writes at most 10 bytes
+        struct Truncated;
+        impl<T: I2CDevice> CountedTransport<T> for Truncated {
+            fn write(&self, dev: &mut T, data: &[u8]) -> Result<usize, T::Error> {
+                let sent = data.len().min(10);
+                dev.write(&data[..sent])?;
+                Ok(sent)
+            }
+        }
+        module.set_counted_transport(Truncated);
+        bus.clear_transcript();
+        // The total doesn't count the newline terminating the command
+        assert!(matches!(
+            module.send_command("PIM:CHAN 1,OFF"),
+            Err(SupMCUError::PartialWrite {
+                sent: 10,
+                total: 14,
+                ..
+            })
+        ));
+        // The truncated command is terminated, and not sent again
+        assert_eq!(writes(&bus), vec!["PIM:CHAN 1", "\n"]);
+
+        // Telemetry requests are sent again
+        /// Writes half of each command while its count lasts
+        struct Halved(AtomicUsize);
+        impl<T: I2CDevice> CountedTransport<T> for Halved {
+            fn write(&self, dev: &mut T, data: &[u8]) -> Result<usize, T::Error> {
+                let sent = match self
+                    .0
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                {
+                    Ok(_) => data.len() / 2,
+                    Err(_) => data.len(),
+                };
+                dev.write(&data[..sent])?;
+                Ok(sent)
+            }
+        }
+        module.set_counted_transport(Halved(AtomicUsize::new(1)));
+        bus.clear_transcript();
+        module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+        let cmd = terminate_command(
+            &module
+                .create_tlm_command(
+                    module
+                        .get_definition()
+                        .unwrap()
+                        .telemetry_item(TelemetryType::SupMCU, 1)
+                        .unwrap(),
+                )
+                .unwrap(),
+        );
+        assert_eq!(
+            writes(&bus),
+            vec![cmd[..cmd.len() / 2].to_string(), "\n".into(), cmd.clone()]
+        );
+    }
+
+    #[test]
+    fn definition_templates() {
+        let mut bus = sim_bus(22);
+        let address = bus.master.modules[0].address;
+        let known = bus.master.modules[0].get_definition().unwrap().clone();
+        let other = bus.master.modules[1].get_definition().unwrap().clone();
+        bus.master.add_template(SupMCUModuleDefinition {
+            address: 0x7f,
+            unique_name: Some("spare".into()),
+            ..known.clone()
+        });
+        bus.master.add_template(other.clone());
+        assert_eq!(bus.master.templates().len(), 2);
+
+        bus.master.modules[0].definition = None;
+        bus.clear_transcript();
+        let module = ModuleRef::Address(address);
+        bus.master
+            .apply_template(&module, &known.name, true)
+            .unwrap();
+        assert_eq!(bus.master.modules[0].get_definition().unwrap(), &known);
+        // Verifying takes a request for the version and one for the item counts
+        let writes = bus
+            .transcript()
+            .iter()
+            .filter(|t| matches!(t.kind, sim::TransactionKind::Write(_)))
+            .count();
+        assert_eq!(writes, 2);
+
+        assert!(matches!(
+            bus.master.apply_template(&module, &other.name, true),
+            Err(SupMCUError::TemplateMismatch(a, msg)) if a == address && msg.contains(&other.name)
+        ));
+        assert_eq!(bus.master.modules[0].get_definition().unwrap(), &known);
+        // The module reports the module items the template is missing
+        let mut short = known.clone();
+        short
+            .telemetry
+            .retain(|d| d.telemetry_type == TelemetryType::SupMCU);
+        bus.master.modules[0].apply_template(&short);
+        let e = bus.master.modules[0].verify_template().unwrap_err();
+        assert!(
+            e.to_string().contains("Module telemetry items, not 0"),
+            "{e}"
+        );
+        bus.master.modules[0].apply_template(&known);
+        assert!(matches!(
+            bus.master.apply_template(&module, "nope", false),
+            Err(SupMCUError::UnknownTemplate(_))
+        ));
+
+        // Unverified templates are applied as they are
+        bus.master
+            .apply_template(&module, &other.name, false)
+            .unwrap();
+        assert_eq!(
+            bus.master.modules[0].get_definition().unwrap().name,
+            other.name
+        );
+        assert_eq!(
+            bus.master.modules[0].get_definition().unwrap().address,
+            address
+        );
+    }
+
+    #[test]
+    fn for_each_limited() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut bus = sim_bus(9);
+        let addresses: Vec<u16> = bus.master.modules.iter().map(|m| m.address).collect();
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let (active, peak) = (&active, &peak);
+        let probe = |module: &mut SupMCUModule<TestI2CDevice>| {
+            let address = module.address;
+            async move {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                time::sleep(Duration::from_millis(20)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                address
+            }
+        };
+
+        let results = bus.master.for_each_limited(2, probe);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            results.iter().map(|(a, _)| *a).collect::<Vec<_>>(),
+            addresses
+        );
+        assert!(results.iter().all(|(a, b)| a == b));
+
+        peak.store(0, Ordering::SeqCst);
+        bus.master.set_concurrency_limit(Some(1));
+        let results = bus.master.for_each(probe);
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+        assert_eq!(results, addresses);
+
+        peak.store(0, Ordering::SeqCst);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let results = rt.block_on(for_each_limited_async(&mut bus.master.modules, 3, probe));
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            results.iter().map(|(a, _)| *a).collect::<Vec<_>>(),
+            addresses
+        );
+    }
+
+    #[test]
+    fn concurrency_limit_simulated() {
+        let mut bus = sim_bus(1462);
+        let modules = bus.master.modules.len();
+        bus.master.set_concurrency_limit(Some(1));
+        bus.clear_transcript();
+        let results = bus
+            .master
+            .for_each(|m| m.get_telemetry_async(TelemetryType::SupMCU, 1));
+        assert!(results.iter().all(Result::is_ok));
+
+        // Each module's request is answered before the next module's is sent
+        let transcript = bus.transcript();
+        assert_eq!(transcript.len(), 2 * modules);
+        for pair in transcript.chunks(2) {
+            assert!(matches!(pair[0].kind, sim::TransactionKind::Write(_)));
+            assert!(matches!(pair[1].kind, sim::TransactionKind::Read(_)));
+            assert_eq!(pair[0].address, pair[1].address);
+        }
+        let addresses: HashSet<u16> = transcript.iter().map(|t| t.address).collect();
+        assert_eq!(addresses.len(), modules);
+    }
+
+    #[test]
+    fn interleaved_sweep() {
+        let mut bus = sim_bus(5);
+        let lengths: Vec<usize> = bus
+            .master
+            .modules
+            .iter()
+            .map(|m| m.get_definition().unwrap().telemetry.len())
+            .collect();
+        let telemetry = bus.master.get_all_telemetry_interleaved();
+        assert_eq!(telemetry.iter().map(Vec::len).collect::<Vec<_>>(), lengths);
+        assert!(telemetry.iter().flatten().all(|tlm| tlm.is_ok()));
+
+        // Cancel once a few turns' worth of responses have been read
+        bus.clear_transcript();
+        let cancel = CancellationToken::new();
+        let modules = lengths.len();
+        let canceller = {
+            let cancel = cancel.clone();
+            let state = bus.state.clone();
+            thread::spawn(move || {
+                while state
+                    .lock()
+                    .unwrap()
+                    .transcript
+                    .iter()
+                    .filter(|t| matches!(t.kind, sim::TransactionKind::Read(_)))
+                    .count()
+                    < modules * 3
+                {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                cancel.cancel();
+            })
+        };
+        let sweeps = bus.master.get_all_telemetry_interleaved_until(&cancel);
+        canceller.join().unwrap();
+        let completed: Vec<usize> = sweeps
+            .iter()
+            .map(|sweep| {
+                let sweep = sweep.as_ref().unwrap();
+                let done = sweep.iter().take_while(|item| item.is_complete()).count();
+                assert!(sweep[done..].iter().all(|item| !item.is_complete()));
+                done
+            })
+            .collect();
+        let turns = *completed.iter().max().unwrap();
+        assert!(turns >= 3 && turns < *lengths.iter().max().unwrap());
+        for (done, len) in completed.iter().zip(lengths) {
+            assert_eq!(*done, turns.min(len));
+        }
+    }
+
+    /// A bus with two modules of the same model
+    fn twin_bus() -> sim::SimBus {
+        let defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        let mut twin = defs[0].clone();
+        twin.address = 0x7e;
+        sim::SimBus::new(7, vec![defs[0].clone(), twin]).unwrap()
+    }
+
+    #[test]
+    fn empty_format_item() {
+        let mut defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        defs.truncate(1);
+        let idx = defs[0]
+            .telemetry
+            .iter()
+            .filter(|d| d.telemetry_type == TelemetryType::Module)
+            .count();
+        let action = SupMCUTelemetryDefinition {
+            name: "Trigger action".into(),
+            format: SupMCUFormat::new(""),
+            default_sim_value: Some(vec![]),
+            idx,
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        };
+        defs[0].telemetry.push(action.clone());
+        let mut bus = sim::SimBus::new(3, defs).unwrap();
+        let module = &mut bus.master.modules[0];
+
+        let tlm = module.get_telemetry_by_def(&action).unwrap();
+        assert!(tlm.header.ready);
+        assert!(tlm.data.is_empty());
+        let all = module.get_all_telemetry().unwrap();
+        assert!(all[&action.name].is_empty());
+    }
+
+    #[test]
+    fn bus_diagnosis_configured() {
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, RemoteBusItems, SupMCUBus};
+        use diag::{Diagnosis, RemoteBusStats};
+
+        let mut defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        defs.truncate(2);
+        let names = ["nacks", "overruns", "bus_errors"].map(String::from);
+        // Too large to survive a round trip through f64
+        let counts = [(1 << 53) + 1, 0, 1];
+        let counters: Vec<SupMCUTelemetryDefinition> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SupMCUTelemetryDefinition {
+                name: name.to_string(),
+                format: SupMCUFormat::new("l"),
+                idx: defs[0].telemetry.len() + i,
+                telemetry_type: TelemetryType::SupMCU,
+                ..Default::default()
+            })
+            .collect();
+        defs[0].telemetry.extend(counters.iter().cloned());
+        let mut bus = sim::SimBus::new(1493, defs).unwrap();
+        let clock = bus.clock();
+        let address = bus.master.modules[0].address;
+        for (def, count) in counters.iter().zip(counts) {
+            let values = vec![vec![SupMCUValue::U64(count)]; 3];
+            bus.device_mut(address).unwrap().script(def, values);
+        }
+        let config = BusConfig {
+            device: String::new(),
+            def_file: None,
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: vec![],
+            health_interval_ms: Some(100),
+            diagnose: true,
+            remote_bus_items: vec![RemoteBusItems {
+                module: ModuleRef::Address(address),
+                items: names.clone(),
+            }],
+            persist: false,
+        };
+        let sim::SimBus { master, .. } = bus;
+        let mut handle = SupMCUBus::start_simulated(master, config, clock.clone()).unwrap();
+        clock.run_for(Duration::from_millis(250));
+        let events = queued_events(&mut handle);
+        handle.stop().unwrap();
+
+        let diagnoses: Vec<_> = events
+            .into_iter()
+            .filter_map(|e| match e {
+                BusEvent::Diagnosis(d) if d.address == address => Some(d),
+                _ => None,
+            })
+            .collect();
+        // The first diagnosis counts from 0, the later ones from the one before
+        assert!(diagnoses.len() > 1);
+        assert_eq!(diagnoses[0].diagnosis, Diagnosis::ModuleOnly);
+        for diagnosis in &diagnoses {
+            assert_ne!(diagnosis.diagnosis, Diagnosis::RemoteUnavailable);
+            assert_eq!(
+                diagnosis.remote,
+                Some(RemoteBusStats {
+                    nacks_sent: counts[0],
+                    buffer_overruns: counts[1],
+                    bus_errors: counts[2],
+                })
+            );
+        }
+        assert_eq!(diagnoses[1].diagnosis, Diagnosis::Healthy);
+
+        let mut tlm = SupMCUTelemetry {
+            definition: SupMCUTelemetryDefinition {
+                name: names[0].clone(),
+                ..Default::default()
+            },
+            header: SupMCUHDR {
+                ready: true,
+                timestamp: 0,
+            },
+            data: vec![SupMCUValue::I32(-1)],
+            host_time: None,
+        };
+        assert!(matches!(
+            diag::counter_value(&tlm),
+            Err(SupMCUError::UnexpectedValue(name, SupMCUValue::I32(-1))) if name == names[0]
+        ));
+        tlm.data = vec![SupMCUValue::Double(3.0)];
+        assert!(diag::counter_value(&tlm).is_err());
+        tlm.data = vec![SupMCUValue::I16(7)];
+        assert_eq!(diag::counter_value(&tlm).unwrap(), 7);
+    }
+
+    #[test]
+    fn duplicate_names_disambiguated() {
+        let mut bus = twin_bus();
+        bus.master.disambiguate_names();
+        let def = bus.master.modules[0].get_definition().unwrap().clone();
+        let first = format!("{}_{:#04x}", def.name, def.address);
+        assert_eq!(def.unique_name.as_ref(), Some(&first));
+        assert_eq!(
+            bus.master.modules[1]
+                .get_definition()
+                .unwrap()
+                .display_name(),
+            format!("{}_0x7e", def.name)
+        );
+
+        assert!(matches!(
+            bus.master.module_by_ref(&ModuleRef::Name(def.name.clone())),
+            Err(SupMCUError::AmbiguousModule(name, addresses))
+                if name == def.name
+                    && addresses == vec![format!("{:#04x}", def.address), "0x7e".to_string()]
+        ));
+        let results = bus
+            .master
+            .send_commands(&[(ModuleRef::Name(def.name.clone()), "SUP:LED ON".into())]);
+        assert!(matches!(
+            results[0].1,
+            Err(SupMCUError::AmbiguousModule(..))
+        ));
+
+        // Commands are still prefixed with the command name
+        bus.clear_transcript();
+        let module = bus
+            .master
+            .module_by_ref_mut(&ModuleRef::Name(format!("{}_0x7e", def.name)))
+            .unwrap();
+        assert_eq!(module.address, 0x7e);
+        module.get_telemetry(TelemetryType::Module, 0).unwrap();
+        let transcript = bus.transcript();
+        assert_eq!(transcript[0].address, 0x7e);
+        assert_eq!(
+            transcript[0].kind,
+            sim::TransactionKind::Write(format!("{}:TEL? 0\n", def.name))
+        );
+    }
+
+    #[test]
+    fn rename_module_persists() {
+        let tmp_path = "test-definition.rename.tmp.json";
+        let mut bus = twin_bus();
+        bus.master.save_def_file(tmp_path).unwrap();
+        bus.master.load_def_file(Path::new(tmp_path)).unwrap();
+        let name = bus.master.modules[0].get_definition().unwrap().name.clone();
+
+        let renamed = bus
+            .master
+            .rename_module(&ModuleRef::Address(0x7e), "PAYLOAD");
+        let defs = read_def_file(Path::new(tmp_path));
+        std::fs::remove_file(tmp_path).unwrap();
+        renamed.unwrap();
+        assert_eq!(defs.unwrap()[1].unique_name.as_deref(), Some("PAYLOAD"));
+        assert!(!bus.master.is_dirty());
+        assert_eq!(
+            bus.master
+                .module_by_ref(&ModuleRef::Name("PAYLOAD".into()))
+                .unwrap()
+                .address,
+            0x7e
+        );
+
+        assert!(matches!(
+            bus.master.rename_module(&ModuleRef::Address(0x7e), &name),
+            Err(SupMCUError::DuplicateModuleName(_))
+        ));
+        let first = bus.master.modules[0]
+            .get_definition()
+            .unwrap()
+            .display_name()
+            .to_string();
+        assert!(matches!(
+            bus.master
+                .rename_module(&ModuleRef::Name("PAYLOAD".into()), &first),
+            Err(SupMCUError::DuplicateModuleName(_))
+        ));
+    }
+
+    #[test]
+    fn deferred_response_delay() {
+        let tmp_path = "test-definition.delay.tmp.json";
+        let mut bus = sim_bus(10);
+        bus.master.save_def_file(tmp_path).unwrap();
+        bus.master.load_def_file(Path::new(tmp_path)).unwrap();
+        let def = bus.master.modules[0].get_definition().unwrap().clone();
+        let saved_delay = || read_def_file(Path::new(tmp_path)).unwrap()[0].response_delay;
+
+        bus.master.modules[0].set_persist_response_delay(false);
+        for delay in [0.1, 0.2, 0.3] {
+            bus.master.response_delay(&def, delay).unwrap();
+        }
+        let deferred = saved_delay();
+        let dirty = bus.master.is_dirty();
+        let saved = bus.master.save_if_dirty().unwrap();
+        let flushed = saved_delay();
+
+        bus.master.set_persist_response_delay(true);
+        bus.master.response_delay(&def, 0.4).unwrap();
+        let immediate = saved_delay();
+        std::fs::remove_file(tmp_path).unwrap();
+
+        assert_eq!(deferred, def.response_delay);
+        assert!(dirty && saved);
+        assert_eq!(flushed, 0.3);
+        assert_eq!(immediate, 0.4);
+    }
+
+    #[test]
+    fn change_sets() {
+        let tmp_path = "test-definition.changes.tmp.json";
+        let mut bus = twin_bus();
+        bus.master.save_def_file(tmp_path).unwrap();
+        bus.master.load_def_file(Path::new(tmp_path)).unwrap();
+        // Saves the unique names the twins were given
+        bus.master.save_if_dirty().unwrap();
+        let defs = bus.master.get_definitions().unwrap();
+        let saved = || read_def_file(Path::new(tmp_path)).unwrap();
+
+        let changes = bus.master.begin_changes().unwrap();
+        let nested = bus.master.begin_changes();
+        bus.master.response_delay(&defs[0], 0.2).unwrap();
+        bus.master.response_delay(&defs[0], 0.3).unwrap();
+        bus.master
+            .rename_module(&ModuleRef::Address(0x7e), "PAYLOAD")
+            .unwrap();
+        let unsaved = saved();
+        let summary = changes.summary();
+        changes.rollback(&mut bus.master).unwrap();
+        let rolled_back = bus.master.get_definitions().unwrap();
+        let rolled_back_dirty = bus.master.is_dirty();
+
+        let changes = bus.master.begin_changes().unwrap();
+        bus.master.response_delay(&defs[1], 0.25).unwrap();
+        bus.master
+            .rename_module(&ModuleRef::Address(0x7e), "PAYLOAD")
+            .unwrap();
+        let written = changes.commit(&mut bus.master).unwrap();
+        let committed = saved();
+        let rewritten = bus.master.save_if_dirty().unwrap();
+        std::fs::remove_file(tmp_path).unwrap();
+
+        assert!(matches!(nested, Err(SupMCUError::ChangeSetOpen)));
+        assert_eq!(unsaved, defs);
+        let name = defs[0].display_name();
+        assert_eq!(
+            summary,
+            format!(
+                "{name} ({:#04X}) response_delay: {} -> 0.2\n\
+                 {name} ({:#04X}) response_delay: 0.2 -> 0.3\n\
+                 {} (0x7E) unique_name: {} -> PAYLOAD\n",
+                defs[0].address,
+                defs[0].response_delay,
+                defs[0].address,
+                defs[1].display_name(),
+                defs[1].unique_name.as_deref().unwrap_or("-"),
+            )
+        );
+        assert_eq!(rolled_back, defs);
+        assert!(!rolled_back_dirty);
+
+        assert!(written && !rewritten);
+        assert_eq!(committed[1].response_delay, 0.25);
+        assert_eq!(committed[1].unique_name.as_deref(), Some("PAYLOAD"));
+        assert_eq!(committed[0], defs[0]);
+        assert!(!bus.master.is_dirty());
+
+        // A dropped change set no longer blocks new ones
+        drop(bus.master.begin_changes().unwrap());
+        let changes = bus.master.begin_changes().unwrap();
+        let other = twin_bus().master.begin_changes().unwrap();
+        assert!(matches!(
+            other.rollback(&mut bus.master),
+            Err(SupMCUError::ChangeSetClosed)
+        ));
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn change_set_rollback_while_persisting() {
+        use bus::{BusConfig, DiscoveryPolicy, SupMCUBus};
+
+        let tmp_path = Path::new("test-definition.rollback.tmp.json");
+        let bus = sim_bus(8);
+        bus.master.save_def_file(tmp_path).unwrap();
+        let saved = || read_def_file(tmp_path).unwrap();
+        let defs = saved();
+        let config = BusConfig {
+            device: String::new(),
+            def_file: Some(tmp_path.to_path_buf()),
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: vec![],
+            health_interval_ms: Some(1),
+            diagnose: false,
+            remote_bus_items: vec![],
+            persist: true,
+        };
+        let handle = SupMCUBus::start_with_master(bus.master, config).unwrap();
+
+        let mut master = handle.master().lock().unwrap();
+        let changes = master.begin_changes().unwrap();
+        master.response_delay(&defs[0], 0.5).unwrap();
+        let explicit = master.save_def_file(tmp_path);
+        drop(master);
+        // The persist loop runs while the change set is open
+        thread::sleep(Duration::from_millis(50));
+        let unsaved = saved();
+        let mut master = handle.master().lock().unwrap();
+        changes.rollback(&mut master).unwrap();
+        let in_memory = master.get_definitions().unwrap();
+        drop(master);
+        handle.stop().unwrap();
+        let stopped = saved();
+        std::fs::remove_file(tmp_path).unwrap();
+
+        assert!(matches!(explicit, Err(SupMCUError::ChangeSetOpen)));
+        assert_eq!(unsaved, defs);
+        assert_eq!(stopped, in_memory);
+        assert_eq!(stopped, defs);
+    }
+
+    /// A sink collecting what's written to it, for checking logs
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedSink {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn endianness_override() {
+        let mut bus = sim_bus(17);
+        let module = &mut bus.master.modules[0];
+        let mut def = module.get_definition().unwrap().telemetry[15].clone();
+        def.format = SupMCUFormat::new("sif");
+        module.i2c_dev.definition.telemetry[15] = def.clone();
+        let values = vec![
+            SupMCUValue::U16(0x1234),
+            SupMCUValue::U32(0xdeadbeef),
+            SupMCUValue::Float(1.0),
+        ];
+        module.i2c_dev.script(&def, vec![values.clone(); 2]);
+
+        let tlm = module
+            .get_telemetry_by_def_endian(&def, Endianness::Big)
+            .unwrap();
+        assert_eq!(
+            tlm.data,
+            vec![
+                SupMCUValue::U16(0x3412),
+                SupMCUValue::U32(0xefbeadde),
+                SupMCUValue::Float(f32::from_bits(1.0f32.to_bits().swap_bytes())),
+            ]
+        );
+        // Only that read is affected
+        assert_eq!(module.get_telemetry_by_def(&def).unwrap().data, values);
+    }
+
+    #[test]
+    fn split_long_reads() {
+        let mut bus = sim_bus(18);
+        let mut module = bus.master.modules.remove(0);
+        let mut def = module.get_definition().unwrap().telemetry[15].clone();
+        def.format = SupMCUFormat::new("S");
+        def.length = Some(600);
+        module.i2c_dev.definition.telemetry[15] = def.clone();
+        let text: String = (0..599).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let value = vec![SupMCUValue::Str(text)];
+        module.i2c_dev.script(&def, vec![value.clone(); 2]);
+        // Modules have no transfer limit unless one is set
+        assert_eq!(module.max_transfer_len(), None);
+        module.set_max_transfer_len(Some(DEFAULT_MAX_TRANSFER_LEN));
+
+        bus.clear_transcript();
+        assert!(matches!(
+            module.get_telemetry_by_def(&def),
+            Err(SupMCUError::TransferTooLong {
+                size: 613,
+                limit: 255,
+                ..
+            })
+        ));
+        let reads = |bus: &sim::SimBus| -> Vec<usize> {
+            bus.transcript()
+                .iter()
+                .filter_map(|t| match &t.kind {
+                    sim::TransactionKind::Read(data) => Some(data.len()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert!(reads(&bus).is_empty());
+
+        module.get_definition_mut().unwrap().continued_reads = true;
+        module.i2c_dev.continued_reads = true;
+        bus.clear_transcript();
+        assert_eq!(module.get_telemetry_by_def(&def).unwrap().data, value);
+        assert_eq!(reads(&bus), vec![255, 255, 103]);
+    }
+
+    #[test]
+    fn change_counter_polls() {
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, PollEntry, SupMCUBus};
+
+        let mut bus = sim_bus(20);
+        let mut module = bus.master.modules.remove(0);
+        assert!(matches!(
+            module.change_counter(),
+            Err(SupMCUError::NoChangeCounter(_))
+        ));
+        let mut def = module.get_definition().unwrap().clone();
+        let idx = def
+            .get_supmcu_telemetry()
+            .iter()
+            .map(|d| d.idx)
+            .max()
+            .unwrap()
+            + 1;
+        def.telemetry.push(SupMCUTelemetryDefinition {
+            name: "Tlm changes".into(),
+            format: SupMCUFormat::new("i"),
+            idx,
+            telemetry_type: TelemetryType::SupMCU,
+            ..Default::default()
+        });
+        def.response_delay = 0.0;
+        def.change_counter = Some(idx);
+        module.i2c_dev.definition = def.clone();
+
+        // Discovery only finds the counter once its item is set
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut discovered =
+            SupMCUModule::new_test(SmallRng::seed_from_u64(1487), def.clone(), false, Some(5))
+                .unwrap();
+        let options = DiscoverOptions {
+            commands: false,
+            ..Default::default()
+        };
+        rt.block_on(discovered.discover_with_options(options.clone()))
+            .unwrap();
+        assert_eq!(discovered.get_definition().unwrap().change_counter, None);
+        discovered.set_change_counter_item("TLM CHANGES");
+        assert_eq!(
+            discovered.get_definition().unwrap().change_counter,
+            Some(idx)
+        );
+        rt.block_on(discovered.discover_with_options(options))
+            .unwrap();
+        assert_eq!(
+            discovered.get_definition().unwrap().change_counter,
+            Some(idx)
+        );
+        discovered.set_change_counter_item("Not a counter");
+        assert_eq!(discovered.get_definition().unwrap().change_counter, None);
+
+        module.set_definition(def);
+        let mut last = u32::MAX;
+        let TelemetryChange::Changed(first) =
+            module.get_all_telemetry_if_changed(&mut last).unwrap()
+        else {
+            panic!("the first call didn't sweep");
+        };
+        assert_eq!(last, 0);
+
+        // Unchanged polls only read the counter
+        bus.clear_transcript();
+        for _ in 0..3 {
+            assert_eq!(
+                module.get_all_telemetry_if_changed(&mut last).unwrap(),
+                TelemetryChange::Unchanged
+            );
+        }
+        let writes: Vec<String> = bus
+            .transcript()
+            .into_iter()
+            .filter_map(|t| match t.kind {
+                sim::TransactionKind::Write(cmd) => Some(cmd),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(writes.len(), 3);
+        assert!(writes
+            .iter()
+            .all(|cmd| cmd.contains(&format!("TEL? {idx}"))));
+
+        module.i2c_dev.change_telemetry();
+        let TelemetryChange::Changed(second) =
+            module.get_all_telemetry_if_changed(&mut last).unwrap()
+        else {
+            panic!("the change wasn't noticed");
+        };
+        assert_eq!(last, 1);
+        assert_ne!(first, second);
+
+        // The bus only delivers polled items that changed, reading the counter once per cycle
+        let address = module.address;
+        let items: Vec<String> = module.get_definition().unwrap().telemetry[1..3]
+            .iter()
+            .map(|d| d.name.clone())
+            .collect();
+        bus.master.modules.insert(0, module);
+        let config = BusConfig {
+            device: String::new(),
+            def_file: None,
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: items
+                .iter()
+                .map(|telemetry| PollEntry {
+                    module: ModuleRef::Address(address),
+                    telemetry: telemetry.clone(),
+                    interval_ms: 10,
+                })
+                .collect(),
+            health_interval_ms: None,
+            diagnose: false,
+            remote_bus_items: vec![],
+            persist: false,
+        };
+        let clock = bus.clock();
+        bus.clear_transcript();
+        let sim::SimBus { master, state, .. } = bus;
+        let mut handle = SupMCUBus::start_simulated(master, config, clock.clone()).unwrap();
+        let counter_reads = || {
+            let command = format!("SUP:TEL? {idx}\n");
+            let transcript = &sim::lock(&state).transcript;
+            transcript
+                .iter()
+                .filter(|t| t.kind == sim::TransactionKind::Write(command.clone()))
+                .count()
+        };
+        let telemetry_events = |handle: &mut bus::BusHandle<_>| {
+            queued_events(handle)
+                .iter()
+                .filter(|e| matches!(e, BusEvent::Telemetry { .. }))
+                .count()
+        };
+
+        // Both items are read on the first cycle, then nothing changes for 10 cycles
+        clock.run_for(Duration::from_millis(105));
+        assert_eq!(telemetry_events(&mut handle), 2);
+        assert_eq!(counter_reads(), 11);
+
+        handle.master().lock().unwrap().modules[0]
+            .i2c_dev
+            .change_telemetry();
+        clock.run_for(Duration::from_millis(100));
+        assert_eq!(telemetry_events(&mut handle), 2);
+        assert_eq!(counter_reads(), 21);
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn command_bytes() {
+        let mut bus = sim_bus(16);
+        let module = &mut bus.master.modules[0];
+        assert_eq!(module.command_bytes("SUP:LED ON"), b"SUP:LED ON\n");
+        assert_eq!(module.command_bytes("SUP:LED ON\n"), b"SUP:LED ON\n");
+        assert_eq!(module.command_bytes(""), b"\n");
+
+        let bytes = module.command_bytes("SUP:LED OFF");
+        module.send_command("SUP:LED OFF").unwrap();
+        assert!(matches!(
+            &bus.transcript().last().unwrap().kind,
+            sim::TransactionKind::Write(cmd) if cmd.as_bytes() == bytes
+        ));
+    }
+
+    #[test]
+    fn slim_definitions() {
+        let path = "test-slim.tmp.json";
+        let mut bus = sim_bus(15);
+        let bm = 3;
+        let mut def = bus.master.modules[bm].get_definition().unwrap().clone();
+        def.telemetry.push(SupMCUTelemetryDefinition {
+            idx: 200,
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        });
+        bus.master.modules[bm].set_definition(def.clone());
+
+        let full = serde_json::to_string(&bus.master.get_definitions().unwrap()).unwrap();
+        bus.master
+            .save_slim_def_file(path, SlimOptions::all())
+            .unwrap();
+        let slim = std::fs::read_to_string(path);
+        let loaded = bus.master.load_def_file(Path::new(path));
+        std::fs::remove_file(path).unwrap();
+        loaded.unwrap();
+        assert!(slim.unwrap().len() < full.len());
+
+        let slim_def = bus.master.modules[bm].get_definition().unwrap().clone();
+        assert_eq!(slim_def.telemetry.len(), def.telemetry.len() - 1);
+        // Action-only items have no format, but aren't placeholders
+        let mut action_def = def.clone();
+        action_def.telemetry.push(SupMCUTelemetryDefinition {
+            name: "action".into(),
+            idx: 201,
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        });
+        let slim_action_def = action_def.slim(SlimOptions::all());
+        assert_eq!(
+            slim_action_def.telemetry.len(),
+            action_def.telemetry.len() - 1
+        );
+        assert!(slim_action_def.telemetry.iter().any(|t| t.name == "action"));
+        assert!(slim_def.commands.is_empty());
+        assert!(slim_def.telemetry.iter().all(|t| !t.simulatable()));
+        // Telemetry can still be read
+        assert!(bus
+            .master
+            .get_all_telemetry()
+            .iter()
+            .flatten()
+            .all(Result::is_ok));
+
+        // Nothing was removed from the telemetry of the first module
+        assert!(matches!(
+            bus.master.modules[0].get_telemetry_by_name("not an item"),
+            Err(SupMCUError::UnknownTelemName(_))
+        ));
+        let module = &mut bus.master.modules[bm];
+        assert!(matches!(
+            module.send_known_command::<&str>(&def.commands[0].name, &[]),
+            Err(SupMCUError::Slimmed(name, _)) if name == def.name
+        ));
+        let simulatable = def.telemetry.iter().find(|t| t.simulatable()).unwrap();
+        let simulatable = slim_def.telemetry_item(simulatable.telemetry_type, simulatable.idx);
+        assert!(matches!(
+            module.check_simulated(simulatable.unwrap(), None, 0.0),
+            Err(SupMCUError::Slimmed(..))
+        ));
+
+        let kept = slim_def.telemetry[0].name.clone();
+        let kept_def = slim_def.slim(SlimOptions {
+            keep: Some(vec![kept.clone()]),
+            ..Default::default()
+        });
+        let size = |def: &SupMCUModuleDefinition| serde_json::to_string(def).unwrap().len();
+        assert!(size(&kept_def) * 10 < size(&slim_def));
+        module.set_definition(kept_def);
+        assert!(module.get_telemetry_by_name(&kept).is_ok());
+        assert!(matches!(
+            module.get_telemetry_by_name(&slim_def.telemetry[1].name),
+            Err(SupMCUError::Slimmed(..))
+        ));
+    }
+
+    #[test]
+    fn audit_raw_writes() {
+        use audit::{AuditOutcome, AuditRecord};
+
+        // There's no I2C device to open, so the writes fail after being audited
+        let mut master: SupMCUMaster<LinuxI2CDevice> =
+            SupMCUMaster::from_modules(vec![], "/dev/i2c-none".into()).unwrap();
+        let sink = SharedSink::default();
+        master.enable_audit_log(sink.clone(), AuditFormat::default());
+        assert!(master.raw_write(0x48, &[1, 2], false).is_err());
+        assert!(master.raw_write_reg(0x48, 0x10, &[3], true).is_err());
+
+        let records: Vec<AuditRecord> = sink
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.address == 0x48
+            && r.event == AuditEvent::Command
+            && r.outcome == AuditOutcome::Error
+            && r.error.is_some()));
+
+        // A panic while holding the log doesn't stop it from recording
+        let recorder = master.audit.clone().unwrap();
+        let _ = thread::spawn(move || {
+            let _log = recorder.lock();
+            panic!("poisoning the audit log");
+        })
+        .join();
+        assert!(master.raw_write(0x48, &[4], false).is_err());
+        assert_eq!(sink.lines().len(), 3);
+    }
+
+    #[test]
+    fn audit_log_file_appends() {
+        use audit::AuditRecord;
+
+        let path = "test-audit.tmp.jsonl";
+        let mut bus = sim_bus(14);
+        for _ in 0..2 {
+            bus.master
+                .enable_audit_log_file(path, AuditFormat::default())
+                .unwrap();
+            bus.master.modules[0].send_command("SUP:LED ON").unwrap();
+        }
+        bus.master.disable_audit_log();
+        // Enabled again on another sink, the numbering goes on
+        let sink = SharedSink::default();
+        bus.master
+            .enable_audit_log(sink.clone(), AuditFormat::default());
+        bus.master.modules[0].send_command("SUP:LED ON").unwrap();
+        bus.master.disable_audit_log();
+
+        // Another master appending to the file, after a crash cut a record short
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"{\"seq\":99,\"time").unwrap();
+        drop(file);
+        let mut other = sim_bus(14);
+        other
+            .master
+            .enable_audit_log_file(path, AuditFormat::default())
+            .unwrap();
+        other.master.modules[0].send_command("SUP:LED OFF").unwrap();
+        drop(other);
+
+        let contents = std::fs::read_to_string(path);
+        std::fs::remove_file(path).unwrap();
+        let contents = contents.unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let seqs: Vec<u64> = lines
+            .iter()
+            .filter_map(|line| serde_json::from_str::<AuditRecord>(line).ok())
+            .map(|r| r.seq)
+            .collect();
+        assert_eq!(seqs, vec![0, 1, 2]);
+        assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+        let sunk: AuditRecord = serde_json::from_str(&sink.lines()[0]).unwrap();
+        assert_eq!(sunk.seq, 2);
+    }
+
+    #[test]
+    fn audit_dry_runs_and_added_modules() {
+        use audit::{AuditOutcome, AuditRecord};
+
+        let mut bus = sim_bus(1479);
+        let added = sim_bus(1479).master.modules.remove(1);
+        let sink = SharedSink::default();
+        bus.master
+            .enable_audit_log(sink.clone(), AuditFormat::default());
+        bus.master.modules[0].set_dry_run(true);
+        bus.master.modules[0].send_command("SUP:LED ON").unwrap();
+        bus.master.add_module(added);
+        let added = bus.master.modules.last_mut().unwrap();
+        added.send_command("SUP:LED OFF").unwrap();
+        let added = added.address;
+
+        let records: Vec<AuditRecord> = sink
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].outcome, AuditOutcome::DryRun);
+        assert_eq!(records[0].payload.as_deref(), Some("SUP:LED ON"));
+        assert_eq!(records[1].outcome, AuditOutcome::Ok);
+        assert_eq!(records[1].address, added);
+    }
+
+    #[test]
+    fn item_counts() {
+        let mut bus = sim_bus(8);
+        let module = &mut bus.master.modules[0];
+        let def = module.get_definition().unwrap().clone();
+        assert_eq!(
+            module.telemetry_counts().unwrap(),
+            (
+                def.get_supmcu_telemetry().len() as u16,
+                def.get_module_telemetry().len() as u16
+            )
+        );
+        assert_eq!(module.command_count().unwrap(), def.commands.len() as u16);
+    }
+
+    #[test]
+    fn mock_device_errors() {
+        let mut bus = sim_bus(12);
+        let address = bus.master.modules[0].address;
+        let device = bus.device_mut(address).unwrap();
+        let mut buf = [0; 8];
+        assert!(device.read(&mut buf).is_err());
+        assert!(device.smbus_read_byte_data(0x10).is_err());
+        assert!(device.write(b"SUP:COM? 999\n").is_err());
+        assert!(device.write(b"SUP:TEL? 0,BOGUS\n").is_err());
+        // A read longer than the response fails instead of panicking
+        device.write(b"SUP:TEL? 19\n").unwrap();
+        assert!(device.read(&mut [0; 512]).is_err());
+    }
+
+    #[test]
+    fn sim_transcript_deterministic() {
+        let run = |seed| {
+            let mut bus = sim_bus(seed);
+            let addresses: Vec<u16> = bus.master.modules.iter().map(|m| m.address).collect();
+            bus.inject(
+                addresses[0],
+                sim::FaultPlan {
+                    nonready: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            for module in bus.master.modules.iter_mut() {
+                module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+                module.get_telemetry(TelemetryType::Module, 0).unwrap();
+            }
+            bus.advance(Duration::from_secs(1));
+            bus.master.modules[0]
+                .get_telemetry(TelemetryType::SupMCU, 1)
+                .unwrap();
+            bus.transcript()
         };
-        Ok(SupMCUMaster {
-            modules: addresses
-                .into_iter()
-                .map(|addr| SupMCUModule::new(device, addr, max_retries))
-                .collect::<Result<Vec<SupMCUModule<LinuxI2CDevice>>, SupMCUError>>()?,
+        let transcript = run(7);
+        assert_eq!(transcript, run(7));
+        assert_ne!(transcript, run(8));
+        // The modules' response delays passed on the virtual clock too
+        assert!(transcript.last().unwrap().at > Duration::from_secs(1));
+    }
+
+    #[test]
+    fn telemetry_stream_cancellation() {
+        use futures::StreamExt;
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut bus = sim_bus(3);
+        let module = &mut bus.master.modules[0];
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+        let token = CancellationToken::new();
+
+        rt.block_on(async {
+            let stream =
+                module.telemetry_stream(&def, Duration::from_millis(1), Some(token.clone()));
+            futures::pin_mut!(stream);
+            for _ in 0..2 {
+                assert!(stream.next().await.unwrap().is_ok());
+            }
+            token.cancel();
+            assert!(stream.next().await.is_none());
+        });
+
+        // Dropping the stream mid-read leaves nothing behind for the next read
+        bus.real_time();
+        let module = &mut bus.master.modules[0];
+        rt.block_on(async {
+            let stream = module.telemetry_stream(&def, Duration::from_millis(1), None);
+            futures::pin_mut!(stream);
+            assert!(time::timeout(Duration::from_millis(1), stream.next())
+                .await
+                .is_err());
+        });
+        let telemetry = module.get_telemetry_by_def(&def).unwrap();
+        assert_eq!(telemetry.definition, def);
+    }
+
+    #[test]
+    fn operations_mask_pauses_stream() {
+        use futures::StreamExt;
+        use ops::{OpsLevel, OpsMask};
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut bus = sim_bus(5);
+        let address = bus.master.modules[0].address;
+        let handle = bus.master.operations_mask_handle();
+
+        bus.master.set_operations_mask(
+            OpsMask::new("imaging").restrict(address, OpsLevel::TelemetryOnly),
+        );
+        // Taken out of the master so the transcript can be checked while it's streaming
+        let mut module = bus.master.modules.remove(0);
+        assert!(matches!(
+            module.send_command("SUP:LED ON"),
+            Err(SupMCUError::MaskedByOpsRule(a, rule)) if a == address && rule == "imaging"
+        ));
+        assert!(module.get_telemetry(TelemetryType::SupMCU, 0).is_ok());
+        handle.lift();
+
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+        rt.block_on(async {
+            let stream = module.telemetry_stream(&def, Duration::from_millis(1), None);
+            futures::pin_mut!(stream);
+            assert!(stream.next().await.unwrap().is_ok());
+
+            handle.set(OpsMask::new("thruster firing").restrict(address, OpsLevel::None));
+            bus.clear_transcript();
+            assert!(time::timeout(Duration::from_millis(20), stream.next())
+                .await
+                .is_err());
+            assert!(bus.transcript().iter().all(|t| t.address != address));
+
+            handle.lift();
+            assert!(stream.next().await.unwrap().is_ok());
+        });
+        assert!(bus.transcript().iter().any(|t| t.address == address));
+    }
+
+    #[test]
+    fn pause_collection() {
+        use futures::StreamExt;
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut bus = sim_bus(8);
+        let address = bus.master.modules[0].address;
+        let handle = bus.master.pause_handle();
+        let mut module = bus.master.modules.remove(0);
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+
+        handle.pause();
+        assert!(bus.master.collection_paused());
+        // Direct reads aren't paused
+        assert!(module.get_telemetry_by_def(&def).is_ok());
+        rt.block_on(async {
+            let stream = module.telemetry_stream(&def, Duration::from_millis(1), None);
+            futures::pin_mut!(stream);
+            bus.clear_transcript();
+            assert!(time::timeout(Duration::from_millis(20), stream.next())
+                .await
+                .is_err());
+            assert!(bus.transcript().iter().all(|t| t.address != address));
+
+            handle.resume();
+            assert!(stream.next().await.unwrap().is_ok());
+            handle.pause();
+            assert!(time::timeout(Duration::from_millis(20), stream.next())
+                .await
+                .is_err());
+        });
+        assert!(bus.transcript().iter().any(|t| t.address == address));
+    }
+
+    #[test]
+    fn bus_facade_from_config() {
+        use bus::{BusConfig, BusEvent, SupMCUBus};
+        use futures::StreamExt;
+
+        let config = BusConfig::load("test-bus-config.json").unwrap();
+        let rt = runtime::Builder::new_current_thread().build().unwrap();
+        let mut handle = SupMCUBus::start_with_master(sim_bus(6).master, config).unwrap();
+
+        let read = rt.block_on(async {
+            handle
+                .events()
+                .filter_map(|event| async move {
+                    match event {
+                        BusEvent::Telemetry {
+                            module, telemetry, ..
+                        } => Some((module, telemetry)),
+                        _ => None,
+                    }
+                })
+                .take(3)
+                .collect::<Vec<_>>()
+                .await
+        });
+        assert_eq!(read.len(), 3);
+        // Entries are polled in the order they're configured
+        assert_eq!(read[0].0, ModuleRef::Address(84));
+        assert_eq!(read[0].1.definition.name, "scpi_cmds_processed");
+        assert_eq!(read[1].0, ModuleRef::Name("GPS".into()));
+        assert_eq!(read[1].1.definition.name, "elapsed_time_s");
+        let latest = handle
+            .latest(&ModuleRef::Address(84), "scpi_cmds_processed")
+            .unwrap();
+        assert_eq!(latest.definition.name, "scpi_cmds_processed");
+        assert!(handle
+            .latest(&ModuleRef::Address(84), "elapsed_time_s")
+            .is_none());
+        assert_eq!(rt.block_on(handle.definitions()).unwrap().len(), 6);
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn bus_try_get_telemetry() {
+        use bus::{BusConfig, SupMCUBus};
+
+        let mut config = BusConfig::load("test-bus-config.json").unwrap();
+        config.poll.clear();
+        config.health_interval_ms = None;
+        let handle = SupMCUBus::start_with_master(sim_bus(6).master, config).unwrap();
+        let module = ModuleRef::Name("GPS".into());
+
+        let tlm = handle.try_get_telemetry(&module, "elapsed_time_s").unwrap();
+        assert_eq!(tlm.definition.name, "elapsed_time_s");
+        // Doesn't wait for another operation using the master
+        let master = handle.master().lock().unwrap();
+        assert!(matches!(
+            handle.try_get_telemetry(&module, "elapsed_time_s"),
+            Err(SupMCUError::WouldBlock)
+        ));
+        drop(master);
+        assert!(handle.try_get_telemetry(&module, "elapsed_time_s").is_ok());
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn definition_freshness() {
+        let tmp_path = Path::new("test-definition.freshness.tmp.json");
+        let defs = read_def_file(Path::new("test-definition.json")).unwrap();
+        let mut bus = sim::SimBus::new(5, vec![defs[0].clone()]).unwrap();
+        bus.master.modules[0].definition = None;
+        bus.master
+            .discover_modules_with_options(DiscoverOptions {
+                supmcu: false,
+                commands: false,
+                ..Default::default()
+            })
+            .unwrap();
+        let def = bus.master.modules[0].get_definition().unwrap().clone();
+        let version = format!("{} something", def.name);
+        assert_eq!(def.firmware_at_discovery.as_ref(), Some(&version));
+        assert!(def.age().unwrap() < Duration::from_secs(60));
+        assert!(bus
+            .master
+            .stale_definitions(Duration::from_secs(60))
+            .is_empty());
+
+        // The age is kept through saving and loading
+        bus.master.save_def_file(tmp_path).unwrap();
+        let loaded = read_def_file(tmp_path);
+        std::fs::remove_file(tmp_path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded[0].discovered_at, def.discovered_at);
+        assert!(loaded[0].age().unwrap() < Duration::from_secs(60));
+        let mut undated = loaded[0].clone();
+        undated.discovered_at = None;
+        assert!(undated.age().is_none());
+
+        // Discovery read the version, so reads don't read it again
+        let version_requests = |bus: &sim::SimBus| {
+            bus.transcript()
+                .iter()
+                .filter(|t| t.kind == sim::TransactionKind::Write("SUP:TEL? 0\n".into()))
+                .count()
+        };
+        bus.clear_transcript();
+        bus.master.modules[0]
+            .get_telemetry(TelemetryType::Module, 0)
+            .unwrap();
+        assert_eq!(version_requests(&bus), 0);
+        assert!(!bus.master.modules[0].firmware_changed());
+
+        // A module running other firmware than it was discovered with is checked only once
+        let mut bus = sim::SimBus::new(5, vec![defs[0].clone()]).unwrap();
+        bus.master.modules[0].set_definition(loaded[0].clone());
+        let address = def.address;
+        let version_def = defs[0]
+            .telemetry_item(TelemetryType::SupMCU, 0)
+            .unwrap()
+            .clone();
+        let updated = vec![SupMCUValue::Str(format!("{} updated", def.name))];
+        bus.device_mut(address)
+            .unwrap()
+            .script(&version_def, vec![updated.clone(), updated]);
+        // A version read that fails is tried again before the next read
+        let plan = sim::FaultPlan {
+            failed_reads: 1,
+            ..Default::default()
+        };
+        bus.inject(address, plan).unwrap();
+        let module = &mut bus.master.modules[0];
+        module.get_telemetry(TelemetryType::Module, 0).unwrap();
+        assert_eq!(module.current_firmware(), None);
+        assert!(!module.firmware_changed());
+        for _ in 0..3 {
+            module.get_telemetry(TelemetryType::Module, 0).unwrap();
+        }
+        assert_eq!(version_requests(&bus), 2);
+        let module = &bus.master.modules[0];
+        assert!(module.firmware_changed());
+        assert_eq!(
+            module.current_firmware(),
+            Some(format!("{} updated", def.name).as_str())
+        );
+        assert_eq!(bus.master.stale_definitions(Duration::ZERO).len(), 1);
+
+        // The bus's health checks report the change once
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, SupMCUBus};
+        use futures::StreamExt;
+        let config = BusConfig {
+            device: String::new(),
             def_file: None,
-            rt: runtime::Builder::new_multi_thread()
-                .worker_threads(2)
-                .enable_all()
-                .build()?,
-        })
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: vec![],
+            health_interval_ms: Some(1),
+            diagnose: false,
+            remote_bus_items: vec![],
+            persist: false,
+        };
+        let mut handle = SupMCUBus::start_with_master(bus.master, config).unwrap();
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let reported = rt.block_on(async {
+            let events = handle.events();
+            futures::pin_mut!(events);
+            let mut reported = vec![];
+            let mut health_checks = 0;
+            while health_checks < 5 {
+                match time::timeout(Duration::from_secs(10), events.next()).await {
+                    Ok(Some(BusEvent::Health { alive: true, .. })) => health_checks += 1,
+                    Ok(Some(BusEvent::FirmwareChanged { address, firmware })) => {
+                        reported.push((address, firmware))
+                    }
+                    event => panic!("unexpected {event:?}"),
+                }
+            }
+            reported
+        });
+        handle.stop().unwrap();
+        assert_eq!(reported, vec![(address, format!("{} updated", def.name))]);
     }
 
-    /// Initialize a SupMCUMaster with empty SupMCUModules, usually followed by discovery.
-    pub fn new<S: AsRef<str>>(
-        device: S,
-        blacklist: Option<Vec<u16>>,
-    ) -> Result<Self, SupMCUError> {
-        SupMCUMaster::new_ext(device, Some(DEFAULT_RETRIES), None, blacklist)
+    /// Takes the events a bus has queued, without waiting for more
+    fn queued_events<I>(handle: &mut bus::BusHandle<I>) -> Vec<bus::BusEvent>
+    where
+        I: I2CDevice + Send + Sync + 'static,
+    {
+        use futures::{FutureExt, StreamExt};
+
+        let events = handle.events();
+        futures::pin_mut!(events);
+        std::iter::from_fn(|| events.next().now_or_never().flatten()).collect()
+    }
+
+    #[test]
+    fn bus_polling_backpressure() {
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, PollEntry, SupMCUBus};
+
+        let bus = sim_bus(15);
+        let clock = bus.clock();
+        let sim::SimBus {
+            mut master, state, ..
+        } = bus;
+        let address = master.modules[0].address;
+        let telemetry = master.modules[0].get_definition().unwrap().telemetry[1].clone();
+        // Reads take the 50ms response delay, plus the latency while the module is slow
+        master.modules[0].i2c_dev.read_latency = Duration::from_millis(150);
+        let config = BusConfig {
+            device: String::new(),
+            def_file: None,
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: vec![PollEntry {
+                module: ModuleRef::Address(address),
+                telemetry: telemetry.name.clone(),
+                interval_ms: 100,
+            }],
+            health_interval_ms: Some(1000),
+            diagnose: false,
+            remote_bus_items: vec![],
+            persist: false,
+        };
+        let mut handle = SupMCUBus::start_simulated(master, config, clock.clone()).unwrap();
+        // Returns the first degradation or recovery, and whether the health checks after it
+        // reported the item as degraded
+        let changes = |events: &[BusEvent]| {
+            let changed = events
+                .iter()
+                .position(|e| {
+                    matches!(
+                        e,
+                        BusEvent::PollerDegraded(_) | BusEvent::PollerRecovered(_)
+                    )
+                })
+                .unwrap();
+            let health: Vec<bool> = events[changed..]
+                .iter()
+                .filter_map(|e| match e {
+                    BusEvent::Health {
+                        address: a,
+                        pollers,
+                        ..
+                    } if *a == address => Some(pollers[0].is_degraded()),
+                    BusEvent::Health { pollers, .. } => {
+                        assert!(pollers.is_empty());
+                        None
+                    }
+                    _ => None,
+                })
+                .collect();
+            (changed, health)
+        };
+
+        clock.run_for(Duration::from_secs(2));
+        let events = queued_events(&mut handle);
+        let (changed, health) = changes(&events);
+        let BusEvent::PollerDegraded(degraded) = &events[changed] else {
+            panic!("unexpected {:?}", events[changed]);
+        };
+        assert_eq!(degraded.effective_interval, Duration::from_millis(200));
+        assert!(degraded.skipped_ticks >= 2);
+        assert!(degraded.last_latency.unwrap() > degraded.target_interval);
+        assert!(!health.is_empty() && health.iter().all(|degraded| *degraded));
+        // Overlapped ticks were skipped rather than read back to back
+        let rates: Vec<f64> = events
+            .iter()
+            .filter_map(|e| match e {
+                BusEvent::Telemetry {
+                    achieved_rate_hz, ..
+                } => *achieved_rate_hz,
+                _ => None,
+            })
+            .collect();
+        assert!(!rates.is_empty() && rates.iter().all(|rate| *rate <= 10.0));
+
+        // The module speeds up again
+        handle.master().lock().unwrap().modules[0]
+            .i2c_dev
+            .read_latency = Duration::ZERO;
+        clock.run_for(Duration::from_secs(2));
+        let events = queued_events(&mut handle);
+        let (changed, health) = changes(&events);
+        let BusEvent::PollerRecovered(recovered) = &events[changed] else {
+            panic!("unexpected {:?}", events[changed]);
+        };
+        assert_eq!(recovered.effective_interval, recovered.target_interval);
+        assert!(!health.is_empty() && health.iter().all(|degraded| !*degraded));
+        assert!(!handle.poller_status()[0].is_degraded());
+        handle.stop().unwrap();
+
+        // Requests never queued up: each was sent after the previous one was answered, and
+        // no sooner than the item's interval
+        let command = format!("SUP:TEL? {}\n", telemetry.idx);
+        let transcript = sim::lock(&state).transcript.clone();
+        let polls: Vec<&sim::Transaction> = transcript
+            .iter()
+            .filter(|t| t.kind == sim::TransactionKind::Write(command.clone()))
+            .collect();
+        for pair in polls.windows(2) {
+            assert!(pair[1].at - pair[0].at >= Duration::from_millis(100));
+        }
+        for (i, t) in transcript.iter().enumerate() {
+            if let sim::TransactionKind::Write(_) = t.kind {
+                assert!(matches!(
+                    transcript.get(i + 1).map(|t| &t.kind),
+                    Some(sim::TransactionKind::Read(_)) | None
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn bus_pollers_simulated() {
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, PollEntry, PollerStatus, SupMCUBus};
+
+        let intervals = [100, 250];
+        let run = || -> (Vec<sim::Transaction>, Vec<PollerStatus>, Vec<BusEvent>) {
+            let bus = sim_bus(1462);
+            let clock = bus.clock();
+            let address = bus.master.modules[0].address;
+            let def = bus.master.modules[0].get_definition().unwrap();
+            let poll = def.telemetry[1..3]
+                .iter()
+                .zip(intervals)
+                .map(|(item, interval_ms)| PollEntry {
+                    module: ModuleRef::Address(address),
+                    telemetry: item.name.clone(),
+                    interval_ms,
+                })
+                .collect();
+            let config = BusConfig {
+                device: String::new(),
+                def_file: None,
+                discovery: DiscoveryPolicy::FileOnly,
+                poll,
+                health_interval_ms: None,
+                diagnose: false,
+                remote_bus_items: vec![],
+                persist: false,
+            };
+            let sim::SimBus { master, state, .. } = bus;
+            let mut handle = SupMCUBus::start_simulated(master, config, clock.clone()).unwrap();
+            clock.run_for(Duration::from_secs(1));
+            let statuses = handle.poller_status();
+            let events = queued_events(&mut handle);
+            handle.stop().unwrap();
+            let transcript = sim::lock(&state).transcript.clone();
+            (transcript, statuses, events)
+        };
+        let (transcript, statuses, events) = run();
+
+        // Each item is read at its own interval, at most one read of the other item late
+        let def = sim_bus(1462).master.modules[0]
+            .get_definition()
+            .unwrap()
+            .clone();
+        let mut reads = vec![];
+        for (item, interval) in def.telemetry[1..3].iter().zip(intervals) {
+            let command = format!("SUP:TEL? {}\n", item.idx);
+            let starts: Vec<Duration> = transcript
+                .iter()
+                .filter(|t| t.kind == sim::TransactionKind::Write(command.clone()))
+                .map(|t| t.at)
+                .collect();
+            for gap in starts.windows(2).map(|w| w[1] - w[0]) {
+                let interval = Duration::from_millis(interval);
+                assert!(gap >= interval && gap <= interval + Duration::from_millis(51));
+            }
+            reads.push(starts.len());
+        }
+        // The first item's schedule slips behind a read of the second three times
+        assert_eq!(reads, vec![9, 4]);
+        for (status, interval) in statuses.iter().zip(intervals) {
+            assert_eq!(status.achieved_rate_hz, Some(1000.0 / interval as f64));
+            assert!(!status.is_degraded());
+            assert_eq!(status.skipped_ticks, 0);
+        }
+        assert_eq!(events.len(), reads.iter().sum::<usize>());
+        assert!(events
+            .iter()
+            .all(|e| matches!(e, BusEvent::Telemetry { .. })));
+
+        // The virtual clock makes the polls reproducible
+        assert_eq!(run().0, transcript);
+    }
+
+    #[test]
+    fn bus_events_bounded() {
+        use bus::{BusConfig, BusEvent, DiscoveryPolicy, PollEntry, SupMCUBus, EVENT_CAPACITY};
+        use futures::StreamExt;
+
+        let sim::SimBus { master, state, .. } = sim_bus(1469);
+        let address = master.modules[0].address;
+        let telemetry = master.modules[0].get_definition().unwrap().telemetry[1]
+            .name
+            .clone();
+        let config = BusConfig {
+            device: String::new(),
+            def_file: None,
+            discovery: DiscoveryPolicy::FileOnly,
+            poll: vec![PollEntry {
+                module: ModuleRef::Address(address),
+                telemetry,
+                interval_ms: 0,
+            }],
+            health_interval_ms: None,
+            diagnose: false,
+            remote_bus_items: vec![],
+            persist: false,
+        };
+        let mut handle = SupMCUBus::start_with_master(master, config).unwrap();
+
+        // Nobody takes the events of well over a queue's worth of reads
+        let reads = || sim::lock(&state).transcript.len() / 2;
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while reads() < 3 * EVENT_CAPACITY {
+            assert!(Instant::now() < deadline, "only {} reads", reads());
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Polling stops while the master is locked, the events of its last reads sent
+        let shared = handle.master().clone();
+        let master = shared.lock().unwrap();
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let (dropped, queued) = rt.block_on(async {
+            let events = handle.events();
+            futures::pin_mut!(events);
+            let Some(BusEvent::Lagged(dropped)) = events.next().await else {
+                panic!("the dropped events weren't reported");
+            };
+            let mut queued = 0;
+            while let Ok(event) = time::timeout(Duration::from_millis(50), events.next()).await {
+                match event {
+                    Some(BusEvent::Telemetry { .. }) => queued += 1,
+                    event => panic!("unexpected {event:?}"),
+                }
+            }
+            (dropped, queued)
+        });
+        // Every read was either queued or counted as dropped
+        assert_eq!(queued, EVENT_CAPACITY);
+        assert_eq!(dropped as usize + queued, reads());
+        drop(master);
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn simulated_values() {
+        let mut bus = sim_bus(16);
+        let module = &mut bus.master.modules[0];
+        let simulated = module
+            .telemetry_def(TelemetryType::SupMCU, SIMULATED_TLM_IDX)
+            .unwrap();
+        let mut def = module.get_definition().unwrap().telemetry[15].clone();
+        def.format = SupMCUFormat::new("f");
+        def.default_sim_value = Some(vec![SupMCUValue::Float(12.5)]);
+        module.i2c_dev.definition.telemetry[15] = def.clone();
+
+        module
+            .i2c_dev
+            .script(&simulated, vec![vec![SupMCUValue::U16(1)]; 3]);
+        module.i2c_dev.script(
+            &def,
+            vec![
+                vec![SupMCUValue::Float(12.52)],
+                vec![SupMCUValue::Float(13.0)],
+                vec![SupMCUValue::Float(3.0)],
+            ],
+        );
+        let check = module.check_simulated(&def, None, 0.05).unwrap();
+        assert!(check.passed());
+        let check = module.check_simulated(&def, None, 0.05).unwrap();
+        assert!(check.active && !check.matches);
+        assert_eq!(check.actual, vec![SupMCUValue::Float(13.0)]);
+        let expected = Some(vec![SupMCUValue::Float(3.0)]);
+        assert!(module
+            .check_simulated(&def, expected, 0.0)
+            .unwrap()
+            .passed());
+
+        // Simulation isn't active
+        module
+            .i2c_dev
+            .script(&simulated, vec![vec![SupMCUValue::U16(0)]]);
+        module
+            .i2c_dev
+            .script(&def, vec![vec![SupMCUValue::Float(12.5)]]);
+        let check = module.check_simulated(&def, None, 0.0).unwrap();
+        assert!(!check.active && check.matches && !check.passed());
+
+        def.default_sim_value = None;
+        assert!(matches!(
+            module.check_simulated(&def, None, 0.0),
+            Err(SupMCUError::NotSimulatable(_))
+        ));
+    }
+
+    #[test]
+    fn telemetry_simulatable() {
+        let mut bus = sim_bus(7);
+        let module = &mut bus.master.modules[0];
+        let mut def = module.get_definition().unwrap().telemetry[1].clone();
+        assert!(!module.is_telemetry_simulatable(&def).unwrap());
+
+        def.default_sim_value = Some(vec![SupMCUValue::U32(0)]);
+        module.i2c_dev.definition.telemetry[1] = def.clone();
+        assert!(module.is_telemetry_simulatable(&def).unwrap());
+    }
+
+    #[test]
+    fn quirky_discovery_strings() {
+        let expected = read_def_file(Path::new("test-definition.json"))
+            .unwrap()
+            .remove(0);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let options = DiscoverOptions {
+            commands: false,
+            ..Default::default()
+        };
+        let quirks: [fn(&str, &str) -> String; 2] = [
+            // CR LF terminated
+            |_, s| format!("{s}\r\n"),
+            // Padded with spaces, and spaced out formats
+            |suffix, s| match suffix {
+                "FORMAT" => format!(" {}  ", s.chars().join(" ")),
+                _ => format!("{s}   "),
+            },
+        ];
+        for quirk in quirks {
+            let rng = SmallRng::seed_from_u64(1481);
+            let mut module = SupMCUModule::new_test(rng, expected.clone(), false, Some(5)).unwrap();
+            module.i2c_dev.string_quirk = Some(quirk);
+            rt.block_on(module.discover_with_options(options.clone()))
+                .unwrap();
+            let discovered = module.get_definition().unwrap();
+            assert_eq!(discovered.telemetry.len(), expected.telemetry.len());
+            for (item, expected) in discovered.telemetry.iter().zip(&expected.telemetry) {
+                assert_eq!(item.name, normalize_name(&expected.name));
+                assert_eq!(item.format, expected.format, "{}", item.name);
+            }
+            let raw = module.raw_discovery_strings();
+            assert_eq!(raw.len(), expected.telemetry.len());
+            let first = &raw[&TelemetryKey::from(&expected.telemetry[0])];
+            assert_eq!(
+                first.name.as_deref(),
+                Some(quirk("NAME", &expected.telemetry[0].name).as_str())
+            );
+            assert!(first.format.is_some());
+        }
+
+        assert_eq!(
+            discovery::sanitize_string(" Firmware\tversion \r\n"),
+            "Firmware version"
+        );
+        assert_eq!(
+            discovery::sanitize_string("line one\r\n line two"),
+            "line one line two"
+        );
+        assert_eq!(discovery::sanitize_string("two  spaces"), "two  spaces");
+        assert_eq!(discovery::sanitize_string("GPSRM 1.2  \r"), "GPSRM 1.2");
+        assert_eq!(discovery::sanitize_format(" u , s\r\n"), "u,s");
+    }
+
+    #[test]
+    fn shuffled_definition_order() {
+        let shuffled = read_def_file(Path::new("test-definition-shuffled.json"))
+            .unwrap()
+            .remove(0);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        // Discovering from a mock answering from the shuffled definition
+        let rng = SmallRng::seed_from_u64(1470);
+        let mut module =
+            SupMCUModule::new_test(rng.clone(), shuffled.clone(), false, Some(5)).unwrap();
+        let options = DiscoverOptions {
+            commands: false,
+            strict_indices: true,
+            ..Default::default()
+        };
+        rt.block_on(module.discover_with_options(options)).unwrap();
+        let discovered = module.get_definition().unwrap();
+        assert_eq!(discovered.telemetry.len(), shuffled.telemetry.len());
+        for item in discovered.telemetry.iter() {
+            let expected = shuffled
+                .telemetry_item(item.telemetry_type, item.idx)
+                .unwrap();
+            assert_eq!(item.name, normalize_name(&expected.name));
+            assert_eq!(item.format, expected.format);
+        }
+
+        // Sweeping a module loaded with the shuffled definition
+        let mut module = SupMCUModule::new_test(rng, shuffled.clone(), false, Some(5)).unwrap();
+        module.set_definition(shuffled.clone());
+        let swept: Vec<_> = rt
+            .block_on(module.get_all_telemetry_async())
+            .unwrap()
+            .into_iter()
+            .map(|t| t.unwrap().definition)
+            .collect();
+        assert_eq!(swept, shuffled.sweep_order());
+        assert!(swept
+            .windows(2)
+            .all(|w| (w[0].telemetry_type == TelemetryType::SupMCU
+                || w[1].telemetry_type == TelemetryType::Module)
+                && (w[0].telemetry_type != w[1].telemetry_type || w[0].idx < w[1].idx)));
+    }
+
+    #[test]
+    fn bounded_command_argument() {
+        let mut defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        defs.truncate(1);
+        let channels = SupMCUTelemetryDefinition {
+            name: "channel_count".into(),
+            format: SupMCUFormat::new("u"),
+            idx: defs[0].get_module_telemetry().len(),
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        };
+        defs[0].telemetry.push(channels.clone());
+        let mut command = SupMCUCommand::parse("PIM:CHAN <n>,<ON|OFF>", 0);
+        command.signature.as_mut().unwrap().params[0].bounds_from = Some(TelemetryBound {
+            telemetry_name: "channel_count".into(),
+            element: 0,
+            transform: BoundTransform::Max,
+        });
+        defs[0].commands.push(command);
+        let address = defs[0].address;
+        let mut bus = sim::SimBus::new(8, defs).unwrap();
+        bus.device_mut(address).unwrap().script(
+            &channels,
+            vec![vec![SupMCUValue::U8(8)], vec![SupMCUValue::U8(10)]],
+        );
+
+        let module = &mut bus.master.modules[0];
+        module.send_known_command("PIM:CHAN", &["8", "ON"]).unwrap();
+        bus.clear_transcript();
+        let module = &mut bus.master.modules[0];
+        assert!(matches!(
+            module.send_known_command("PIM:CHAN", &["9", "ON"]),
+            Err(SupMCUError::ArgumentOutOfRange { arg: 9, max: 8, .. })
+        ));
+        // Channels are numbered from 1
+        for arg in ["0", "-1"] {
+            let module = &mut bus.master.modules[0];
+            assert!(matches!(
+                module.send_known_command("PIM:CHAN", &[arg, "ON"]),
+                Err(SupMCUError::ArgumentOutOfRange { arg: a, min: 1, max: 8, .. })
+                    if a.to_string() == arg
+            ));
+        }
+        assert!(bus.transcript().is_empty());
+
+        // Re-reading the channel count updates the bound
+        let module = &mut bus.master.modules[0];
+        module.get_telemetry_by_name("channel_count").unwrap();
+        module.send_known_command("PIM:CHAN", &["9", "ON"]).unwrap();
+    }
+
+    #[test]
+    fn dry_run() {
+        let mut bus = sim_bus(6);
+        let address = bus.master.modules[0].address;
+        bus.master.set_dry_run(true);
+        assert!(bus.master.is_dry_run());
+        bus.clear_transcript();
+
+        let module = &mut bus.master.modules[0];
+        module.send_command("SUP:LED ON").unwrap();
+        assert!(matches!(
+            module.get_telemetry(TelemetryType::SupMCU, 0),
+            Err(SupMCUError::DryRun(a)) if a == address
+        ));
+        assert!(module
+            .send_known_command("BOGUS:CMD", &[] as &[&str])
+            .is_err());
+        assert!(bus.transcript().is_empty());
+
+        bus.master.set_dry_run(false);
+        let module = &mut bus.master.modules[0];
+        module.send_command("SUP:LED ON").unwrap();
+        assert_eq!(bus.transcript().len(), 1);
+    }
+
+    #[test]
+    fn checksum_kinds() {
+        let defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        for kind in [
+            ChecksumKind::None,
+            ChecksumKind::Crc32Cksum,
+            ChecksumKind::Crc16Ccitt,
+            ChecksumKind::Sum8,
+        ] {
+            let mut def = defs[0].clone();
+            def.checksum = kind;
+            let module =
+                SupMCUModule::new_test(SmallRng::seed_from_u64(7), def, false, Some(5)).unwrap();
+            let mut master = SupMCUMaster::from_modules(vec![module], "".into()).unwrap();
+            master
+                .discover_modules_with_options(DiscoverOptions {
+                    module: false,
+                    commands: false,
+                    ..Default::default()
+                })
+                .unwrap();
+            let module = &mut master.modules[0];
+            assert_eq!(module.get_definition().unwrap().checksum, kind);
+            module.get_telemetry(TelemetryType::SupMCU, 0).unwrap();
+
+            let wrong = match kind {
+                ChecksumKind::Crc32Cksum => ChecksumKind::Crc16Ccitt,
+                _ => ChecksumKind::Crc32Cksum,
+            };
+            module.get_definition_mut().unwrap().checksum = wrong;
+            assert!(matches!(
+                module.get_telemetry(TelemetryType::SupMCU, 0),
+                Err(SupMCUError::ValidationError { kind, .. }) if kind == wrong
+            ));
+        }
+    }
+
+    #[test]
+    fn detect_checksum_per_read() {
+        let mut defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        defs.truncate(3);
+        defs[0].checksum = ChecksumKind::Crc16Ccitt;
+        defs[1].checksum = ChecksumKind::None;
+        defs[2].checksum = ChecksumKind::Crc32Cksum;
+        let mut bus = sim::SimBus::new(12, defs).unwrap();
+
+        let mut kinds = vec![];
+        for module in bus.master.modules.iter_mut() {
+            // Nothing configured, as on a bus whose modules' layouts aren't known
+            module.get_definition_mut().unwrap().checksum = ChecksumKind::None;
+            let def = module.get_definition().unwrap().telemetry[1].clone();
+            let (tlm, kind) = module.get_telemetry_by_def_detect_checksum(&def).unwrap();
+            assert!(tlm.header.ready);
+            kinds.push(kind);
+        }
+        assert_eq!(
+            kinds,
+            [
+                ChecksumKind::Crc16Ccitt,
+                ChecksumKind::None,
+                ChecksumKind::Crc32Cksum
+            ]
+        );
+
+        let module = &mut bus.master.modules[0];
+        module.detect_checksums = true;
+        let mut response = module.last_response.clone();
+        assert!(module.verify_checksum(&response).is_ok());
+        let crc = response.len() - FOOTER_SIZE;
+        response[crc] ^= 0x01;
+        assert!(matches!(
+            module.verify_checksum(&response),
+            Err(SupMCUError::ValidationError { .. })
+        ));
+        response[crc] ^= 0x01;
+        response[0] = 0xff;
+        assert!(matches!(
+            module.verify_checksum(&response),
+            Err(SupMCUError::ValidationError { .. })
+        ));
+        // A zeroed footer is a response without a checksum, which only has its header to check
+        response[crc..].fill(0);
+        assert!(matches!(
+            module.verify_checksum(&response),
+            Err(SupMCUError::ParsingError(ParsingError::InvalidBytes(_)))
+        ));
+        response[0] = 0x01;
+        assert!(module.verify_checksum(&response).is_ok());
+        module.detect_checksums = false;
+        // Without detection the configured kind applies again
+        module.get_definition_mut().unwrap().checksum = ChecksumKind::Crc32Cksum;
+        assert!(module.get_telemetry(TelemetryType::SupMCU, 1).is_err());
+    }
+
+    #[test]
+    fn bus_usage_window() {
+        let start = Instant::now();
+        let mut usage = BusUsage::default();
+        for i in 0..20 {
+            usage.record(start + Duration::from_secs(i), Duration::from_millis(10));
+        }
+        let now = start + Duration::from_secs(19);
+        assert_eq!(
+            usage.busy_time(now, Duration::from_secs(5)),
+            Duration::from_millis(60)
+        );
+        assert_eq!(
+            usage.busy_time(now, Duration::from_secs(60)),
+            Duration::from_millis(200)
+        );
+        // Transactions older than the longest window are dropped
+        usage.record(start + Duration::from_secs(100), Duration::from_millis(10));
+        assert_eq!(usage.transactions.len(), 1);
+    }
+
+    #[test]
+    fn estimate_schedule() {
+        let mut bus = sim_bus(4);
+        let master = &mut bus.master;
+        let address = master.modules[0].address;
+        let tlm = master.modules[0]
+            .get_definition()
+            .unwrap()
+            .telemetry
+            .clone();
+        let measured = TelemetryKey::from(&tlm[1]);
+        let unmeasured = TelemetryKey::from(&tlm[2]);
+        master.modules[0]
+            .usage
+            .item_costs
+            .insert(measured, Duration::from_millis(10));
+
+        let estimate = master
+            .estimate_schedule(&[
+                (address, measured, Duration::from_millis(100)),
+                (address, unmeasured, Duration::from_secs(1)),
+            ])
+            .unwrap();
+        assert!(estimate.items[0].measured);
+        assert!((estimate.items[0].utilization - 0.1).abs() < 1e-9);
+        assert!(!estimate.items[1].measured);
+        let bytes = format!("SUP:TEL? {}", tlm[2].idx).len()
+            + 3
+            + SupMCUModule::<TestI2CDevice>::telemetry_response_size(&tlm[2]).unwrap();
+        assert_eq!(estimate.items[1].cost, I2C_BYTE_TIME * bytes as u32);
+        assert!(!estimate.exceeds_ceiling);
+
+        master.set_utilization_ceiling(0.05);
+        master.modules[0].get_telemetry_by_def(&tlm[2]).unwrap();
+        let estimate = master
+            .estimate_schedule(&[(address, measured, Duration::from_millis(100))])
+            .unwrap();
+        assert!(estimate.exceeds_ceiling);
+        assert!(master.modules[0].item_cost(&tlm[2]).is_some());
+
+        let estimate = master
+            .estimate_schedule(&[(address, measured, Duration::ZERO)])
+            .unwrap();
+        assert_eq!(estimate.utilization, 1.0);
+
+        let utilization = master.bus_utilization();
+        assert_eq!(utilization.modules.len(), master.modules.len());
+        assert!(utilization.total > 0.0);
+
+        master.set_utilization_window(Duration::ZERO);
+        let utilization = master.bus_utilization();
+        assert_eq!(utilization.window, MIN_UTILIZATION_WINDOW);
+        assert!(utilization.total.is_finite());
     }
 
-    /// Initialize a SupMCUMaster, specifying addresses of modules to interact with
-    pub fn new_with_addrs<S: AsRef<str>>(
-        device: S,
-        addresses: Vec<u16>,
-    ) -> Result<Self, SupMCUError> {
-        SupMCUMaster::new_ext(device, Some(DEFAULT_RETRIES), Some(addresses), None)
+    #[test]
+    fn macro_verification_aborts() {
+        let mut bus = sim_bus(5);
+        let address = bus.master.modules[0].address;
+        let def = bus.master.modules[0].get_definition().unwrap().telemetry[1].clone();
+        bus.device_mut(address).unwrap().script(
+            &def,
+            vec![vec![SupMCUValue::U64(1)], vec![SupMCUValue::U64(2)]],
+        );
+        let steps = vec![
+            MacroStep::Command("SUP:LED ON".into()),
+            MacroStep::Wait(Duration::from_millis(1)),
+            MacroStep::VerifyTelemetry {
+                name: def.name.clone(),
+                expected: vec![SupMCUValue::U64(1)],
+                tolerance: None,
+            },
+            MacroStep::Command("SUP:LED OFF".into()),
+        ];
+        bus.master.modules[0]
+            .get_definition_mut()
+            .unwrap()
+            .macros
+            .push(CommandMacro {
+                name: "blink".into(),
+                steps,
+            });
+
+        let report = bus.master.run_macro("blink").unwrap();
+        assert!(report.completed());
+        assert_eq!(report.steps_run, 4);
+
+        bus.clear_transcript();
+        let report = bus.master.run_macro("blink").unwrap();
+        assert!(!report.completed());
+        assert_eq!(report.steps_run, 3);
+        let failure = report.failure.unwrap();
+        assert_eq!(failure.step, 2);
+        assert_eq!(failure.actual, vec![SupMCUValue::U64(2)]);
+        let writes: Vec<_> = bus
+            .transcript()
+            .into_iter()
+            .filter_map(|t| match t.kind {
+                sim::TransactionKind::Write(cmd) => Some(cmd),
+                _ => None,
+            })
+            .collect();
+        assert!(writes.contains(&"SUP:LED ON\n".to_string()));
+        assert!(!writes.contains(&"SUP:LED OFF\n".to_string()));
+
+        assert!(matches!(
+            bus.master.run_macro("missing"),
+            Err(SupMCUError::UnknownMacro(_))
+        ));
     }
 
-    /// Initialize a SupMCUMaster with modules definitions that have been saved to disk
-    pub fn new_from_file<S: AsRef<str>, P: AsRef<Path>>(
-            device: S,
-            file: P,
-        ) -> Result<Self, SupMCUError> {
-        let def_file = Some(PathBuf::from(file.as_ref()));
-        let defs: Vec<SupMCUModuleDefinition> = serde_json::from_reader(File::open(file)?)?;
-        let modules = defs
+    #[test]
+    fn emergency_stop() {
+        use ops::{EmergencyOutcome, OpsLevel, OpsMask};
+
+        let mut bus = sim_bus(5);
+        let addresses: Vec<u16> = bus.master.modules.iter().map(|m| m.address).collect();
+        let def = bus.master.modules[1].get_definition().unwrap().telemetry[1].clone();
+        bus.device_mut(addresses[1])
+            .unwrap()
+            .script(&def, vec![vec![SupMCUValue::U64(2)]]);
+        let mut safe_mode = |idx: usize, mode: SafeMode, steps: Vec<MacroStep>| {
+            let module_def = bus.master.modules[idx].get_definition_mut().unwrap();
+            module_def.safe_mode = Some(mode);
+            module_def.macros.push(CommandMacro {
+                name: "safe".into(),
+                steps,
+            });
+        };
+        safe_mode(0, SafeMode::Command("SUP:LED OFF".into()), vec![]);
+        safe_mode(
+            1,
+            SafeMode::Macro("safe".into()),
+            vec![
+                MacroStep::Command("SUP:LED OFF".into()),
+                MacroStep::VerifyTelemetry {
+                    name: def.name.clone(),
+                    expected: vec![SupMCUValue::U64(1)],
+                    tolerance: None,
+                },
+            ],
+        );
+        safe_mode(
+            2,
+            SafeMode::Macro("safe".into()),
+            vec![
+                MacroStep::Command("SUP:LED OFF".into()),
+                MacroStep::Wait(Duration::from_secs(10)),
+            ],
+        );
+        safe_mode(3, SafeMode::Macro("missing".into()), vec![]);
+
+        assert!(matches!(
+            bus.master.emergency_stop(),
+            Err(SupMCUError::EmergencyNotAllowed)
+        ));
+
+        let mask = addresses.iter().fold(OpsMask::new("eclipse"), |mask, &a| {
+            mask.restrict(a, OpsLevel::None)
+        });
+        bus.master.set_operations_mask(mask);
+        bus.master.set_dry_run(true);
+        bus.master.allow_emergency(true);
+        // The deadline is on the host's clock
+        bus.real_time();
+        bus.master
+            .set_emergency_deadline(Duration::from_millis(300));
+        bus.clear_transcript();
+        let report = bus.master.emergency_stop().unwrap();
+
+        assert!(report.elapsed_ms < 2000);
+        let outcomes: Vec<_> = report.modules.iter().map(|m| &m.outcome).collect();
+        assert_eq!(outcomes[0], &EmergencyOutcome::Sent);
+        assert!(matches!(
+            outcomes[1],
+            EmergencyOutcome::VerificationFailed { failure } if failure.actual == vec![SupMCUValue::U64(2)]
+        ));
+        assert_eq!(outcomes[2], &EmergencyOutcome::DeadlineExceeded);
+        // The wait that would end after the deadline fails without waiting for it
+        assert!(report.modules[2].elapsed_ms < 300);
+        assert!(matches!(outcomes[3], EmergencyOutcome::Error { .. }));
+        assert!(outcomes[4..]
+            .iter()
+            .all(|o| **o == EmergencyOutcome::NoSafeMode));
+        let unconfirmed: Vec<u16> = report.unconfirmed().map(|m| m.address).collect();
+        assert_eq!(unconfirmed, addresses[1..4]);
+        let written: Vec<u16> = bus
+            .transcript()
             .into_iter()
-            .map(|d| SupMCUModule::new_from_def(device.as_ref(), None, d).unwrap())
+            .filter(|t| t.kind == sim::TransactionKind::Write("SUP:LED OFF\n".into()))
+            .map(|t| t.address)
             .collect();
-        Ok(SupMCUMaster {
-            modules,
-            def_file,
-            rt: runtime::Builder::new_multi_thread()
-                .worker_threads(2)
-                .enable_all()
-                .build()?,
+        assert_eq!(written.len(), 3);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["modules"][2]["outcome"], "deadline_exceeded");
+        assert_eq!(json["deadline_ms"], 300);
 
-        })
+        // The mask and dry-run mode apply again afterwards
+        assert!(matches!(
+            bus.master.modules[0].send_command("SUP:LED ON"),
+            Err(SupMCUError::MaskedByOpsRule(..))
+        ));
     }
 
-    /// Initialize a SupMCUMaster without allowing any attempts to retry telemetry requests
-    /// that return non-ready responses.
-    pub fn new_no_retries<S: AsRef<str>>(device: S) -> Result<Self, SupMCUError> {
-        SupMCUMaster::new_ext(device, None, None, None)
+    #[test]
+    fn bus_macro_spans_modules() {
+        let mut bus = sim_bus(6);
+        let names: Vec<String> = bus.master.modules[..2]
+            .iter()
+            .map(|m| m.get_definition().unwrap().name.clone())
+            .collect();
+        bus.master.add_macro(BusMacro {
+            name: "leds".into(),
+            steps: names
+                .iter()
+                .map(|module| BusMacroStep {
+                    module: module.clone(),
+                    step: MacroStep::Command("SUP:LED ON".into()),
+                })
+                .collect(),
+        });
+        let report = bus.master.run_macro("leds").unwrap();
+        assert!(report.completed());
+        let addresses: Vec<u16> = bus.transcript().iter().map(|t| t.address).collect();
+        assert_eq!(
+            addresses,
+            vec![bus.master.modules[0].address, bus.master.modules[1].address]
+        );
+
+        // A macro naming an unknown module doesn't run any of its steps
+        bus.clear_transcript();
+        bus.master.add_macro(BusMacro {
+            name: "typo".into(),
+            steps: vec![
+                BusMacroStep {
+                    module: names[0].clone(),
+                    step: MacroStep::Command("SUP:LED ON".into()),
+                },
+                BusMacroStep {
+                    module: "nonexistent".into(),
+                    step: MacroStep::Command("SUP:LED ON".into()),
+                },
+            ],
+        });
+        assert!(matches!(
+            bus.master.run_macro("typo"),
+            Err(SupMCUError::UnknownModuleName(name)) if name == "nonexistent"
+        ));
+        assert!(bus.transcript().is_empty());
     }
-}
 
-#[cfg(test)]
-mod test {
+    #[test]
+    fn asleep_modules_fail_fast() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let keep = master.modules[0].get_definition().unwrap().name.clone();
+        let results = master.sleep_all_except(&[keep.as_str()]);
+        assert!(results
+            .iter()
+            .all(|(_, r)| matches!(r, Err(SupMCUError::NotConfigured(..)))));
+        assert!(master
+            .modules
+            .iter()
+            .all(|m| m.power_state() == PowerState::Awake));
+        for module in master.modules.iter_mut() {
+            module.set_power_commands("SUP:POW SLEEP", "SUP:POW WAKE");
+        }
+        let results = master.sleep_all_except(&[keep.as_str()]);
+        assert!(!results.is_empty());
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
 
-    use i2c::TestI2CDevice;
-    use rand::rngs::SmallRng;
-    use rand::SeedableRng;
+        let asleep = master
+            .modules
+            .iter_mut()
+            .find(|m| m.power_state() == PowerState::Asleep)
+            .unwrap();
+        let address = asleep.address;
+        let start = Instant::now();
+        assert!(matches!(
+            asleep.get_telemetry(TelemetryType::SupMCU, 0),
+            Err(SupMCUError::ModuleAsleep(a)) if a == address
+        ));
+        // Nothing was sent, so there was no response delay
+        assert!(start.elapsed() < Duration::from_millis(10));
+        assert_eq!(master.modules[0].power_state(), PowerState::Awake);
+        assert!(master.modules[0]
+            .get_telemetry(TelemetryType::SupMCU, 0)
+            .is_ok());
+    }
 
-    use super::*;
+    #[test]
+    fn shared_runtime() {
+        let app = runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        let new_master = || {
+            let mut master =
+                SupMCUMaster::new_test(SmallRng::seed_from_u64(3), false, Some(5)).unwrap();
+            master
+                .load_def_file(Path::new("test-definition.json"))
+                .unwrap();
+            master
+        };
 
-    impl SupMCUModule<TestI2CDevice> {
-        pub fn new_test(
-            rng: SmallRng,
-            def: SupMCUModuleDefinition,
-            nonreadys: bool,
-            max_retries: Option<u8>,
-        ) -> Result<Self, SupMCUError> {
-            Ok(SupMCUModule {
-                i2c_dev: Box::new(TestI2CDevice::new(rng, def, nonreadys)),
-                last_cmd: "".into(),
-                definition: None,
-                max_retries,
-                address: 0,
+        // Created, used and dropped by one of the application's tasks, the master keeps its
+        // own runtime until it's given the application's
+        let (owned, spawned, versions) = app.block_on(async {
+            let mut master = new_master();
+            let owned = master.rt.owned.is_some();
+            let spawned = master.runtime_handle().spawn(async { 7 }).await.unwrap();
+            master.set_runtime_handle(runtime::Handle::current());
+            let versions = master.for_each(|m| m.get_telemetry_async(TelemetryType::SupMCU, 0));
+            (owned, spawned, versions)
+        });
+        assert!(owned);
+        assert_eq!(spawned, 7);
+        assert!(versions.iter().all(Result::is_ok));
+
+        let mut master = new_master();
+        assert!(master.rt.owned.is_some());
+        master.set_runtime_handle(app.handle().clone());
+        assert!(master.rt.owned.is_none());
+        assert_eq!(master.runtime_handle().id(), app.handle().id());
+        let versions = app.block_on(async {
+            master.for_each(|m| m.get_telemetry_async(TelemetryType::SupMCU, 0))
+        });
+        assert!(versions.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn shared_address_sub_modules() {
+        let defs: Vec<SupMCUModuleDefinition> =
+            serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
+                .unwrap();
+        let address = defs[0].address;
+        let a = SupMCUModuleDefinition {
+            scpi_prefix: Some("A".into()),
+            ..defs[0].clone()
+        };
+        let b = SupMCUModuleDefinition {
+            address,
+            scpi_prefix: Some("B".into()),
+            ..defs[2].clone()
+        };
+        let mut bus = sim::SimBus::new(11, vec![a.clone(), b.clone()]).unwrap();
+        for module in bus.master.modules.iter_mut() {
+            module.i2c_dev.read_latency = Duration::from_millis(2);
+            module.definition = None;
+            module.set_scpi_prefix(None);
+        }
+
+        // Without a prefix the modules don't answer, and each prefix needs a module
+        let options = DiscoverOptions {
+            supmcu: false,
+            commands: false,
+            ..Default::default()
+        };
+        assert!(bus
+            .master
+            .discover_modules_with_options(options.clone())
+            .is_err());
+        let prefixes = BTreeMap::from([(address, vec!["A".to_string()])]);
+        assert!(matches!(
+            bus.master.discover_modules_with_options(DiscoverOptions {
+                prefixes,
+                ..options.clone()
+            }),
+            Err(SupMCUError::PrefixMismatch(a, 1, 2)) if a == address
+        ));
+
+        bus.clear_transcript();
+        let prefixes = BTreeMap::from([(address, vec!["A".to_string(), "B".to_string()])]);
+        bus.master
+            .discover_modules_with_options(DiscoverOptions {
+                prefixes,
+                ..options
             })
+            .unwrap();
+        let discovered = bus.master.get_definitions().unwrap();
+        for (discovered, def) in discovered.iter().zip([&a, &b]) {
+            assert_eq!(discovered.name, def.name);
+            assert_eq!(discovered.scpi_prefix, def.scpi_prefix);
+            assert_eq!(
+                discovered.get_module_telemetry().len(),
+                def.get_module_telemetry().len()
+            );
         }
 
-        pub fn update_def(&mut self) {
-            self.i2c_dev.definition = self.definition.clone().unwrap();
+        // Each request is answered before the other module sends its own
+        let mut pending: Option<String> = None;
+        for t in bus.transcript() {
+            match t.kind {
+                sim::TransactionKind::Write(cmd) => {
+                    let prefix = cmd.split(':').next().unwrap().to_string();
+                    if let Some(pending) = pending.replace(prefix.clone()) {
+                        assert_eq!(pending, prefix, "interleaved requests");
+                    }
+                }
+                sim::TransactionKind::Read(_) => pending = None,
+                sim::TransactionKind::Fault(_) => (),
+            }
         }
+
+        // Commands wait for the other module's transaction too, and not for the module's own
+        let (first, second) = bus.master.modules.split_at_mut(1);
+        let transaction = first[0].address_lock.lock().unwrap();
+        first[0].send_command("SUP:TEL? 0").unwrap();
+        thread::scope(|s| {
+            let sender = s.spawn(|| {
+                second[0].send_command("SUP:TEL? 0").unwrap();
+                Instant::now()
+            });
+            thread::sleep(Duration::from_millis(20));
+            let released = Instant::now();
+            drop(transaction);
+            assert!(sender.join().unwrap() >= released);
+        });
+
+        // The modules are looked up by name, not by their shared address
+        assert!(matches!(
+            bus.master.module_by_ref(&ModuleRef::Address(address)),
+            Err(SupMCUError::AmbiguousModule(_, names))
+                if names == vec![a.name.clone(), b.name.clone()]
+        ));
+        let module = bus
+            .master
+            .module_by_ref(&ModuleRef::Name(b.name.clone()))
+            .unwrap();
+        assert_eq!(module.scpi_prefix(), Some("B"));
+
+        // Definitions with the same address are only duplicates with the same prefix
+        let open = |def: SupMCUModuleDefinition| -> Result<_, SupMCUError> {
+            let rng = SmallRng::seed_from_u64(0);
+            let mut module = SupMCUModule::new_test(rng, def.clone(), false, None)?;
+            module.set_definition(def);
+            Ok(module)
+        };
+        let defs = vec![a.clone(), b.clone()];
+        let mut master = SupMCUMaster::from_defs(defs, "".into(), None, true, open).unwrap();
+        assert_eq!(
+            master.modules[1].command_bytes("SUP:TEL? 0"),
+            b"B:SUP:TEL? 0\n"
+        );
+        master.modules[0]
+            .get_telemetry(TelemetryType::SupMCU, 0)
+            .unwrap();
+        assert!(matches!(
+            SupMCUMaster::from_defs(vec![a.clone(), a], "".into(), None, true, open),
+            Err(SupMCUError::DuplicateAddress(_))
+        ));
     }
 
-    impl SupMCUMaster<TestI2CDevice> {
-        pub fn new_test(
-            rng: SmallRng,
-            nonreadys: bool,
-            max_retries: Option<u8>,
-        ) -> Result<Self, SupMCUError> {
-            let defs: Vec<SupMCUModuleDefinition> =
-                serde_json::from_reader(File::open(Path::new("test-definition.json"))?)?;
+    #[test]
+    fn load_def_file_shared_address() {
+        let tmp_path = Path::new("test-definition.shared.tmp.json");
+        let mut defs = read_def_file(Path::new("test-definition.json")).unwrap();
+        defs[1].address = defs[0].address;
+        defs[0].scpi_prefix = Some("A".into());
+        defs[1].scpi_prefix = Some("B".into());
+        write_def_file(tmp_path, &defs).unwrap();
+        let mut master = SupMCUMaster::new_test(SmallRng::seed_from_u64(0), false, None).unwrap();
+        master.load_def_file(tmp_path).unwrap();
 
-            Ok(SupMCUMaster {
-                modules: defs
-                    .into_iter()
-                    .map(|def| {
-                        SupMCUModule::new_test(rng.clone(), def, nonreadys, max_retries)
-                    })
-                    .collect::<Result<Vec<SupMCUModule<TestI2CDevice>>, SupMCUError>>()?,
-                def_file: None,
-                rt: runtime::Builder::new_multi_thread()
-                    .worker_threads(2)
-                    .enable_all()
-                    .build()?,
+        // The modules moved to one address share its lock
+        let lock = |i: usize| master.modules[i].address_lock.shared.clone();
+        assert!(Arc::ptr_eq(&lock(0).unwrap(), &lock(1).unwrap()));
+        assert!(lock(2).is_none());
+
+        // Every definition needs a module
+        defs.push(defs[2].clone());
+        write_def_file(tmp_path, &defs).unwrap();
+        let mut master = SupMCUMaster::new_test(SmallRng::seed_from_u64(0), false, None).unwrap();
+        let loaded = master.load_def_file(tmp_path);
+        std::fs::remove_file(tmp_path).unwrap();
+        assert!(matches!(
+            loaded,
+            Err(SupMCUError::TooManyDefinitions {
+                definitions: 7,
+                modules: 6
             })
+        ));
+        assert!(master.modules.iter().all(|m| m.definition.is_none()));
+    }
+
+    #[test]
+    fn wake_needs_two_pings() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        assert!(matches!(
+            module.wake(Duration::from_millis(1)),
+            Err(SupMCUError::NotConfigured(..))
+        ));
+        module.set_power_commands("SUP:POW SLEEP", "SUP:POW WAKE");
+        module.set_power_state(PowerState::Asleep).unwrap();
+        module.i2c_dev.nonready_responses = 1;
+
+        assert_eq!(module.wake(Duration::from_millis(1)).unwrap(), 2);
+        assert_eq!(module.power_state(), PowerState::Awake);
+        assert!(module.get_telemetry(TelemetryType::SupMCU, 0).is_ok());
+    }
+
+    #[test]
+    fn standard_items_cover_discovery() {
+        let items = standard_telemetry_items();
+        assert!(items.windows(2).all(|w| w[0].0 < w[1].0));
+        for premade in [
+            discovery::PremadeTelemetryDefs::TlmAmount,
+            discovery::PremadeTelemetryDefs::CmdAmount,
+            discovery::PremadeTelemetryDefs::McuId,
+        ] {
+            let def: SupMCUTelemetryDefinition = premade.into();
+            assert!(items.iter().any(|(idx, _)| *idx == def.idx));
         }
     }
 
     #[test]
-    fn discover_module() {
+    fn dump_large_string() {
         let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let module = &mut master.modules[0];
+        let def = SupMCUTelemetryDefinition {
+            name: "log_buffer".into(),
+            format: SupMCUFormat::new("S"),
+            length: Some(4097),
+            idx: module
+                .get_definition()
+                .unwrap()
+                .get_module_telemetry()
+                .len(),
+            telemetry_type: TelemetryType::Module,
+            ..Default::default()
+        };
+        module
+            .get_definition_mut()
+            .unwrap()
+            .telemetry
+            .push(def.clone());
+        module.update_def();
+        let log: String = (0..4096).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let value = vec![SupMCUValue::Str(log.clone())];
+        module
+            .i2c_dev
+            .script(&def, vec![value.clone(), value.clone()]);
 
-        SupMCUMaster::new_test(rng, true, Some(5))
+        let mut out = vec![];
+        let info = module.dump_telemetry_to(&def, &mut out).unwrap();
+        assert_eq!(info.bytes_written, 4096);
+        assert_eq!(out, log.as_bytes());
+        assert_eq!(module.get_telemetry_by_def(&def).unwrap().data, value);
+
+        // A string mixed with other fields is parsed rather than written raw
+        let mixed = SupMCUTelemetryDefinition {
+            name: "tagged_log".into(),
+            format: SupMCUFormat::new("uS"),
+            length: Some(64),
+            idx: def.idx + 1,
+            ..def
+        };
+        module
+            .get_definition_mut()
             .unwrap()
-            .discover_modules()
-            .unwrap();
+            .telemetry
+            .push(mixed.clone());
+        module.update_def();
+        let value = vec![SupMCUValue::U8(0), SupMCUValue::Str("boot".into())];
+        module.i2c_dev.script(&mixed, vec![value]);
+        let mut out = vec![];
+        let info = module.dump_telemetry_to(&mixed, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0, boot\n");
+        assert_eq!(info.bytes_written, 8);
     }
 
-    /// This test should panic, but there is a small chance that it won't (causing the test to fail) because the
-    /// module returns non-ready responses randomly. Try to have larger modules in the `test_definition.json` file,
-    /// to decrease the chance of this happening.  
     #[test]
-    #[should_panic]
-    fn nonready_no_retry() {
+    fn raw_access_refuses_modules() {
         let rng = SmallRng::from_entropy();
+        let master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        let address = master.modules[0].get_address();
+        assert!(matches!(
+            master.check_raw_address(address, false),
+            Err(SupMCUError::ManagedAddress(a)) if a == address
+        ));
+        assert!(master.check_raw_address(address, true).is_ok());
+        assert!(master.check_raw_address(0x48, false).is_ok());
+    }
 
-        SupMCUMaster::new_test(rng, true, None)
-            .unwrap()
-            .discover_modules()
+    #[test]
+    fn raw_access_masked() {
+        use ops::{OpsLevel, OpsMask};
+
+        // Allowed accesses fail opening the (missing) I2C device instead
+        let mut master: SupMCUMaster<LinuxI2CDevice> =
+            SupMCUMaster::from_modules(vec![], "/dev/i2c-none".into()).unwrap();
+        master.set_operations_mask(OpsMask::new("imaging").restrict(0x48, OpsLevel::TelemetryOnly));
+        let masked = |result: Result<(), SupMCUError>| matches!(result, Err(SupMCUError::MaskedByOpsRule(0x48, rule)) if rule == "imaging");
+        assert!(masked(master.raw_write(0x48, &[1], false)));
+        assert!(masked(master.raw_write_reg(0x48, 0x10, &[1], false)));
+        assert!(masked(master.smbus_write_byte(0x48, 0x10, 1)));
+        assert!(matches!(
+            master.raw_read_reg(0x48, 0x10, 1, false),
+            Err(SupMCUError::I2CDevError { .. })
+        ));
+
+        master.set_operations_mask(OpsMask::new("imaging").restrict(0x48, OpsLevel::None));
+        assert!(masked(master.raw_read(0x48, 1, false).map(|_| ())));
+        assert!(masked(master.smbus_read_byte(0x48, 0x10).map(|_| ())));
+        assert!(matches!(
+            master.raw_read(0x49, 1, false),
+            Err(SupMCUError::I2CDevError { .. })
+        ));
+    }
+
+    #[test]
+    fn raw_block_transfers_fit_smbus() {
+        // The length is checked before opening the (missing) I2C device
+        let mut master: SupMCUMaster<LinuxI2CDevice> =
+            SupMCUMaster::from_modules(vec![], "/dev/i2c-none".into()).unwrap();
+        let too_long = |result: Result<_, SupMCUError>| {
+            matches!(
+                result,
+                Err(SupMCUError::ArgumentOutOfRange {
+                    arg: 33,
+                    max: 32,
+                    ..
+                })
+            )
+        };
+        assert!(too_long(master.raw_read_reg(0x48, 0x10, 33, false)));
+        assert!(too_long(
+            master
+                .raw_write_reg(0x48, 0x10, &[0; 33], false)
+                .map(|_| vec![])
+        ));
+        assert!(matches!(
+            master.raw_write_reg(0x48, 0x10, &[0; 32], false),
+            Err(SupMCUError::I2CDevError { .. })
+        ));
+    }
+
+    #[test]
+    fn raw_telemetry() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
             .unwrap();
+        let module = &mut master.modules[0];
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+        module
+            .i2c_dev
+            .script(&def, vec![vec![SupMCUValue::U64(0x0102)]]);
+
+        let (raw, tlm) = module.get_telemetry_raw(&def).unwrap();
+        assert_eq!(raw.len(), HEADER_SIZE + 8 + FOOTER_SIZE);
+        assert_eq!(&raw[HEADER_SIZE..HEADER_SIZE + 2], &[0x02, 0x01]);
+        assert_eq!(tlm.data, vec![SupMCUValue::U64(0x0102)]);
+        assert_eq!(module.last_raw_response(), raw.as_slice());
     }
 
     #[test]
-    fn get_telemetry_values() {
-        // Telemetry values are generated from this rng
+    fn telemetry_history() {
         let rng = SmallRng::from_entropy();
-        let mut master = SupMCUMaster::new_test(rng.clone(), false, Some(5)).unwrap();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
         master
             .load_def_file(Path::new("test-definition.json"))
             .unwrap();
-        for module in master.modules.iter_mut() {
-            // rng needs to be cloned so that each module starts with a "fresh"/unused rng initialized from the same seed
-            let mut local_rng = rng.clone();
-            for tel_def in module
-                .get_definition_mut()
-                .unwrap()
-                .telemetry
-                .clone()
-                .iter_mut()
-            {
-                // Skip telemetry items that have special purposes
-                if tel_def.telemetry_type == TelemetryType::SupMCU
-                    && (tel_def.idx == 0 || tel_def.idx == 14 || tel_def.idx == 17 || tel_def.idx ==19)
-                {
-                    continue;
-                }
-                assert_eq!(
-                    // Because both functions are using the exact same rng, the numbers generated should be the same
-                    module.get_telemetry_by_def(tel_def).unwrap().data,
-                    tel_def.format.random_data(&mut local_rng)
-                );
-            }
+        let module = &mut master.modules[0];
+        let def = module.get_definition().unwrap().telemetry[1].clone();
+        module.i2c_dev.script(
+            &def,
+            (0..6).map(|i| vec![SupMCUValue::U64(10 + 2 * i)]).collect(),
+        );
+        module.enable_history(&def, 4);
+
+        for _ in 0..6 {
+            module.get_telemetry_by_def(&def).unwrap();
         }
+        let values: Vec<SupMCUTelemetryData> = module
+            .history(&def)
+            .iter()
+            .map(|s| s.data.clone())
+            .collect();
+        assert_eq!(values, [14, 16, 18, 20].map(|v| vec![SupMCUValue::U64(v)]));
+
+        let trend = module.trend(&def, 3).unwrap();
+        assert_eq!(trend.samples, 3);
+        assert!((trend.slope - 2.0).abs() < 1e-9);
+        assert!((trend.delta - 4.0).abs() < 1e-9);
+
+        assert_eq!(module.export_history().len(), 1);
+        module.clear_history(&def);
+        assert!(module.history(&def).is_empty());
+        assert!(module.trend(&def, 3).is_none());
     }
 
     /// tests saving and loading of a bus definition
@@ -1242,4 +9572,167 @@ mod test {
             reload_master.get_definitions().unwrap(),
         );
     }
+
+    #[test]
+    fn archive_round_trip() {
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng.clone(), false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let archive = master.export_archive().unwrap();
+        assert_eq!(archive.metadata.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(archive.metadata.discovered_at, None);
+
+        let json = serde_json::to_string(&archive).unwrap();
+        let mut reload_master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        reload_master
+            .import_archive(serde_json::from_str(&json).unwrap())
+            .unwrap();
+        assert_eq!(
+            master.get_definitions().unwrap(),
+            reload_master.get_definitions().unwrap(),
+        );
+
+        let mut bogus = archive;
+        bogus.modules[0].address = 0x7f;
+        assert!(matches!(
+            reload_master.import_archive(bogus),
+            Err(SupMCUError::ModuleNotFound(_, 0x7f))
+        ));
+    }
+
+    #[test]
+    fn negative_response_delay() {
+        let tmp_path = "test-definition-delay.tmp";
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        let def = master.modules[0].get_definition().unwrap().clone();
+        assert!(matches!(
+            master.response_delay(&def, -0.5),
+            Err(SupMCUError::InvalidResponseDelay(..))
+        ));
+        assert!(matches!(
+            master.response_delay(&def, f32::NAN),
+            Err(SupMCUError::InvalidResponseDelay(..))
+        ));
+        assert!(matches!(
+            master.response_delay(&def, 1e30),
+            Err(SupMCUError::InvalidResponseDelay(..))
+        ));
+        assert_eq!(
+            master.modules[0].get_definition().unwrap().response_delay,
+            def.response_delay
+        );
+
+        let mut defs = master.get_definitions().unwrap();
+        defs[1].response_delay = -1.0;
+        write_def_file(Path::new(tmp_path), &defs).unwrap();
+        let res = master.load_def_file(Path::new(tmp_path));
+        std::fs::remove_file(tmp_path).unwrap();
+        assert!(matches!(
+            res,
+            Err(SupMCUError::InvalidResponseDelay(_, d)) if d == -1.0
+        ));
+    }
+
+    #[test]
+    fn session_replay() {
+        let (path, replay_path) = ("test-session.tmp", "test-session-replay.tmp");
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master.modules.truncate(1);
+        master.record_session(path);
+        master.discover_modules().unwrap();
+        let tlm = master.modules[0]
+            .get_telemetry(TelemetryType::SupMCU, 1)
+            .unwrap();
+        master.end_session().unwrap();
+
+        let mut replay = SupMCUMaster::from_session(path, true).unwrap();
+        replay.record_session(replay_path);
+        replay.discover_modules().unwrap();
+        let replayed = replay.modules[0]
+            .get_telemetry(TelemetryType::SupMCU, 1)
+            .unwrap();
+        replay.end_session().unwrap();
+        assert_eq!(tlm.data, replayed.data);
+        // The definitions only differ in when they were discovered
+        let undated = |defs: Vec<SupMCUModuleDefinition>| -> Vec<SupMCUModuleDefinition> {
+            defs.into_iter()
+                .map(|def| SupMCUModuleDefinition {
+                    discovered_at: None,
+                    ..def
+                })
+                .collect()
+        };
+        assert_eq!(
+            undated(master.get_definitions().unwrap()),
+            undated(replay.get_definitions().unwrap())
+        );
+        let events = |session: Session| -> Vec<(u16, SessionEvent)> {
+            session
+                .transactions
+                .into_iter()
+                .map(|t| (t.address, t.event))
+                .collect()
+        };
+        let recorded = Session::load(path).unwrap();
+        let rerecorded = Session::load(replay_path).unwrap();
+        std::fs::remove_file(replay_path).unwrap();
+        assert!(!recorded.transactions.is_empty());
+        assert_eq!(events(recorded), events(rerecorded));
+
+        // Reading in a different order than recorded diverges in strict mode
+        let mut replay = SupMCUMaster::from_session(path, true).unwrap();
+        std::fs::remove_file(path).unwrap();
+        replay.modules[0].set_definition(master.modules[0].get_definition().unwrap().clone());
+        match replay.modules[0].get_telemetry(TelemetryType::SupMCU, 2) {
+            Err(SupMCUError::SessionDivergence(..)) => {}
+            other => panic!("expected a divergence, got {other:?}"),
+        }
+        // SMBus transactions aren't recorded
+        assert!(matches!(
+            replay.modules[0].i2c_dev.smbus_read_block_data(0),
+            Err(SupMCUError::SessionDivergence(..))
+        ));
+    }
+
+    #[test]
+    fn session_saved_on_drop() {
+        let path = "test-session-drop.tmp";
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        master.modules.truncate(1);
+        master.record_session(path);
+        master.discover_modules().unwrap();
+        drop(master);
+        let saved = Session::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert!(!saved.unwrap().transactions.is_empty());
+    }
+
+    /// tests saving and loading of a bus definition as TOML
+    #[cfg(feature = "toml")]
+    #[test]
+    fn save_load_defs_toml() {
+        let tmp_path = "test-definition.tmp.toml";
+        let rng = SmallRng::from_entropy();
+        let mut master = SupMCUMaster::new_test(rng.clone(), false, Some(5)).unwrap();
+        master
+            .load_def_file(Path::new("test-definition.json"))
+            .unwrap();
+        master.save_def_file(Path::new(tmp_path)).unwrap();
+        let mut reload_master = SupMCUMaster::new_test(rng, false, Some(5)).unwrap();
+        let res = reload_master.load_def_file(Path::new(tmp_path));
+        std::fs::remove_file(tmp_path).unwrap();
+        res.unwrap();
+        assert_eq!(
+            master.get_definitions().unwrap(),
+            reload_master.get_definitions().unwrap(),
+        );
+    }
 }
@@ -0,0 +1,242 @@
+/*!
+Operations masks, for flight rules that restrict I2C traffic to some modules.
+
+An [`OpsMask`] lists the operations each module is allowed, e.g. no traffic at all to the
+propulsion module while a thruster fires.  While a mask applies, commands a module isn't
+allowed fail immediately with `MaskedByOpsRule`, without touching the bus.  Telemetry
+streams of masked modules pause instead of failing, and resume once the mask is lifted.  Raw
+I2C access, e.g. [`super::SupMCUMaster::raw_write`], is masked too: reads are queries, while
+writes need [`OpsLevel::Full`].
+
+Masks are set on a [`super::SupMCUMaster`], or through an [`OpsMaskHandle`] while the master's
+modules are borrowed, e.g. by a running telemetry stream.
+
+Telemetry collection as a whole can also be paused with a [`PauseHandle`], e.g. to keep the
+bus free for a commanding window.  Paused telemetry streams and bus pollers stop issuing reads
+until collection is resumed, while commands and one-off reads go through as usual.
+
+An emergency stop, [`super::SupMCUMaster::emergency_stop`], overrides both: it puts every
+module in the safe state its definition configures, see [`super::parsing::SafeMode`], ignoring
+the mask and dry-run mode.  It's privileged, so a master has to allow it first.
+*/
+use crate::{supmcu::parsing::VerificationFailure, SupMCUError};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// How long an emergency stop waits for modules to be safed by default, see
+/// [`super::SupMCUMaster::set_emergency_deadline`]
+pub const DEFAULT_EMERGENCY_DEADLINE: Duration = Duration::from_secs(5);
+
+/// The operations a module is allowed while an [`OpsMask`] applies
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OpsLevel {
+    /// No traffic at all
+    None,
+    /// Only queries, e.g. telemetry requests
+    TelemetryOnly,
+    /// Anything
+    #[default]
+    Full,
+}
+
+impl OpsLevel {
+    /// Returns true if `cmd` may be sent at this level.
+    ///
+    /// A command is a query if its header ends with `?`, like `SUP:TEL? 0`.
+    pub fn allows(self, cmd: &str) -> bool {
+        match self {
+            OpsLevel::None => false,
+            OpsLevel::TelemetryOnly => cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|header| header.ends_with('?')),
+            OpsLevel::Full => true,
+        }
+    }
+
+    /// Returns true if raw I2C reads, or writes if `write` is set, are allowed at this level
+    pub fn allows_raw(self, write: bool) -> bool {
+        match self {
+            OpsLevel::None => false,
+            OpsLevel::TelemetryOnly => !write,
+            OpsLevel::Full => true,
+        }
+    }
+}
+
+/// A flight rule restricting the operations of some modules
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpsMask {
+    /// The name of the rule, reported in `MaskedByOpsRule` errors
+    pub rule: String,
+    /// The operations allowed for each module address.  Modules not listed are unrestricted.
+    pub modules: HashMap<u16, OpsLevel>,
+}
+
+impl OpsMask {
+    /// Creates a mask for `rule` that doesn't restrict any module yet
+    pub fn new<S: Into<String>>(rule: S) -> Self {
+        OpsMask {
+            rule: rule.into(),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Restricts the module at `address` to `level`
+    pub fn restrict(mut self, address: u16, level: OpsLevel) -> Self {
+        self.modules.insert(address, level);
+        self
+    }
+
+    /// Returns the operations the module at `address` is allowed
+    pub fn level(&self, address: u16) -> OpsLevel {
+        self.modules.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Returns true if the mask doesn't restrict any module
+    pub fn is_lifted(&self) -> bool {
+        self.modules.values().all(|level| *level == OpsLevel::Full)
+    }
+
+    /// Fails with `MaskedByOpsRule` if `cmd` may not be sent to the module at `address`
+    pub fn check(&self, address: u16, cmd: &str) -> Result<(), SupMCUError> {
+        if self.level(address).allows(cmd) {
+            Ok(())
+        } else {
+            Err(SupMCUError::MaskedByOpsRule(address, self.rule.clone()))
+        }
+    }
+
+    /// Fails with `MaskedByOpsRule` if the device at `address` may not be read, or written if
+    /// `write` is set, with raw I2C access
+    pub fn check_raw(&self, address: u16, write: bool) -> Result<(), SupMCUError> {
+        if self.level(address).allows_raw(write) {
+            Ok(())
+        } else {
+            Err(SupMCUError::MaskedByOpsRule(address, self.rule.clone()))
+        }
+    }
+}
+
+/// A shared handle to the operations mask of a master
+#[derive(Clone, Debug)]
+pub struct OpsMaskHandle(Arc<watch::Sender<OpsMask>>);
+
+impl Default for OpsMaskHandle {
+    fn default() -> Self {
+        OpsMaskHandle(Arc::new(watch::channel(OpsMask::default()).0))
+    }
+}
+
+impl OpsMaskHandle {
+    /// Replaces the mask, resuming any paused streams the new mask allows
+    pub fn set(&self, mask: OpsMask) {
+        if mask.is_lifted() {
+            log::info!("Operations mask {:?} lifted", self.get().rule);
+        } else {
+            log::info!("Operations mask {:?} set: {:?}", mask.rule, mask.modules);
+        }
+        self.0.send_replace(mask);
+    }
+
+    /// Removes all restrictions
+    pub fn lift(&self) {
+        self.set(OpsMask::default());
+    }
+
+    /// Returns the current mask
+    pub fn get(&self) -> OpsMask {
+        self.0.borrow().clone()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<OpsMask> {
+        self.0.subscribe()
+    }
+}
+
+/// A shared handle pausing the telemetry collection of a master, see
+/// [`super::SupMCUMaster::pause_collection`]
+#[derive(Clone, Debug)]
+pub struct PauseHandle(Arc<watch::Sender<bool>>);
+
+impl Default for PauseHandle {
+    fn default() -> Self {
+        PauseHandle(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl PauseHandle {
+    /// Pauses collection.  Reads in progress finish, but no new ones are started.
+    pub fn pause(&self) {
+        if !self.0.send_replace(true) {
+            log::info!("Telemetry collection paused");
+        }
+    }
+
+    /// Resumes collection
+    pub fn resume(&self) {
+        if self.0.send_replace(false) {
+            log::info!("Telemetry collection resumed");
+        }
+    }
+
+    /// Returns true if collection is paused
+    pub fn is_paused(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.0.subscribe()
+    }
+}
+
+/// How a module came out of an emergency stop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum EmergencyOutcome {
+    /// The safe-mode commands were sent and the module's telemetry confirms it's safe
+    Verified,
+    /// The safe-mode commands were sent, with nothing configured to verify them
+    Sent,
+    /// The module has no safe mode, so nothing was done
+    NoSafeMode,
+    /// The safe-mode commands were sent, but the module's telemetry doesn't confirm it's safe
+    VerificationFailed { failure: VerificationFailure },
+    /// The module wasn't safed before the deadline
+    DeadlineExceeded,
+    /// Safing the module failed
+    Error { error: String },
+}
+
+/// The outcome of an emergency stop for a module
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModuleEmergency {
+    pub address: u16,
+    /// The display name of the module, if it has a definition
+    pub module: Option<String>,
+    #[serde(flatten)]
+    pub outcome: EmergencyOutcome,
+    pub elapsed_ms: u64,
+}
+
+/// The outcome of an emergency stop, see [`super::SupMCUMaster::emergency_stop`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct EmergencyReport {
+    pub modules: Vec<ModuleEmergency>,
+    pub deadline_ms: u64,
+    pub elapsed_ms: u64,
+}
+
+impl EmergencyReport {
+    /// Returns the modules whose safe mode couldn't be confirmed: they failed verification,
+    /// missed the deadline or failed
+    pub fn unconfirmed(&self) -> impl Iterator<Item = &ModuleEmergency> {
+        self.modules.iter().filter(|m| {
+            !matches!(
+                m.outcome,
+                EmergencyOutcome::Verified | EmergencyOutcome::Sent | EmergencyOutcome::NoSafeMode
+            )
+        })
+    }
+}
@@ -1,21 +1,31 @@
-use crate::ParsingError;
-use byteorder::{ReadBytesExt, LE};
+use crate::{ParsingError, SupMCUError};
+use byteorder::{ByteOrder, ReadBytesExt, BE, LE};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::{BufRead, Cursor};
+use std::io::Cursor;
 use std::mem::size_of;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use std::time::SystemTime;
 
-use async_graphql::{Enum, SimpleObject};
+use async_graphql::{
+    Enum, InputValueError, InputValueResult, Json, Scalar, ScalarType, SimpleObject, Value,
+};
 
 #[cfg(feature = "pumqry")]
 use clap::ValueEnum;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 use rand::rngs::SmallRng;
 
-use super::DEFAULT_RESPONSE_DELAY;
+use super::{
+    checksum::ChecksumKind, scpi::Mnemonics, DEFAULT_RESPONSE_DELAY, FOOTER_SIZE, HEADER_SIZE,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Enum)]
 #[repr(u8)]
@@ -38,6 +48,10 @@ pub enum DataType {
 }
 
 // e.g. SupMCUValue::I8.into() == 't'
+//
+// The hex types are always converted to their lowercase characters ('x' and 'z'), even though
+// 'X' and 'Z' are also parsed as Hex8 and Hex16.  The case only affects how the values are
+// displayed by the firmware, not how they are encoded, so the format is unchanged by this.
 impl Into<char> for DataType {
     fn into(self) -> char {
         self as u8 as char
@@ -90,10 +104,61 @@ impl DataType {
             DataType::Hex16 => Some(2),
         }
     }
+
+    /// Returns the name of the Rust type values of the data type are parsed into,
+    /// e.g. `"u16"` for UINT16, for generating code and documentation from definitions
+    pub fn rust_type_name(&self) -> &'static str {
+        match self {
+            DataType::Str => "String",
+            DataType::Char => "char",
+            DataType::UINT8 | DataType::Hex8 => "u8",
+            DataType::INT8 => "i8",
+            DataType::UINT16 | DataType::Hex16 => "u16",
+            DataType::INT16 => "i16",
+            DataType::UINT32 => "u32",
+            DataType::INT32 => "i32",
+            DataType::UINT64 => "u64",
+            DataType::INT64 => "i64",
+            DataType::Float => "f32",
+            DataType::Double => "f64",
+        }
+    }
+
+    /// Returns the `byteorder::ReadBytesExt` method that reads the data type, e.g.
+    /// `"read_u16::<LE>"`, or `None` for Str, which is read up to its NUL terminator.
+    ///
+    /// Char values are read with `read_u8` and converted with `as char`.
+    pub fn byteorder_read_method(&self) -> Option<&'static str> {
+        match self {
+            DataType::Str => None,
+            DataType::Char | DataType::UINT8 | DataType::Hex8 => Some("read_u8"),
+            DataType::INT8 => Some("read_i8"),
+            DataType::UINT16 | DataType::Hex16 => Some("read_u16::<LE>"),
+            DataType::INT16 => Some("read_i16::<LE>"),
+            DataType::UINT32 => Some("read_u32::<LE>"),
+            DataType::INT32 => Some("read_i32::<LE>"),
+            DataType::UINT64 => Some("read_u64::<LE>"),
+            DataType::INT64 => Some("read_i64::<LE>"),
+            DataType::Float => Some("read_f32::<LE>"),
+            DataType::Double => Some("read_f64::<LE>"),
+        }
+    }
+}
+
+/// The byte order of the numbers in telemetry data.  SupMCU modules are little-endian, and
+/// response headers always are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize, SimpleObject)]
 /// A format to describe the module telemetry data
+///
+/// A format can be empty, for action-only items whose response is just a header.  Their
+/// length is 0 and they parse to no values.
 pub struct SupMCUFormat {
     format: Vec<DataType>,
 }
@@ -108,7 +173,8 @@ impl IntoIterator for SupMCUFormat {
 }
 
 impl SupMCUFormat {
-    /// Creates a new SupMCUFormat from the valid format characters in a string
+    /// Creates a new SupMCUFormat from the valid format characters in a string.  A string
+    /// without any, like `""`, gives an empty format.
     pub fn new(fmt_str: &str) -> Self {
         let mut format = vec![];
         for c in fmt_str.chars() {
@@ -119,6 +185,11 @@ impl SupMCUFormat {
         SupMCUFormat { format }
     }
 
+    /// Returns true if the format has no types
+    pub fn is_empty(&self) -> bool {
+        self.format.is_empty()
+    }
+
     /// Returns the byte length of the data that the format
     /// specifies or `None` if there is a string type
     pub fn get_byte_length(&self) -> Option<usize> {
@@ -133,7 +204,49 @@ impl SupMCUFormat {
         Some(sum)
     }
 
+    /// Checks that the format fits a known response body length (without the header and
+    /// footer), e.g. to catch mistakes in a hand-written definition before it misparses.
+    ///
+    /// Formats containing strings have no fixed size, so their other fields only need to fit
+    /// in the body.  Fails with `LengthMismatch` otherwise.
+    pub fn validate_against_length(&self, expected_body_len: usize) -> Result<(), ParsingError> {
+        let (needed, fits) = match self.get_byte_length() {
+            Some(length) => (length, length == expected_body_len),
+            None => {
+                let fixed = self
+                    .format
+                    .iter()
+                    .filter_map(DataType::get_byte_length)
+                    .sum();
+                (fixed, fixed <= expected_body_len)
+            }
+        };
+        if fits {
+            Ok(())
+        } else {
+            Err(ParsingError::LengthMismatch {
+                format: self.get_format_str(),
+                needed,
+                actual: expected_body_len,
+            })
+        }
+    }
+
+    /// Returns the total size of a telemetry response with this format, including the header
+    /// and footer.
+    ///
+    /// Formats containing a string have no fixed size, so `string_length` is used as the data
+    /// length instead, like the `length` of a telemetry definition.  Returns `None` if the
+    /// format contains a string and no length is given.
+    pub fn response_size(&self, string_length: Option<usize>) -> Option<usize> {
+        self.get_byte_length()
+            .or(string_length)
+            .map(|length| length + HEADER_SIZE + FOOTER_SIZE)
+    }
+
     /// Returns the stored format string
+    ///
+    /// Hex types are normalized to lowercase, so a format created from `"X"` returns `"x"`.
     pub fn get_format_str(&self) -> String {
         let mut s = String::new();
         for c in self.format.as_slice() {
@@ -143,40 +256,100 @@ impl SupMCUFormat {
     }
 
     /// Parses telemetry data into a vector of `SupMCUValue`s
-    pub fn parse_data(
+    pub fn parse_data(&self, rdr: &mut Cursor<&Vec<u8>>) -> Result<Vec<SupMCUValue>, ParsingError> {
+        self.parse_data_endian(rdr, Endianness::Little)
+    }
+
+    /// Parses telemetry data into a vector of `SupMCUValue`s, reading numbers in the byte
+    /// order `endian`
+    pub fn parse_data_endian(
         &self,
         rdr: &mut Cursor<&Vec<u8>>,
+        endian: Endianness,
     ) -> Result<Vec<SupMCUValue>, ParsingError> {
-        let mut out = vec![];
+        match endian {
+            Endianness::Little => self.parse_values::<LE>(rdr),
+            Endianness::Big => self.parse_values::<BE>(rdr),
+        }
+    }
 
-        for dt in self.format.as_slice() {
-            out.push(match dt {
-                DataType::Str => {
-                    let mut buf = vec![];
-                    rdr.read_until(0, &mut buf)?;
-                    buf.pop();
-                    SupMCUValue::Str(String::from_utf8(buf)?)
+    fn parse_values<B: ByteOrder>(
+        &self,
+        rdr: &mut Cursor<&Vec<u8>>,
+    ) -> Result<Vec<SupMCUValue>, ParsingError> {
+        let data = rdr.get_ref().as_slice();
+        let start = (rdr.position() as usize).min(data.len());
+        let payload = &data[start..];
+        let ranges = self.walk(payload, start, self.format.len())?;
+        let values = self
+            .format
+            .iter()
+            .zip(&ranges)
+            .enumerate()
+            .map(|(index, (dt, range))| {
+                read_value::<B>(*dt, &payload[range.clone()])
+                    .map_err(|e| field_error(index, *dt, start + range.start, e))
+            })
+            .collect::<Result<_, _>>()?;
+        let end = ranges.last().map_or(0, |range| range.end);
+        rdr.set_position((start + end) as u64);
+        Ok(values)
+    }
+
+    /// Returns the byte range of each element of the format in `payload`, the telemetry data
+    /// without its header.
+    ///
+    /// Strings run up to and including their NUL terminator, or to the end of the payload if
+    /// it has none, so the ranges are contiguous from the start of the payload.  Fails with a
+    /// `FieldError` for the first element the payload is too short for.
+    pub fn element_ranges(&self, payload: &[u8]) -> Result<Vec<Range<usize>>, ParsingError> {
+        self.walk(payload, 0, self.format.len())
+    }
+
+    /// Returns the bytes of element `k` of the format in `payload`, as given by
+    /// `element_ranges`.  Only the elements up to `k` need to fit in the payload.
+    pub fn element_slice<'a>(&self, payload: &'a [u8], k: usize) -> Result<&'a [u8], ParsingError> {
+        if k >= self.format.len() {
+            return Err(ParsingError::InvalidBytes(format!(
+                "Format {} has no element {k}",
+                self.get_format_str()
+            )));
+        }
+        let ranges = self.walk(payload, 0, k + 1)?;
+        Ok(&payload[ranges[k].clone()])
+    }
+
+    /// Finds the ranges of the first `count` elements in `payload`.  `base` is the offset of
+    /// the payload in the buffer being parsed, for the offsets of errors.
+    fn walk(
+        &self,
+        payload: &[u8],
+        base: usize,
+        count: usize,
+    ) -> Result<Vec<Range<usize>>, ParsingError> {
+        let mut ranges = Vec::with_capacity(count);
+        let mut pos = 0;
+        for (index, dt) in self.format.iter().take(count).enumerate() {
+            let rest = &payload[pos..];
+            let len = match dt.get_byte_length() {
+                Some(len) if len > rest.len() => {
+                    let e = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                    return Err(field_error(index, *dt, base + pos, e.into()));
                 }
-                DataType::Char => SupMCUValue::Char(rdr.read_u8()? as char),
-                DataType::UINT8 => SupMCUValue::U8(rdr.read_u8()?),
-                DataType::INT8 => SupMCUValue::I8(rdr.read_i8()?),
-                DataType::UINT16 => SupMCUValue::U16(rdr.read_u16::<LE>()?),
-                DataType::INT16 => SupMCUValue::I16(rdr.read_i16::<LE>()?),
-                DataType::UINT32 => SupMCUValue::U32(rdr.read_u32::<LE>()?),
-                DataType::INT32 => SupMCUValue::I32(rdr.read_i32::<LE>()?),
-                DataType::UINT64 => SupMCUValue::U64(rdr.read_u64::<LE>()?),
-                DataType::INT64 => SupMCUValue::I64(rdr.read_i64::<LE>()?),
-                DataType::Float => SupMCUValue::Float(rdr.read_f32::<LE>()?),
-                DataType::Double => SupMCUValue::Double(rdr.read_f64::<LE>()?),
-                DataType::Hex8 => SupMCUValue::Hex8(rdr.read_u8()?),
-                DataType::Hex16 => SupMCUValue::Hex16(rdr.read_u16::<LE>()?),
-            });
+                Some(len) => len,
+                None => rest
+                    .iter()
+                    .position(|b| *b == 0)
+                    .map_or(rest.len(), |nul| nul + 1),
+            };
+            ranges.push(pos..pos + len);
+            pos += len;
         }
-        Ok(out)
+        Ok(ranges)
     }
 
     /// Generates random data as a vector of `SupMCUValue`s
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-utils"))]
     pub fn random_data(&self, rng: &mut SmallRng) -> Vec<SupMCUValue> {
         use rand::Rng;
 
@@ -204,8 +377,46 @@ impl SupMCUFormat {
     }
 }
 
+/// Wraps an error parsing element `index` of a format at byte `offset`
+fn field_error(index: usize, dt: DataType, offset: usize, error: ParsingError) -> ParsingError {
+    ParsingError::FieldError {
+        index,
+        format: dt.into(),
+        offset: offset as u64,
+        error: Box::new(error),
+    }
+}
+
+/// Reads a single value of type `dt` from its bytes, as found by `SupMCUFormat::walk`
+fn read_value<B: ByteOrder>(dt: DataType, mut rdr: &[u8]) -> Result<SupMCUValue, ParsingError> {
+    Ok(match dt {
+        DataType::Str => {
+            let s = rdr.strip_suffix(&[0]).unwrap_or(rdr);
+            SupMCUValue::Str(String::from_utf8(s.to_vec())?)
+        }
+        DataType::Char => SupMCUValue::Char(rdr.read_u8()? as char),
+        DataType::UINT8 => SupMCUValue::U8(rdr.read_u8()?),
+        DataType::INT8 => SupMCUValue::I8(rdr.read_i8()?),
+        DataType::UINT16 => SupMCUValue::U16(rdr.read_u16::<B>()?),
+        DataType::INT16 => SupMCUValue::I16(rdr.read_i16::<B>()?),
+        DataType::UINT32 => SupMCUValue::U32(rdr.read_u32::<B>()?),
+        DataType::INT32 => SupMCUValue::I32(rdr.read_i32::<B>()?),
+        DataType::UINT64 => SupMCUValue::U64(rdr.read_u64::<B>()?),
+        DataType::INT64 => SupMCUValue::I64(rdr.read_i64::<B>()?),
+        DataType::Float => SupMCUValue::Float(rdr.read_f32::<B>()?),
+        DataType::Double => SupMCUValue::Double(rdr.read_f64::<B>()?),
+        DataType::Hex8 => SupMCUValue::Hex8(rdr.read_u8()?),
+        DataType::Hex16 => SupMCUValue::Hex16(rdr.read_u16::<B>()?),
+    })
+}
+
+/// A telemetry value, serialized as `{"type": "U16", "value": 1}`
+///
+/// Variants are added along with new data types, so matches on this outside the crate need a
+/// wildcard arm, e.g. falling back to the value's `Display`.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
+#[non_exhaustive]
 pub enum SupMCUValue {
     Str(String),
     Char(char),
@@ -223,6 +434,8 @@ pub enum SupMCUValue {
     Hex16(u16),
 }
 
+/// Floats are written with the formatter's precision if it has one, e.g. `{:.2}`, which
+/// other values ignore
 impl fmt::Display for SupMCUValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -236,14 +449,88 @@ impl fmt::Display for SupMCUValue {
             SupMCUValue::I32(i) => write!(f, "{i}"),
             SupMCUValue::U64(i) => write!(f, "{i}"),
             SupMCUValue::I64(i) => write!(f, "{i}"),
-            SupMCUValue::Float(i) => write!(f, "{i}"),
-            SupMCUValue::Double(i) => write!(f, "{i}"),
+            SupMCUValue::Float(i) => match f.precision() {
+                Some(digits) => write!(f, "{i:.digits$}"),
+                None => write!(f, "{i}"),
+            },
+            SupMCUValue::Double(i) => match f.precision() {
+                Some(digits) => write!(f, "{i:.digits$}"),
+                None => write!(f, "{i}"),
+            },
             SupMCUValue::Hex8(i) => write!(f, "0x{i:x}"),
             SupMCUValue::Hex16(i) => write!(f, "0x{i:x}"),
         }
     }
 }
 
+impl SupMCUValue {
+    /// Formats the value like `Display`, with floats rounded to `digits` decimal places
+    pub fn format_with_precision(&self, digits: usize) -> String {
+        format!("{self:.digits$}")
+    }
+
+    /// Returns the value as a float, if it is numeric
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SupMCUValue::Str(_) | SupMCUValue::Char(_) => None,
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => Some(*i as f64),
+            SupMCUValue::I8(i) => Some(*i as f64),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => Some(*i as f64),
+            SupMCUValue::I16(i) => Some(*i as f64),
+            SupMCUValue::U32(i) => Some(*i as f64),
+            SupMCUValue::I32(i) => Some(*i as f64),
+            SupMCUValue::U64(i) => Some(*i as f64),
+            SupMCUValue::I64(i) => Some(*i as f64),
+            SupMCUValue::Float(i) => Some(*i as f64),
+            SupMCUValue::Double(i) => Some(*i),
+        }
+    }
+
+    /// Returns the value as an unsigned integer, if it is a non-negative integer
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            SupMCUValue::U8(i) | SupMCUValue::Hex8(i) => Some((*i).into()),
+            SupMCUValue::U16(i) | SupMCUValue::Hex16(i) => Some((*i).into()),
+            SupMCUValue::U32(i) => Some((*i).into()),
+            SupMCUValue::U64(i) => Some(*i),
+            SupMCUValue::I8(i) => (*i).try_into().ok(),
+            SupMCUValue::I16(i) => (*i).try_into().ok(),
+            SupMCUValue::I32(i) => (*i).try_into().ok(),
+            SupMCUValue::I64(i) => (*i).try_into().ok(),
+            SupMCUValue::Str(_)
+            | SupMCUValue::Char(_)
+            | SupMCUValue::Float(_)
+            | SupMCUValue::Double(_) => None,
+        }
+    }
+
+    /// Returns true if the values are equal, comparing floats within machine epsilon of their
+    /// magnitude.  NaNs are equal to each other, so they don't count as changes.
+    pub fn approx_eq(&self, other: &SupMCUValue) -> bool {
+        match (self, other) {
+            (SupMCUValue::Float(a), SupMCUValue::Float(b)) => {
+                a == b
+                    || (a.is_nan() && b.is_nan())
+                    || (a - b).abs() <= f32::EPSILON * a.abs().max(b.abs())
+            }
+            (SupMCUValue::Double(a), SupMCUValue::Double(b)) => {
+                a == b
+                    || (a.is_nan() && b.is_nan())
+                    || (a - b).abs() <= f64::EPSILON * a.abs().max(b.abs())
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Returns true if the values are equal, or numbers differing by at most `tolerance`
+    pub fn within(&self, other: &SupMCUValue, tolerance: f64) -> bool {
+        match (self.as_f64(), other.as_f64()) {
+            (Some(a), Some(b)) => self.approx_eq(other) || (a - b).abs() <= tolerance,
+            _ => self == other,
+        }
+    }
+}
+
 impl Into<Vec<u8>> for SupMCUValue {
     fn into(self) -> Vec<u8> {
         match self {
@@ -265,7 +552,7 @@ impl Into<Vec<u8>> for SupMCUValue {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SupMCUHDR {
     pub ready: bool,
     pub timestamp: u32,
@@ -282,7 +569,7 @@ impl TryFrom<&mut Cursor<&Vec<u8>>> for SupMCUHDR {
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 impl Into<Vec<u8>> for SupMCUHDR {
     fn into(self) -> Vec<u8> {
         let mut buf = vec![self.ready as u8];
@@ -293,28 +580,163 @@ impl Into<Vec<u8>> for SupMCUHDR {
 
 pub type SupMCUTelemetryData = Vec<SupMCUValue>;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A custom decoder for a telemetry item, used in place of the item's format.
+///
+/// Receives the raw data bytes of a response (without the header) and returns the parsed values.
+pub type TelemetryDecoder =
+    Arc<dyn Fn(&[u8]) -> Result<SupMCUTelemetryData, ParsingError> + Send + Sync>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SupMCUTelemetry {
     pub definition: SupMCUTelemetryDefinition,
     pub header: SupMCUHDR,
     pub data: SupMCUTelemetryData,
+    /// Host wall-clock time the response was read, if the module records it, see
+    /// [`SupMCUModule::set_host_timestamps`](crate::supmcu::SupMCUModule::set_host_timestamps)
+    #[serde(default)]
+    pub host_time: Option<SystemTime>,
 }
 
 impl SupMCUTelemetry {
+    /// Returns the fields whose values differ from a previous reading of the same item, as
+    /// `(index, previous value, value)`.
+    ///
+    /// Values are compared with [`SupMCUValue::approx_eq`].  Fields only one of the readings
+    /// has aren't listed.
+    pub fn changed_fields<'a>(
+        &'a self,
+        previous: &'a SupMCUTelemetry,
+    ) -> Vec<(usize, &'a SupMCUValue, &'a SupMCUValue)> {
+        previous
+            .data
+            .iter()
+            .zip(self.data.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| !old.approx_eq(new))
+            .map(|(i, (old, new))| (i, old, new))
+            .collect()
+    }
+
+    /// Deserializes the values into `T`, e.g. a tuple or a struct with a field per value, see
+    /// [`super::de`]
+    pub fn deserialize_into<T: serde::de::DeserializeOwned>(&self) -> Result<T, SupMCUError> {
+        Ok(super::de::from_values(&self.data)?)
+    }
+
     pub fn from_bytes(
         buff: Vec<u8>,
         def: &SupMCUTelemetryDefinition,
+    ) -> Result<Self, ParsingError> {
+        SupMCUTelemetry::from_bytes_endian(buff, def, Endianness::Little)
+    }
+
+    /// Parses a telemetry response, reading the numbers of the data in the byte order `endian`
+    pub fn from_bytes_endian(
+        buff: Vec<u8>,
+        def: &SupMCUTelemetryDefinition,
+        endian: Endianness,
     ) -> Result<Self, ParsingError> {
         let mut rdr = Cursor::new(&buff);
 
         Ok(SupMCUTelemetry {
             definition: def.clone(),
             header: SupMCUHDR::try_from(&mut rdr)?,
-            data: def.format.parse_data(&mut rdr)?,
+            data: def.format.parse_data_endian(&mut rdr, endian)?,
+            host_time: None,
+        })
+    }
+
+    /// Parses a telemetry response, decoding the data with `decoder` instead of the format
+    pub fn from_bytes_with_decoder(
+        buff: Vec<u8>,
+        def: &SupMCUTelemetryDefinition,
+        decoder: &TelemetryDecoder,
+    ) -> Result<Self, ParsingError> {
+        let mut rdr = Cursor::new(&buff);
+        let header = SupMCUHDR::try_from(&mut rdr)?;
+        let start = rdr.position() as usize;
+        let end = def
+            .format
+            .get_byte_length()
+            .or(def.length)
+            .map_or(buff.len(), |len| (start + len).min(buff.len()));
+
+        Ok(SupMCUTelemetry {
+            definition: def.clone(),
+            header,
+            data: decoder(&buff[start..end])?,
+            host_time: None,
         })
     }
 }
 
+/// Parses several telemetry responses read back-to-back in one transaction.
+///
+/// `buff` holds the responses in the order of `defs`, each a header followed by the item's
+/// data.  Items with a string format need a `length` to find where they end.  Any trailing
+/// bytes, like a footer, are ignored.
+pub fn parse_telemetry_batch(
+    defs: &[SupMCUTelemetryDefinition],
+    buff: &[u8],
+) -> Result<Vec<SupMCUTelemetry>, ParsingError> {
+    let mut start = 0;
+    defs.iter()
+        .map(|def| {
+            let length = def
+                .format
+                .get_byte_length()
+                .or(def.length)
+                .ok_or_else(|| ParsingError::MissingLengthError(def.name.clone()))?;
+            let end = start + HEADER_SIZE + length;
+            let bytes = buff.get(start..end).ok_or_else(|| {
+                ParsingError::InvalidBytes(format!(
+                    "{} needs bytes {start}..{end} of a {} byte batch",
+                    def.name,
+                    buff.len()
+                ))
+            })?;
+            start = end;
+            SupMCUTelemetry::from_bytes(bytes.to_vec(), def)
+        })
+        .collect()
+}
+
+/// Parses a telemetry response (the header followed by the data) captured outside this crate.
+///
+/// Any trailing bytes, like the footer, are ignored.
+pub fn parse_telemetry(format_str: &str, bytes: &[u8]) -> Result<SupMCUTelemetry, ParsingError> {
+    let def = SupMCUTelemetryDefinition {
+        format: SupMCUFormat::new(format_str),
+        length: Some(bytes.len().saturating_sub(HEADER_SIZE)),
+        ..Default::default()
+    };
+    SupMCUTelemetry::from_bytes(bytes.to_vec(), &def)
+}
+
+/// Parses a telemetry response written as hex, e.g. copied from a log.
+///
+/// Bytes may be separated by spaces or commas and prefixed with `0x`, so `"01 2a ff"`,
+/// `"0x01,0x2a,0xff"` and `"012aff"` are all the same response.
+pub fn parse_telemetry_hex(format_str: &str, hex: &str) -> Result<SupMCUTelemetry, ParsingError> {
+    parse_telemetry(format_str, &decode_hex(hex)?)
+}
+
+/// Decodes a hex string, ignoring separators and `0x` prefixes
+fn decode_hex(hex: &str) -> Result<Vec<u8>, ParsingError> {
+    let digits: String = hex
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|token| token.trim_start_matches("0x").trim_start_matches("0X"))
+        .collect();
+    let invalid = || ParsingError::InvalidBytes(format!("Invalid hex string {hex:?}"));
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
 #[cfg(test)]
 impl<'a> Into<&'a [u8]> for SupMCUTelemetry {
     fn into(self) -> &'a [u8] {
@@ -322,7 +744,7 @@ impl<'a> Into<&'a [u8]> for SupMCUTelemetry {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Default, Copy, Enum)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize, Default, Copy, Enum)]
 #[cfg_attr(feature = "pumqry", derive(ValueEnum))]
 #[cfg_attr(feature = "pumqry", clap(rename_all = "lower"))]
 pub enum TelemetryType {
@@ -340,31 +762,252 @@ impl fmt::Display for TelemetryType {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Copy, Enum, Default)]
+/// An inconsistency in the indices of a module's telemetry items
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum IndexProblem {
+    /// No item has this index, though it is below the module's item count
+    Missing(TelemetryType, usize),
+    /// More than one item has this index
+    Duplicate(TelemetryType, usize),
+    /// An item has this index, though it isn't below the module's item count
+    OutOfRange(TelemetryType, usize),
+    /// The item with this index has no name, so the module may not have an item there
+    Unnamed(TelemetryType, usize),
+}
+
+impl fmt::Display for IndexProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexProblem::Missing(t, idx) => write!(f, "{t} item {idx} is missing"),
+            IndexProblem::Duplicate(t, idx) => write!(f, "{t} item {idx} is duplicated"),
+            IndexProblem::OutOfRange(t, idx) => write!(f, "{t} item {idx} is out of range"),
+            IndexProblem::Unnamed(t, idx) => write!(f, "{t} item {idx} has no name"),
+        }
+    }
+}
+
+/// The microcontroller a module's supervisor runs on, as reported by the `MCU ID` SupMCU telemetry item.
+///
+/// IDs not in this list decode to `Other` when using [`McuType::from`], so new parts don't
+/// break discovery.  In GraphQL, the type is a string in the same format as `Display`.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize, Copy, Default)]
 pub enum McuType {
     #[default]
     UNKNOWN,
     PIC24EP256MC206,
     PIC24EP512MC206,
+    /// An MCU ID this version doesn't know about
+    Other(u8),
+}
+
+/// The family of a [`McuType`], which determines the firmware image format
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, Enum)]
+pub enum McuFamily {
+    PIC24,
+    Unknown,
+}
+
+impl McuType {
+    /// All the MCUs with a known ID
+    pub const KNOWN: [McuType; 2] = [McuType::PIC24EP256MC206, McuType::PIC24EP512MC206];
+
+    /// Returns the MCU ID reported by modules with this MCU
+    pub fn id(&self) -> u8 {
+        match self {
+            McuType::UNKNOWN => 0,
+            McuType::PIC24EP256MC206 => 1,
+            McuType::PIC24EP512MC206 => 2,
+            McuType::Other(id) => *id,
+        }
+    }
+
+    /// Returns the size of the program flash in KiB, if the MCU is known
+    pub fn flash_kb(&self) -> Option<u32> {
+        match self {
+            McuType::PIC24EP256MC206 => Some(256),
+            McuType::PIC24EP512MC206 => Some(512),
+            McuType::UNKNOWN | McuType::Other(_) => None,
+        }
+    }
+
+    /// Returns the size of the RAM in KiB, if the MCU is known
+    pub fn ram_kb(&self) -> Option<u32> {
+        match self {
+            McuType::PIC24EP256MC206 => Some(32),
+            McuType::PIC24EP512MC206 => Some(48),
+            McuType::UNKNOWN | McuType::Other(_) => None,
+        }
+    }
+
+    /// Returns the family of the MCU, [`McuFamily::Unknown`] if the MCU isn't known
+    pub fn family(&self) -> McuFamily {
+        match self {
+            McuType::PIC24EP256MC206 | McuType::PIC24EP512MC206 => McuFamily::PIC24,
+            McuType::UNKNOWN | McuType::Other(_) => McuFamily::Unknown,
+        }
+    }
 }
 
 impl fmt::Display for McuType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            McuType::UNKNOWN => write!(f, "UKNOWN"),
+            McuType::UNKNOWN => write!(f, "UNKNOWN"),
             McuType::PIC24EP256MC206 => write!(f, "PIC24EP256MC206"),
             McuType::PIC24EP512MC206 => write!(f, "PIC24EP512MC206"),
+            McuType::Other(id) => write!(f, "OTHER_{id}"),
         }
     }
 }
 
+impl FromStr for McuType {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "UNKNOWN" {
+            return Ok(McuType::UNKNOWN);
+        }
+        if let Some(id) = s.strip_prefix("OTHER_") {
+            let id = id
+                .parse::<u8>()
+                .map_err(|_| ParsingError::McuNameParsingError(s.to_string()))?;
+            return Ok(McuType::from(id));
+        }
+        McuType::KNOWN
+            .into_iter()
+            .find(|mcu| mcu.to_string() == s)
+            .ok_or_else(|| ParsingError::McuNameParsingError(s.to_string()))
+    }
+}
+
+#[Scalar]
+impl ScalarType for McuType {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(s) => s.parse().map_err(InputValueError::custom),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
 impl TryFrom<&u8> for McuType {
     type Error = ParsingError;
+    /// Strictly decodes an MCU ID, failing on IDs that aren't known
     fn try_from(value: &u8) -> Result<Self, Self::Error> {
-        match value {
-            1 => Ok(Self::PIC24EP256MC206),
-            2 => Ok(Self::PIC24EP512MC206),
-            _ => Err(ParsingError::McuIdParsingError(*value)),
+        match McuType::from(*value) {
+            McuType::Other(id) => Err(ParsingError::McuIdParsingError(id)),
+            mcu => Ok(mcu),
+        }
+    }
+}
+
+impl From<u8> for McuType {
+    /// Decodes an MCU ID, falling back to `Other` for IDs that aren't known
+    fn from(value: u8) -> Self {
+        if value == 0 {
+            return McuType::UNKNOWN;
+        }
+        McuType::KNOWN
+            .into_iter()
+            .find(|mcu| mcu.id() == value)
+            .unwrap_or(McuType::Other(value))
+    }
+}
+
+/// The cause of a module's last reset, decoded from the `last_processor_reset` SupMCU telemetry item.
+///
+/// The raw value is a snapshot of the PIC24 `RCON` register, so the cause is determined from
+/// its flag bits.  When several flags are set the most specific one is reported (e.g. a power-on
+/// reset also sets the brown-out flag).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResetCause {
+    /// Trap conflict (`TRAPR`)
+    TrapConflict,
+    /// Illegal opcode or uninitialized W register access (`IOPUWR`)
+    IllegalOpcode,
+    /// Configuration mismatch (`CM`)
+    ConfigMismatch,
+    /// Watchdog timer time-out (`WDTO`)
+    Watchdog,
+    /// Software `RESET` instruction (`SWR`)
+    Software,
+    /// External reset via the MCLR pin (`EXTR`)
+    External,
+    /// Power-on reset (`POR`)
+    PowerOn,
+    /// Brown-out reset (`BOR`)
+    BrownOut,
+    /// A value without any of the known reset flags
+    Other(u16),
+}
+
+impl ResetCause {
+    const TRAPR: u16 = 1 << 15;
+    const IOPUWR: u16 = 1 << 14;
+    const CM: u16 = 1 << 9;
+    const EXTR: u16 = 1 << 7;
+    const SWR: u16 = 1 << 6;
+    const WDTO: u16 = 1 << 4;
+    const BOR: u16 = 1 << 1;
+    const POR: u16 = 1;
+}
+
+impl From<u16> for ResetCause {
+    fn from(rcon: u16) -> Self {
+        let flags = [
+            (ResetCause::TRAPR, ResetCause::TrapConflict),
+            (ResetCause::IOPUWR, ResetCause::IllegalOpcode),
+            (ResetCause::CM, ResetCause::ConfigMismatch),
+            (ResetCause::WDTO, ResetCause::Watchdog),
+            (ResetCause::SWR, ResetCause::Software),
+            (ResetCause::EXTR, ResetCause::External),
+            (ResetCause::POR, ResetCause::PowerOn),
+            (ResetCause::BOR, ResetCause::BrownOut),
+        ];
+        flags
+            .into_iter()
+            .find(|(flag, _)| rcon & flag != 0)
+            .map_or(ResetCause::Other(rcon), |(_, cause)| cause)
+    }
+}
+
+impl From<u8> for ResetCause {
+    fn from(rcon: u8) -> Self {
+        ResetCause::from(rcon as u16)
+    }
+}
+
+impl fmt::Display for ResetCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResetCause::TrapConflict => write!(f, "trap conflict"),
+            ResetCause::IllegalOpcode => write!(f, "illegal opcode"),
+            ResetCause::ConfigMismatch => write!(f, "configuration mismatch"),
+            ResetCause::Watchdog => write!(f, "watchdog time-out"),
+            ResetCause::Software => write!(f, "software reset"),
+            ResetCause::External => write!(f, "external reset (MCLR)"),
+            ResetCause::PowerOn => write!(f, "power-on reset"),
+            ResetCause::BrownOut => write!(f, "brown-out reset"),
+            ResetCause::Other(rcon) => write!(f, "unknown reset ({rcon:#06x})"),
+        }
+    }
+}
+
+/// Identifies a telemetry item within a module
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TelemetryKey {
+    pub telemetry_type: TelemetryType,
+    pub idx: usize,
+}
+
+impl From<&SupMCUTelemetryDefinition> for TelemetryKey {
+    fn from(def: &SupMCUTelemetryDefinition) -> Self {
+        TelemetryKey {
+            telemetry_type: def.telemetry_type,
+            idx: def.idx,
         }
     }
 }
@@ -400,12 +1043,424 @@ impl SupMCUTelemetryDefinition {
     }
 }
 
+/// The kind of value a command parameter accepts
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ParamKind {
+    /// One of a fixed set of keywords, e.g. `<ON|OFF>`
+    Choice(Vec<String>),
+    /// A decimal integer, e.g. `<n>`
+    Integer,
+    /// A hexadecimal number, e.g. `<hex>`
+    Hex,
+    /// Any other text
+    Str,
+}
+
+impl ParamKind {
+    /// Determines the parameter kind from the text between the brackets of a placeholder
+    fn from_placeholder(placeholder: &str) -> Self {
+        let placeholder = placeholder.trim();
+        if placeholder.contains('|') {
+            return ParamKind::Choice(
+                placeholder
+                    .split('|')
+                    .map(|choice| choice.trim().to_string())
+                    .collect(),
+            );
+        }
+        let lower = placeholder.to_lowercase();
+        if lower.contains("hex") || lower.starts_with("0x") {
+            ParamKind::Hex
+        } else if matches!(
+            lower.as_str(),
+            "n" | "#" | "int" | "integer" | "num" | "number" | "value"
+        ) || lower.ends_with('#')
+        {
+            ParamKind::Integer
+        } else {
+            ParamKind::Str
+        }
+    }
+
+    /// Checks whether an argument is a valid value of this kind
+    pub fn accepts(&self, arg: &str) -> bool {
+        match self {
+            ParamKind::Choice(choices) => choices.iter().any(|c| c.eq_ignore_ascii_case(arg)),
+            ParamKind::Integer => arg.parse::<i64>().is_ok(),
+            ParamKind::Hex => {
+                u64::from_str_radix(arg.trim_start_matches("0x").trim_start_matches("0X"), 16)
+                    .is_ok()
+            }
+            ParamKind::Str => true,
+        }
+    }
+}
+
+/// How the largest valid value of an integer parameter is derived from a telemetry value, and
+/// what the smallest one is
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundTransform {
+    /// The value is the maximum, e.g. the number of channels numbered from 1
+    #[default]
+    Max,
+    /// The value is a count of items numbered from 0
+    ZeroBasedCount,
+    /// The maximum is the value plus an offset, and the minimum is 0
+    Offset(i64),
+}
+
+impl BoundTransform {
+    /// Returns the maximum for a telemetry value
+    pub fn apply(self, value: i64) -> i64 {
+        match self {
+            BoundTransform::Max => value,
+            BoundTransform::ZeroBasedCount => value - 1,
+            BoundTransform::Offset(offset) => value + offset,
+        }
+    }
+
+    /// Returns the minimum, 1 for items numbered from 1 and 0 otherwise
+    pub fn min(self) -> i64 {
+        match self {
+            BoundTransform::Max => 1,
+            BoundTransform::ZeroBasedCount | BoundTransform::Offset(_) => 0,
+        }
+    }
+}
+
+/// A telemetry value bounding an integer command parameter, e.g. the channel count bounding
+/// the channel number of `PIM:CHAN <n>,<ON|OFF>`.  Firmware silently ignores out of range
+/// values, so [`SupMCUModule::send_known_command`](crate::supmcu::SupMCUModule::send_known_command)
+/// rejects them before sending.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TelemetryBound {
+    /// The name of the telemetry item
+    pub telemetry_name: String,
+    /// The index of the value in the item's data
+    #[serde(default)]
+    pub element: usize,
+    #[serde(default)]
+    pub transform: BoundTransform,
+}
+
+/// A positional parameter of a command
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommandParam {
+    pub kind: ParamKind,
+    /// Whether the parameter was written in `[...]`
+    pub optional: bool,
+    /// The telemetry value bounding the parameter, which has to be added to the definition
+    /// by hand as modules don't report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds_from: Option<TelemetryBound>,
+}
+
+/// The parameters of a command, parsed from the notation used in the `SUP:COM?` response,
+/// e.g. `<ON|OFF>`, `<n>`, `[<hex>]`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct CommandSignature {
+    pub params: Vec<CommandParam>,
+}
+
+impl CommandSignature {
+    /// Parses the parameter part of a command signature, returning `None` if the notation
+    /// isn't recognized.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut params = vec![];
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' | ',' => continue,
+                '[' => {
+                    let inner: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                    for mut param in CommandSignature::parse(&inner)?.params {
+                        param.optional = true;
+                        params.push(param);
+                    }
+                }
+                '<' | '{' => {
+                    let close = if c == '<' { '>' } else { '}' };
+                    let inner: String = chars.by_ref().take_while(|&c| c != close).collect();
+                    params.push(CommandParam {
+                        kind: ParamKind::from_placeholder(&inner),
+                        optional: false,
+                        bounds_from: None,
+                    });
+                }
+                _ => return None,
+            }
+        }
+        Some(CommandSignature { params })
+    }
+
+    /// Validates the number and kinds of arguments against the signature
+    pub fn validate<S: AsRef<str>>(&self, args: &[S]) -> Result<(), ParsingError> {
+        let required = self.params.iter().filter(|p| !p.optional).count();
+        if args.len() < required || args.len() > self.params.len() {
+            return Err(ParsingError::CommandArgumentError(format!(
+                "expected {required}-{} arguments, got {}",
+                self.params.len(),
+                args.len()
+            )));
+        }
+        for (i, (arg, param)) in args.iter().zip(self.params.iter()).enumerate() {
+            if !param.kind.accepts(arg.as_ref()) {
+                return Err(ParsingError::CommandArgumentError(format!(
+                    "argument {i} `{}` is not a valid {:?}",
+                    arg.as_ref(),
+                    param.kind
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, SimpleObject)]
 pub struct SupMCUCommand {
     pub name: String,
+    /// The command's parameters, if the module reported them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub signature: Option<CommandSignature>,
     pub idx: u16,
 }
 
+impl SupMCUCommand {
+    /// Parses a `SUP:COM?` response, e.g. `SUPervisor:LED <ON|OFF|FLASH>`.
+    ///
+    /// If the signature can't be parsed, the whole response is kept as the name.
+    pub fn parse(response: &str, idx: u16) -> Self {
+        let response = response.trim();
+        if let Some((name, params)) = response.split_once(char::is_whitespace) {
+            if let Some(signature) = CommandSignature::parse(params) {
+                return SupMCUCommand {
+                    name: name.to_string(),
+                    signature: Some(signature),
+                    idx,
+                };
+            }
+        }
+        SupMCUCommand {
+            name: response.to_string(),
+            signature: None,
+            idx,
+        }
+    }
+}
+
+/// A step of a [`CommandMacro`]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MacroStep {
+    /// Sends a command to the module
+    Command(String),
+    /// Waits before the next step, written as a number of milliseconds
+    Wait(#[serde(with = "duration_ms")] Duration),
+    /// Reads a telemetry item and aborts the macro unless it has the expected values, with
+    /// numbers differing by at most `tolerance`, see [`MacroStep::verifies`]
+    VerifyTelemetry {
+        name: String,
+        expected: SupMCUTelemetryData,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tolerance: Option<f64>,
+    },
+}
+
+impl MacroStep {
+    /// Returns true if `actual` has the values a verification step expects.  Floats are
+    /// compared within machine epsilon of their magnitude without a `tolerance`, since values
+    /// a module computes rarely match the expected ones exactly.  Steps that aren't
+    /// verifications don't check anything.
+    pub fn verifies(&self, actual: &[SupMCUValue]) -> bool {
+        match self {
+            MacroStep::VerifyTelemetry {
+                expected,
+                tolerance,
+                ..
+            } => {
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual)
+                        .all(|(e, a)| e.within(a, tolerance.unwrap_or(0.0)))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A named sequence of commands, waits and checks, e.g. to safe a module
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CommandMacro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+/// How a module enters its safe state in an emergency stop
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SafeMode {
+    /// Sends a single command, without verification
+    Command(String),
+    /// Runs one of the module's macros, whose telemetry checks verify the module is safe
+    Macro(String),
+}
+
+/// A step of a [`BusMacro`], run on the module with the command name `module`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BusMacroStep {
+    pub module: String,
+    pub step: MacroStep,
+}
+
+/// A macro whose steps span several modules
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BusMacro {
+    pub name: String,
+    pub steps: Vec<BusMacroStep>,
+}
+
+/// A verification step of a macro that read unexpected values
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct VerificationFailure {
+    /// Index of the step in the macro
+    pub step: usize,
+    pub address: u16,
+    /// Name of the telemetry item
+    pub name: String,
+    pub expected: SupMCUTelemetryData,
+    pub actual: SupMCUTelemetryData,
+}
+
+/// The outcome of running a macro
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct MacroReport {
+    pub name: String,
+    /// The number of steps run, including a failed verification
+    pub steps_run: usize,
+    /// The verification that aborted the macro, if one failed
+    pub failure: Option<VerificationFailure>,
+    pub elapsed_ms: u64,
+}
+
+impl MacroReport {
+    /// Returns true if every step of the macro ran
+    pub fn completed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// (De)serializes a `Duration` as a whole number of milliseconds
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(d.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+/// The outcome of sending a command and (optionally) reading a telemetry item to confirm it
+#[derive(Clone, Debug, Serialize, SimpleObject)]
+pub struct CommandResult {
+    /// Whether the command was sent and its confirmation (if any) was read
+    pub accepted: bool,
+    /// The command as it was sent to the module
+    pub echoed_command: String,
+    /// The values of the confirmation telemetry item
+    pub confirmation: Option<Json<SupMCUTelemetryData>>,
+    /// Time taken to send the command and read the confirmation
+    pub elapsed_ms: u64,
+}
+
+/// Where a module's CPU self-test results item keeps its results, which depends on the
+/// firmware, see [`super::SupMCUModule::set_self_test_fields`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestFields {
+    /// The field holding the bitmask of failed tests
+    pub result: usize,
+    /// The field counting completed runs, if the firmware has one.  Without it a run is only
+    /// noticed when it changes the results.
+    pub runs: Option<usize>,
+}
+
+/// The outcome of a single self-test
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, SimpleObject)]
+pub struct SelfTestResult {
+    pub name: String,
+    pub passed: bool,
+}
+
+/// The outcome of running a module's self-test
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, SimpleObject)]
+pub struct SelfTestReport {
+    pub address: u16,
+    pub module: String,
+    /// Whether every test passed
+    pub passed: bool,
+    pub results: Vec<SelfTestResult>,
+    /// The raw result bitmask, where a set bit is a failed test
+    pub bits: u64,
+    /// Time taken to run the self-test
+    pub elapsed_ms: u64,
+}
+
+/// The outcome of checking that a module returns the simulated values of a telemetry item
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SimulationCheck {
+    /// Name of the telemetry item
+    pub name: String,
+    /// Whether the module reports that telemetry is being simulated
+    pub active: bool,
+    /// Whether the values read match the simulated ones
+    pub matches: bool,
+    pub expected: SupMCUTelemetryData,
+    pub actual: SupMCUTelemetryData,
+}
+
+impl SimulationCheck {
+    /// Returns true if simulation is active and the item returned the simulated values
+    pub fn passed(&self) -> bool {
+        self.active && self.matches
+    }
+}
+
+/// Strings of a telemetry item's discovery responses that had to be cleaned up, as the module
+/// sent them
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RawDiscoveryStrings {
+    pub name: Option<String>,
+    pub format: Option<String>,
+}
+
+/// Decodes a self-test result bitmask, where bit `i` is set if test `names[i]` failed.
+///
+/// Failed bits without a name are reported as `bit <i>`.
+pub fn decode_self_test<S: AsRef<str>>(bits: u64, names: &[S]) -> Vec<SelfTestResult> {
+    (0..64)
+        .filter_map(|i| {
+            let passed = bits & (1 << i) == 0;
+            match names.get(i) {
+                Some(name) => Some(SelfTestResult {
+                    name: name.as_ref().to_string(),
+                    passed,
+                }),
+                None if !passed => Some(SelfTestResult {
+                    name: format!("bit {i}"),
+                    passed,
+                }),
+                None => None,
+            }
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, SimpleObject)]
 pub struct SupMCUModuleDefinition {
     /// This is the prefix to every SCPI MODULE command (e.g. `{cmd_name}:TEL? 15`)
@@ -416,6 +1471,184 @@ pub struct SupMCUModuleDefinition {
     pub commands: Vec<SupMCUCommand>,
     pub mcu: McuType,
     pub response_delay: f32,
+    /// Module-specific long form mnemonics and their short forms, used to match commands
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    #[graphql(skip)]
+    pub mnemonics: HashMap<String, String>,
+    /// Named command sequences that can be run with [`super::SupMCUModule::run_macro`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[graphql(skip)]
+    pub macros: Vec<CommandMacro>,
+    /// The kind of checksum in the footers of the module's responses
+    #[serde(default, skip_serializing_if = "ChecksumKind::is_none")]
+    pub checksum: ChecksumKind,
+    /// A name telling the module apart from others with the same command name, e.g.
+    /// `BIM_0x41`.  Commands are still prefixed with `name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_name: Option<String>,
+    /// The SCPI prefix selecting this module among logical modules sharing its I2C address,
+    /// e.g. `A` for `A:SUP:TEL? 0`, see [`super::SupMCUModule::scpi_prefix`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scpi_prefix: Option<String>,
+    /// Whether the module's firmware continues a response where the previous read stopped,
+    /// so that responses longer than the I2C adapter's transfer limit can be read in parts,
+    /// see [`super::SupMCUModule::set_max_transfer_len`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continued_reads: bool,
+    /// The SupMCU index of the telemetry change counter, if the module's firmware has one,
+    /// see [`super::SupMCUModule::get_all_telemetry_if_changed`] and
+    /// [`super::SupMCUModule::set_change_counter_item`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_counter: Option<usize>,
+    /// The module telemetry index of the item counting the module's boots, if the module's
+    /// firmware has one, see [`super::SupMCUModule::boot_count`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_count: Option<usize>,
+    /// When the definition was discovered, see [`SupMCUModuleDefinition::age`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub discovered_at: Option<SystemTime>,
+    /// The module's firmware version string when the definition was discovered, which reads
+    /// are checked against once per session, see
+    /// [`super::SupMCUModule::firmware_changed`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_at_discovery: Option<String>,
+    /// What puts the module in its safe state, see
+    /// [`super::SupMCUMaster::emergency_stop`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub safe_mode: Option<SafeMode>,
+    /// What was removed from the definition by [`SupMCUModuleDefinition::slim`], if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub slimmed: Option<Slimmed>,
+    /// The commands by normalized name, built by [`SupMCUModuleDefinition::find_command`]
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub command_index: CommandIndex,
+}
+
+/// A definition's commands by normalized name, built on the first lookup and rebuilt only
+/// when the commands or mnemonics change, see [`SupMCUModuleDefinition::find_command`]
+#[derive(Default)]
+pub struct CommandIndex(RwLock<Option<Arc<CommandTable>>>);
+
+struct CommandTable {
+    /// The command names and mnemonics the table was built from
+    names: Vec<String>,
+    mnemonics: HashMap<String, String>,
+    table: Mnemonics,
+    commands: HashMap<String, usize>,
+}
+
+impl CommandTable {
+    fn new(def: &SupMCUModuleDefinition) -> Self {
+        let table = def.mnemonics();
+        let mut commands = HashMap::new();
+        for (i, command) in def.commands.iter().enumerate() {
+            commands.entry(table.normalize(&command.name)).or_insert(i);
+        }
+        CommandTable {
+            names: def.commands.iter().map(|c| c.name.clone()).collect(),
+            mnemonics: def.mnemonics.clone(),
+            table,
+            commands,
+        }
+    }
+
+    fn is_current(&self, def: &SupMCUModuleDefinition) -> bool {
+        self.mnemonics == def.mnemonics
+            && self.names.len() == def.commands.len()
+            && self
+                .names
+                .iter()
+                .zip(def.commands.iter())
+                .all(|(n, c)| *n == c.name)
+    }
+}
+
+impl CommandIndex {
+    /// Returns the index of the command of `def` called `name`, if there is one
+    fn find(&self, def: &SupMCUModuleDefinition, name: &str) -> Option<usize> {
+        let cached = self
+            .0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let table = match cached {
+            Some(table) if table.is_current(def) => table,
+            _ => {
+                let table = Arc::new(CommandTable::new(def));
+                *self.0.write().unwrap_or_else(PoisonError::into_inner) = Some(table.clone());
+                table
+            }
+        };
+        table.commands.get(&table.table.normalize(name)).copied()
+    }
+}
+
+impl Clone for CommandIndex {
+    fn clone(&self) -> Self {
+        let cached = self
+            .0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        CommandIndex(RwLock::new(cached))
+    }
+}
+
+/// Indices are derived from the definition, so they don't make definitions differ
+impl PartialEq for CommandIndex {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for CommandIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CommandIndex")
+    }
+}
+
+/// Selects what [`SupMCUModuleDefinition::slim`] removes from a definition
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SlimOptions {
+    /// Remove the default simulated values of the telemetry items
+    pub sim_defaults: bool,
+    /// Remove the command list
+    pub commands: bool,
+    /// Remove placeholder telemetry items, which have no name.  Items with an empty format are
+    /// kept, as they can be action-only items.
+    pub placeholders: bool,
+    /// Remove the telemetry items not named in the list, if given.  Items other methods rely
+    /// on, like the standard SupMCU items, have to be listed to be kept.
+    pub keep: Option<Vec<String>>,
+}
+
+impl SlimOptions {
+    /// Removes everything that isn't needed to read telemetry
+    pub fn all() -> Self {
+        SlimOptions {
+            sim_defaults: true,
+            commands: true,
+            placeholders: true,
+            keep: None,
+        }
+    }
+}
+
+/// What [`SupMCUModuleDefinition::slim`] removed from a definition, so that using the removed
+/// parts fails with a `Slimmed` error instead of an unknown name
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slimmed {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sim_defaults: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub commands: bool,
+    /// The number of telemetry items removed
+    #[serde(default)]
+    pub telemetry: usize,
 }
 
 impl Default for SupMCUModuleDefinition {
@@ -428,6 +1661,19 @@ impl Default for SupMCUModuleDefinition {
             commands: vec![],
             mcu: McuType::UNKNOWN,
             response_delay: DEFAULT_RESPONSE_DELAY,
+            mnemonics: HashMap::new(),
+            macros: vec![],
+            checksum: ChecksumKind::None,
+            unique_name: None,
+            scpi_prefix: None,
+            continued_reads: false,
+            change_counter: None,
+            boot_count: None,
+            discovered_at: None,
+            firmware_at_discovery: None,
+            safe_mode: None,
+            slimmed: None,
+            command_index: CommandIndex::default(),
         }
     }
 }
@@ -439,6 +1685,127 @@ impl fmt::Display for SupMCUModuleDefinition {
 }
 
 impl SupMCUModuleDefinition {
+    /// Returns the unique name of the module if it has one, otherwise its command name
+    pub fn display_name(&self) -> &str {
+        self.unique_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Returns how long ago the definition was discovered, or `None` if it's unknown, e.g.
+    /// for definitions written by hand or saved by older versions
+    pub fn age(&self) -> Option<Duration> {
+        self.discovered_at
+            .map(|discovered_at| discovered_at.elapsed().unwrap_or_default())
+    }
+
+    /// Returns a copy of the definition without the parts selected by `options`, e.g. to
+    /// keep flight definition files small.
+    ///
+    /// Telemetry can still be read with the slimmed definition.  Using a removed part, like
+    /// sending a known command after the commands were removed, fails with `Slimmed`.
+    pub fn slim(&self, options: SlimOptions) -> SupMCUModuleDefinition {
+        let mut slim = self.clone();
+        let mut slimmed = slim.slimmed.take().unwrap_or_default();
+        let before = slim.telemetry.len();
+        slim.telemetry.retain(|def| {
+            !(options.placeholders && def.name.is_empty())
+                && options
+                    .keep
+                    .as_ref()
+                    .is_none_or(|keep| keep.contains(&def.name))
+        });
+        slimmed.telemetry += before - slim.telemetry.len();
+        if options.sim_defaults {
+            for def in slim.telemetry.iter_mut() {
+                def.default_sim_value = None;
+            }
+            slimmed.sim_defaults = true;
+        }
+        if options.commands {
+            slim.commands.clear();
+            slimmed.commands = true;
+        }
+        slim.slimmed = Some(slimmed);
+        slim
+    }
+
+    /// Returns the error for a missing command: `Slimmed` if the commands were removed by
+    /// [`SupMCUModuleDefinition::slim`], otherwise `UnknownCommandName`
+    pub(crate) fn missing_command(&self, name: &str) -> SupMCUError {
+        match &self.slimmed {
+            Some(slimmed) if slimmed.commands => {
+                SupMCUError::Slimmed(self.name.clone(), format!("command {name}"))
+            }
+            _ => SupMCUError::UnknownCommandName(name.to_string()),
+        }
+    }
+
+    /// Returns the error for a missing telemetry item, `Slimmed` if items were removed by
+    /// [`SupMCUModuleDefinition::slim`], otherwise `missing`
+    pub(crate) fn missing_telemetry(&self, item: String, missing: SupMCUError) -> SupMCUError {
+        match &self.slimmed {
+            Some(slimmed) if slimmed.telemetry > 0 => {
+                SupMCUError::Slimmed(self.name.clone(), format!("telemetry item {item}"))
+            }
+            _ => missing,
+        }
+    }
+
+    /// Returns the error for a telemetry item without simulated values, `Slimmed` if they were
+    /// removed by [`SupMCUModuleDefinition::slim`], otherwise `NotSimulatable`
+    pub(crate) fn missing_sim_values(&self, item: &str) -> SupMCUError {
+        match &self.slimmed {
+            Some(slimmed) if slimmed.sim_defaults => {
+                SupMCUError::Slimmed(self.name.clone(), format!("simulated values of {item}"))
+            }
+            _ => SupMCUError::NotSimulatable(item.to_string()),
+        }
+    }
+
+    /// Returns the telemetry item of `telemetry_type` at index `idx`, wherever it is in
+    /// the definition
+    pub fn telemetry_item(
+        &self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Option<&SupMCUTelemetryDefinition> {
+        self.telemetry
+            .iter()
+            .find(|def| def.telemetry_type == telemetry_type && def.idx == idx)
+    }
+
+    /// Returns the index of the SupMCU telemetry item called `name` if it can be a change
+    /// counter, i.e. is a single `u16` or `u32`, see
+    /// [`super::SupMCUModule::set_change_counter_item`]
+    pub fn find_change_counter(&self, name: &str) -> Option<usize> {
+        self.telemetry
+            .iter()
+            .find(|def| {
+                def.telemetry_type == TelemetryType::SupMCU
+                    && super::normalize_name(&def.name) == super::normalize_name(name)
+                    && matches!(def.format.format[..], [DataType::UINT16 | DataType::UINT32])
+            })
+            .map(|def| def.idx)
+    }
+
+    /// Returns the telemetry item of `telemetry_type` at index `idx` as a mutable reference
+    pub fn telemetry_item_mut(
+        &mut self,
+        telemetry_type: TelemetryType,
+        idx: usize,
+    ) -> Option<&mut SupMCUTelemetryDefinition> {
+        self.telemetry
+            .iter_mut()
+            .find(|def| def.telemetry_type == telemetry_type && def.idx == idx)
+    }
+
+    /// Returns the telemetry items in the order they're read by sweeps: SupMCU items, then
+    /// module items, each by index, whatever their order in the definition.
+    pub fn sweep_order(&self) -> Vec<SupMCUTelemetryDefinition> {
+        let mut items = self.get_supmcu_telemetry();
+        items.extend(self.get_module_telemetry());
+        items
+    }
+
     pub fn get_supmcu_telemetry(&self) -> Vec<SupMCUTelemetryDefinition> {
         self.telemetry
             .clone()
@@ -448,6 +1815,65 @@ impl SupMCUModuleDefinition {
             .collect()
     }
 
+    /// Returns the mnemonic table for the module's commands: the defaults, the mnemonics
+    /// in the definition and the ones in the SCPI notation of its command names.
+    pub fn mnemonics(&self) -> Mnemonics {
+        let mut mnemonics = Mnemonics::default();
+        for command in self.commands.iter() {
+            mnemonics.learn(&command.name);
+        }
+        for (long, short) in self.mnemonics.iter() {
+            mnemonics.insert(long, short);
+        }
+        mnemonics
+    }
+
+    /// Returns the command called `name`, if the module has one.
+    ///
+    /// Names are compared in their normalized form, so short and long forms in any case
+    /// match, e.g. `supervisor:led` finds `SUP:LED`.  The normalized names are kept in the
+    /// definition's [`CommandIndex`], so only the first lookup normalizes every command.
+    pub fn find_command(&self, name: &str) -> Option<&SupMCUCommand> {
+        let idx = self.command_index.find(self, name)?;
+        self.commands.get(idx)
+    }
+
+    /// Returns true if the module has a command called `name`
+    pub fn has_command(&self, name: &str) -> bool {
+        self.find_command(name).is_some()
+    }
+
+    /// Checks that the indices of the items of `telemetry_type` are exactly `0..count`, and
+    /// that each of those items has a name.
+    pub fn index_problems(&self, telemetry_type: TelemetryType, count: usize) -> Vec<IndexProblem> {
+        let mut seen = vec![false; count];
+        let mut problems = vec![];
+        for def in self
+            .telemetry
+            .iter()
+            .filter(|def| def.telemetry_type == telemetry_type)
+            .sorted_by_key(|def| def.idx)
+        {
+            match seen.get_mut(def.idx) {
+                None => problems.push(IndexProblem::OutOfRange(telemetry_type, def.idx)),
+                Some(true) => problems.push(IndexProblem::Duplicate(telemetry_type, def.idx)),
+                Some(slot) => {
+                    *slot = true;
+                    if def.name.is_empty() {
+                        problems.push(IndexProblem::Unnamed(telemetry_type, def.idx));
+                    }
+                }
+            }
+        }
+        problems.extend(
+            seen.iter()
+                .enumerate()
+                .filter(|(_, seen)| !**seen)
+                .map(|(idx, _)| IndexProblem::Missing(telemetry_type, idx)),
+        );
+        problems
+    }
+
     pub fn get_module_telemetry(&self) -> Vec<SupMCUTelemetryDefinition> {
         self.telemetry
             .clone()
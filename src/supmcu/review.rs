@@ -0,0 +1,132 @@
+/*!
+Reviewing discovered telemetry definitions before they're kept, see
+[`super::SupMCUMaster::discover_modules_with_review`].
+
+Names normalized from the module's answers are sometimes wrong, or a firmware reports a wrong
+format.  A reviewer sees every discovered item and decides whether to keep it as is, rename it,
+override its format or leave it out.  Decisions can be recorded to a [`ReviewDecisions`] file
+and replayed on the next discovery, so fixes don't have to be made by hand again.
+
+The standard SupMCU items the library reads itself, listed in [`INTERNAL_ITEMS`], can't be
+left out or renamed: such decisions are logged and the item is kept as discovered.
+*/
+use crate::{
+    supmcu::parsing::{SupMCUFormat, SupMCUTelemetryDefinition, TelemetryType},
+    SupMCUError,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs::File, path::Path};
+
+/// Indices of the standard SupMCU items the library reads itself: the firmware version, the
+/// self-test results, the last reset cause and whether telemetry is simulated, see
+/// [`super::STANDARD_TELEMETRY_ITEMS`]
+pub const INTERNAL_ITEMS: [usize; 4] = [0, 4, 13, 16];
+
+/// A discovered telemetry item offered to a reviewer
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReviewItem {
+    pub address: u16,
+    /// The command name of the module
+    pub module: String,
+    pub definition: SupMCUTelemetryDefinition,
+}
+
+/// What to do with a discovered telemetry item
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReviewDecision {
+    /// Keep the item as discovered
+    Accept,
+    /// Keep the item under another name
+    Rename(String),
+    /// Keep the item with another format.  Its default simulated value is dropped, since it
+    /// was parsed with the discovered format.
+    OverrideFormat(SupMCUFormat),
+    /// Leave the item out of the definition
+    Skip,
+}
+
+impl ReviewDecision {
+    /// Returns true if the decision leaves out or renames one of the [`INTERNAL_ITEMS`]
+    pub fn breaks_internal_item(&self, def: &SupMCUTelemetryDefinition) -> bool {
+        matches!(self, ReviewDecision::Rename(_) | ReviewDecision::Skip)
+            && def.telemetry_type == TelemetryType::SupMCU
+            && INTERNAL_ITEMS.contains(&def.idx)
+    }
+
+    /// Applies the decision to a discovered item, returning the item to keep, if any
+    pub(crate) fn apply(self, def: SupMCUTelemetryDefinition) -> Option<SupMCUTelemetryDefinition> {
+        match self {
+            ReviewDecision::Accept => Some(def),
+            ReviewDecision::Rename(name) => Some(SupMCUTelemetryDefinition { name, ..def }),
+            ReviewDecision::OverrideFormat(format) => Some(SupMCUTelemetryDefinition {
+                format,
+                default_sim_value: None,
+                ..def
+            }),
+            ReviewDecision::Skip => None,
+        }
+    }
+}
+
+/// A review decision about an item of a module, see [`ReviewDecisions`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedDecision {
+    /// The command name of the module
+    pub module: String,
+    pub telemetry_type: TelemetryType,
+    pub idx: usize,
+    pub decision: ReviewDecision,
+}
+
+/// Review decisions, recorded during an interactive review to be replayed on later
+/// discoveries.
+///
+/// Items are matched by module command name, telemetry type and index, so decisions apply to
+/// every module of the same type.  Items without a decision are accepted.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReviewDecisions {
+    pub decisions: Vec<RecordedDecision>,
+}
+
+impl ReviewDecisions {
+    /// Reads decisions from a JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SupMCUError> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Writes the decisions to a JSON file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SupMCUError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Records the decision made about an item, replacing an earlier one about the same item.
+    /// Accepting an item isn't recorded, since it's the default.
+    pub fn record(&mut self, item: &ReviewItem, decision: &ReviewDecision) {
+        self.decisions.retain(|d| !d.matches(item));
+        if *decision != ReviewDecision::Accept {
+            self.decisions.push(RecordedDecision {
+                module: item.module.clone(),
+                telemetry_type: item.definition.telemetry_type,
+                idx: item.definition.idx,
+                decision: decision.clone(),
+            });
+        }
+    }
+
+    /// Returns the recorded decision about an item, or `Accept` if there is none
+    pub fn decide(&self, item: &ReviewItem) -> ReviewDecision {
+        self.decisions
+            .iter()
+            .find(|d| d.matches(item))
+            .map_or(ReviewDecision::Accept, |d| d.decision.clone())
+    }
+}
+
+impl RecordedDecision {
+    fn matches(&self, item: &ReviewItem) -> bool {
+        self.module == item.module
+            && self.telemetry_type == item.definition.telemetry_type
+            && self.idx == item.definition.idx
+    }
+}
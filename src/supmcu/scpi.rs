@@ -0,0 +1,93 @@
+/*!
+Normalization of SCPI command strings.
+
+SupMCU firmware accepts each mnemonic of a command in its short or long form, in any case,
+e.g. `SUP:TEL? 0`, `SUPERVISOR:TELEMETRY? 0` and `sup:Telemetry? 0` are the same request.
+[`Mnemonics::normalize`] turns any of them into the canonical uppercase short form so they
+can be compared.  Arguments are left as written, as string arguments are case-sensitive.  Commands are always sent to modules exactly as written; normalization is
+only used to match them.
+*/
+use std::collections::HashMap;
+
+/// Long forms of the mnemonics used by all SupMCU modules, with their short forms
+pub const DEFAULT_MNEMONICS: &[(&str, &str)] = &[
+    ("SUPERVISOR", "SUP"),
+    ("TELEMETRY", "TEL"),
+    ("COMMAND", "COM"),
+    ("SELFTEST", "SELF"),
+    ("RESET", "RES"),
+];
+
+/// A table of long form mnemonics and their short forms, used to normalize commands
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mnemonics(HashMap<String, String>);
+
+impl Default for Mnemonics {
+    fn default() -> Self {
+        Mnemonics(
+            DEFAULT_MNEMONICS
+                .iter()
+                .map(|(long, short)| (long.to_string(), short.to_string()))
+                .collect(),
+        )
+    }
+}
+
+impl Mnemonics {
+    /// Adds a mnemonic, matched case-insensitively
+    pub fn insert(&mut self, long: &str, short: &str) {
+        self.0.insert(long.to_uppercase(), short.to_uppercase());
+    }
+
+    /// Adds the mnemonics of a command written in SCPI notation, where the short form is
+    /// the uppercase prefix of each mnemonic, e.g. `SUPervisor:LED` adds `SUPERVISOR`.
+    pub fn learn(&mut self, notation: &str) {
+        let header = notation.split_whitespace().next().unwrap_or_default();
+        for token in header.split(':') {
+            let token = token.trim_end_matches('?');
+            let short: String = token
+                .chars()
+                .take_while(|c| !c.is_ascii_lowercase())
+                .collect();
+            if !short.is_empty() && short.len() < token.len() {
+                self.insert(token, &short);
+            }
+        }
+    }
+
+    /// Returns the canonical form of a command: uppercase short form mnemonics, then the
+    /// arguments as written after a single space, e.g. `supervisor:name  'Flight Unit'`
+    /// becomes `SUP:NAME 'Flight Unit'`.  Arguments may be case-sensitive strings, so they
+    /// aren't changed.
+    pub fn normalize(&self, cmd: &str) -> String {
+        let cmd = cmd.trim();
+        let (header, args) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+        let header = header
+            .split(':')
+            .map(|token| {
+                let token = token.to_uppercase();
+                let (mnemonic, query) = match token.strip_suffix('?') {
+                    Some(mnemonic) => (mnemonic, "?"),
+                    None => (token.as_str(), ""),
+                };
+                let mnemonic = self.0.get(mnemonic).map_or(mnemonic, String::as_str);
+                format!("{mnemonic}{query}")
+            })
+            .collect::<Vec<_>>()
+            .join(":");
+        match args.trim_start() {
+            "" => header,
+            args => format!("{header} {args}"),
+        }
+    }
+
+    /// Returns true if `a` and `b` are the same command
+    pub fn same_command(&self, a: &str, b: &str) -> bool {
+        self.normalize(a) == self.normalize(b)
+    }
+}
+
+/// Normalizes a command using only the [`DEFAULT_MNEMONICS`], see [`Mnemonics::normalize`]
+pub fn normalize(cmd: &str) -> String {
+    Mnemonics::default().normalize(cmd)
+}
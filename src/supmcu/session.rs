@@ -0,0 +1,269 @@
+/*!
+Recording and replaying the I2C transactions of a [`super::SupMCUMaster`].
+
+A session file holds the modules on the bus (with the definitions they had when recording
+started) and every command written to and response read from them, so a run can be replayed
+without hardware with [`super::SupMCUMaster::from_session`], e.g. to reproduce a parsing bug.
+
+Session files are CBOR encoded, which keeps the responses compact.
+
+Modules are read in parallel, so transactions are only ordered per module.  Replays match each
+module's transactions in that order.
+*/
+use crate::{supmcu::parsing::SupMCUModuleDefinition, SupMCUError};
+use i2cdev::core::I2CDevice;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// The version of the session file format written by this crate
+pub const SESSION_VERSION: u32 = 2;
+
+/// A module on the bus when a session was recorded
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionModule {
+    pub address: u16,
+    pub max_retries: Option<u8>,
+    /// The module's definition when recording started, if it had one
+    pub definition: Option<SupMCUModuleDefinition>,
+}
+
+/// A command written to, or response read from, a module
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEvent {
+    Write(String),
+    Read(Vec<u8>),
+}
+
+/// A transaction of a recorded session
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTransaction {
+    /// Microseconds since recording started
+    pub at_us: u64,
+    pub address: u16,
+    pub event: SessionEvent,
+}
+
+/// The contents of a session file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub device: String,
+    pub modules: Vec<SessionModule>,
+    pub transactions: Vec<SessionTransaction>,
+}
+
+impl Session {
+    /// Reads a session file, failing with `UnsupportedSessionVersion` if it was written in
+    /// another format version
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SupMCUError> {
+        let session: Session = ciborium::from_reader(BufReader::new(File::open(path)?))?;
+        if session.version != SESSION_VERSION {
+            return Err(SupMCUError::UnsupportedSessionVersion(session.version));
+        }
+        Ok(session)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SupMCUError> {
+        ciborium::into_writer(self, BufWriter::new(File::create(path)?))?;
+        Ok(())
+    }
+
+    /// Returns the events of the module at `address`, in order
+    pub fn events(&self, address: u16) -> impl Iterator<Item = &SessionEvent> {
+        self.transactions
+            .iter()
+            .filter(move |t| t.address == address)
+            .map(|t| &t.event)
+    }
+}
+
+/// A session being recorded, shared by the modules of a master.  It is written when dropped
+/// if it wasn't saved, so a run that fails before ending the session still leaves a file.
+#[derive(Debug)]
+pub(crate) struct SessionLog {
+    pub(crate) path: PathBuf,
+    start: Instant,
+    pub(crate) session: Session,
+    saved: bool,
+}
+
+pub(crate) type SessionRecorder = Arc<Mutex<SessionLog>>;
+
+impl SessionLog {
+    pub(crate) fn new(path: PathBuf, device: String, modules: Vec<SessionModule>) -> Self {
+        SessionLog {
+            path,
+            start: Instant::now(),
+            session: Session {
+                version: SESSION_VERSION,
+                device,
+                modules,
+                transactions: vec![],
+            },
+            saved: false,
+        }
+    }
+
+    /// Writes the session file
+    pub(crate) fn save(&mut self) -> Result<(), SupMCUError> {
+        self.session.save(&self.path)?;
+        self.saved = true;
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, address: u16, event: SessionEvent) {
+        self.session.transactions.push(SessionTransaction {
+            at_us: self.start.elapsed().as_micros() as u64,
+            address,
+            event,
+        });
+    }
+}
+
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        if !self.saved {
+            if let Err(e) = self.save() {
+                warn!("couldn't write session file {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+/// An I2C device that answers with the responses recorded in a session.
+///
+/// In strict mode every write and read must match the next recorded transaction of the module,
+/// otherwise `SessionDivergence` is returned.  Otherwise each read is answered with the next
+/// response recorded after the command last written, whatever order the commands come in.
+pub struct ReplayDevice {
+    address: u16,
+    strict: bool,
+    /// The module's recorded transactions not replayed yet, for strict mode
+    events: VecDeque<SessionEvent>,
+    /// The responses recorded after each command, for non-strict mode
+    responses: HashMap<String, VecDeque<Vec<u8>>>,
+    last_cmd: String,
+}
+
+impl ReplayDevice {
+    pub fn new(session: &Session, address: u16, strict: bool) -> Self {
+        let events: VecDeque<SessionEvent> = session.events(address).cloned().collect();
+        let mut responses: HashMap<String, VecDeque<Vec<u8>>> = HashMap::new();
+        let mut cmd = String::new();
+        for event in events.iter() {
+            match event {
+                SessionEvent::Write(written) => cmd.clone_from(written),
+                SessionEvent::Read(bytes) => responses
+                    .entry(cmd.clone())
+                    .or_default()
+                    .push_back(bytes.clone()),
+            }
+        }
+        ReplayDevice {
+            address,
+            strict,
+            events,
+            responses,
+            last_cmd: String::new(),
+        }
+    }
+
+    fn diverged(&self, msg: String) -> SupMCUError {
+        SupMCUError::SessionDivergence(self.address, msg)
+    }
+
+    /// SMBus transactions aren't recorded, so replaying one always diverges
+    fn smbus_unrecorded(&self) -> SupMCUError {
+        self.diverged("SMBus transactions aren't recorded in sessions".into())
+    }
+}
+
+impl I2CDevice for ReplayDevice {
+    type Error = SupMCUError;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        let bytes = if self.strict {
+            match self.events.pop_front() {
+                Some(SessionEvent::Read(bytes)) => bytes,
+                other => {
+                    return Err(self.diverged(format!("expected {other:?}, got a read")));
+                }
+            }
+        } else {
+            self.responses
+                .get_mut(&self.last_cmd)
+                .and_then(VecDeque::pop_front)
+                .ok_or_else(|| {
+                    self.diverged(format!("no recorded response to {:?}", self.last_cmd))
+                })?
+        };
+        if bytes.len() != data.len() {
+            return Err(self.diverged(format!(
+                "read {} bytes, but {} were recorded",
+                data.len(),
+                bytes.len()
+            )));
+        }
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let cmd = String::from_utf8(data.to_vec())?;
+        if self.strict {
+            match self.events.pop_front() {
+                Some(SessionEvent::Write(recorded)) if recorded == cmd => {}
+                other => {
+                    return Err(self.diverged(format!("expected {other:?}, got write {cmd:?}")));
+                }
+            }
+        }
+        self.last_cmd = cmd;
+        Ok(())
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> Result<(), Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<(), Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+
+    fn smbus_process_block(
+        &mut self,
+        _register: u8,
+        _values: &[u8],
+    ) -> Result<Vec<u8>, Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+
+    fn smbus_read_i2c_block_data(
+        &mut self,
+        _register: u8,
+        _len: u8,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+
+    fn smbus_write_i2c_block_data(
+        &mut self,
+        _register: u8,
+        _values: &[u8],
+    ) -> Result<(), Self::Error> {
+        Err(self.smbus_unrecorded())
+    }
+}
@@ -0,0 +1,327 @@
+/*!
+A deterministic simulated I2C bus of mock SupMCU modules, for integration tests.
+
+A [`SimBus`] holds a [`SupMCUMaster`] whose modules are [`TestI2CDevice`]s answering from their
+definitions.  Every module's PRNG is derived from a single seed, every transaction is recorded
+in a transcript in the order it happened, and transactions are stamped with a virtual clock.
+The modules wait on the virtual clock, see [`SimClock`], so response delays, retry backoffs and
+the devices' read latency move it forward instantly instead of sleeping, and
+[`SimBus::advance`] moves it too.  Running the same sequential operations with the same seed
+always produces the same transcript.
+
+The pollers of a [`super::bus::SupMCUBus`] can run on the virtual clock too, see
+[`SupMCUBus::start_simulated`](super::bus::SupMCUBus::start_simulated) and
+[`SimClock::run_for`].  Other code timing itself with the host's clock, like tokio timeouts
+racing a read, needs the modules to really wait, see [`SimBus::real_time`].
+
+Downstream crates can enable the `test-utils` feature in their `dev-dependencies` to use it:
+
+```toml
+[dev-dependencies]
+supmcu-rs = { version = "0.5", features = ["test-utils"] }
+```
+
+```
+use supmcu_rs::supmcu::{parsing::*, sim::{FaultPlan, SimBus, TransactionKind}};
+use std::{fs::File, time::Duration};
+
+let defs: Vec<SupMCUModuleDefinition> =
+    serde_json::from_reader(File::open("test-definition.json")?)?;
+let mut bus = SimBus::new(42, defs)?;
+bus.inject(0x5c, FaultPlan { nonready: 1, ..Default::default() })?;
+
+bus.advance(Duration::from_secs(1));
+let module = bus.master.modules.iter_mut().find(|m| m.get_address() == 0x5c).unwrap();
+module.get_telemetry(TelemetryType::SupMCU, 0)?;
+// The non-ready response made the module send its request twice
+let writes = bus
+    .transcript()
+    .iter()
+    .filter(|t| matches!(t.kind, TransactionKind::Write(_)))
+    .count();
+assert_eq!(writes, 2);
+// The module's response delays passed on the virtual clock
+assert!(bus.now() > Duration::from_secs(1));
+# Ok::<(), supmcu_rs::SupMCUError>(())
+```
+*/
+use crate::{
+    supmcu::{
+        async_rt::{AsyncRuntime, Sleep},
+        i2c::TestI2CDevice,
+        parsing::*,
+        SupMCUMaster, SupMCUModule, DEFAULT_RETRIES,
+    },
+    SupMCUError,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::Duration,
+};
+
+/// A transaction on a [`SimBus`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    /// Virtual time of the transaction
+    pub at: Duration,
+    pub address: u16,
+    pub kind: TransactionKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// A command written to the module
+    Write(String),
+    /// A response read from the module
+    Read(Vec<u8>),
+    /// An injected fault
+    Fault(String),
+}
+
+/// Faults to inject into a module on a [`SimBus`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultPlan {
+    /// The number of upcoming responses that are non-ready
+    pub nonready: usize,
+    /// The number of upcoming reads that fail
+    pub failed_reads: usize,
+}
+
+/// The state shared by all the devices on a [`SimBus`]
+#[derive(Debug, Default)]
+pub(crate) struct SimState {
+    now: Duration,
+    /// Whether waits take real time, see [`SimBus::real_time`]
+    real_time: bool,
+    pub(crate) transcript: Vec<Transaction>,
+    /// How far a bus worker may move the clock to reach its next deadline, see
+    /// [`SimClock::run_for`].  Workers don't run before it's first set, so that the first
+    /// run starts from a clock no worker has moved yet.
+    horizon: Option<Duration>,
+    /// The deadline a bus worker is waiting for, `Duration::MAX` if it has none
+    worker_deadline: Option<Duration>,
+}
+
+impl SimState {
+    pub(crate) fn record(&mut self, address: u16, kind: TransactionKind) {
+        self.transcript.push(Transaction {
+            at: self.now,
+            address,
+            kind,
+        });
+    }
+}
+
+/// Locks the state of a bus.  It stays consistent if a holder of the lock panicked, e.g. a
+/// failed assertion in a test, each change being made under a single lock.
+pub(crate) fn lock(state: &Mutex<SimState>) -> MutexGuard<'_, SimState> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Moves the virtual clock forward, returning whether waits also take real time
+fn advance(state: &Mutex<SimState>, duration: Duration) -> bool {
+    let mut state = lock(state);
+    state.now += duration;
+    state.real_time
+}
+
+/// Waits for `duration` on the bus's clock, sleeping too if the bus runs in real time
+pub(crate) fn wait(state: &Mutex<SimState>, duration: Duration) {
+    if advance(state, duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// How often a bus worker waiting on the virtual clock checks whether it was stopped
+const STOP_CHECK: Duration = Duration::from_millis(10);
+
+/// The timer of the modules on a [`SimBus`], waiting on its virtual clock.
+///
+/// Sleeping moves the clock forward by the duration and completes as soon as the other tasks
+/// had a turn, so tests don't wait for response delays and backoffs, and their transcripts are
+/// stamped with the time the modules would have waited.
+#[derive(Clone, Debug)]
+pub struct SimClock {
+    state: Arc<Mutex<SimState>>,
+    /// Signalled when a bus worker starts waiting or may move the clock further
+    worker: Arc<Condvar>,
+}
+
+impl SimClock {
+    /// Returns the virtual time since the bus was created
+    pub fn now(&self) -> Duration {
+        lock(&self.state).now
+    }
+
+    /// Lets a bus worker started with
+    /// [`SupMCUBus::start_simulated`](super::bus::SupMCUBus::start_simulated) run for
+    /// `duration` of virtual time.
+    ///
+    /// The worker moves the clock from one poll or health check to the next, and the reads
+    /// move it by their delays.  This returns once the worker waits for a deadline past
+    /// `duration` from now, so the bus can be inspected and changed between calls.  It
+    /// blocks until a worker is started.
+    pub fn run_for(&self, duration: Duration) {
+        let mut state = lock(&self.state);
+        state.horizon = Some(state.now + duration);
+        self.worker.notify_all();
+        while state
+            .worker_deadline
+            .is_none_or(|deadline| Some(deadline) <= state.horizon)
+        {
+            state = self
+                .worker
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Waits for a bus worker's `deadline` to be within the horizon set by
+    /// [`SimClock::run_for`], then moves the clock to it.  Returns false once `stopped` is
+    /// disconnected instead.
+    pub(crate) fn wait_until(
+        &self,
+        deadline: Option<Duration>,
+        stopped: &mpsc::Receiver<()>,
+    ) -> bool {
+        let mut state = lock(&self.state);
+        loop {
+            if !matches!(stopped.try_recv(), Err(mpsc::TryRecvError::Empty)) {
+                state.worker_deadline = None;
+                return false;
+            }
+            if let Some(deadline) = deadline.filter(|deadline| Some(*deadline) <= state.horizon) {
+                state.now = state.now.max(deadline);
+                state.worker_deadline = None;
+                return true;
+            }
+            state.worker_deadline = Some(deadline.unwrap_or(Duration::MAX));
+            self.worker.notify_all();
+            state = self
+                .worker
+                .wait_timeout(state, STOP_CHECK)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+impl AsyncRuntime for SimClock {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        if advance(&self.state, duration) {
+            Box::pin(tokio::time::sleep(duration))
+        } else {
+            // Other modules' tasks run meanwhile, as they would while really waiting
+            Box::pin(tokio::task::yield_now())
+        }
+    }
+
+    fn sleep_blocking(&self, duration: Duration) {
+        wait(&self.state, duration);
+    }
+}
+
+/// A simulated bus of mock modules with a virtual clock and a transaction transcript
+pub struct SimBus {
+    pub master: SupMCUMaster<TestI2CDevice>,
+    pub(crate) state: Arc<Mutex<SimState>>,
+    worker: Arc<Condvar>,
+}
+
+impl SimBus {
+    /// Creates a bus with a module for each definition.
+    ///
+    /// The modules' PRNGs are seeded from a stream seeded with `seed`, so each module gets
+    /// different but reproducible telemetry values.  Modules start with their definitions
+    /// loaded and always answer ready unless faults are injected.  They wait on the bus's
+    /// virtual clock, see [`SimClock`].
+    pub fn new(seed: u64, defs: Vec<SupMCUModuleDefinition>) -> Result<Self, SupMCUError> {
+        let state = Arc::new(Mutex::new(SimState::default()));
+        let worker = Arc::new(Condvar::new());
+        let mut seeds = SmallRng::seed_from_u64(seed);
+        let modules = defs
+            .into_iter()
+            .map(|def| {
+                let address = def.address;
+                let rng = SmallRng::seed_from_u64(seeds.gen());
+                let mut dev = TestI2CDevice::new(rng, def.clone(), false);
+                dev.bus = Some(state.clone());
+                let mut module = SupMCUModule::from_device(dev, address, Some(DEFAULT_RETRIES));
+                module.set_definition(def);
+                module.set_async_runtime(SimClock {
+                    state: state.clone(),
+                    worker: worker.clone(),
+                });
+                module
+            })
+            .collect();
+        Ok(SimBus {
+            master: SupMCUMaster::from_modules(modules, "sim".into())?,
+            state,
+            worker,
+        })
+    }
+
+    /// Makes the modules and devices really wait as well as moving the virtual clock, the
+    /// modules on tokio's timer.
+    ///
+    /// This is for tests of code timing itself with the host's clock, like tokio timeouts,
+    /// which would otherwise see every read complete instantly.
+    pub fn real_time(&self) {
+        lock(&self.state).real_time = true;
+    }
+
+    /// Returns the timer of the bus's virtual clock, e.g. for a module added to the master
+    pub fn clock(&self) -> SimClock {
+        SimClock {
+            state: self.state.clone(),
+            worker: self.worker.clone(),
+        }
+    }
+
+    /// Moves the virtual clock forward
+    pub fn advance(&self, duration: Duration) {
+        lock(&self.state).now += duration;
+    }
+
+    /// Returns the virtual time since the bus was created
+    pub fn now(&self) -> Duration {
+        self.clock().now()
+    }
+
+    /// Injects faults into the module at `address`, replacing any that haven't happened yet
+    pub fn inject(&mut self, address: u16, plan: FaultPlan) -> Result<(), SupMCUError> {
+        let module = self.module_mut(address)?;
+        module.i2c_dev.nonready_responses = plan.nonready;
+        module.i2c_dev.failed_reads = plan.failed_reads;
+        Ok(())
+    }
+
+    /// Returns the mock device of the module at `address`, e.g. to script telemetry values
+    pub fn device_mut(&mut self, address: u16) -> Result<&mut TestI2CDevice, SupMCUError> {
+        Ok(&mut *self.module_mut(address)?.i2c_dev)
+    }
+
+    /// Returns all the transactions so far, in the order they happened
+    pub fn transcript(&self) -> Vec<Transaction> {
+        lock(&self.state).transcript.clone()
+    }
+
+    /// Clears the transcript, keeping the virtual time
+    pub fn clear_transcript(&self) {
+        lock(&self.state).transcript.clear();
+    }
+
+    fn module_mut(
+        &mut self,
+        address: u16,
+    ) -> Result<&mut SupMCUModule<TestI2CDevice>, SupMCUError> {
+        self.master
+            .modules
+            .iter_mut()
+            .find(|m| m.address == address)
+            .ok_or_else(|| SupMCUError::ModuleNotFound(String::new(), address))
+    }
+}
@@ -0,0 +1,617 @@
+//! Reads from simulated modules with faults injected, see [`sim::FaultPlan`]
+use super::*;
+
+/// A simulated bus, with faults injected into its first module
+struct FaultBus {
+    bus: sim::SimBus,
+    /// The address of the module faults are injected into
+    address: u16,
+}
+
+impl FaultBus {
+    fn new(seed: u64) -> Self {
+        FaultBus::from_bus(sim_bus(seed))
+    }
+
+    fn with_defs(seed: u64, defs: Vec<SupMCUModuleDefinition>) -> Self {
+        FaultBus::from_bus(sim::SimBus::new(seed, defs).unwrap())
+    }
+
+    fn from_bus(bus: sim::SimBus) -> Self {
+        let address = bus.master.modules[0].address;
+        FaultBus { bus, address }
+    }
+
+    fn module(&mut self) -> &mut SupMCUModule<TestI2CDevice> {
+        &mut self.bus.master.modules[0]
+    }
+
+    /// Fails the module's next `reads` reads
+    fn fail_reads(&mut self, reads: usize) {
+        let plan = sim::FaultPlan {
+            failed_reads: reads,
+            ..Default::default()
+        };
+        self.bus.inject(self.address, plan).unwrap();
+    }
+
+    /// Makes the module's next `responses` responses non-ready
+    fn nonready(&mut self, responses: usize) {
+        let plan = sim::FaultPlan {
+            nonready: responses,
+            ..Default::default()
+        };
+        self.bus.inject(self.address, plan).unwrap();
+    }
+}
+
+impl Deref for FaultBus {
+    type Target = sim::SimBus;
+
+    fn deref(&self) -> &sim::SimBus {
+        &self.bus
+    }
+}
+
+impl DerefMut for FaultBus {
+    fn deref_mut(&mut self) -> &mut sim::SimBus {
+        &mut self.bus
+    }
+}
+
+#[test]
+fn wait_ready() {
+    let mut bus = FaultBus::new(6);
+    bus.nonready(3);
+    let start = bus.now();
+    let module = bus.module();
+    let def = module.get_definition().unwrap().telemetry[1].clone();
+    module
+        .wait_ready(&def, Duration::from_secs(5), Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(module.stats().nonready, 3);
+    // Polled every 100ms on the virtual clock
+    assert!(bus.now() - start >= Duration::from_millis(300));
+}
+
+#[test]
+fn bus_diagnosis() {
+    use diag::{Diagnosis, RemoteBusStats};
+
+    let mut defs: Vec<SupMCUModuleDefinition> =
+        serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap()).unwrap();
+    defs.truncate(2);
+    let names = ["nacks", "overruns", "bus_errors"].map(String::from);
+    let counters: Vec<SupMCUTelemetryDefinition> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| SupMCUTelemetryDefinition {
+            name: name.to_string(),
+            format: SupMCUFormat::new("l"),
+            default_sim_value: Some(vec![SupMCUValue::U64(0)]),
+            idx: defs[0].telemetry.len() + i,
+            telemetry_type: TelemetryType::SupMCU,
+            ..Default::default()
+        })
+        .collect();
+    defs[0].telemetry.extend(counters.iter().cloned());
+    let mut bus = FaultBus::with_defs(9, defs);
+    let address = bus.address;
+    bus.module().set_remote_bus_items(names.clone());
+    // nacks, overruns and bus errors of each diagnosis
+    let readings = [[0, 0, 0], [0, 0, 0], [3, 0, 0], [3, 1, 0], [1, 0, 0]];
+    for (i, def) in counters.iter().enumerate() {
+        let values = readings
+            .iter()
+            .map(|r| vec![SupMCUValue::U64(r[i])])
+            .collect();
+        bus.device_mut(address).unwrap().script(def, values);
+    }
+    let fail_read = |bus: &mut FaultBus| {
+        bus.fail_reads(1);
+        let def = bus.module().get_definition().unwrap().telemetry[1].clone();
+        assert!(bus.module().get_telemetry_by_def(&def).is_err());
+    };
+
+    let healthy = bus.module().bus_diagnosis();
+    fail_read(&mut bus);
+    let host_only = bus.module().bus_diagnosis();
+    let module_only = bus.module().bus_diagnosis();
+    fail_read(&mut bus);
+    let both = bus.module().bus_diagnosis();
+    let reset = bus.module().bus_diagnosis();
+    let unconfigured = bus.master.modules[1].bus_diagnosis();
+    bus.master.modules[1].set_remote_bus_items(names.clone());
+    let unavailable = bus.master.modules[1].bus_diagnosis();
+
+    assert_eq!(healthy.diagnosis, Diagnosis::Healthy);
+    assert_eq!(host_only.diagnosis, Diagnosis::HostOnly);
+    assert_eq!(host_only.local_delta.failures, 1);
+    assert_eq!(
+        host_only.to_string(),
+        format!(
+            "{address:#04X}: host saw failures the module didn't: suspect wiring or pull-ups\n  \
+             host:   1 failed (+1), 0 non-ready (+0), 3 read (+3)\n  \
+             module: 0 NACKs sent (+0), 0 overruns (+0), 0 bus errors (+0)"
+        )
+    );
+    assert_eq!(module_only.diagnosis, Diagnosis::ModuleOnly);
+    assert_eq!(module_only.remote_delta.unwrap().nacks_sent, 3);
+    assert_eq!(module_only.local_delta.failures, 0);
+    assert_eq!(both.diagnosis, Diagnosis::Both);
+    assert_eq!(
+        both.remote_delta,
+        Some(RemoteBusStats {
+            nacks_sent: 0,
+            buffer_overruns: 1,
+            bus_errors: 0,
+        })
+    );
+    // The module was reset, so its counters count from 0 again
+    assert_eq!(reset.diagnosis, Diagnosis::ModuleOnly);
+    assert_eq!(reset.remote_delta, reset.remote);
+    assert_eq!(unavailable.diagnosis, Diagnosis::RemoteUnavailable);
+    assert!(unavailable.remote_error.unwrap().contains(&names[0]));
+    assert_eq!(unconfigured.diagnosis, Diagnosis::RemoteUnavailable);
+    assert!(unconfigured
+        .remote_error
+        .unwrap()
+        .contains("no I2C error counter items configured"));
+    assert_eq!(
+        Diagnosis::Both.to_string(),
+        "both sides saw errors: suspect noise or contention on the bus"
+    );
+    assert_eq!(
+        Diagnosis::classify(&RemoteBusStats::default(), &ReadStats::default()),
+        Diagnosis::Healthy
+    );
+}
+
+#[test]
+fn telemetry_by_names() {
+    let mut bus = FaultBus::new(11);
+    let names: Vec<String> = bus.module().get_definition().unwrap().telemetry[1..4]
+        .iter()
+        .rev()
+        .map(|d| d.name.clone())
+        .collect();
+    let requests = |bus: &sim::SimBus| {
+        bus.transcript()
+            .iter()
+            .filter(|t| matches!(t.kind, sim::TransactionKind::Write(_)))
+            .count()
+    };
+
+    // The first item fails, the others are still read, in the requested order
+    bus.fail_reads(1);
+    let telemetry = bus.module().get_telemetry_by_names(&names).unwrap();
+    assert!(telemetry.keys().eq(names.iter()));
+    assert!(telemetry[0].is_err());
+    assert!(telemetry.values().skip(1).all(Result::is_ok));
+
+    // Strict reads stop at the first failure
+    bus.fail_reads(1);
+    bus.clear_transcript();
+    assert!(bus.module().get_telemetry_by_names_strict(&names).is_err());
+    assert_eq!(requests(&bus), 1);
+    let telemetry = bus.module().get_telemetry_by_names_strict(&names).unwrap();
+    assert!(telemetry.keys().eq(names.iter()));
+
+    // Unknown names fail before anything is sent
+    bus.clear_transcript();
+    let unknown = [names[0].as_str(), "not an item"];
+    assert!(matches!(
+        bus.module().get_telemetry_by_names(&unknown),
+        Err(SupMCUError::UnknownTelemName(name)) if name == "not an item"
+    ));
+    assert!(matches!(
+        bus.module().get_telemetry_by_names_strict(&unknown),
+        Err(SupMCUError::UnknownTelemName(_))
+    ));
+    assert_eq!(requests(&bus), 0);
+
+    let rt = runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    bus.fail_reads(1);
+    let module = bus.module();
+    let telemetry = rt
+        .block_on(module.get_telemetry_by_names_async(&names))
+        .unwrap();
+    assert!(telemetry.keys().eq(names.iter()));
+    assert!(telemetry[0].is_err());
+    assert_eq!(
+        rt.block_on(module.get_telemetry_by_names_strict_async(&names))
+            .unwrap()
+            .len(),
+        names.len()
+    );
+}
+
+#[test]
+fn audit_log() {
+    use audit::{AuditOutcome, AuditRecord};
+
+    let mut bus = FaultBus::new(13);
+    let address = bus.address;
+    let sink = SharedSink::default();
+    bus.master
+        .enable_audit_log(sink.clone(), AuditFormat::JsonLines { payload_limit: 12 });
+    let module = bus.module();
+    let def = module.get_definition().unwrap().telemetry[0].clone();
+    module.send_command("SUP:LED FLASH").unwrap();
+    module.get_telemetry_by_def(&def).unwrap();
+    bus.fail_reads(1);
+    let module = bus.module();
+    assert!(module.get_telemetry_by_def(&def).is_err());
+    bus.master.disable_audit_log();
+    bus.module().send_command("SUP:LED ON").unwrap();
+
+    let lines = sink.lines();
+    let records: Vec<AuditRecord> = lines
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(records.iter().map(|r| r.seq).eq(0..5));
+    assert!(records
+        .iter()
+        .all(|r| r.address == address && r.module.is_some() && r.time_us > 0));
+
+    // Command, then a request and its read, twice
+    let kinds: Vec<_> = records.iter().map(|r| r.event.clone()).collect();
+    let read = AuditEvent::Telemetry {
+        telemetry: def.name.clone(),
+    };
+    assert_eq!(
+        kinds,
+        [
+            AuditEvent::Command,
+            AuditEvent::Command,
+            read.clone(),
+            AuditEvent::Command,
+            read
+        ]
+    );
+    assert_eq!(records[0].payload.as_deref(), Some("SUP:LED FLAS"));
+    assert_eq!(records[0].truncated, Some(13));
+    assert_eq!(records[2].outcome, AuditOutcome::Ok);
+    assert_eq!(records[4].outcome, AuditOutcome::Error);
+    assert!(records[4].payload.is_none() && records[4].error.is_some());
+
+    let json: serde_json::Value = serde_json::from_str(&lines[4]).unwrap();
+    let mut keys: Vec<&str> = json
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    keys.sort();
+    assert_eq!(
+        keys,
+        [
+            "address",
+            "error",
+            "kind",
+            "module",
+            "outcome",
+            "seq",
+            "telemetry",
+            "time_us"
+        ]
+    );
+}
+
+#[test]
+fn version_string_probe() {
+    let mut bus = FaultBus::new(19);
+    assert!(bus.module().has_version_string());
+
+    bus.fail_reads(1);
+    assert!(!bus.module().has_version_string());
+
+    let module = bus.module();
+    let version: SupMCUTelemetryDefinition =
+        discovery::PremadeTelemetryDefs::FirmwareVersion.into();
+    module
+        .i2c_dev
+        .script(&version, vec![vec![SupMCUValue::Str("\u{7f}\u{3}".into())]]);
+    assert!(!module.has_version_string());
+    assert!(module.has_version_string());
+
+    assert!(discovery::is_version_string("BM2-1.2 Revision A\r\n"));
+    assert!(!discovery::is_version_string(""));
+    assert!(!discovery::is_version_string("2BM"));
+}
+
+#[cfg(feature = "generic-async")]
+#[test]
+fn generic_async_runtime() {
+    use async_rt::SleeperRuntime;
+    use futures::{channel::oneshot, executor::block_on};
+
+    // A timer on its own thread, without any tokio runtime around
+    let sleeper = SleeperRuntime::new(|duration| {
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let _ = tx.send(());
+        });
+        async move {
+            let _ = rx.await;
+        }
+    });
+    let mut bus = FaultBus::new(9);
+    bus.nonready(1);
+    let module = bus.module();
+    module.set_async_runtime(sleeper.clone());
+    let def = module.get_definition().unwrap().telemetry[1].clone();
+    let telemetry = block_on(module.get_telemetry_by_def_async(&def)).unwrap();
+    assert_eq!(telemetry.definition, def);
+    assert_eq!(module.stats().retries, 1);
+
+    let pending = future::pending::<()>();
+    assert_eq!(
+        block_on(async_rt::timeout(
+            &sleeper,
+            Duration::from_millis(1),
+            pending
+        )),
+        None
+    );
+    assert_eq!(
+        block_on(async_rt::timeout(&sleeper, Duration::from_secs(1), async {
+            1
+        })),
+        Some(1)
+    );
+}
+
+#[test]
+fn read_stats() {
+    let mut bus = FaultBus::new(1);
+    bus.module()
+        .get_telemetry(TelemetryType::SupMCU, 1)
+        .unwrap();
+    bus.nonready(2);
+    let master = &mut bus.master;
+    let module = &mut master.modules[0];
+    module.get_telemetry(TelemetryType::SupMCU, 1).unwrap();
+    assert_eq!(
+        *module.stats(),
+        ReadStats {
+            successes: 2,
+            failures: 0,
+            retries: 2,
+            nonready: 2,
+        }
+    );
+    assert_eq!(master.read_stats().successes, 2);
+
+    master.modules[0].reset_stats();
+    assert_eq!(*master.modules[0].stats(), ReadStats::default());
+}
+
+#[test]
+fn sim_failed_reads() {
+    let mut bus = FaultBus::new(2);
+    bus.fail_reads(1);
+    let module = bus.module();
+    assert!(module.get_telemetry(TelemetryType::SupMCU, 1).is_err());
+    assert_eq!(module.stats().failures, 1);
+    assert!(module.get_telemetry(TelemetryType::SupMCU, 1).is_ok());
+    assert!(bus
+        .transcript()
+        .iter()
+        .any(|t| matches!(t.kind, sim::TransactionKind::Fault(_))));
+}
+
+#[test]
+fn anomaly_bundle() {
+    let mut bus = FaultBus::new(9);
+    let address = bus.address;
+    let module = bus.module();
+    let reset = module
+        .get_definition()
+        .unwrap()
+        .telemetry
+        .iter()
+        .find(|d| d.name == RESET_CAUSE_TLM)
+        .unwrap()
+        .clone();
+    module.enable_history(&reset, 4);
+    module.reset_cause().unwrap();
+    for _ in 0..10 {
+        module.get_telemetry(TelemetryType::SupMCU, 5).unwrap();
+    }
+    bus.fail_reads(1);
+    let err = bus
+        .module()
+        .get_telemetry(TelemetryType::SupMCU, 5)
+        .unwrap_err();
+
+    let bundle = bus.master.anomaly_bundle(&err);
+    assert_eq!(bundle.address, Some(address));
+    assert_eq!(bundle.kind, "I2CTelemetryError");
+    assert_eq!(bundle.last_command.as_deref(), Some("SUP:TEL? 5"));
+    let item = bundle.definition.as_ref().unwrap().item.as_ref().unwrap();
+    assert_eq!(item.idx, 5);
+    assert_eq!(bundle.transactions.len(), RECENT_TRANSACTIONS);
+    // Untrimmed transactions are shared with the module
+    assert!(Arc::ptr_eq(
+        &bundle.transactions[0],
+        &bus.module().recent[0]
+    ));
+    assert_eq!(bundle.resets.len(), 1);
+    assert_eq!(bundle.stats.unwrap().failures, 1);
+    assert!(!bundle.trimmed);
+
+    for max_bytes in [512, 1024, 2048] {
+        bus.master.set_anomaly_max_bytes(max_bytes);
+        let bundle = bus.master.anomaly_bundle(&err);
+        assert!(bundle.trimmed);
+        assert!(bundle.to_json().unwrap().len() <= max_bytes);
+        assert_eq!(bundle.kind, "I2CTelemetryError");
+    }
+}
+
+#[test]
+fn anomaly_hook() {
+    let mut bus = FaultBus::new(10);
+    let address = bus.address;
+    let bundles = Arc::new(Mutex::new(vec![]));
+    let received = bundles.clone();
+    bus.master
+        .on_anomaly(&[ErrorCategory::Transport], move |bundle| {
+            received.lock().unwrap().push(bundle)
+        });
+    bus.fail_reads(1);
+    bus.master.get_all_telemetry();
+
+    let bundles = bundles.lock().unwrap();
+    assert_eq!(bundles.len(), 1);
+    assert_eq!(bundles[0].address, Some(address));
+    assert_eq!(bundles[0].category, ErrorCategory::Transport);
+}
+
+#[test]
+fn retries_agree_with_is_retryable() {
+    let mut bus = FaultBus::new(3);
+    let max_retries = bus.module().max_retries.unwrap() as usize;
+    bus.nonready(max_retries + 2);
+    let module = bus.module();
+    let e = module.get_telemetry(TelemetryType::SupMCU, 0).unwrap_err();
+    assert!(matches!(e, SupMCUError::NonReadyError(..)));
+    assert!(e.is_retryable());
+    assert_eq!(module.stats().retries, max_retries as u64 + 1);
+
+    // Errors that aren't retryable are returned without retrying
+    let retries = module.stats().retries;
+    let e = module.get_telemetry_by_name("not an item").unwrap_err();
+    assert!(!e.is_retryable());
+    assert_eq!(module.stats().retries, retries);
+    bus.fail_reads(1);
+    let module = bus.module();
+    let e = module.get_telemetry(TelemetryType::SupMCU, 0).unwrap_err();
+    assert!(matches!(e, SupMCUError::I2CTelemetryError(..)));
+    assert!(!e.is_retryable());
+    assert_eq!(module.stats().retries, retries);
+}
+
+#[test]
+fn retries_for_one_read() {
+    let mut bus = FaultBus::new(12);
+    let max_retries = bus.module().max_retries;
+    let def = bus.module().get_definition().unwrap().telemetry[0].clone();
+    let patience = max_retries.unwrap() + 2;
+    bus.nonready(patience as usize);
+    let module = bus.module();
+    assert!(module.get_telemetry_by_def_retries(&def, patience).is_ok());
+    assert_eq!(module.max_retries, max_retries);
+
+    bus.nonready(2);
+    let module = bus.module();
+    assert!(matches!(
+        module.get_telemetry_by_def_retries(&def, 0),
+        Err(SupMCUError::NonReadyError(..))
+    ));
+    assert_eq!(module.max_retries, max_retries);
+}
+
+#[test]
+fn per_call_read_options() {
+    let mut bus = FaultBus::new(13);
+    let address = bus.address;
+    let clock = bus.clock();
+    let module = bus.module();
+    module.get_definition_mut().unwrap().response_delay = 0.3;
+    let max_retries = module.max_retries;
+    let def = module.get_definition().unwrap().telemetry[1].clone();
+
+    let start = clock.now();
+    let fast = module
+        .get_telemetry_by_def_with(
+            &def,
+            &ReadOptions {
+                response_delay: Some(0.0),
+                keep_raw: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(clock.now(), start);
+    assert_eq!(fast.raw.as_deref(), Some(module.last_raw_response()));
+    let slow = module.get_telemetry_by_def(&def);
+    assert!(slow.is_ok() && clock.now() - start >= Duration::from_millis(300));
+    module.get_definition_mut().unwrap().response_delay = 0.0;
+
+    bus.nonready(2);
+    let module = bus.module();
+    let no_retries = ReadOptions {
+        max_retries: Some(0),
+        ..Default::default()
+    };
+    assert!(matches!(
+        module.get_telemetry_by_name_with(&def.name, &no_retries),
+        Err(SupMCUError::NonReadyError(..))
+    ));
+    assert_eq!(module.max_retries, max_retries);
+    bus.nonready(2);
+    let module = bus.module();
+    assert!(module.get_telemetry_by_def(&def).is_ok());
+
+    bus.nonready(2);
+    let module = bus.module();
+    let expired = ReadOptions {
+        timeout: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    assert!(matches!(
+        module.get_telemetry_with(def.telemetry_type, def.idx, &expired),
+        Err(SupMCUError::BusTimeout(addresses)) if addresses == vec![address]
+    ));
+    assert!(module.read_deadline.is_none());
+
+    // The simulated module doesn't fill its footer
+    module.get_definition_mut().unwrap().checksum = ChecksumKind::Crc32Cksum;
+    let unchecked = ReadOptions {
+        verify_checksum: Some(false),
+        ..Default::default()
+    };
+    let read = module.get_telemetry_by_def_with(&def, &unchecked).unwrap();
+    assert!(read.raw.is_none());
+    assert!(matches!(
+        module.get_telemetry_by_def(&def),
+        Err(SupMCUError::ValidationError { .. })
+    ));
+    assert_eq!(module.read_options, ReadOptions::default());
+
+    let bad_delay = ReadOptions {
+        response_delay: Some(f32::NAN),
+        ..Default::default()
+    };
+    assert!(matches!(
+        module.get_telemetry_by_def_with(&def, &bad_delay),
+        Err(SupMCUError::InvalidResponseDelay(..))
+    ));
+
+    // Dropping a read part way restores the module's settings
+    bus.real_time();
+    let module = bus.module();
+    let slow = ReadOptions {
+        response_delay: Some(5.0),
+        timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let rt = runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let read = rt.block_on(async {
+        let read = module.get_telemetry_by_def_with_async(&def, &slow);
+        time::timeout(Duration::from_millis(10), read).await
+    });
+    assert!(read.is_err());
+    assert_eq!(module.read_options, ReadOptions::default());
+    assert!(module.read_deadline.is_none());
+}
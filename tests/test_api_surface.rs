@@ -0,0 +1,250 @@
+//! Snapshots of the serialized values and error messages, which tools downstream parse.
+//!
+//! A change to either is a breaking change.  If it's intended, regenerate the golden files with
+//! `UPDATE_GOLDEN=1 cargo test --features toml --test test_api_surface` and bump the version
+//! accordingly.
+use std::time::{Duration, UNIX_EPOCH};
+use std::{fs, path::Path};
+use supmcu_rs::{
+    supmcu::{checksum::ChecksumKind, export, parsing::*},
+    ParsingError, SupMCUError,
+};
+
+/// Compares `actual` to the golden file `name`, or rewrites it if `UPDATE_GOLDEN` is set
+fn check_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert!(
+        expected == actual,
+        "{name} changed, this breaks the public API.\nexpected:\n{expected}\nactual:\n{actual}"
+    );
+}
+
+#[test]
+fn value_serialization() {
+    let values = [
+        SupMCUValue::Str("hello".into()),
+        SupMCUValue::Char('c'),
+        SupMCUValue::U8(u8::MAX),
+        SupMCUValue::I8(i8::MIN),
+        SupMCUValue::U16(u16::MAX),
+        SupMCUValue::I16(i16::MIN),
+        SupMCUValue::U32(u32::MAX),
+        SupMCUValue::I32(i32::MIN),
+        SupMCUValue::U64(u64::MAX),
+        SupMCUValue::I64(i64::MIN),
+        SupMCUValue::Float(1.5),
+        SupMCUValue::Double(-0.25),
+        SupMCUValue::Hex8(0xab),
+        SupMCUValue::Hex16(0xbeef),
+    ];
+    let mut json = serde_json::to_string_pretty(&values).unwrap();
+    json.push('\n');
+    check_golden("values.json", &json);
+    let parsed: Vec<SupMCUValue> = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, values);
+}
+
+/// Returns the error of a cancelled tokio task
+fn join_error() -> SupMCUError {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let task = tokio::spawn(std::future::pending::<()>());
+        task.abort();
+        task.await.unwrap_err().into()
+    })
+}
+
+/// Formats errors one per line, after their kinds
+fn error_lines(errors: &[SupMCUError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{}: {e}\n", e.kind()))
+        .collect()
+}
+
+#[test]
+fn error_messages() {
+    let io = || std::io::Error::other("io");
+    let errors = [
+        SupMCUError::IoError(io()),
+        SupMCUError::I2CDevError {
+            device: "/dev/i2c-1".into(),
+            address: 0x52,
+            error: io().into(),
+        },
+        SupMCUError::I2CCommandError(0x52, "SUP:LED ON".into()),
+        SupMCUError::I2CTelemetryError(0x52, "SUP:TEL? 0".into()),
+        SupMCUError::ParsingError(ParsingError::InvalidFormatCharacter('!')),
+        SupMCUError::TelemetryIndexError(TelemetryType::SupMCU, 42),
+        SupMCUError::NonReadyError(0x52, "SUP:TEL? 0".into()),
+        SupMCUError::ValidationError {
+            kind: ChecksumKind::Crc32Cksum,
+            expected: 0x1234,
+            actual: 0x5678,
+        },
+        SupMCUError::MissingDefinitionError,
+        join_error(),
+        serde_json::from_str::<u8>("x").unwrap_err().into(),
+        ciborium::from_reader::<u8, _>(&[0x61, b'x'][..])
+            .unwrap_err()
+            .into(),
+        SupMCUError::ModuleNotFound("BM2".into(), 0x52),
+        SupMCUError::UnexpectedValue("SUP:TEL? 0".into(), SupMCUValue::U8(7)),
+        SupMCUError::UnknownTelemName("Firmware version".into()),
+        SupMCUError::UnknownModuleName("BM2".into()),
+        SupMCUError::UnknownCommandName("LED".into()),
+        SupMCUError::DuplicateAddress(0x52),
+        SupMCUError::BusTimeout(vec![0x52, 0x53]),
+        SupMCUError::FormatDriftError("Firmware version".into(), "S".into(), "u".into()),
+        SupMCUError::ManagedAddress(0x52),
+        SupMCUError::ModuleAsleep(0x52),
+        SupMCUError::InvalidResponseDelay("BM2".into(), -1.0),
+        SupMCUError::UnknownMacro("reset".into()),
+        SupMCUError::AmbiguousMacro("reset".into()),
+        SupMCUError::UnsupportedSessionVersion(9),
+        SupMCUError::SessionDivergence(0x52, "expected SUP:TEL? 0".into()),
+        SupMCUError::InconsistentIndices(0x52, "index 3 is missing".into()),
+        SupMCUError::MaskedByOpsRule(0x52, "eclipse".into()),
+        SupMCUError::ArgumentOutOfRange {
+            command: "PIM:CHAN".into(),
+            arg: 9,
+            min: 1,
+            max: 8,
+        },
+        SupMCUError::DryRun(0x52),
+        SupMCUError::AmbiguousModule("0x41".into(), vec!["BM2".into(), "PIM".into()]),
+        SupMCUError::DuplicateModuleName("BM2".into()),
+        SupMCUError::NotSimulatable("Firmware version".into()),
+        SupMCUError::Slimmed("BM2".into(), "command LED".into()),
+        SupMCUError::TransferTooLong {
+            address: 0x52,
+            item: "Firmware version".into(),
+            size: 300,
+            limit: 255,
+        },
+        SupMCUError::NoChangeCounter(0x52),
+        SupMCUError::PartialWrite {
+            address: 0x52,
+            command: "PIM:CHAN 1,OFF".into(),
+            sent: 10,
+            total: 14,
+        },
+        SupMCUError::UnknownTemplate("BM2".into()),
+        SupMCUError::TemplateMismatch(0x52, "the module is a PIM, not a BM2".into()),
+        SupMCUError::ChangeSetOpen,
+        SupMCUError::ChangeSetClosed,
+        SupMCUError::EmergencyNotAllowed,
+        SupMCUError::PrefixMismatch(0x40, 2, 1),
+        SupMCUError::TooManyDefinitions {
+            definitions: 7,
+            modules: 6,
+        },
+        SupMCUError::WouldBlock,
+        SupMCUError::SelfTestTimeout(0x40),
+        SupMCUError::NotConfigured(0x40, "sleep command".into()),
+    ];
+    // The id of the task in AsyncError's message differs between runs
+    let messages: String = error_lines(&errors)
+        .lines()
+        .map(
+            |line| match line.strip_prefix("AsyncError: AsyncError: task ") {
+                Some(rest) => {
+                    let (_, rest) = rest.split_once(' ').unwrap();
+                    format!("AsyncError: AsyncError: task <id> {rest}\n")
+                }
+                None => format!("{line}\n"),
+            },
+        )
+        .collect();
+    check_golden("errors.txt", &messages);
+}
+
+/// The errors only there with the `toml` feature
+#[cfg(feature = "toml")]
+#[test]
+fn toml_error_messages() {
+    let errors = [
+        toml::from_str::<u8>("x").unwrap_err().into(),
+        toml::to_string(&1u8).unwrap_err().into(),
+    ];
+    check_golden("errors_toml.txt", &error_lines(&errors));
+}
+
+#[test]
+fn exported_readings() {
+    let module = |name: &str, unique_name: Option<&str>, address| SupMCUModuleDefinition {
+        name: name.into(),
+        unique_name: unique_name.map(Into::into),
+        address,
+        ..Default::default()
+    };
+    let reading = |name: &str, telemetry_type, data, host_time| SupMCUTelemetry {
+        definition: SupMCUTelemetryDefinition {
+            name: name.into(),
+            telemetry_type,
+            ..Default::default()
+        },
+        header: SupMCUHDR {
+            ready: true,
+            timestamp: 1000,
+        },
+        data,
+        host_time,
+    };
+    let at = Some(UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789));
+    let (bm2, pim) = (
+        module("BM2", None, 0x52),
+        module("PIM", Some("PIM A,B=1 "), 0x53),
+    );
+    let readings = [
+        reading(
+            "Firmware version",
+            TelemetryType::SupMCU,
+            vec![SupMCUValue::Str("say \"hi\" \\ bye".into())],
+            at,
+        ),
+        reading(
+            "bus,voltage",
+            TelemetryType::Module,
+            vec![
+                SupMCUValue::Float(8.12),
+                SupMCUValue::Double(f64::NAN),
+                SupMCUValue::U64(u64::MAX),
+                SupMCUValue::I8(-3),
+                SupMCUValue::Hex16(0xbeef),
+                SupMCUValue::Char('c'),
+            ],
+            None,
+        ),
+        reading("nothing", TelemetryType::Module, vec![], at),
+    ];
+    let lines = export::to_line_protocol(
+        [
+            (&bm2, &readings[0]),
+            (&pim, &readings[1]),
+            (&bm2, &readings[2]),
+        ],
+        "supmcu ",
+    );
+    check_golden("line_protocol.txt", &lines);
+
+    let mut sink = export::JsonLinesSink::new(vec![]);
+    for reading in readings.iter().take(2) {
+        sink.write(0x52, reading).unwrap();
+    }
+    let json_lines = String::from_utf8(sink.into_inner()).unwrap();
+    check_golden("json_lines.txt", &json_lines);
+    // NaN is written as null, so lines are valid JSON but don't all parse back
+    for line in json_lines.lines() {
+        serde_json::from_str::<serde_json::Value>(line).unwrap();
+    }
+}
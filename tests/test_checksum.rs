@@ -0,0 +1,80 @@
+use supmcu_rs::{supmcu::checksum::ChecksumKind, SupMCUError};
+
+const CHECK_DATA: &[u8] = b"123456789";
+
+#[test]
+fn known_vectors() {
+    for (kind, check) in [
+        (ChecksumKind::None, 0),
+        (ChecksumKind::Crc32Cksum, 0x765E7680),
+        (ChecksumKind::Crc16Ccitt, 0x29B1),
+        (ChecksumKind::Sum8, 0xDD),
+    ] {
+        assert_eq!(kind.compute(CHECK_DATA), check, "{kind}");
+    }
+}
+
+#[test]
+fn verify_footers() {
+    for kind in [
+        ChecksumKind::None,
+        ChecksumKind::Crc32Cksum,
+        ChecksumKind::Crc16Ccitt,
+        ChecksumKind::Sum8,
+    ] {
+        let mut response = CHECK_DATA.to_vec();
+        response.extend(kind.footer(CHECK_DATA));
+        assert!(kind.verify(&response).is_ok(), "{kind}");
+        assert_eq!(ChecksumKind::detect(&response), kind);
+
+        // Padding after the checksum is ignored
+        *response.last_mut().unwrap() = 0xFF;
+        assert!(kind.verify(&response).is_ok(), "{kind}");
+    }
+}
+
+#[test]
+fn mismatched_checksum() {
+    let mut response = CHECK_DATA.to_vec();
+    response.extend(ChecksumKind::Crc16Ccitt.footer(CHECK_DATA));
+    response[0] = b'0';
+    match ChecksumKind::Crc16Ccitt.verify(&response) {
+        Err(SupMCUError::ValidationError {
+            kind,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(kind, ChecksumKind::Crc16Ccitt);
+            assert_eq!(actual, 0x29B1);
+            assert_ne!(expected, actual);
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(ChecksumKind::detect(&response), ChecksumKind::None);
+}
+
+#[test]
+fn verify_detected_footers() {
+    for kind in [
+        ChecksumKind::None,
+        ChecksumKind::Crc32Cksum,
+        ChecksumKind::Crc16Ccitt,
+        ChecksumKind::Sum8,
+    ] {
+        let mut response = CHECK_DATA.to_vec();
+        response.extend(kind.footer(CHECK_DATA));
+        assert_eq!(ChecksumKind::verify_detected(&response).unwrap(), kind);
+    }
+
+    // A flipped CRC byte isn't taken for a response without a checksum
+    let mut response = CHECK_DATA.to_vec();
+    response.extend(ChecksumKind::Crc32Cksum.footer(CHECK_DATA));
+    response[CHECK_DATA.len() + 3] ^= 0x80;
+    assert!(matches!(
+        ChecksumKind::verify_detected(&response),
+        Err(SupMCUError::ValidationError {
+            kind: ChecksumKind::Crc32Cksum,
+            ..
+        })
+    ));
+}
@@ -0,0 +1,75 @@
+//! Exit codes of `pumqry`, run on a session recorded from a simulated module.
+//!
+//! `discover --compare` exits with 0 if the definitions match the file, 1 if they differ and 2
+//! on errors, which scripts checking for firmware changes rely on.
+#![cfg(all(feature = "pumqry", feature = "test-utils"))]
+use std::{
+    fs::{self, File},
+    process::Command,
+};
+use supmcu_rs::supmcu::{parsing::SupMCUModuleDefinition, session::Session, sim};
+
+/// Records a discovery of a simulated module to `session`, returning its definition
+fn record_discovery(session: &str) -> Vec<SupMCUModuleDefinition> {
+    let defs: Vec<SupMCUModuleDefinition> =
+        serde_json::from_reader(File::open("test-definition.json").unwrap()).unwrap();
+    let defs = defs[..1].to_vec();
+    let mut bus = sim::SimBus::new(1454, defs.clone()).unwrap();
+    bus.master.record_session(session);
+    bus.master.discover_modules().unwrap();
+    bus.master.end_session().unwrap();
+    // The replay discovers the module from scratch, like pumqry does on hardware
+    let mut recorded = Session::load(session).unwrap();
+    for module in recorded.modules.iter_mut() {
+        module.definition = None;
+    }
+    recorded.save(session).unwrap();
+    defs
+}
+
+/// Runs `pumqry --replay session discover --compare old`, returning its exit code
+fn compare_exit_code(session: &str, old: &str) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_pumqry"))
+        .args(["--replay", session, "discover", "--compare", old])
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn compare_exit_codes() {
+    let session = "test-session.cli.tmp";
+    let (same, changed) = (
+        "test-definition.cli-same.tmp.json",
+        "test-definition.cli-changed.tmp.json",
+    );
+    let mut defs = record_discovery(session);
+    fs::write(same, serde_json::to_string(&defs).unwrap()).unwrap();
+    defs[0].telemetry.pop();
+    fs::write(changed, serde_json::to_string(&defs).unwrap()).unwrap();
+
+    let codes = [
+        compare_exit_code(session, same),
+        compare_exit_code(session, changed),
+        compare_exit_code("test-session.missing.tmp", same),
+    ];
+    for file in [session, same, changed] {
+        fs::remove_file(file).unwrap();
+    }
+    assert_eq!(codes, [Some(0), Some(1), Some(2)]);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn compare_toml_definitions() {
+    let session = "test-session.cli-toml.tmp";
+    let file = "test-definition.cli.tmp.toml";
+    let bus = sim::SimBus::new(1454, record_discovery(session)).unwrap();
+    bus.master.save_def_file(file).unwrap();
+
+    let code = compare_exit_code(session, file);
+    fs::remove_file(session).unwrap();
+    fs::remove_file(file).unwrap();
+    assert_eq!(code, Some(0));
+}
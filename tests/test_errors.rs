@@ -0,0 +1,201 @@
+use serde_json::json;
+use supmcu_rs::{supmcu::checksum::ChecksumKind, SupMCUError};
+
+#[test]
+fn serialize_error_with_address() {
+    let e = SupMCUError::NonReadyError(0x52, "SUP:TEL? 0".into());
+    assert_eq!(
+        serde_json::to_value(&e).unwrap(),
+        json!({
+            "kind": "NonReadyError",
+            "address": 0x52,
+            "message": e.to_string(),
+        })
+    );
+}
+
+#[test]
+fn serialize_error_without_address() {
+    let e = SupMCUError::MissingDefinitionError;
+    assert_eq!(
+        serde_json::to_value(&e).unwrap(),
+        json!({
+            "kind": "MissingDefinitionError",
+            "message": e.to_string(),
+        })
+    );
+}
+
+#[test]
+fn module_lookup_errors() {
+    use supmcu_rs::supmcu::ModuleRef;
+
+    let e = SupMCUError::from(&ModuleRef::Name("BM2".into()));
+    assert!(matches!(&e, SupMCUError::UnknownModuleName(name) if name == "BM2"));
+    assert_eq!(e.address(), None);
+    let e = SupMCUError::from(&ModuleRef::Address(0x52));
+    assert_eq!(e.address(), Some(0x52));
+}
+
+#[test]
+fn error_categories() {
+    use supmcu_rs::{supmcu::parsing::*, ErrorCategory::*, ParsingError};
+    let io = || std::io::Error::other("io");
+    let cases = [
+        (SupMCUError::IoError(io()), Transport),
+        (
+            SupMCUError::I2CDevError {
+                device: "/dev/i2c-1".into(),
+                address: 0x52,
+                error: io().into(),
+            },
+            Transport,
+        ),
+        (SupMCUError::I2CCommandError(0x52, "".into()), Transport),
+        (SupMCUError::I2CTelemetryError(0x52, "".into()), Transport),
+        (SupMCUError::BusTimeout(vec![0x52]), Transport),
+        (
+            SupMCUError::PartialWrite {
+                address: 0x52,
+                command: "PIM:CHAN 1,OFF".into(),
+                sent: 10,
+                total: 14,
+            },
+            Transport,
+        ),
+        (SupMCUError::NonReadyError(0x52, "".into()), Protocol),
+        (
+            SupMCUError::ValidationError {
+                kind: ChecksumKind::Crc32Cksum,
+                expected: 1,
+                actual: 2,
+            },
+            Protocol,
+        ),
+        (
+            SupMCUError::UnexpectedValue("x".into(), SupMCUValue::U8(0)),
+            Protocol,
+        ),
+        (
+            SupMCUError::FormatDriftError("x".into(), "u".into(), "i".into()),
+            Protocol,
+        ),
+        (SupMCUError::ModuleAsleep(0x52), Protocol),
+        (SupMCUError::SessionDivergence(0x52, "".into()), Protocol),
+        (SupMCUError::InconsistentIndices(0x52, "".into()), Protocol),
+        (
+            SupMCUError::ParsingError(ParsingError::InvalidFormatCharacter('!')),
+            Parsing,
+        ),
+        (serde_json::from_str::<u8>("x").unwrap_err().into(), Parsing),
+        (
+            ciborium::from_reader::<u8, _>(&[0x61, b'x'][..])
+                .unwrap_err()
+                .into(),
+            Parsing,
+        ),
+        (
+            SupMCUError::TelemetryIndexError(TelemetryType::SupMCU, 0),
+            Configuration,
+        ),
+        (SupMCUError::MissingDefinitionError, Configuration),
+        (SupMCUError::ModuleNotFound("".into(), 0x52), Configuration),
+        (SupMCUError::UnknownTelemName("x".into()), Configuration),
+        (SupMCUError::UnknownModuleName("x".into()), Configuration),
+        (SupMCUError::UnknownCommandName("x".into()), Configuration),
+        (SupMCUError::DuplicateAddress(0x52), Configuration),
+        (
+            SupMCUError::InvalidResponseDelay("x".into(), -1.0),
+            Configuration,
+        ),
+        (SupMCUError::UnknownMacro("x".into()), Configuration),
+        (SupMCUError::UnsupportedSessionVersion(0), Configuration),
+        (SupMCUError::ManagedAddress(0x52), Usage),
+        (SupMCUError::AmbiguousMacro("x".into()), Usage),
+        (SupMCUError::MaskedByOpsRule(0x52, "x".into()), Usage),
+        (SupMCUError::DryRun(0x52), Usage),
+        (
+            SupMCUError::AmbiguousModule("x".into(), vec!["0x41".into(), "0x42".into()]),
+            Usage,
+        ),
+        (SupMCUError::DuplicateModuleName("x".into()), Usage),
+        (SupMCUError::NotSimulatable("x".into()), Configuration),
+        (SupMCUError::Slimmed("x".into(), "y".into()), Configuration),
+        (
+            SupMCUError::TransferTooLong {
+                address: 0x52,
+                item: "x".into(),
+                size: 300,
+                limit: 255,
+            },
+            Configuration,
+        ),
+        (SupMCUError::NoChangeCounter(0x52), Configuration),
+        (SupMCUError::UnknownTemplate("BM2".into()), Configuration),
+        (
+            SupMCUError::TemplateMismatch(0x52, "x".into()),
+            Configuration,
+        ),
+        (SupMCUError::ChangeSetOpen, Configuration),
+        (SupMCUError::ChangeSetClosed, Configuration),
+        (SupMCUError::EmergencyNotAllowed, Configuration),
+        (SupMCUError::PrefixMismatch(0x40, 2, 1), Configuration),
+        (
+            SupMCUError::TooManyDefinitions {
+                definitions: 7,
+                modules: 6,
+            },
+            Configuration,
+        ),
+        (SupMCUError::WouldBlock, Transport),
+        (SupMCUError::SelfTestTimeout(0x40), Protocol),
+        (SupMCUError::NotConfigured(0x40, "x".into()), Configuration),
+        (
+            SupMCUError::ArgumentOutOfRange {
+                command: "PIM:CHAN".into(),
+                arg: 9,
+                min: 1,
+                max: 8,
+            },
+            Usage,
+        ),
+    ];
+    for (e, category) in cases {
+        assert_eq!(e.category(), category, "{}", e.kind());
+        assert_eq!(
+            e.is_configuration(),
+            category == Configuration,
+            "{}",
+            e.kind()
+        );
+    }
+}
+
+#[test]
+fn retryable_errors() {
+    assert!(SupMCUError::NonReadyError(0x52, "SUP:TEL? 0".into()).is_retryable());
+    assert!(!SupMCUError::I2CTelemetryError(0x52, "".into()).is_retryable());
+    assert!(SupMCUError::BusTimeout(vec![0x52]).is_retryable());
+    assert!(SupMCUError::WouldBlock.is_retryable());
+    assert!(!SupMCUError::MissingDefinitionError.is_retryable());
+    assert!(!SupMCUError::ManagedAddress(0x52).is_retryable());
+}
+
+#[test]
+fn serialize_parse_error_position() {
+    use std::io::Cursor;
+    use supmcu_rs::supmcu::parsing::SupMCUFormat;
+    let e: SupMCUError = SupMCUFormat::new("uf")
+        .parse_data(&mut Cursor::new(&vec![0; 3]))
+        .unwrap_err()
+        .into();
+    assert_eq!(
+        serde_json::to_value(&e).unwrap(),
+        json!({
+            "kind": "ParsingError",
+            "field": 1,
+            "offset": 1,
+            "message": e.to_string(),
+        })
+    );
+}
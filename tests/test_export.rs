@@ -0,0 +1,81 @@
+use std::{net::UdpSocket, time::Duration};
+use supmcu_rs::supmcu::{
+    export::{send_udp, to_line_protocol, MAX_DATAGRAM_LEN},
+    parsing::{
+        SupMCUHDR, SupMCUModuleDefinition, SupMCUTelemetry, SupMCUTelemetryDefinition, SupMCUValue,
+    },
+};
+
+#[test]
+fn udp_datagrams_split_at_lines() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    let lines: String = (0..100)
+        .map(|i| format!("supmcu_item,module=BM2,address=0x52,type=Module v0={i}i\n"))
+        .collect();
+
+    let sent = send_udp(&lines, listener.local_addr().unwrap()).unwrap();
+    let mut received = String::new();
+    let mut buf = [0; 2 * MAX_DATAGRAM_LEN];
+    for _ in 0..sent {
+        let len = listener.recv(&mut buf).unwrap();
+        assert!(len <= MAX_DATAGRAM_LEN);
+        let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(datagram.ends_with('\n'));
+        received.push_str(datagram);
+    }
+    assert!(sent > 1);
+    assert_eq!(received, lines);
+}
+
+#[test]
+fn udp_ipv6_and_long_lines() {
+    // Not every host has IPv6 loopback
+    if let Ok(listener) = UdpSocket::bind("[::1]:0") {
+        listener
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let line = "supmcu_item,module=BM2 v0=1i\n";
+        assert_eq!(send_udp(line, listener.local_addr().unwrap()).unwrap(), 1);
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], line.as_bytes());
+    }
+
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let long = format!("supmcu_item v0=\"{}\"\n", "x".repeat(MAX_DATAGRAM_LEN));
+    let lines = format!("supmcu_item v0=1i\n{long}");
+    let err = send_udp(&lines, listener.local_addr().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("doesn't fit in a datagram"));
+    // Nothing is sent, not even the lines that fit
+    assert!(listener.recv(&mut [0; 16]).is_err());
+}
+
+#[test]
+fn trailing_backslashes_escaped() {
+    let module = SupMCUModuleDefinition {
+        name: "PIM".into(),
+        unique_name: Some(r"PIM\".into()),
+        address: 0x53,
+        ..Default::default()
+    };
+    let reading = SupMCUTelemetry {
+        definition: SupMCUTelemetryDefinition {
+            name: r"volts\".into(),
+            ..Default::default()
+        },
+        header: SupMCUHDR {
+            ready: true,
+            timestamp: 0,
+        },
+        data: vec![SupMCUValue::U8(1)],
+        host_time: None,
+    };
+    assert_eq!(
+        to_line_protocol([(&module, &reading)], ""),
+        "volts\\\\,module=PIM\\\\,address=0x53,type=SupMCU v0=1i\n"
+    );
+}
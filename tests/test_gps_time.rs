@@ -0,0 +1,88 @@
+use std::time::{Duration, SystemTime};
+use supmcu_rs::supmcu::gps_time::*;
+
+fn unix(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[test]
+fn known_vectors() {
+    for (week, tow_ms, leap_seconds, utc) in [
+        // The GPS epoch, 1980-01-06 00:00:00
+        (0, 0, 0, unix(315_964_800)),
+        // First rollover, 1999-08-21 23:59:47
+        (1024, 0, 13, unix(935_279_987)),
+        // Week 2000, 2018-05-05 23:59:42
+        (2000, 0, 18, unix(1_525_564_782)),
+        // Second rollover, 2019-04-06 23:59:42
+        (2048, 0, 18, unix(1_554_595_182)),
+        // 2020-01-01 00:00:00
+        (2086, 259_218_000, 18, unix(1_577_836_800)),
+        // 2020-01-01 00:00:00.250
+        (
+            2086,
+            259_218_250,
+            18,
+            unix(1_577_836_800) + Duration::from_millis(250),
+        ),
+    ] {
+        assert_eq!(
+            gps_week_tow_to_utc(week, tow_ms, leap_seconds),
+            utc,
+            "week {week}"
+        );
+        assert_eq!(
+            utc_to_gps_week_tow(utc, leap_seconds),
+            Some((week, tow_ms)),
+            "week {week}"
+        );
+    }
+}
+
+#[test]
+fn leap_second_override() {
+    let gps = gps_week_tow_to_utc(2086, 259_218_000, DEFAULT_LEAP_SECONDS);
+    let stale = gps_week_tow_to_utc(2086, 259_218_000, 17);
+    assert_eq!(stale.duration_since(gps).unwrap(), Duration::from_secs(1));
+}
+
+#[test]
+fn end_of_week() {
+    // The last millisecond of week 2085 is just before week 2086
+    let last = gps_week_tow_to_utc(2085, 604_799_999, DEFAULT_LEAP_SECONDS);
+    let next = gps_week_tow_to_utc(2086, 0, DEFAULT_LEAP_SECONDS);
+    assert_eq!(next.duration_since(last).unwrap(), Duration::from_millis(1));
+    assert_eq!(
+        utc_to_gps_week_tow(last, DEFAULT_LEAP_SECONDS),
+        Some((2085, 604_799_999))
+    );
+}
+
+#[test]
+fn before_epoch() {
+    assert_eq!(utc_to_gps_week_tow(unix(0), DEFAULT_LEAP_SECONDS), None);
+    assert_eq!(utc_to_gps_week_tow(unix(315_964_799), 0), None);
+}
+
+#[test]
+fn week_rollover() {
+    // 2019-04-07 00:00:00 GPS reported as week 0 by a receiver with 10-bit weeks
+    let reported = 2048 % WEEK_ROLLOVER;
+    assert_eq!(
+        resolve_week_rollover(reported, Some(unix(1_559_347_200))),
+        2048
+    );
+    // Before 2019 the same week number meant the 1999 rollover
+    assert_eq!(
+        resolve_week_rollover(reported, Some(unix(946_684_800))),
+        1024
+    );
+    // Weeks just before a rollover resolve to the previous cycle
+    assert_eq!(resolve_week_rollover(1023, Some(unix(1_559_347_200))), 2047);
+    // A reference before the first rollover
+    assert_eq!(resolve_week_rollover(500, Some(unix(315_964_800))), 500);
+    // Full week numbers are returned as they are
+    assert_eq!(resolve_week_rollover(2086, Some(unix(0))), 2086);
+    // Without a reference the host time is used, which is past the 2019 rollover
+    assert!(resolve_week_rollover(0, None) >= 2048);
+}
@@ -0,0 +1,121 @@
+//! The GraphQL mutations, run against a simulated bus.
+//!
+//! The schema is snapshotted like the other public formats, see `test_api_surface.rs`.
+#![cfg(feature = "test-utils")]
+use async_graphql::{EmptySubscription, Object, Schema};
+use std::{
+    fs::{self, File},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use supmcu_rs::supmcu::{
+    graphql::CommandMutation, i2c::TestI2CDevice, parsing::SupMCUModuleDefinition, sim,
+};
+
+/// The query root every schema needs
+struct Query;
+
+#[Object]
+impl Query {
+    async fn version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+}
+
+type TestSchema = Schema<Query, CommandMutation<TestI2CDevice>, EmptySubscription>;
+
+fn schema() -> (TestSchema, SupMCUModuleDefinition) {
+    let defs: Vec<SupMCUModuleDefinition> =
+        serde_json::from_reader(File::open("test-definition.json").unwrap()).unwrap();
+    let bus = sim::SimBus::new(1449, defs).unwrap();
+    let def = bus.master.modules[0].get_definition().unwrap().clone();
+    let master = Arc::new(Mutex::new(bus.master));
+    let schema = Schema::new(Query, CommandMutation::new(master), EmptySubscription);
+    (schema, def)
+}
+
+fn execute(schema: &TestSchema, query: String) -> async_graphql::Response {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(schema.execute(query))
+}
+
+#[test]
+fn schema_snapshot() {
+    let sdl = schema().0.sdl();
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/schema.graphql");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &sdl).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert!(
+        expected == sdl,
+        "the schema changed, this breaks clients.\nexpected:\n{expected}\nactual:\n{sdl}"
+    );
+}
+
+#[test]
+fn send_command_mutation() {
+    let (schema, def) = schema();
+    let query = format!(
+        r#"mutation {{
+            sendCommand(module: "{:#04x}", command: "SUP:LED ON", confirmation: "{}") {{
+                accepted echoedCommand confirmation elapsedMs
+            }}
+        }}"#,
+        def.address, def.telemetry[1].name
+    );
+    let response = execute(&schema, query);
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    let result = &data["sendCommand"];
+    assert_eq!(result["accepted"], true);
+    assert_eq!(result["echoedCommand"], "SUP:LED ON");
+    assert_eq!(result["confirmation"].as_array().unwrap().len(), 1);
+    assert!(result["elapsedMs"].is_u64());
+
+    // Without a confirmation the command is accepted once sent
+    let query = format!(
+        r#"mutation {{ sendCommand(module: "{}", command: "SUP:LED OFF") {{ accepted confirmation }} }}"#,
+        def.name
+    );
+    let data = execute(&schema, query).data.into_json().unwrap();
+    assert_eq!(data["sendCommand"]["accepted"], true);
+    assert!(data["sendCommand"]["confirmation"].is_null());
+}
+
+#[test]
+fn send_command_error_extensions() {
+    let (schema, def) = schema();
+    let kind = |query: String| {
+        let response = execute(&schema, query);
+        assert_eq!(response.errors.len(), 1);
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        extensions.get("kind").unwrap().clone().into_json().unwrap()
+    };
+
+    assert_eq!(
+        kind(
+            r#"mutation { sendCommand(module: "NOPE", command: "SUP:LED ON") { accepted } }"#
+                .into()
+        ),
+        "UnknownModuleName"
+    );
+    assert_eq!(
+        kind(
+            r#"mutation { sendCommand(module: "0x7f", command: "SUP:LED ON") { accepted } }"#
+                .into()
+        ),
+        "ModuleNotFound"
+    );
+    assert_eq!(
+        kind(format!(
+            r#"mutation {{ sendCommand(module: "{}", command: "SUP:LED ON", confirmation: "nope") {{ accepted }} }}"#,
+            def.name
+        )),
+        "UnknownTelemName"
+    );
+}
@@ -22,6 +22,20 @@ fn create_all_data_types() {
     assert_eq!(DataType::Hex16, DataType::try_from('Z').unwrap());
 }
 
+#[test]
+fn data_type_rust_names() {
+    assert_eq!(DataType::UINT16.rust_type_name(), "u16");
+    assert_eq!(DataType::Hex16.rust_type_name(), "u16");
+    assert_eq!(DataType::Str.rust_type_name(), "String");
+    assert_eq!(DataType::Double.rust_type_name(), "f64");
+    assert_eq!(DataType::Char.byteorder_read_method(), Some("read_u8"));
+    assert_eq!(
+        DataType::INT32.byteorder_read_method(),
+        Some("read_i32::<LE>")
+    );
+    assert_eq!(DataType::Str.byteorder_read_method(), None);
+}
+
 #[test]
 #[should_panic]
 fn create_invalid_data_type() {
@@ -33,6 +47,14 @@ fn getting_format_string() {
     assert_eq!("fun", SupMCUFormat::new("f,u. o\\n").get_format_str());
 }
 
+#[test]
+fn hex_format_case_round_trip() {
+    let upper = SupMCUFormat::new("XZuX");
+    assert_eq!("xzux", upper.get_format_str());
+    assert_eq!(upper, SupMCUFormat::new(&upper.get_format_str()));
+    assert_eq!(upper, SupMCUFormat::new("xzux"));
+}
+
 #[test]
 fn format_length() {
     // char + int8 + uint16 + int32 + double + hex16
@@ -101,9 +123,631 @@ fn value_to_string() {
     assert_eq!("j", SupMCUValue::Char('j').to_string());
 }
 
+#[test]
+fn value_precision() {
+    assert_eq!(
+        "12.35",
+        SupMCUValue::Float(12.3456).format_with_precision(2)
+    );
+    assert_eq!(
+        "0.000",
+        SupMCUValue::Double(1.5e-7).format_with_precision(3)
+    );
+    assert_eq!("2", SupMCUValue::Double(1.5).format_with_precision(0));
+    assert_eq!("-1.250", format!("{:.3}", SupMCUValue::Float(-1.25)));
+    // Other values ignore the precision
+    assert_eq!("123456", SupMCUValue::U32(123456).format_with_precision(2));
+    assert_eq!("0x12", SupMCUValue::Hex8(0x12).format_with_precision(2));
+    assert_eq!(
+        "Hello World!",
+        SupMCUValue::Str("Hello World!".into()).format_with_precision(2)
+    );
+}
+
 #[test]
 fn load_definition() {
     let _defs: Vec<SupMCUModuleDefinition> =
-        serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap())
-            .unwrap();
+        serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap()).unwrap();
+}
+
+#[test]
+fn parse_command_signatures() {
+    use ParamKind::*;
+
+    #[allow(clippy::type_complexity)]
+    let cases: Vec<(&str, &str, Option<Vec<(ParamKind, bool)>>)> = vec![
+        (
+            "SUPervisor:LED <ON|OFF|FLASH>",
+            "SUPervisor:LED",
+            Some(vec![(
+                Choice(vec!["ON".into(), "OFF".into(), "FLASH".into()]),
+                false,
+            )]),
+        ),
+        ("SUPervisor:RESet", "SUPervisor:RESet", None),
+        (
+            "SUPervisor:RESet <NOW>",
+            "SUPervisor:RESet",
+            Some(vec![(Str, false)]),
+        ),
+        (
+            "SUPervisor:CLOCk <n>",
+            "SUPervisor:CLOCk",
+            Some(vec![(Integer, false)]),
+        ),
+        (
+            "SUPervisor:DEBug {0|1}",
+            "SUPervisor:DEBug",
+            Some(vec![(Choice(vec!["0".into(), "1".into()]), false)]),
+        ),
+        (
+            "SUPervisor:OSCillator <hex>",
+            "SUPervisor:OSCillator",
+            Some(vec![(Hex, false)]),
+        ),
+        (
+            "SUPervisor:TELemetry? <n>[,<NAME|FORMAT|LENGTH>]",
+            "SUPervisor:TELemetry?",
+            Some(vec![
+                (Integer, false),
+                (
+                    Choice(vec!["NAME".into(), "FORMAT".into(), "LENGTH".into()]),
+                    true,
+                ),
+            ]),
+        ),
+        (
+            "SUPervisor:COMmands? <n>",
+            "SUPervisor:COMmands?",
+            Some(vec![(Integer, false)]),
+        ),
+        (
+            "SUPervisor:NVM <UNLOCK|WRITE|ERASE> [<n>]",
+            "SUPervisor:NVM",
+            Some(vec![
+                (
+                    Choice(vec!["UNLOCK".into(), "WRITE".into(), "ERASE".into()]),
+                    false,
+                ),
+                (Integer, true),
+            ]),
+        ),
+        (
+            "EPSM:BUS <n>,<ON|OFF>",
+            "EPSM:BUS",
+            Some(vec![
+                (Integer, false),
+                (Choice(vec!["ON".into(), "OFF".into()]), false),
+            ]),
+        ),
+        (
+            "BSM:PORT:POWer <port#>, <ON | OFF>",
+            "BSM:PORT:POWer",
+            Some(vec![
+                (Integer, false),
+                (Choice(vec!["ON".into(), "OFF".into()]), false),
+            ]),
+        ),
+        ("GPS:LOG <string>", "GPS:LOG", Some(vec![(Str, false)])),
+        (
+            "BM2:BQFlash <0xADDR> <value>",
+            "BM2:BQFlash",
+            Some(vec![(Hex, false), (Integer, false)]),
+        ),
+        // Unrecognized notation falls back to the raw response
+        ("GPS:PASS some free text", "GPS:PASS some free text", None),
+    ];
+
+    for (response, name, params) in cases {
+        let cmd = SupMCUCommand::parse(response, 3);
+        assert_eq!(cmd.name, name, "{response}");
+        assert_eq!(cmd.idx, 3);
+        assert_eq!(
+            cmd.signature.map(|s| s
+                .params
+                .into_iter()
+                .map(|p| (p.kind, p.optional))
+                .collect::<Vec<_>>()),
+            params,
+            "{response}"
+        );
+    }
+}
+
+#[test]
+fn validate_command_arguments() {
+    let sig = SupMCUCommand::parse("EPSM:BUS <n>,<ON|OFF> [<hex>]", 0)
+        .signature
+        .unwrap();
+    assert!(sig.validate(&["1", "on"]).is_ok());
+    assert!(sig.validate(&["1", "OFF", "0x1f"]).is_ok());
+    assert!(sig.validate(&["1"]).is_err());
+    assert!(sig.validate(&["one", "ON"]).is_err());
+    assert!(sig.validate(&["1", "MAYBE"]).is_err());
+    assert!(sig.validate(&["1", "ON", "xyz"]).is_err());
+    assert!(sig.validate(&["1", "ON", "1f", "extra"]).is_err());
+}
+
+#[test]
+fn decode_reset_causes() {
+    assert_eq!(ResetCause::TrapConflict, ResetCause::from(0x8000u16));
+    assert_eq!(ResetCause::IllegalOpcode, ResetCause::from(0x4000u16));
+    assert_eq!(ResetCause::ConfigMismatch, ResetCause::from(0x0200u16));
+    assert_eq!(ResetCause::External, ResetCause::from(0x0080u16));
+    assert_eq!(ResetCause::Software, ResetCause::from(0x0040u16));
+    assert_eq!(ResetCause::Watchdog, ResetCause::from(0x0010u16));
+    assert_eq!(ResetCause::BrownOut, ResetCause::from(0x0002u16));
+    assert_eq!(ResetCause::PowerOn, ResetCause::from(0x0001u16));
+    // Power-on resets also set the brown-out flag
+    assert_eq!(ResetCause::PowerOn, ResetCause::from(0x0003u8));
+    assert_eq!(ResetCause::Other(0x0c00), ResetCause::from(0x0c00u16));
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    let cause = ResetCause::try_from(0x10u8);
+    assert_eq!(Ok(ResetCause::Watchdog), cause);
+    assert_eq!("watchdog time-out", ResetCause::Watchdog.to_string());
+}
+
+#[test]
+fn find_commands() {
+    let defs: Vec<SupMCUModuleDefinition> =
+        serde_json::from_reader(File::open(Path::new("test-definition.json")).unwrap()).unwrap();
+    let cmd = defs[0].find_command("SUPervisor:LED").unwrap();
+    assert_eq!(cmd.name, "SUPervisor:LED");
+    assert!(defs[0].has_command("GPS:DEBug"));
+    assert!(!defs[0].has_command("GPS:NOPE"));
+}
+
+#[test]
+fn decode_mcu_ids() {
+    let table = [
+        (0, McuType::UNKNOWN, None, None, McuFamily::Unknown),
+        (
+            1,
+            McuType::PIC24EP256MC206,
+            Some(256),
+            Some(32),
+            McuFamily::PIC24,
+        ),
+        (
+            2,
+            McuType::PIC24EP512MC206,
+            Some(512),
+            Some(48),
+            McuFamily::PIC24,
+        ),
+        (200, McuType::Other(200), None, None, McuFamily::Unknown),
+    ];
+    for (id, mcu, flash, ram, family) in table {
+        assert_eq!(mcu, McuType::from(id));
+        assert_eq!(id, mcu.id());
+        assert_eq!(flash, mcu.flash_kb());
+        assert_eq!(ram, mcu.ram_kb());
+        assert_eq!(family, mcu.family());
+        assert_eq!(mcu, mcu.to_string().parse::<McuType>().unwrap());
+    }
+    assert!(McuType::try_from(&200u8).is_err());
+    assert_eq!(McuType::PIC24EP512MC206, McuType::try_from(&2u8).unwrap());
+}
+
+#[test]
+fn mcu_type_serde_round_trip() {
+    for mcu in [McuType::PIC24EP256MC206, McuType::Other(42)] {
+        let json = serde_json::to_string(&mcu).unwrap();
+        assert_eq!(mcu, serde_json::from_str::<McuType>(&json).unwrap());
+    }
+    assert_eq!(
+        McuType::PIC24EP512MC206,
+        serde_json::from_str::<McuType>("\"PIC24EP512MC206\"").unwrap()
+    );
+}
+
+#[test]
+fn format_response_size() {
+    assert_eq!(Some(5 + 3 + 8), SupMCUFormat::new("us").response_size(None));
+    assert_eq!(
+        Some(5 + 4 + 8),
+        SupMCUFormat::new("f").response_size(Some(77))
+    );
+    assert_eq!(
+        Some(5 + 77 + 8),
+        SupMCUFormat::new("S").response_size(Some(77))
+    );
+    assert_eq!(None, SupMCUFormat::new("S").response_size(None));
+}
+
+#[test]
+fn parse_hex_telemetry() {
+    // Ready header with timestamp 0x2a, then a u16 and a string
+    let tlm = parse_telemetry_hex("sS", "01 2a 00 00 00 34 12 68 69 00").unwrap();
+    assert!(tlm.header.ready);
+    assert_eq!(tlm.header.timestamp, 0x2a);
+    assert_eq!(
+        tlm.data,
+        vec![SupMCUValue::U16(0x1234), SupMCUValue::Str("hi".into())]
+    );
+
+    let same = parse_telemetry_hex("sS", "0x01,0x2a,0x00,0x00,0x00,0x34,0x12,0x68,0x69,0x00");
+    assert_eq!(tlm.data, same.unwrap().data);
+    let same = parse_telemetry_hex("sS", "012a0000003412686900");
+    assert_eq!(tlm.data, same.unwrap().data);
+
+    assert!(parse_telemetry_hex("s", "01 2a 0").is_err());
+    assert!(parse_telemetry_hex("s", "zz").is_err());
+}
+
+#[test]
+fn scpi_short_and_long_forms() {
+    use supmcu_rs::supmcu::scpi::{self, Mnemonics};
+
+    assert_eq!(scpi::normalize("SUPERVISOR:TELEMETRY? 0"), "SUP:TEL? 0");
+    assert_eq!(scpi::normalize("  sup:Telemetry?   0 "), "SUP:TEL? 0");
+    let mnemonics = Mnemonics::default();
+    assert!(mnemonics.same_command("SUP:TEL? 0", "supervisor:tel? 0"));
+    assert!(mnemonics.same_command("SUP:LED ON", "Supervisor:LED ON"));
+    // Arguments are kept as written
+    assert_eq!(
+        scpi::normalize("sup:name  'Flight  Unit'"),
+        "SUP:NAME 'Flight  Unit'"
+    );
+    assert!(!mnemonics.same_command("SUP:LED on", "SUP:LED ON"));
+    assert!(mnemonics.same_command("SUP:SELF", "supervisor:selftest"));
+    assert_eq!(scpi::normalize("SUP:TEST"), "SUP:TEST");
+    assert!(!mnemonics.same_command("SUP:TEL? 0", "SUP:TEL? 1"));
+    assert!(!mnemonics.same_command("SUP:LED ON", "SUP:LED OFF"));
+    assert!(!mnemonics.same_command("SUP:TEL? 0", "SUP:COM? 0"));
+
+    let mut def = SupMCUModuleDefinition {
+        name: "BM".into(),
+        commands: vec![
+            SupMCUCommand::parse("SUPervisor:LED <ON|OFF|FLASH>", 0),
+            SupMCUCommand::parse("BM:HEATer <ON|OFF>", 1),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(def.find_command("sup:led").unwrap().idx, 0);
+    assert_eq!(def.find_command("BM:HEATER").unwrap().idx, 1);
+    assert_eq!(def.find_command("bm:heat").unwrap().idx, 1);
+    assert!(def.find_command("BM:BALANCE").is_none());
+
+    def.mnemonics.insert("BALANCE".into(), "BAL".into());
+    def.commands
+        .push(SupMCUCommand::parse("BM:BAL <ON|OFF>", 2));
+    assert_eq!(def.find_command("bm:balance").unwrap().idx, 2);
+    // Lookups follow commands changed in place
+    def.commands[1].name = "BM:COOLer".into();
+    assert_eq!(def.find_command("bm:cool").unwrap().idx, 1);
+    assert!(def.find_command("bm:heat").is_none());
+}
+
+#[test]
+fn parse_empty_format() {
+    let format = SupMCUFormat::new("");
+    assert!(format.is_empty());
+    assert_eq!(format.get_byte_length(), Some(0));
+    assert_eq!(format.response_size(None), Some(5 + 8));
+    assert!(format.validate_against_length(0).is_ok());
+
+    // Just a ready header, with or without the footer
+    let tlm = parse_telemetry_hex("", "01 2a 00 00 00").unwrap();
+    assert!(tlm.header.ready);
+    assert_eq!(tlm.header.timestamp, 0x2a);
+    assert!(tlm.data.is_empty());
+    let mut bytes = vec![0x01, 0x2a, 0x00, 0x00, 0x00];
+    bytes.extend([0; 8]);
+    assert!(parse_telemetry("", &bytes).unwrap().data.is_empty());
+
+    let def = SupMCUTelemetryDefinition {
+        name: "action".into(),
+        ..Default::default()
+    };
+    let tlm = SupMCUTelemetry::from_bytes(bytes.clone(), &def).unwrap();
+    assert!(tlm.data.is_empty());
+    let batch = parse_telemetry_batch(&[def.clone(), def], &bytes[..10]).unwrap();
+    assert!(batch.iter().all(|t| t.data.is_empty()));
+}
+
+#[test]
+fn parse_batch() {
+    let defs = vec![
+        SupMCUTelemetryDefinition {
+            name: "count".into(),
+            format: SupMCUFormat::new("s"),
+            ..Default::default()
+        },
+        SupMCUTelemetryDefinition {
+            name: "name".into(),
+            format: SupMCUFormat::new("S"),
+            length: Some(3),
+            ..Default::default()
+        },
+    ];
+    // Two responses with their headers, then a footer
+    let mut buff = vec![0x01, 0x2a, 0x00, 0x00, 0x00, 0x34, 0x12];
+    buff.extend([0x00, 0x2b, 0x00, 0x00, 0x00, 0x68, 0x69, 0x00]);
+    buff.extend([0; 8]);
+    let tlm = parse_telemetry_batch(&defs, &buff).unwrap();
+    assert_eq!(tlm.len(), 2);
+    assert!(tlm[0].header.ready);
+    assert_eq!(tlm[0].data, vec![SupMCUValue::U16(0x1234)]);
+    assert!(!tlm[1].header.ready);
+    assert_eq!(tlm[1].header.timestamp, 0x2b);
+    assert_eq!(tlm[1].data, vec![SupMCUValue::Str("hi".into())]);
+
+    assert!(matches!(
+        parse_telemetry_batch(&defs, &buff[..10]),
+        Err(supmcu_rs::ParsingError::InvalidBytes(_))
+    ));
+}
+
+#[test]
+fn telemetry_index_problems() {
+    let item = |name: &str, idx| SupMCUTelemetryDefinition {
+        name: name.into(),
+        idx,
+        telemetry_type: TelemetryType::Module,
+        ..Default::default()
+    };
+    let mut def = SupMCUModuleDefinition {
+        telemetry: vec![item("a", 0), item("b", 1), item("c", 2)],
+        ..Default::default()
+    };
+    assert!(def.index_problems(TelemetryType::Module, 3).is_empty());
+    assert!(def.index_problems(TelemetryType::SupMCU, 0).is_empty());
+
+    def.telemetry = vec![
+        item("a", 0),
+        item("", 1),
+        item("c", 3),
+        item("d", 3),
+        item("e", 5),
+    ];
+    assert_eq!(
+        def.index_problems(TelemetryType::Module, 4),
+        vec![
+            IndexProblem::Unnamed(TelemetryType::Module, 1),
+            IndexProblem::Duplicate(TelemetryType::Module, 3),
+            IndexProblem::OutOfRange(TelemetryType::Module, 5),
+            IndexProblem::Missing(TelemetryType::Module, 2),
+        ]
+    );
+}
+
+#[test]
+fn changed_fields() {
+    let reading = |data| SupMCUTelemetry {
+        definition: SupMCUTelemetryDefinition::default(),
+        header: SupMCUHDR {
+            ready: true,
+            timestamp: 0,
+        },
+        data,
+        host_time: None,
+    };
+    let previous = reading(vec![
+        SupMCUValue::U16(1),
+        SupMCUValue::Float(0.1 + 0.2),
+        SupMCUValue::Double(f64::NAN),
+        SupMCUValue::Str("ok".into()),
+    ]);
+    let current = reading(vec![
+        SupMCUValue::U16(2),
+        SupMCUValue::Float(0.3),
+        SupMCUValue::Double(f64::NAN),
+        SupMCUValue::Str("fault".into()),
+    ]);
+    assert_eq!(
+        current.changed_fields(&previous),
+        vec![
+            (0, &SupMCUValue::U16(1), &SupMCUValue::U16(2)),
+            (
+                3,
+                &SupMCUValue::Str("ok".into()),
+                &SupMCUValue::Str("fault".into())
+            ),
+        ]
+    );
+    assert!(current.changed_fields(&current).is_empty());
+}
+
+#[test]
+fn deserialize_telemetry() {
+    use serde::Deserialize;
+    use supmcu_rs::{ParsingError, SupMCUError};
+
+    let reading = SupMCUTelemetry {
+        definition: SupMCUTelemetryDefinition::default(),
+        header: SupMCUHDR {
+            ready: true,
+            timestamp: 0,
+        },
+        data: vec![
+            SupMCUValue::U16(7400),
+            SupMCUValue::I16(-120),
+            SupMCUValue::Float(21.5),
+            SupMCUValue::Hex8(0x2a),
+            SupMCUValue::Str("OK".into()),
+        ],
+        host_time: None,
+    };
+    let values: (u16, i16, f32, u8, String) = reading.deserialize_into().unwrap();
+    assert_eq!(values, (7400, -120, 21.5, 0x2a, "OK".to_string()));
+    // Integers widen, and floats do too
+    let values: (u32, i64, f64, u64, String) = reading.deserialize_into().unwrap();
+    assert_eq!(values, (7400, -120, 21.5, 0x2a, "OK".to_string()));
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Battery {
+        #[serde(rename = "vbatt")]
+        voltage: u16,
+        current: i16,
+        temperature: f64,
+        flags: u16,
+        status: String,
+    }
+    assert_eq!(
+        reading.deserialize_into::<Battery>().unwrap(),
+        Battery {
+            voltage: 7400,
+            current: -120,
+            temperature: 21.5,
+            flags: 0x2a,
+            status: "OK".into(),
+        }
+    );
+
+    let message = |e: SupMCUError| match e {
+        SupMCUError::ParsingError(ParsingError::DeserializeError(msg)) => msg,
+        e => panic!("unexpected error {e}"),
+    };
+    // -120 doesn't fit an unsigned integer
+    let e = reading.deserialize_into::<(u16, u16, f32, u8, String)>();
+    assert_eq!(
+        message(e.unwrap_err()),
+        "field 1: can't read I16(-120) as u16"
+    );
+    let e = reading.deserialize_into::<(u16, i16, i32, u8, String)>();
+    assert_eq!(
+        message(e.unwrap_err()),
+        "field 2: can't read Float(21.5) as i32"
+    );
+    let e = reading.deserialize_into::<(u16, i16)>();
+    assert!(message(e.unwrap_err()).contains("5 values"));
+    let e = reading.deserialize_into::<(u16, i16, f32, u8, String, u8)>();
+    assert!(message(e.unwrap_err()).contains("invalid length 5"));
+
+    // A single value can be read directly
+    let single = [SupMCUValue::Hex16(0xbeef)];
+    assert_eq!(
+        supmcu_rs::supmcu::de::from_values::<u32>(&single).unwrap(),
+        0xbeef
+    );
+    assert!(supmcu_rs::supmcu::de::from_values::<u32>(&reading.data).is_err());
+}
+
+#[test]
+fn parse_error_position() {
+    // The u32 at byte 3 is cut short
+    let data = vec![0x01, 0x34, 0x12, 0xff, 0xff];
+    let e = SupMCUFormat::new("usi")
+        .parse_data(&mut Cursor::new(&data))
+        .unwrap_err();
+    assert_eq!(e.position(), Some((2, 3)));
+    assert!(matches!(
+        e,
+        supmcu_rs::ParsingError::FieldError { format: 'i', ref error, .. }
+            if matches!(**error, supmcu_rs::ParsingError::ByteParsingError(_))
+    ));
+    assert!(
+        e.to_string()
+            .starts_with("Failed to parse field 2 ('i') at byte 3: "),
+        "{e}"
+    );
+
+    // Offsets of whole responses count the header
+    let def = SupMCUTelemetryDefinition {
+        format: SupMCUFormat::new("s"),
+        ..Default::default()
+    };
+    let e = SupMCUTelemetry::from_bytes(vec![1, 0, 0, 0, 0, 0x34], &def).unwrap_err();
+    assert_eq!(e.position(), Some((0, 5)));
+}
+
+#[test]
+fn validate_format_length() {
+    let format = SupMCUFormat::new("usi");
+    assert!(format.validate_against_length(7).is_ok());
+    let e = format.validate_against_length(8).unwrap_err();
+    assert!(matches!(
+        e,
+        supmcu_rs::ParsingError::LengthMismatch {
+            needed: 7,
+            actual: 8,
+            ..
+        }
+    ));
+    assert_eq!(
+        e.to_string(),
+        "Format usi takes 7 bytes, but the response body is 8"
+    );
+
+    // Strings take whatever the other fields leave
+    let format = SupMCUFormat::new("uS");
+    assert!(format.validate_against_length(1).is_ok());
+    assert!(format.validate_against_length(20).is_ok());
+    assert!(format.validate_against_length(0).is_err());
+}
+
+#[test]
+fn format_element_ranges() {
+    let format = SupMCUFormat::new("uSsSci");
+    let mut data = vec![7];
+    data.extend(b"abc\0");
+    data.extend([0x34, 0x12]);
+    data.push(0); // An empty string is just its terminator
+    data.push(b'z');
+    data.extend([1, 0, 0, 0]);
+
+    let ranges = format.element_ranges(&data).unwrap();
+    assert_eq!(ranges, vec![0..1, 1..5, 5..7, 7..8, 8..9, 9..13]);
+    assert_eq!(format.element_slice(&data, 1).unwrap(), b"abc\0");
+    assert_eq!(format.element_slice(&data, 3).unwrap(), b"\0");
+    assert_eq!(format.element_slice(&data, 5).unwrap(), &[1, 0, 0, 0]);
+    assert!(format.element_slice(&data, 6).is_err());
+
+    // Parsing decodes exactly these ranges
+    let values = format.parse_data(&mut Cursor::new(&data)).unwrap();
+    for (k, value) in values.iter().enumerate() {
+        let bytes = format.element_slice(&data, k).unwrap().to_vec();
+        let element = SupMCUFormat::new(&format.get_format_str()[k..=k]);
+        let parsed = element.parse_data(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(parsed, vec![value.clone()]);
+    }
+
+    // A final string without a terminator runs to the end
+    let format = SupMCUFormat::new("uS");
+    assert_eq!(
+        format.element_ranges(&[1, b'h', b'i']).unwrap(),
+        vec![0..1, 1..3]
+    );
+    assert_eq!(format.element_ranges(&[1]).unwrap(), vec![0..1, 1..1]);
+}
+
+#[test]
+fn format_element_ranges_truncated() {
+    let format = SupMCUFormat::new("uSi");
+    let data = vec![1, b'a', 0, 0xff, 0xff];
+    let e = format.element_ranges(&data).unwrap_err();
+    assert_eq!(e.position(), Some((2, 3)));
+
+    // Earlier elements still have slices, and parsing fails at the same place
+    assert_eq!(format.element_slice(&data, 1).unwrap(), b"a\0");
+    assert_eq!(
+        format.element_slice(&data, 2).unwrap_err().position(),
+        Some((2, 3))
+    );
+    let e = format.parse_data(&mut Cursor::new(&data)).unwrap_err();
+    assert_eq!(e.position(), Some((2, 3)));
+}
+
+#[test]
+fn macro_verification_tolerance() {
+    let step = |tolerance| MacroStep::VerifyTelemetry {
+        name: "bus_voltage".into(),
+        expected: vec![SupMCUValue::Float(3.3), SupMCUValue::U16(2)],
+        tolerance,
+    };
+    let read = |volts| vec![SupMCUValue::Float(volts), SupMCUValue::U16(2)];
+    assert!(step(None).verifies(&read(3.3)));
+    assert!(step(None).verifies(&read(1.1 * 3.0)));
+    assert!(!step(None).verifies(&read(3.31)));
+    assert!(step(Some(0.05)).verifies(&read(3.31)));
+    assert!(!step(Some(0.05)).verifies(&read(3.4)));
+    assert!(!step(Some(0.05)).verifies(&read(3.3)[..1]));
+
+    let json = serde_json::to_string(&step(None)).unwrap();
+    assert!(!json.contains("tolerance"));
+    assert_eq!(
+        serde_json::from_str::<MacroStep>(&json).unwrap(),
+        step(None)
+    );
 }